@@ -0,0 +1,169 @@
+//! Blocking facade over the async [`Api`](crate::Api).
+//!
+//! Requests are driven to completion on an internal single-threaded runtime, so
+//! the methods must not be called from within an async runtime.
+
+use std::sync::Arc;
+
+use futures::TryStreamExt;
+use serde::de::DeserializeOwned;
+use tokio::runtime::{Builder, Runtime};
+
+use crate::{
+    models::{self, Character, CurrencyType},
+    Auth, Endpoint, Error, RawResponse, Result,
+};
+
+/// Blocking API client for interacting with the DT Api.
+#[derive(Clone, Debug)]
+pub struct Api {
+    inner: crate::Api,
+    runtime: Arc<Runtime>,
+}
+
+impl Api {
+    /// Creates a new blocking API client.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the internal runtime cannot be created.
+    pub fn new() -> Result<Self> {
+        Self::from_async(crate::Api::new())
+    }
+
+    /// Creates a blocking API client wrapping an existing async client.
+    ///
+    /// # Parameters
+    ///
+    /// - `api` - The async client to wrap.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the internal runtime cannot be created.
+    pub fn from_async(api: crate::Api) -> Result<Self> {
+        let runtime = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(Error::Runtime)?;
+        Ok(Self {
+            inner: api,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// Gets the summary for the account.
+    ///
+    /// See [`crate::Api::get_summary`].
+    pub fn get_summary(&self, auth: &Auth) -> Result<models::Summary> {
+        self.runtime.block_on(self.inner.get_summary(auth))
+    }
+
+    /// Gets the store for the character.
+    ///
+    /// See [`crate::Api::get_store`].
+    pub fn get_store(
+        &self,
+        auth: &Auth,
+        currency_type: CurrencyType,
+        character: &Character,
+    ) -> Result<models::Store> {
+        self.runtime
+            .block_on(self.inner.get_store(auth, currency_type, character))
+    }
+
+    /// Gets the inventory of the character.
+    ///
+    /// See [`crate::Api::get_inventory`].
+    pub fn get_inventory(&self, auth: &Auth, character: &Character) -> Result<models::Inventory> {
+        self.runtime
+            .block_on(self.inner.get_inventory(auth, character))
+    }
+
+    /// Gets the wallets of the account.
+    ///
+    /// See [`crate::Api::get_wallets`].
+    pub fn get_wallets(&self, auth: &Auth) -> Result<models::Wallets> {
+        self.runtime.block_on(self.inner.get_wallets(auth))
+    }
+
+    /// Gets the master data.
+    ///
+    /// See [`crate::Api::get_master_data`].
+    pub fn get_master_data(&self, auth: &Auth) -> Result<models::MasterData> {
+        self.runtime.block_on(self.inner.get_master_data(auth))
+    }
+
+    /// Gets a single page of a leaderboard.
+    ///
+    /// See [`crate::Api::get_leaderboard_page`].
+    pub fn get_leaderboard_page(
+        &self,
+        auth: &Auth,
+        board: &str,
+        continuation_token: Option<&str>,
+    ) -> Result<models::Paginated<models::LeaderboardEntry>> {
+        self.runtime.block_on(
+            self.inner
+                .get_leaderboard_page(auth, board, continuation_token),
+        )
+    }
+
+    /// Gets every entry of a leaderboard.
+    ///
+    /// See [`crate::Api::get_leaderboard`].
+    pub fn get_leaderboard(
+        &self,
+        auth: &Auth,
+        board: &str,
+    ) -> Result<Vec<models::LeaderboardEntry>> {
+        self.runtime
+            .block_on(self.inner.get_leaderboard(auth, board))
+    }
+
+    /// Gets a single page of a paginated resource.
+    ///
+    /// See [`crate::Api::get_page`].
+    pub fn get_page<T: DeserializeOwned + std::fmt::Debug>(
+        &self,
+        auth: &Auth,
+        path: &str,
+        continuation_token: Option<&str>,
+    ) -> Result<models::Paginated<T>> {
+        self.runtime
+            .block_on(self.inner.get_page(auth, path, continuation_token))
+    }
+
+    /// Fetches every page of a paginated resource, following continuation tokens.
+    ///
+    /// See [`crate::Api::fetch_all_pages`].
+    pub fn fetch_all_pages<T: DeserializeOwned + std::fmt::Debug>(
+        &self,
+        auth: &Auth,
+        path: &str,
+    ) -> Result<Vec<T>> {
+        self.runtime
+            .block_on(self.inner.fetch_all_pages(auth, path).try_collect())
+    }
+
+    /// Gets the raw JSON response of an endpoint without parsing it into a model.
+    ///
+    /// See [`crate::Api::get_raw`].
+    pub fn get_raw(&self, auth: &Auth, endpoint: Endpoint<'_>) -> Result<serde_json::Value> {
+        self.runtime.block_on(self.inner.get_raw(auth, endpoint))
+    }
+
+    /// Gets the response of an endpoint as received, whatever its status.
+    ///
+    /// See [`crate::Api::get_response`].
+    pub fn get_response(&self, auth: &Auth, endpoint: Endpoint<'_>) -> Result<RawResponse> {
+        self.runtime
+            .block_on(self.inner.get_response(auth, endpoint))
+    }
+
+    /// Refreshes the authentication token.
+    ///
+    /// See [`crate::Api::refresh_auth`].
+    pub fn refresh_auth(&self, auth: &Auth) -> Result<Auth> {
+        self.runtime.block_on(self.inner.refresh_auth(auth))
+    }
+}
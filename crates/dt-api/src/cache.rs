@@ -0,0 +1,268 @@
+//! Caching decorator over any [`ApiClient`].
+//!
+//! Responses are kept per account (master data is shared between accounts) for
+//! a configurable time per endpoint. Stores are never kept past the end of their
+//! rotation, so a long store TTL can't serve offers that are no longer on sale.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    models::{self, AccountId, Character, CharacterId, CurrencyType},
+    ApiClient, Auth, Result,
+};
+
+/// Endpoints whose responses are cached by [`CachedApi`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CachedEndpoint {
+    /// The account summary.
+    Summary,
+    /// The store for a character and currency type.
+    Store,
+    /// The master data.
+    MasterData,
+}
+
+impl CachedEndpoint {
+    fn default_ttl(self) -> Duration {
+        match self {
+            CachedEndpoint::Summary => Duration::from_secs(5 * 60),
+            CachedEndpoint::Store => Duration::from_secs(60 * 60),
+            CachedEndpoint::MasterData => Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Key {
+    Summary(AccountId),
+    Store(AccountId, CurrencyType, CharacterId),
+    MasterData,
+}
+
+impl Key {
+    fn account_id(self) -> Option<AccountId> {
+        match self {
+            Key::Summary(account_id) | Key::Store(account_id, _, _) => Some(account_id),
+            Key::MasterData => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+    Summary(models::Summary),
+    Store(models::Store),
+    MasterData(models::MasterData),
+}
+
+#[derive(Debug)]
+struct Entry {
+    value: Value,
+    inserted: DateTime<Utc>,
+    expires: DateTime<Utc>,
+}
+
+#[derive(Debug, Default)]
+struct Cache {
+    entries: HashMap<Key, Entry>,
+}
+
+impl Cache {
+    fn get(&self, key: &Key, now: DateTime<Utc>) -> Option<Value> {
+        self.entries
+            .get(key)
+            .filter(|entry| entry.expires > now)
+            .map(|entry| entry.value.clone())
+    }
+
+    /// Inserts an entry, evicting expired entries and then the oldest ones to
+    /// stay within `max_entries`.
+    fn insert(&mut self, key: Key, entry: Entry, max_entries: usize, now: DateTime<Utc>) {
+        if max_entries == 0 {
+            return;
+        }
+        self.entries.insert(key, entry);
+        if self.entries.len() <= max_entries {
+            return;
+        }
+        self.entries.retain(|_, entry| entry.expires > now);
+        while self.entries.len() > max_entries {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.inserted)
+                .map(|(key, _)| *key)
+            else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+/// [`ApiClient`] that caches the responses of the client it wraps.
+///
+/// Clones share the same cache. Errors are never cached.
+#[derive(Clone, Debug)]
+pub struct CachedApi<C> {
+    inner: C,
+    ttls: HashMap<CachedEndpoint, Duration>,
+    max_entries: usize,
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl<C: ApiClient> CachedApi<C> {
+    /// Default maximum number of cached responses.
+    pub const DEFAULT_MAX_ENTRIES: usize = 1024;
+
+    /// Creates a caching client wrapping `inner`.
+    ///
+    /// By default summaries are cached for 5 minutes, and stores and master
+    /// data for an hour, up to [`Self::DEFAULT_MAX_ENTRIES`] responses.
+    ///
+    /// # Parameters
+    ///
+    /// - `inner` - The client to cache the responses of.
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            ttls: HashMap::new(),
+            max_entries: Self::DEFAULT_MAX_ENTRIES,
+            cache: Arc::default(),
+        }
+    }
+
+    /// Sets how long responses from `endpoint` are cached for.
+    ///
+    /// # Parameters
+    ///
+    /// - `endpoint` - The endpoint to set the TTL for.
+    /// - `ttl` - How long to cache responses for. A zero TTL disables caching.
+    pub fn with_ttl(mut self, endpoint: CachedEndpoint, ttl: Duration) -> Self {
+        self.ttls.insert(endpoint, ttl);
+        self
+    }
+
+    /// Sets the maximum number of cached responses.
+    ///
+    /// Once full, expired responses are evicted first, then the oldest ones.
+    ///
+    /// # Parameters
+    ///
+    /// - `max_entries` - The maximum number of responses to keep.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Returns the wrapped client.
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    /// Drops the cached summary and stores of an account.
+    ///
+    /// # Parameters
+    ///
+    /// - `account_id` - The account to drop the responses of.
+    pub fn invalidate(&self, account_id: AccountId) {
+        self.lock()
+            .entries
+            .retain(|key, _| key.account_id() != Some(account_id));
+    }
+
+    /// Drops every cached response.
+    pub fn clear(&self) {
+        self.lock().entries.clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Cache> {
+        // Entries are only ever replaced whole, so a poisoned cache is still
+        // consistent.
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn ttl(&self, endpoint: CachedEndpoint) -> Duration {
+        self.ttls
+            .get(&endpoint)
+            .copied()
+            .unwrap_or_else(|| endpoint.default_ttl())
+    }
+
+    fn get(&self, key: &Key) -> Option<Value> {
+        self.lock().get(key, Utc::now())
+    }
+
+    fn insert(&self, key: Key, endpoint: CachedEndpoint, value: Value) {
+        let now = Utc::now();
+        let Some(mut expires) = chrono::Duration::from_std(self.ttl(endpoint))
+            .ok()
+            .and_then(|ttl| now.checked_add_signed(ttl))
+        else {
+            return;
+        };
+        if let Value::Store(store) = &value {
+            expires = expires.min(store.current_rotation_end);
+        }
+        if expires <= now {
+            return;
+        }
+        let entry = Entry {
+            value,
+            inserted: now,
+            expires,
+        };
+        self.lock().insert(key, entry, self.max_entries, now);
+    }
+}
+
+impl<C: ApiClient> ApiClient for CachedApi<C> {
+    async fn get_summary(&self, auth: &Auth) -> Result<models::Summary> {
+        let key = Key::Summary(auth.sub);
+        if let Some(Value::Summary(summary)) = self.get(&key) {
+            return Ok(summary);
+        }
+        let summary = self.inner.get_summary(auth).await?;
+        self.insert(
+            key,
+            CachedEndpoint::Summary,
+            Value::Summary(summary.clone()),
+        );
+        Ok(summary)
+    }
+
+    async fn get_store(
+        &self,
+        auth: &Auth,
+        currency_type: CurrencyType,
+        character: &Character,
+    ) -> Result<models::Store> {
+        let key = Key::Store(auth.sub, currency_type, character.id);
+        if let Some(Value::Store(store)) = self.get(&key) {
+            return Ok(store);
+        }
+        let store = self.inner.get_store(auth, currency_type, character).await?;
+        self.insert(key, CachedEndpoint::Store, Value::Store(store.clone()));
+        Ok(store)
+    }
+
+    async fn get_master_data(&self, auth: &Auth) -> Result<models::MasterData> {
+        let key = Key::MasterData;
+        if let Some(Value::MasterData(master_data)) = self.get(&key) {
+            return Ok(master_data);
+        }
+        let master_data = self.inner.get_master_data(auth).await?;
+        self.insert(
+            key,
+            CachedEndpoint::MasterData,
+            Value::MasterData(master_data.clone()),
+        );
+        Ok(master_data)
+    }
+}
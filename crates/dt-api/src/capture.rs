@@ -0,0 +1,139 @@
+//! Capture of upstream responses as fixtures for [`replay`](crate::replay).
+//!
+//! An [`Api`](crate::Api) created with
+//! [`Api::with_capture`](crate::Api::with_capture) writes the JSON of every
+//! successful response to the file [`replay`](crate::replay) reads it from,
+//! in the directory of the account, overwriting the previous capture. Each
+//! capture is also appended to `captures.jsonl` with its endpoint, account
+//! hash and time. Fields holding tokens or passwords are [scrubbed](scrub)
+//! first. Auth refreshes are never captured.
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, instrument, warn};
+
+use crate::{
+    models::AccountId,
+    replay::{account_dir, fixture_path},
+    Endpoint, Error, Result,
+};
+
+/// Name of the index of captures, in the capture directory.
+pub const INDEX_FILE: &str = "captures.jsonl";
+
+/// Replaces the values of these fields, compared case-insensitively.
+const SECRET_FIELDS: &[&str] = &[
+    "accesstoken",
+    "refreshtoken",
+    "idtoken",
+    "sessiontoken",
+    "token",
+    "ticket",
+    "password",
+];
+
+const SCRUBBED: &str = "<scrubbed>";
+
+/// Entry of the capture index.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CaptureRecord<'a> {
+    endpoint: String,
+    account: &'a str,
+    captured_at: DateTime<Utc>,
+    /// The fixture, relative to the capture directory.
+    file: &'a Path,
+}
+
+/// Capture directory written by a capturing [`Api`](crate::Api).
+#[derive(Clone, Debug)]
+pub struct Capture {
+    dir: PathBuf,
+}
+
+impl Capture {
+    /// Creates a capture into `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The capture directory.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Parses a successful response, capturing it first.
+    pub(crate) async fn parse<T: DeserializeOwned>(
+        &self,
+        sub: AccountId,
+        endpoint: Endpoint<'_>,
+        res: reqwest::Response,
+    ) -> Result<T> {
+        let mut value = res
+            .json::<serde_json::Value>()
+            .await
+            .map_err(Error::InvalidResponse)?;
+        let data = T::deserialize(&value).map_err(Error::InvalidJson)?;
+        scrub(&mut value);
+        if let Err(e) = self.record(sub, endpoint, &value).await {
+            warn!(endpoint = %endpoint, error = %e, "Failed to capture response");
+        }
+        Ok(data)
+    }
+
+    #[instrument(skip(self, value))]
+    async fn record(
+        &self,
+        sub: AccountId,
+        endpoint: Endpoint<'_>,
+        value: &serde_json::Value,
+    ) -> std::io::Result<()> {
+        let account = account_dir(sub);
+        let file = Path::new(&account).join(fixture_path(endpoint));
+        let path = self.dir.join(&file);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&path, serde_json::to_vec_pretty(value)?).await?;
+
+        let record = CaptureRecord {
+            endpoint: endpoint.to_string(),
+            account: &account,
+            captured_at: Utc::now(),
+            file: &file,
+        };
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(INDEX_FILE))
+            .await?
+            .write_all(&line)
+            .await?;
+        debug!(path = %path.display(), "Captured {}", endpoint);
+        Ok(())
+    }
+}
+
+/// Replace the values of fields holding tokens or passwords, at any depth.
+///
+/// Continuation tokens are kept, as replaying paginated resources needs them.
+pub fn scrub(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (key, value) in fields.iter_mut() {
+                if SECRET_FIELDS.contains(&key.to_ascii_lowercase().as_str()) {
+                    *value = SCRUBBED.into();
+                } else {
+                    scrub(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(scrub),
+        _ => {}
+    }
+}
@@ -0,0 +1,1148 @@
+use std::collections::HashSet;
+
+use futures::{stream, Stream, TryStreamExt};
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+use tracing::{debug, info, instrument};
+
+use crate::{
+    models::{self, AccountId, Character, CharacterId, CurrencyType, Links as _},
+    Auth,
+};
+
+/// Errors that can occur when interacting with the API.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// An error occurred while sending a request to the API.
+    #[error("Sending request failed")]
+    RequestFailed(#[from] reqwest::Error),
+    /// An error occurred while parsing the response from the API.
+    #[error("Parsing response failed")]
+    InvalidResponse(#[source] reqwest::Error),
+    /// The server returned an error response when getting the summary.
+    #[error("Failed to get summary for {sub}: {status}: {error}")]
+    GetSummary {
+        status: reqwest::StatusCode,
+        error: serde_json::Value,
+        sub: AccountId,
+    },
+    /// The server returned an error response when getting the store.
+    #[error("Failed to get {currency_type} store for {archetype}: {status}: {error}")]
+    GetStore {
+        status: reqwest::StatusCode,
+        error: serde_json::Value,
+        currency_type: CurrencyType,
+        archetype: String,
+    },
+    /// The server returned an error response when getting the inventory.
+    #[error("Failed to get inventory for {character_id}: {status}: {error}")]
+    GetInventory {
+        status: reqwest::StatusCode,
+        error: serde_json::Value,
+        character_id: CharacterId,
+    },
+    /// The server returned an error response when getting the wallets.
+    #[error("Failed to get wallets for {sub}: {status}: {error}")]
+    GetWallets {
+        status: reqwest::StatusCode,
+        error: serde_json::Value,
+        sub: AccountId,
+    },
+    /// The server returned an error response when getting the master data.
+    #[error("Failed to get master data: {status}: {error}")]
+    GetMasterData {
+        status: reqwest::StatusCode,
+        error: serde_json::Value,
+    },
+    /// The server returned an error response when getting a page of a paginated resource.
+    #[error("Failed to get page of {path}: {status}: {error}")]
+    GetPage {
+        status: reqwest::StatusCode,
+        error: serde_json::Value,
+        path: String,
+    },
+    /// A paginated resource returned a continuation token it returned before,
+    /// so following it would never end.
+    #[error("Continuation token of {path} repeated")]
+    RepeatedContinuationToken { path: String },
+    /// A paginated resource has more pages than [`Api::with_max_pages`] allows.
+    #[error("{path} has more than {max} pages")]
+    TooManyPages { path: String, max: usize },
+    /// The server returned an error response when refreshing the auth.
+    #[error("Failed to refresh auth: {status}: {error}")]
+    RefreshAuth {
+        status: reqwest::StatusCode,
+        error: serde_json::Value,
+    },
+    /// The server returned `429 Too Many Requests`.
+    #[error("Rate limited getting {endpoint}")]
+    RateLimited {
+        endpoint: String,
+        /// How long to wait before retrying, from the `Retry-After` header.
+        retry_after: Option<std::time::Duration>,
+    },
+    /// A captured response isn't valid JSON for the model.
+    #[cfg(feature = "replay")]
+    #[error("Parsing response failed")]
+    InvalidJson(#[source] serde_json::Error),
+    /// A replaying client has no fixture for the endpoint.
+    #[cfg(feature = "replay")]
+    #[error("No fixture for {endpoint} at {path}")]
+    MissingFixture {
+        endpoint: String,
+        path: std::path::PathBuf,
+    },
+    /// A replaying client failed to read a fixture.
+    #[cfg(feature = "replay")]
+    #[error("Failed to read fixture {path}")]
+    ReadFixture {
+        path: std::path::PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// A fixture doesn't match the model of its endpoint.
+    #[cfg(feature = "replay")]
+    #[error("Invalid fixture {path}")]
+    InvalidFixture {
+        path: std::path::PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// A header given to the [`ApiBuilder`] isn't a valid HTTP header.
+    #[error("Invalid header {name}")]
+    InvalidHeader { name: String },
+    /// The HTTP client could not be built.
+    #[error("Building the HTTP client failed")]
+    BuildClient(#[source] reqwest::Error),
+    /// The runtime backing the blocking client could not be created.
+    #[cfg(feature = "blocking")]
+    #[error("Failed to create runtime")]
+    Runtime(#[source] std::io::Error),
+}
+
+/// Result type for API operations.
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Whether connecting to the API failed, e.g. because its address changed.
+    pub fn is_connect(&self) -> bool {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Error::RequestFailed(e) => e.is_connect(),
+            _ => false,
+        }
+    }
+
+    /// How long the server asked to wait before retrying, if it rate limited
+    /// the request.
+    pub fn retry_after(&self) -> Option<std::time::Duration> {
+        match self {
+            Error::RateLimited { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+/// The error for a `429 Too Many Requests` response to a request for
+/// `endpoint`.
+fn rate_limited(endpoint: impl std::fmt::Display, res: &reqwest::Response) -> Error {
+    let retry_after = res
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| crate::parse_retry_after(value, chrono::Utc::now()));
+    tracing::warn!(retry_after = ?retry_after, "Rate limited getting {}", endpoint);
+    Error::RateLimited {
+        endpoint: endpoint.to_string(),
+        retry_after,
+    }
+}
+
+const BASE_URL: &str = "https://bsp-td-prod.atoma.cloud";
+
+/// Pages of a paginated resource fetched by [`Api::fetch_all_pages`] unless
+/// set with [`Api::with_max_pages`].
+pub const DEFAULT_MAX_PAGES: usize = 1000;
+
+/// A response of the API as it was received, for debugging.
+#[derive(Debug, Clone)]
+pub struct RawResponse {
+    pub status: reqwest::StatusCode,
+    pub headers: reqwest::header::HeaderMap,
+    /// The JSON body, or the body as a string if it isn't JSON.
+    pub body: serde_json::Value,
+}
+
+/// Upstream endpoints that can be requested through the [`Api`].
+#[derive(Clone, Copy, Debug)]
+pub enum Endpoint<'a> {
+    /// The account summary.
+    Summary,
+    /// The store for a character and currency type.
+    Store {
+        currency_type: CurrencyType,
+        character: &'a Character,
+    },
+    /// The store for an archetype and currency type, without the personal
+    /// offers of any character.
+    PublicStore {
+        currency_type: CurrencyType,
+        archetype: &'a str,
+    },
+    /// The inventory of a character.
+    Inventory { character: &'a Character },
+    /// The account wallets, holding the crafting materials.
+    Wallets,
+    /// The master data.
+    MasterData,
+    /// A page of a paginated resource, or a linked resource, by its path
+    /// relative to the API base URL.
+    Page {
+        path: &'a str,
+        continuation_token: Option<&'a str>,
+    },
+}
+
+impl std::fmt::Display for Endpoint<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Endpoint::Summary => write!(f, "summary"),
+            Endpoint::Store {
+                currency_type,
+                character,
+            } => write!(f, "{}_store_{}", currency_type, character.archetype),
+            Endpoint::PublicStore {
+                currency_type,
+                archetype,
+            } => write!(f, "public {}_store_{}", currency_type, archetype),
+            Endpoint::Inventory { character } => write!(f, "inventory of {}", character.id),
+            Endpoint::Wallets => write!(f, "wallets"),
+            Endpoint::MasterData => write!(f, "master data"),
+            Endpoint::Page { path, .. } => write!(f, "page of {}", path),
+        }
+    }
+}
+
+/// Typed requests to the DT Api, implemented by [`Api`] and by decorators
+/// wrapping it, such as `CachedApi`.
+// The futures can't be required to be `Send`, as they aren't on `wasm32`.
+#[allow(async_fn_in_trait)]
+pub trait ApiClient {
+    /// Gets the summary for the account.
+    ///
+    /// See [`Api::get_summary`].
+    async fn get_summary(&self, auth: &Auth) -> Result<models::Summary>;
+
+    /// Gets the store for the character.
+    ///
+    /// See [`Api::get_store`].
+    async fn get_store(
+        &self,
+        auth: &Auth,
+        currency_type: CurrencyType,
+        character: &Character,
+    ) -> Result<models::Store>;
+
+    /// Gets the master data.
+    ///
+    /// See [`Api::get_master_data`].
+    async fn get_master_data(&self, auth: &Auth) -> Result<models::MasterData>;
+}
+
+impl ApiClient for Api {
+    async fn get_summary(&self, auth: &Auth) -> Result<models::Summary> {
+        Api::get_summary(self, auth).await
+    }
+
+    async fn get_store(
+        &self,
+        auth: &Auth,
+        currency_type: CurrencyType,
+        character: &Character,
+    ) -> Result<models::Store> {
+        Api::get_store(self, auth, currency_type, character).await
+    }
+
+    async fn get_master_data(&self, auth: &Auth) -> Result<models::MasterData> {
+        Api::get_master_data(self, auth).await
+    }
+}
+
+/// Builder for an [`Api`] that identifies itself to the upstream with a
+/// custom `User-Agent` or other headers, as some backends behave differently
+/// per client.
+///
+/// The headers are sent with every request. Browsers don't allow setting the
+/// `User-Agent`, so on `wasm32` it may be ignored.
+///
+/// The builder also tunes the connection pool, so that idle connections to the
+/// upstream are closed or kept alive instead of dying silently. The pool
+/// settings have no effect on `wasm32`, where the browser manages connections.
+#[derive(Clone, Debug, Default)]
+pub struct ApiBuilder {
+    user_agent: Option<String>,
+    headers: Vec<(String, String)>,
+    // The browser manages connections on `wasm32`.
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pool_idle_timeout: Option<std::time::Duration>,
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pool_max_idle_per_host: Option<usize>,
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    http2_keep_alive_interval: Option<std::time::Duration>,
+}
+
+impl ApiBuilder {
+    /// Sets the `User-Agent` header of requests.
+    ///
+    /// # Parameters
+    ///
+    /// - `user_agent` - The value of the header.
+    pub fn user_agent(self, user_agent: impl Into<String>) -> Self {
+        Self {
+            user_agent: Some(user_agent.into()),
+            ..self
+        }
+    }
+
+    /// Adds a header to every request, replacing an earlier header of the same
+    /// name.
+    ///
+    /// # Parameters
+    ///
+    /// - `name` - The name of the header.
+    /// - `value` - The value of the header.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Sets how long idle pooled connections are kept open. Defaults to 90
+    /// seconds.
+    ///
+    /// # Parameters
+    ///
+    /// - `timeout` - The idle timeout of connections.
+    pub fn pool_idle_timeout(self, timeout: std::time::Duration) -> Self {
+        Self {
+            pool_idle_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of idle connections kept per host. Defaults to
+    /// no limit.
+    ///
+    /// # Parameters
+    ///
+    /// - `max` - The maximum number of idle connections.
+    pub fn pool_max_idle_per_host(self, max: usize) -> Self {
+        Self {
+            pool_max_idle_per_host: Some(max),
+            ..self
+        }
+    }
+
+    /// Sends HTTP/2 keepalive pings at `interval`, also while the connection
+    /// is idle, so dead connections are noticed before the next request.
+    ///
+    /// # Parameters
+    ///
+    /// - `interval` - The interval between pings.
+    pub fn http2_keep_alive_interval(self, interval: std::time::Duration) -> Self {
+        Self {
+            http2_keep_alive_interval: Some(interval),
+            ..self
+        }
+    }
+
+    /// Creates the API client.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if a header name or value is invalid, or the HTTP
+    /// client cannot be built.
+    pub fn build(self) -> Result<Api> {
+        Ok(Api {
+            client: self.client()?,
+            builder: self,
+            max_pages: DEFAULT_MAX_PAGES,
+            #[cfg(feature = "replay")]
+            replay: None,
+            #[cfg(feature = "replay")]
+            capture: None,
+        })
+    }
+
+    fn client(&self) -> Result<reqwest::Client> {
+        use reqwest::header::{HeaderMap, HeaderName, HeaderValue, USER_AGENT};
+
+        let mut headers = HeaderMap::new();
+        let user_agent = self
+            .user_agent
+            .iter()
+            .map(|user_agent| (USER_AGENT.to_string(), user_agent.clone()));
+        for (name, value) in self.headers.iter().cloned().chain(user_agent) {
+            let invalid = || Error::InvalidHeader { name: name.clone() };
+            let header_name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid())?;
+            let header_value = HeaderValue::from_str(&value).map_err(|_| invalid())?;
+            headers.insert(header_name, header_value);
+        }
+        let client = reqwest::Client::builder().default_headers(headers);
+        #[cfg(not(target_arch = "wasm32"))]
+        let client = {
+            let client = match self.pool_idle_timeout {
+                Some(timeout) => client.pool_idle_timeout(timeout),
+                None => client,
+            };
+            let client = match self.pool_max_idle_per_host {
+                Some(max) => client.pool_max_idle_per_host(max),
+                None => client,
+            };
+            match self.http2_keep_alive_interval {
+                Some(interval) => client
+                    .http2_keep_alive_interval(interval)
+                    .http2_keep_alive_while_idle(true),
+                None => client,
+            }
+        };
+        client.build().map_err(Error::BuildClient)
+    }
+}
+
+/// API client for interacting with the DT Api.
+///
+/// On `wasm32` targets (with the `wasm` feature) requests are made through the
+/// browser fetch API and the returned futures are not `Send`.
+#[derive(Clone, Debug)]
+pub struct Api {
+    client: reqwest::Client,
+    builder: ApiBuilder,
+    max_pages: usize,
+    #[cfg(feature = "replay")]
+    replay: Option<crate::replay::Replay>,
+    #[cfg(feature = "replay")]
+    capture: Option<crate::capture::Capture>,
+}
+
+impl Api {
+    /// Creates a new API client.
+    #[instrument]
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            builder: ApiBuilder::default(),
+            max_pages: DEFAULT_MAX_PAGES,
+            #[cfg(feature = "replay")]
+            replay: None,
+            #[cfg(feature = "replay")]
+            capture: None,
+        }
+    }
+
+    /// Creates a builder for an API client with custom client identification.
+    pub fn builder() -> ApiBuilder {
+        ApiBuilder::default()
+    }
+
+    /// Creates a copy of the client with a new connection pool, so the next
+    /// requests connect again and resolve the upstream host anew.
+    ///
+    /// Replay and capture settings are kept.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the HTTP client cannot be built.
+    pub fn rebuild(&self) -> Result<Self> {
+        Ok(Self {
+            client: self.builder.client()?,
+            ..self.clone()
+        })
+    }
+
+    /// Creates an API client that serves responses from the fixtures in `dir`
+    /// and never makes requests.
+    ///
+    /// See [`crate::replay`] for the layout of the directory.
+    #[cfg(feature = "replay")]
+    #[instrument(skip(dir))]
+    pub fn replay(dir: impl Into<std::path::PathBuf>) -> Self {
+        Self {
+            replay: Some(crate::replay::Replay::new(dir)),
+            ..Self::new()
+        }
+    }
+
+    /// Limits how many pages of a paginated resource
+    /// [`Api::fetch_all_pages`] fetches. Defaults to [`DEFAULT_MAX_PAGES`].
+    ///
+    /// # Parameters
+    ///
+    /// - `max` - The maximum number of pages.
+    pub fn with_max_pages(self, max: usize) -> Self {
+        Self {
+            max_pages: max,
+            ..self
+        }
+    }
+
+    /// Writes every successful response to `dir` as a fixture for
+    /// [`Api::replay`].
+    ///
+    /// See [`crate::capture`] for what is written.
+    #[cfg(feature = "replay")]
+    #[instrument(skip(self, dir))]
+    pub fn with_capture(self, dir: impl Into<std::path::PathBuf>) -> Self {
+        Self {
+            capture: Some(crate::capture::Capture::new(dir)),
+            ..self
+        }
+    }
+
+    fn request(&self, auth: &Auth, endpoint: Endpoint<'_>) -> reqwest::RequestBuilder {
+        let base_url = auth
+            .base_url
+            .as_deref()
+            .map(|url| url.trim_end_matches('/'))
+            .unwrap_or(BASE_URL);
+        match endpoint {
+            Endpoint::Summary => self
+                .client
+                .get(format!("{}/web/{}/summary", base_url, auth.sub.0)),
+            Endpoint::Store {
+                currency_type,
+                character,
+            } => self
+                .client
+                .get(format!(
+                    "{}/store/storefront/{}_store_{}",
+                    base_url, currency_type, character.archetype
+                ))
+                .query(&[
+                    ("accountId", auth.sub.to_string()),
+                    ("personal", "true".to_string()),
+                    ("characterId", character.id.0.to_string()),
+                ]),
+            Endpoint::PublicStore {
+                currency_type,
+                archetype,
+            } => self
+                .client
+                .get(format!(
+                    "{}/store/storefront/{}_store_{}",
+                    base_url, currency_type, archetype
+                ))
+                .query(&[("accountId", auth.sub.to_string())]),
+            Endpoint::Inventory { character } => self.client.get(format!(
+                "{}/data/{}/characters/{}/inventory",
+                base_url, auth.sub.0, character.id.0
+            )),
+            Endpoint::Wallets => self
+                .client
+                .get(format!("{}/store/{}/wallets", base_url, auth.sub.0)),
+            Endpoint::MasterData => self
+                .client
+                .get(format!("{}/master-data/meta/items", base_url)),
+            Endpoint::Page {
+                path,
+                continuation_token,
+            } => {
+                let request = self.client.get(format!("{}{}", base_url, path));
+                match continuation_token {
+                    Some(token) => request.query(&[("continuationToken", token)]),
+                    None => request,
+                }
+            }
+        }
+        .bearer_auth(&auth.access_token)
+    }
+
+    async fn send<T: DeserializeOwned + std::fmt::Debug>(
+        &self,
+        auth: &Auth,
+        endpoint: Endpoint<'_>,
+    ) -> Result<T> {
+        #[cfg(feature = "replay")]
+        if let Some(replay) = &self.replay {
+            return replay.send(auth, endpoint).await;
+        }
+        debug!(endpoint = %endpoint, "Getting {}", endpoint);
+        let res = self.request(auth, endpoint).send().await?;
+        if res.status().is_success() {
+            #[cfg(feature = "replay")]
+            let data = match &self.capture {
+                Some(capture) => capture.parse::<T>(auth.sub, endpoint, res).await?,
+                None => res.json::<T>().await.map_err(Error::InvalidResponse)?,
+            };
+            #[cfg(not(feature = "replay"))]
+            let data = res.json::<T>().await.map_err(Error::InvalidResponse)?;
+            info!("Got {}", endpoint);
+            debug!(data = ?data);
+            Ok(data)
+        } else if res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            Err(rate_limited(endpoint, &res))
+        } else {
+            let status = res.status();
+            let error = error_details(res).await;
+            tracing::error!(
+                status = ?status,
+                error = ?error,
+                "Failed to get {}", endpoint
+            );
+            Err(match endpoint {
+                Endpoint::Summary => Error::GetSummary {
+                    status,
+                    error,
+                    sub: auth.sub,
+                },
+                Endpoint::Store {
+                    currency_type,
+                    character,
+                } => Error::GetStore {
+                    status,
+                    error,
+                    currency_type,
+                    archetype: character.archetype.clone(),
+                },
+                Endpoint::PublicStore {
+                    currency_type,
+                    archetype,
+                } => Error::GetStore {
+                    status,
+                    error,
+                    currency_type,
+                    archetype: archetype.to_string(),
+                },
+                Endpoint::Inventory { character } => Error::GetInventory {
+                    status,
+                    error,
+                    character_id: character.id,
+                },
+                Endpoint::Wallets => Error::GetWallets {
+                    status,
+                    error,
+                    sub: auth.sub,
+                },
+                Endpoint::MasterData => Error::GetMasterData { status, error },
+                Endpoint::Page { path, .. } => Error::GetPage {
+                    status,
+                    error,
+                    path: path.to_string(),
+                },
+            })
+        }
+    }
+
+    /// Gets the summary for the account.
+    ///
+    /// # Parameters
+    ///
+    /// - `auth` - The authentication token.
+    ///
+    /// # Returns
+    ///
+    /// The summary for the account.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the request fails or the server returns an error response.
+    #[instrument(skip(self))]
+    pub async fn get_summary(&self, auth: &Auth) -> Result<models::Summary> {
+        self.send(auth, Endpoint::Summary).await
+    }
+
+    /// Gets the characters of the account, without the rest of the summary.
+    ///
+    /// Follows the `characters` link of `summary` if it has one. Otherwise the
+    /// summary is requested and only its characters are deserialized.
+    ///
+    /// # Parameters
+    ///
+    /// - `auth` - The authentication token.
+    /// - `summary` - A previously fetched summary, for its links.
+    ///
+    /// # Returns
+    ///
+    /// The characters of the account.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the request fails or the server returns an error response.
+    #[instrument(skip(self, summary))]
+    pub async fn get_characters(
+        &self,
+        auth: &Auth,
+        summary: Option<&models::Summary>,
+    ) -> Result<models::Characters> {
+        match summary.and_then(|summary| summary.link(models::Rel::Characters)) {
+            Some(link) => self.follow_link(auth, link).await,
+            None => self.send(auth, Endpoint::Summary).await,
+        }
+    }
+
+    /// Gets the store for the character.
+    ///
+    /// # Parameters
+    ///
+    /// - `auth` - The authentication token.
+    /// - `currency_type` - The type of currency to get the store for.
+    /// - `character` - The character to get the store for.
+    ///
+    /// # Returns
+    ///
+    /// The store for the character.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the request fails or the server returns an error response.
+    #[instrument(skip(self))]
+    pub async fn get_store(
+        &self,
+        auth: &Auth,
+        currency_type: CurrencyType,
+        character: &Character,
+    ) -> Result<models::Store> {
+        self.send(
+            auth,
+            Endpoint::Store {
+                currency_type,
+                character,
+            },
+        )
+        .await
+    }
+
+    /// Gets the public store for an archetype, without personal offers.
+    ///
+    /// The public offers are the same for every character of the archetype,
+    /// so this is a single request however many characters there are.
+    ///
+    /// # Parameters
+    ///
+    /// - `auth` - The authentication token.
+    /// - `currency_type` - The type of currency to get the store for.
+    /// - `archetype` - The archetype to get the store for, e.g. `veteran`.
+    ///
+    /// # Returns
+    ///
+    /// The store for the archetype, with no personal offers.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the request fails or the server returns an error response.
+    #[instrument(skip(self))]
+    pub async fn get_public_store(
+        &self,
+        auth: &Auth,
+        currency_type: CurrencyType,
+        archetype: &str,
+    ) -> Result<models::Store> {
+        self.send(
+            auth,
+            Endpoint::PublicStore {
+                currency_type,
+                archetype,
+            },
+        )
+        .await
+    }
+
+    /// Gets the inventory of the character: the weapons, curios and other
+    /// gear it owns.
+    ///
+    /// # Parameters
+    ///
+    /// - `auth` - The authentication token.
+    /// - `character` - The character to get the inventory for.
+    ///
+    /// # Returns
+    ///
+    /// The inventory of the character.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the request fails or the server returns an error response.
+    #[instrument(skip(self))]
+    pub async fn get_inventory(
+        &self,
+        auth: &Auth,
+        character: &Character,
+    ) -> Result<models::Inventory> {
+        self.send(auth, Endpoint::Inventory { character }).await
+    }
+
+    /// Gets the wallets of the account, holding its crafting materials
+    /// (plasteel and diamantine).
+    ///
+    /// # Parameters
+    ///
+    /// - `auth` - The authentication token.
+    ///
+    /// # Returns
+    ///
+    /// The wallets of the account.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the request fails or the server returns an error response.
+    #[instrument(skip(self))]
+    pub async fn get_wallets(&self, auth: &Auth) -> Result<models::Wallets> {
+        self.send(auth, Endpoint::Wallets).await
+    }
+
+    /// Gets the master data.
+    ///
+    /// # Parameters
+    ///
+    /// - `auth` - The authentication token.
+    ///
+    /// # Returns
+    ///
+    /// The master data.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the request fails or the server returns an error response.
+    #[instrument(skip(self))]
+    pub async fn get_master_data(&self, auth: &Auth) -> Result<models::MasterData> {
+        self.send(auth, Endpoint::MasterData).await
+    }
+
+    /// Gets the item catalog the master data links to.
+    ///
+    /// # Parameters
+    ///
+    /// - `auth` - The authentication token.
+    /// - `master_data` - The master data, from [`Api::get_master_data`].
+    ///
+    /// # Returns
+    ///
+    /// The item catalog, by item id.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the request fails or the server returns an error response.
+    #[instrument(skip(self, master_data))]
+    pub async fn get_item_catalog(
+        &self,
+        auth: &Auth,
+        master_data: &models::MasterData,
+    ) -> Result<models::ItemCatalog> {
+        self.follow_link(auth, &master_data.player_items_link())
+            .await
+    }
+
+    /// Gets a single page of a paginated resource.
+    ///
+    /// # Parameters
+    ///
+    /// - `auth` - The authentication token.
+    /// - `path` - The path of the resource, relative to the account's API base URL.
+    /// - `continuation_token` - The token of the page to get, or `None` for the first page.
+    ///
+    /// # Returns
+    ///
+    /// The page of results.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the request fails or the server returns an error response.
+    #[instrument(skip(self))]
+    pub async fn get_page<T: DeserializeOwned + std::fmt::Debug>(
+        &self,
+        auth: &Auth,
+        path: &str,
+        continuation_token: Option<&str>,
+    ) -> Result<models::Paginated<T>> {
+        self.send(
+            auth,
+            Endpoint::Page {
+                path,
+                continuation_token,
+            },
+        )
+        .await
+    }
+
+    /// Fetches every page of a paginated resource, following continuation tokens.
+    ///
+    /// # Parameters
+    ///
+    /// - `auth` - The authentication token.
+    /// - `path` - The path of the resource, relative to the account's API base URL.
+    ///
+    /// # Returns
+    ///
+    /// A stream of the items of every page, in order. Pages are requested lazily
+    /// as the stream is polled.
+    ///
+    /// # Errors
+    ///
+    /// The stream yields an error and ends if a page cannot be fetched, with
+    /// [`Error::RepeatedContinuationToken`] if the upstream returns a token
+    /// again, or with [`Error::TooManyPages`] if there are more pages than
+    /// [`Api::with_max_pages`] allows.
+    pub fn fetch_all_pages<'a, T: DeserializeOwned + std::fmt::Debug + 'a>(
+        &'a self,
+        auth: &'a Auth,
+        path: &'a str,
+    ) -> impl Stream<Item = Result<T>> + 'a {
+        stream::try_unfold(
+            Some((None, HashSet::new())),
+            move |state: Option<(Option<String>, HashSet<String>)>| async move {
+                let Some((token, mut seen)) = state else {
+                    return Ok(None);
+                };
+                if seen.len() >= self.max_pages {
+                    return Err(Error::TooManyPages {
+                        path: path.to_string(),
+                        max: self.max_pages,
+                    });
+                }
+                let page = self.get_page::<T>(auth, path, token.as_deref()).await?;
+                let next = match page.continuation_token.clone() {
+                    Some(next) if !seen.insert(next.clone()) => {
+                        return Err(Error::RepeatedContinuationToken {
+                            path: path.to_string(),
+                        })
+                    }
+                    Some(next) => Some((Some(next), seen)),
+                    None => None,
+                };
+                Result::Ok(Some((page, next)))
+            },
+        )
+        .map_ok(|page| stream::iter(page.items.into_iter().map(Ok)))
+        .try_flatten()
+    }
+
+    /// Gets the resource a HAL link points to, such as a link of a summary
+    /// found with [`models::Links::link`].
+    ///
+    /// # Parameters
+    ///
+    /// - `auth` - The authentication token.
+    /// - `link` - The link to follow. Absolute links are requested from the
+    ///   account's API base URL too, as the upstream only links to itself.
+    ///
+    /// # Returns
+    ///
+    /// The linked resource.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the request fails or the server returns an error response.
+    #[instrument(skip(self))]
+    pub async fn follow_link<T: DeserializeOwned + std::fmt::Debug>(
+        &self,
+        auth: &Auth,
+        link: &models::Link,
+    ) -> Result<T> {
+        self.send(
+            auth,
+            Endpoint::Page {
+                path: link.path(),
+                continuation_token: None,
+            },
+        )
+        .await
+    }
+
+    /// Gets a single page of a leaderboard.
+    ///
+    /// # Parameters
+    ///
+    /// - `auth` - The authentication token.
+    /// - `board` - The name of the leaderboard.
+    /// - `continuation_token` - The token of the page to get, or `None` for the first page.
+    ///
+    /// # Returns
+    ///
+    /// The page of entries, ordered by rank.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the request fails or the server returns an error response.
+    #[instrument(skip(self))]
+    pub async fn get_leaderboard_page(
+        &self,
+        auth: &Auth,
+        board: &str,
+        continuation_token: Option<&str>,
+    ) -> Result<models::Paginated<models::LeaderboardEntry>> {
+        self.get_page(auth, &leaderboard_path(board), continuation_token)
+            .await
+    }
+
+    /// Gets every entry of a leaderboard, following continuation tokens.
+    ///
+    /// # Parameters
+    ///
+    /// - `auth` - The authentication token.
+    /// - `board` - The name of the leaderboard.
+    ///
+    /// # Returns
+    ///
+    /// The entries of every page, ordered by rank.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if any page cannot be fetched.
+    #[instrument(skip(self))]
+    pub async fn get_leaderboard(
+        &self,
+        auth: &Auth,
+        board: &str,
+    ) -> Result<Vec<models::LeaderboardEntry>> {
+        let path = leaderboard_path(board);
+        self.fetch_all_pages(auth, &path).try_collect().await
+    }
+
+    /// Gets the raw JSON response of an endpoint without parsing it into a model.
+    ///
+    /// # Parameters
+    ///
+    /// - `auth` - The authentication token.
+    /// - `endpoint` - The endpoint to get.
+    ///
+    /// # Returns
+    ///
+    /// The JSON response body.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the request fails or the server returns an error response.
+    #[instrument(skip(self))]
+    pub async fn get_raw(&self, auth: &Auth, endpoint: Endpoint<'_>) -> Result<serde_json::Value> {
+        self.send(auth, endpoint).await
+    }
+
+    /// Gets the response of an endpoint as received, whatever its status.
+    ///
+    /// A replaying client responds with the fixture of the endpoint, with
+    /// status `200 OK` and no headers.
+    ///
+    /// # Parameters
+    ///
+    /// - `auth` - The authentication token.
+    /// - `endpoint` - The endpoint to get.
+    ///
+    /// # Returns
+    ///
+    /// The status, headers and body of the response.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the request fails, or with
+    /// [`Error::RateLimited`] if the server rate limits it.
+    #[instrument(skip(self))]
+    pub async fn get_response(&self, auth: &Auth, endpoint: Endpoint<'_>) -> Result<RawResponse> {
+        #[cfg(feature = "replay")]
+        if let Some(replay) = &self.replay {
+            return Ok(RawResponse {
+                status: reqwest::StatusCode::OK,
+                headers: reqwest::header::HeaderMap::new(),
+                body: replay.send(auth, endpoint).await?,
+            });
+        }
+        debug!(endpoint = %endpoint, "Getting raw {}", endpoint);
+        let res = self.request(auth, endpoint).send().await?;
+        if res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            return Err(rate_limited(endpoint, &res));
+        }
+        let status = res.status();
+        let headers = res.headers().clone();
+        let bytes = res.bytes().await.map_err(Error::InvalidResponse)?;
+        let body = serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| String::from_utf8_lossy(&bytes).into_owned().into());
+        info!(status = ?status, "Got raw {}", endpoint);
+        Ok(RawResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+
+    /// Refreshes the authentication token.
+    ///
+    /// # Parameters
+    ///
+    /// - `auth` - The authentication token to refresh.
+    ///
+    /// # Returns
+    ///
+    /// The refreshed authentication token, keeping the base URL override of `auth`.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the request fails or the server returns an error response.
+    #[instrument(skip(self))]
+    pub async fn refresh_auth(&self, auth: &Auth) -> Result<Auth> {
+        #[cfg(feature = "replay")]
+        if let Some(replay) = &self.replay {
+            return Ok(replay.refresh_auth(auth));
+        }
+        let url = "https://bsp-auth-prod.atoma.cloud/queue/refresh";
+        debug!(url = ?url, "Refreshing auth");
+        let res = self
+            .client
+            .get(url)
+            .bearer_auth(&auth.refresh_token)
+            .send()
+            .await?;
+        if res.status().is_success() {
+            let base_url = auth.base_url.clone();
+            let auth = Auth {
+                base_url,
+                ..res.json::<Auth>().await.map_err(Error::InvalidResponse)?
+            };
+            info!("Refreshed auth");
+            debug!(auth = ?auth);
+            Ok(auth)
+        } else if res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            Err(rate_limited("auth refresh", &res))
+        } else {
+            let status = res.status();
+            let error = error_details(res).await;
+            tracing::error!(
+                status = ?status,
+                error = ?error,
+                "Failed to refresh auth"
+            );
+            Err(Error::RefreshAuth { status, error })
+        }
+    }
+
+    /// Checks that the API can be reached, without authenticating.
+    ///
+    /// A replaying client never makes requests, so it is always reachable.
+    ///
+    /// # Returns
+    ///
+    /// The status of the response; any status means the API was reached, as
+    /// unauthenticated requests may be rejected.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if the request fails.
+    #[instrument(skip(self))]
+    pub async fn ping(&self) -> Result<reqwest::StatusCode> {
+        #[cfg(feature = "replay")]
+        if self.replay.is_some() {
+            return Ok(reqwest::StatusCode::OK);
+        }
+        let res = self.client.get(BASE_URL).send().await?;
+        debug!(status = ?res.status(), "Pinged API");
+        Ok(res.status())
+    }
+}
+
+async fn error_details(res: reqwest::Response) -> serde_json::Value {
+    res.json::<serde_json::Value>()
+        .await
+        .unwrap_or("No error details".into())
+}
+
+/// Path of a leaderboard, relative to the API base URL.
+fn leaderboard_path(board: &str) -> String {
+    format!("/leaderboards/{}", board)
+}
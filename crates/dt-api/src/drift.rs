@@ -0,0 +1,255 @@
+//! Detection of differences between raw API responses and the typed models.
+//!
+//! A raw response is deserialized into its model and serialized back. Anything
+//! lost or changed along the way indicates that the upstream schema no longer
+//! matches the model.
+
+use std::fmt::Display;
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+/// Kind of difference between a raw response and its round-tripped model.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DriftKind {
+    /// The field is present upstream but not in the model.
+    UnknownField,
+    /// The field is produced by the model but not present upstream.
+    MissingField,
+    /// The field has a different JSON type upstream than in the model.
+    TypeMismatch { upstream: String, model: String },
+    /// The field has the same type but a different value after the round-trip.
+    ValueMismatch,
+}
+
+impl Display for DriftKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DriftKind::UnknownField => write!(f, "unknown field"),
+            DriftKind::MissingField => write!(f, "missing field"),
+            DriftKind::TypeMismatch { upstream, model } => {
+                write!(f, "type mismatch (upstream {upstream}, model {model})")
+            }
+            DriftKind::ValueMismatch => write!(f, "value mismatch"),
+        }
+    }
+}
+
+/// A single difference between a raw response and its round-tripped model.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Drift {
+    /// Path to the differing value, e.g. `characters[0].name`.
+    pub path: String,
+    /// The kind of difference.
+    pub kind: DriftKind,
+}
+
+impl Display for Drift {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.kind)
+    }
+}
+
+/// Round-trips a raw response through the model `T` and reports the differences.
+///
+/// # Parameters
+///
+/// - `raw` - The raw JSON response.
+///
+/// # Returns
+///
+/// The parsed model, and the differences between the raw response and the
+/// round-tripped model.
+///
+/// # Errors
+///
+/// An error is returned if the raw response can no longer be parsed into `T`.
+pub fn check<T: DeserializeOwned + Serialize>(
+    raw: &Value,
+) -> Result<(T, Vec<Drift>), serde_json::Error> {
+    let model: T = serde_json::from_value(raw.clone())?;
+    let round_tripped = serde_json::to_value(&model)?;
+    let drifts = compare(raw, &round_tripped);
+    Ok((model, drifts))
+}
+
+/// Compares a raw response with the serialized model.
+///
+/// Fields that are `null` upstream and absent in the model (or vice versa) are
+/// treated as equal, as are numbers with the same numeric value.
+///
+/// # Parameters
+///
+/// - `upstream` - The raw JSON response.
+/// - `model` - The serialized model.
+///
+/// # Returns
+///
+/// The differences between the two values.
+pub fn compare(upstream: &Value, model: &Value) -> Vec<Drift> {
+    let mut drifts = Vec::new();
+    compare_at(String::new(), upstream, model, &mut drifts);
+    drifts
+}
+
+fn compare_at(path: String, upstream: &Value, model: &Value, drifts: &mut Vec<Drift>) {
+    match (upstream, model) {
+        (Value::Object(upstream), Value::Object(model)) => {
+            for (key, value) in upstream {
+                let field_path = join(&path, key);
+                match model.get(key) {
+                    Some(model_value) => compare_at(field_path, value, model_value, drifts),
+                    None if value.is_null() => {}
+                    None => drifts.push(Drift {
+                        path: field_path,
+                        kind: DriftKind::UnknownField,
+                    }),
+                }
+            }
+            for (key, value) in model {
+                if !upstream.contains_key(key) && !value.is_null() {
+                    drifts.push(Drift {
+                        path: join(&path, key),
+                        kind: DriftKind::MissingField,
+                    });
+                }
+            }
+        }
+        (Value::Array(upstream), Value::Array(model)) => {
+            for (i, (upstream, model)) in upstream.iter().zip(model).enumerate() {
+                compare_at(format!("{path}[{i}]"), upstream, model, drifts);
+            }
+            if upstream.len() != model.len() {
+                drifts.push(Drift {
+                    path,
+                    kind: DriftKind::ValueMismatch,
+                });
+            }
+        }
+        (Value::Number(upstream), Value::Number(model)) => {
+            if upstream.as_f64() != model.as_f64() {
+                drifts.push(Drift {
+                    path,
+                    kind: DriftKind::ValueMismatch,
+                });
+            }
+        }
+        (upstream, model) if type_name(upstream) != type_name(model) => drifts.push(Drift {
+            path,
+            kind: DriftKind::TypeMismatch {
+                upstream: type_name(upstream).to_string(),
+                model: type_name(model).to_string(),
+            },
+        }),
+        (upstream, model) => {
+            if upstream != model {
+                drifts.push(Drift {
+                    path,
+                    kind: DriftKind::ValueMismatch,
+                });
+            }
+        }
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn drift(path: &str, kind: DriftKind) -> Drift {
+        Drift {
+            path: path.to_string(),
+            kind,
+        }
+    }
+
+    #[test]
+    fn equal_values_have_no_drift() {
+        let value = json!({ "name": "a", "items": [1, { "id": "x" }], "flag": true });
+        assert_eq!(compare(&value, &value), vec![]);
+    }
+
+    #[test]
+    fn nulls_match_absent_fields() {
+        let upstream = json!({ "name": "a", "extra": null });
+        let model = json!({ "name": "a", "optional": null });
+        assert_eq!(compare(&upstream, &model), vec![]);
+    }
+
+    #[test]
+    fn numbers_compare_by_value() {
+        assert_eq!(compare(&json!({ "n": 1 }), &json!({ "n": 1.0 })), vec![]);
+        assert_eq!(
+            compare(&json!({ "n": 1 }), &json!({ "n": 2 })),
+            vec![drift("n", DriftKind::ValueMismatch)]
+        );
+    }
+
+    #[test]
+    fn reports_unknown_and_missing_fields() {
+        let upstream = json!({ "outer": { "new": 1, "kept": "a" } });
+        let model = json!({ "outer": { "kept": "a", "old": 2 } });
+        assert_eq!(
+            compare(&upstream, &model),
+            vec![
+                drift("outer.new", DriftKind::UnknownField),
+                drift("outer.old", DriftKind::MissingField),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_type_and_value_mismatches_in_arrays() {
+        let upstream = json!({ "items": [{ "id": "1" }, { "id": "b" }, "extra"] });
+        let model = json!({ "items": [{ "id": 1 }, { "id": "c" }] });
+        assert_eq!(
+            compare(&upstream, &model),
+            vec![
+                drift(
+                    "items[0].id",
+                    DriftKind::TypeMismatch {
+                        upstream: "string".to_string(),
+                        model: "number".to_string(),
+                    }
+                ),
+                drift("items[1].id", DriftKind::ValueMismatch),
+                drift("items", DriftKind::ValueMismatch),
+            ]
+        );
+    }
+
+    #[test]
+    fn check_round_trips_through_the_model() {
+        #[derive(serde::Deserialize, Serialize)]
+        struct Model {
+            name: String,
+        }
+
+        let (model, drifts) = check::<Model>(&json!({ "name": "a", "new": 1 })).unwrap();
+        assert_eq!(model.name, "a");
+        assert_eq!(drifts, vec![drift("new", DriftKind::UnknownField)]);
+        assert!(check::<Model>(&json!({ "name": 1 })).is_err());
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::{CharacterId, GearId, Link, Overrides, Trait};
+
+/// Master data item and the rolled overrides of an owned item.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MasterDataInstance {
+    /// The master data item id, as in [`Description::id`](crate::models::Description::id).
+    pub id: String,
+    pub overrides: Overrides,
+}
+
+/// Owned item model
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InventoryItem {
+    #[serde(rename = "uuid")]
+    pub gear_id: GearId,
+    pub master_data_instance: MasterDataInstance,
+    /// Slots the item is equipped in, if any.
+    #[serde(default)]
+    pub slots: Vec<String>,
+    pub character_id: Option<CharacterId>,
+}
+
+impl InventoryItem {
+    /// Get the traits (blessings) of the item.
+    ///
+    /// # Returns
+    ///
+    /// The traits, or an empty slice for items that aren't weapons or curios.
+    pub fn traits(&self) -> &[Trait] {
+        self.master_data_instance
+            .overrides
+            .item()
+            .map_or(&[], |item| item.traits.as_slice())
+    }
+}
+
+/// Inventory model
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Inventory {
+    #[serde(rename = "_links", default)]
+    pub links: HashMap<String, Link>,
+    pub items: Vec<InventoryItem>,
+}
+
+impl Inventory {
+    /// Get the owned weapons.
+    pub fn weapons(&self) -> impl Iterator<Item = &InventoryItem> {
+        self.items
+            .iter()
+            .filter(|item| matches!(item.master_data_instance.overrides, Overrides::Weapon(_)))
+    }
+
+    /// Get the owned curios.
+    pub fn curios(&self) -> impl Iterator<Item = &InventoryItem> {
+        self.items
+            .iter()
+            .filter(|item| matches!(item.master_data_instance.overrides, Overrides::Gadget(_)))
+    }
+
+    /// Count the owned copies of an item.
+    ///
+    /// # Parameters
+    ///
+    /// - `id` - The master data item id, e.g. the `description.id` of an offer.
+    ///
+    /// # Returns
+    ///
+    /// The number of owned items with that master data item id.
+    pub fn count(&self, id: &str) -> usize {
+        self.items
+            .iter()
+            .filter(|item| item.master_data_instance.id == id)
+            .count()
+    }
+}
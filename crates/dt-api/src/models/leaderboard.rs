@@ -0,0 +1,15 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::AccountId;
+
+/// Leaderboard entry model
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeaderboardEntry {
+    pub rank: u32,
+    pub account_id: Option<AccountId>,
+    pub account_name: String,
+    pub character_name: Option<String>,
+    pub archetype: Option<String>,
+    pub score: i64,
+}
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
 
 use crate::models::Link;
 
@@ -19,3 +20,80 @@ pub struct MasterData {
     pub links: HashMap<String, Link>,
     pub player_items: PlayerItems,
 }
+
+impl MasterData {
+    /// Link to the item catalog, as its document is published alongside the
+    /// master data instead of being part of it.
+    pub fn player_items_link(&self) -> Link {
+        Link {
+            href: self.player_items.href.clone(),
+        }
+    }
+}
+
+/// Item of the [`ItemCatalog`]
+#[skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Item {
+    pub display_name: Option<String>,
+    pub item_type: Option<String>,
+    /// Slots the item can be equipped in, such as `slot_primary`.
+    #[serde(default)]
+    pub slots: Vec<String>,
+    /// Archetypes that can use the item, or empty if every archetype can.
+    #[serde(default)]
+    pub archetypes: Vec<String>,
+    /// Properties without a typed field.
+    #[serde(flatten)]
+    pub other: HashMap<String, serde_json::Value>,
+}
+
+impl Item {
+    /// Whether a character of `archetype` can use the item.
+    pub fn usable_by(&self, archetype: &str) -> bool {
+        self.archetypes.is_empty()
+            || self
+                .archetypes
+                .iter()
+                .any(|a| a.eq_ignore_ascii_case(archetype))
+    }
+}
+
+/// Item catalog of the master data, by item id
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ItemCatalog {
+    pub items: HashMap<String, Item>,
+}
+
+impl ItemCatalog {
+    /// Get the items that fit any of `slots`, such as the ones a
+    /// [`crate::models::Overrides::RandomItem`] offer may turn out to be.
+    ///
+    /// # Parameters
+    ///
+    /// - `slots` - The slots of the offer.
+    /// - `archetype` - Only include items usable by this archetype, if given.
+    ///
+    /// # Returns
+    ///
+    /// The matching items with their ids, ordered by id.
+    pub fn candidates<'a>(
+        &'a self,
+        slots: &[String],
+        archetype: Option<&str>,
+    ) -> Vec<(&'a str, &'a Item)> {
+        let mut candidates: Vec<_> = self
+            .items
+            .iter()
+            .filter(|(_, item)| item.slots.iter().any(|slot| slots.contains(slot)))
+            .filter(|(_, item)| match archetype {
+                Some(archetype) => item.usable_by(archetype),
+                None => true,
+            })
+            .map(|(id, item)| (id.as_str(), item))
+            .collect();
+        candidates.sort_unstable_by_key(|(id, _)| *id);
+        candidates
+    }
+}
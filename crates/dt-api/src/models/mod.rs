@@ -1,4 +1,4 @@
-use std::fmt::Display;
+use std::{collections::HashMap, fmt::Display};
 
 use serde::{Deserialize, Serialize};
 
@@ -11,12 +11,117 @@ pub use store::*;
 mod master_data;
 pub use master_data::*;
 
+mod inventory;
+pub use inventory::*;
+
+mod wallet;
+pub use wallet::*;
+
+mod leaderboard;
+pub use leaderboard::*;
+
+mod page;
+pub use page::*;
+
 /// Link model
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Link {
     pub href: String,
 }
 
+impl Link {
+    /// The path and query of the link, without the scheme and host if the
+    /// `href` is absolute.
+    pub fn path(&self) -> &str {
+        match self.href.split_once("://") {
+            Some((_, rest)) => rest.find('/').map_or("/", |start| &rest[start..]),
+            None => &self.href,
+        }
+    }
+}
+
+/// Well-known relations of the HAL links of upstream resources.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Rel {
+    /// The resource itself, `self`.
+    Itself,
+    /// The wallets of the account.
+    Wallets,
+    /// The inventory of a character.
+    Inventory,
+    /// The statistics of the account.
+    Statistics,
+    /// The characters of the account.
+    Characters,
+}
+
+impl Rel {
+    /// Name of the relation in `_links`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Rel::Itself => "self",
+            Rel::Wallets => "wallets",
+            Rel::Inventory => "inventory",
+            Rel::Statistics => "statistics",
+            Rel::Characters => "characters",
+        }
+    }
+}
+
+impl Display for Rel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Resources with HAL `_links` to related resources, which can be fetched
+/// with `Api::follow_link`.
+pub trait Links {
+    /// The links of the resource, by relation.
+    fn links(&self) -> &HashMap<String, Link>;
+
+    /// The link of a well-known relation, if the resource has one.
+    fn link(&self, rel: Rel) -> Option<&Link> {
+        self.links().get(rel.as_str())
+    }
+}
+
+impl Links for Summary {
+    fn links(&self) -> &HashMap<String, Link> {
+        &self.links
+    }
+}
+
+impl Links for Store {
+    fn links(&self) -> &HashMap<String, Link> {
+        &self.links
+    }
+}
+
+impl Links for MasterData {
+    fn links(&self) -> &HashMap<String, Link> {
+        &self.links
+    }
+}
+
+impl Links for Inventory {
+    fn links(&self) -> &HashMap<String, Link> {
+        &self.links
+    }
+}
+
+impl Links for Wallets {
+    fn links(&self) -> &HashMap<String, Link> {
+        &self.links
+    }
+}
+
+impl<T> Links for Paginated<T> {
+    fn links(&self) -> &HashMap<String, Link> {
+        &self.links
+    }
+}
+
 /// Account id wrapper type
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Hash, Copy)]
 #[serde(transparent)]
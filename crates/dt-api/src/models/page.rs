@@ -0,0 +1,17 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::Link;
+
+/// Page of a paginated resource
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Paginated<T> {
+    #[serde(rename = "_links", default)]
+    pub links: HashMap<String, Link>,
+    pub items: Vec<T>,
+    /// Token to request the next page with, `None` on the last page.
+    #[serde(default)]
+    pub continuation_token: Option<String>,
+}
@@ -1,14 +1,14 @@
 use std::collections::HashMap;
 
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_with::{formats::Strict, serde_as, skip_serializing_none, TimestampMilliSeconds};
 use uuid::Uuid;
 
 use crate::models::Link;
 
 /// Enum for currency type
-#[derive(PartialEq, Eq, Copy, Clone, Debug, Serialize, Deserialize)]
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum CurrencyType {
     Marks,
@@ -24,12 +24,23 @@ impl std::fmt::Display for CurrencyType {
     }
 }
 
+impl CurrencyType {
+    /// Name of the currency in game.
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            CurrencyType::Marks => "Marks",
+            CurrencyType::Credits => "Ordo Dockets",
+        }
+    }
+}
+
 /// Catalog id wrapper type
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Copy)]
 #[serde(transparent)]
 pub struct CatalogId(pub Uuid);
 
 /// Catalog model
+#[serde_as]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Catalog {
@@ -37,8 +48,17 @@ pub struct Catalog {
     pub name: String,
     pub generation: i32,
     pub layout_ref: Option<String>,
-    pub valid_from: String,
-    pub valid_to: String,
+    #[serde_as(as = "TimestampMilliSeconds<String, Strict>")]
+    pub valid_from: DateTime<Utc>,
+    #[serde_as(as = "TimestampMilliSeconds<String, Strict>")]
+    pub valid_to: DateTime<Utc>,
+}
+
+impl Catalog {
+    /// Whether the catalog is valid at `time`.
+    pub fn is_valid_at(&self, time: DateTime<Utc>) -> bool {
+        self.valid_from <= time && time < self.valid_to
+    }
 }
 
 /// Amount model
@@ -49,6 +69,31 @@ pub struct Amount {
     pub amount_type: CurrencyType,
 }
 
+impl Amount {
+    /// The amount with thousands separators, e.g. `12,500`.
+    pub fn formatted(&self) -> String {
+        let digits = self.amount.unsigned_abs().to_string();
+        let mut groups = Vec::with_capacity(digits.len() / 3 + 1);
+        let mut end = digits.len();
+        while end > 3 {
+            groups.push(&digits[end - 3..end]);
+            end -= 3;
+        }
+        groups.push(&digits[..end]);
+        groups.reverse();
+        let sign = if self.amount < 0 { "-" } else { "" };
+        format!("{sign}{}", groups.join(","))
+    }
+}
+
+/// Formats the amount with thousands separators and its currency, e.g.
+/// `12,500 marks`.
+impl std::fmt::Display for Amount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.formatted(), self.amount_type)
+    }
+}
+
 /// Price id wrapper type
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Copy)]
 #[serde(transparent)]
@@ -86,6 +131,17 @@ pub struct Stat {
     pub value: f64,
 }
 
+impl Stat {
+    /// The highest percentage the game shows for a stat.
+    pub const MAX_PERCENT: u32 = 80;
+
+    /// Get the value as the game shows it: a whole percentage from 0 to
+    /// [`Stat::MAX_PERCENT`].
+    pub fn percent(&self) -> u32 {
+        (self.value.clamp(0.0, 1.0) * f64::from(Self::MAX_PERCENT)).round() as u32
+    }
+}
+
 /// Trait model
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -127,6 +183,14 @@ pub struct WeaponOverride {
     pub base_stats: Vec<Stat>,
 }
 
+impl WeaponOverride {
+    /// Get the sum of the percentages of the base stats, as the game totals
+    /// them.
+    pub fn stats_total(&self) -> u32 {
+        self.base_stats.iter().map(Stat::percent).sum()
+    }
+}
+
 /// Overrides enum
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(untagged)]
@@ -138,6 +202,21 @@ pub enum Overrides {
     None {},
 }
 
+impl Overrides {
+    /// Get the item overrides of a weapon or gadget.
+    ///
+    /// # Returns
+    ///
+    /// `None` for offers that aren't weapons or gadgets.
+    pub fn item(&self) -> Option<&Override> {
+        match self {
+            Overrides::Weapon(weapon) => Some(&weapon.overrides),
+            Overrides::Gadget(gadget) => Some(gadget),
+            Overrides::RandomItem { .. } | Overrides::None {} => None,
+        }
+    }
+}
+
 /// Gear id wrapper type
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Copy)]
 #[serde(transparent)]
@@ -153,10 +232,78 @@ pub struct Description {
     pub rotation: String,
     #[serde(rename = "type")]
     pub description_type: String,
+    /// Raw properties of the offered item. See [`Description::known_properties`]
+    /// for the well-known keys.
     pub properties: HashMap<String, serde_json::Value>,
     pub overrides: Overrides,
 }
 
+impl Description {
+    /// Get the property `key` as a `T`.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the property is missing or isn't a `T`.
+    pub fn property<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let value = self.properties.get(key)?;
+        T::deserialize(value).ok()
+    }
+
+    /// Get the well-known properties. Each is read on its own, so one with an
+    /// unexpected type doesn't hide the others.
+    pub fn known_properties(&self) -> DescriptionProperties {
+        let item_level = match (
+            self.property(DescriptionProperties::MIN_ITEM_LEVEL),
+            self.property(DescriptionProperties::MAX_ITEM_LEVEL),
+        ) {
+            (Some(min), Some(max)) => Some(ItemLevelRange { min, max }),
+            _ => None,
+        };
+        DescriptionProperties {
+            level: self.property(DescriptionProperties::LEVEL),
+            item_level,
+            skin: self.property(DescriptionProperties::SKIN),
+            tags: self
+                .property(DescriptionProperties::TAGS)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Typed view of the well-known keys of [`Description::properties`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DescriptionProperties {
+    /// Character level the item is offered for, from `level`.
+    pub level: Option<u32>,
+    /// Item levels the item may roll, from `minItemLevel` and `maxItemLevel`.
+    pub item_level: Option<ItemLevelRange>,
+    /// Skin applied to the item, from `skin`.
+    pub skin: Option<String>,
+    /// Tags of the item, from `tags`.
+    pub tags: Vec<String>,
+}
+
+impl DescriptionProperties {
+    pub const LEVEL: &'static str = "level";
+    pub const MIN_ITEM_LEVEL: &'static str = "minItemLevel";
+    pub const MAX_ITEM_LEVEL: &'static str = "maxItemLevel";
+    pub const SKIN: &'static str = "skin";
+    pub const TAGS: &'static str = "tags";
+}
+
+/// Inclusive range of item levels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ItemLevelRange {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl ItemLevelRange {
+    pub fn contains(&self, item_level: u32) -> bool {
+        (self.min..=self.max).contains(&item_level)
+    }
+}
+
 /// Sku id wrapper type
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Copy)]
 #[serde(transparent)]
@@ -210,3 +357,104 @@ pub struct Store {
     #[serde_as(as = "TimestampMilliSeconds<String, Strict>")]
     pub current_rotation_end: DateTime<Utc>,
 }
+
+impl Store {
+    /// Get the personal offers, then the public offers.
+    pub fn offers(&self) -> impl Iterator<Item = &Offer> {
+        self.personal.iter().chain(&self.public)
+    }
+
+    /// Get the cheapest offer of `category`, such as `WEAPON`, compared
+    /// case-insensitively.
+    ///
+    /// # Returns
+    ///
+    /// The first of the cheapest offers, or `None` if no offer is of the
+    /// category.
+    pub fn cheapest(&self, category: &str) -> Option<&Offer> {
+        self.offers()
+            .filter(|offer| offer.sku.category.eq_ignore_ascii_case(category))
+            .min_by_key(|offer| offer.price.amount.amount)
+    }
+
+    /// Check that the validity window of the catalog is consistent with the
+    /// rotation of the store.
+    ///
+    /// # Returns
+    ///
+    /// The first inconsistency found, or `None` if the store is consistent.
+    pub fn rotation_issue(&self) -> Option<RotationIssue> {
+        let catalog = &self.catalog;
+        if catalog.valid_to <= catalog.valid_from {
+            Some(RotationIssue::EmptyWindow)
+        } else if self.current_rotation_end <= catalog.valid_from {
+            Some(RotationIssue::EndsBeforeWindow)
+        } else if self.current_rotation_end > catalog.valid_to {
+            Some(RotationIssue::EndsAfterWindow)
+        } else {
+            None
+        }
+    }
+
+    /// Get when the offers of the store stop being valid: the end of the
+    /// rotation, or the end of the catalog if that is earlier.
+    ///
+    /// An empty catalog window is ignored, as it tells nothing about the
+    /// rotation.
+    pub fn rotates_at(&self) -> DateTime<Utc> {
+        match self.rotation_issue() {
+            Some(RotationIssue::EmptyWindow) => self.current_rotation_end,
+            _ => self.current_rotation_end.min(self.catalog.valid_to),
+        }
+    }
+
+    /// Get the level of the character the personal offers were made for.
+    ///
+    /// # Returns
+    ///
+    /// The highest `characterLevel` of the personal weapons and gadgets, or
+    /// `None` if there are none.
+    pub fn character_level(&self) -> Option<u32> {
+        self.personal
+            .iter()
+            .filter_map(|offer| offer.description.overrides.item())
+            .filter_map(|item| u32::try_from(item.character_level).ok())
+            .max()
+    }
+}
+
+/// Inconsistency between the validity window of a store catalog and the
+/// rotation of the store.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RotationIssue {
+    /// The catalog stops being valid before it starts.
+    EmptyWindow,
+    /// The rotation ends before the catalog starts being valid.
+    EndsBeforeWindow,
+    /// The rotation ends after the catalog stops being valid.
+    EndsAfterWindow,
+}
+
+impl RotationIssue {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RotationIssue::EmptyWindow => "empty_window",
+            RotationIssue::EndsBeforeWindow => "ends_before_window",
+            RotationIssue::EndsAfterWindow => "ends_after_window",
+        }
+    }
+}
+
+impl std::fmt::Display for RotationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RotationIssue::EmptyWindow => write!(f, "catalog is valid for no time"),
+            RotationIssue::EndsBeforeWindow => {
+                write!(f, "rotation ends before the catalog is valid")
+            }
+            RotationIssue::EndsAfterWindow => {
+                write!(f, "rotation ends after the catalog is valid")
+            }
+        }
+    }
+}
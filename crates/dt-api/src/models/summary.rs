@@ -35,6 +35,15 @@ pub struct Character {
     pub level: u32,
 }
 
+/// The characters of an account, without the rest of its summary.
+///
+/// Deserializes from the characters resource a summary links to, or from the
+/// summary itself, skipping everything but its characters.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Characters {
+    pub characters: Vec<Character>,
+}
+
 /// Email model
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Email {
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::Link;
+
+/// Balance model
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Balance {
+    pub amount: i64,
+    /// The currency, e.g. `plasteel`, `diamantine` or `aquilas`.
+    #[serde(rename = "type")]
+    pub balance_type: String,
+}
+
+/// Wallet model
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Wallet {
+    pub balance: Balance,
+    pub last_transaction_id: Option<i64>,
+}
+
+/// Account wallets model
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Wallets {
+    #[serde(rename = "_links", default)]
+    pub links: HashMap<String, Link>,
+    pub wallets: Vec<Wallet>,
+}
+
+impl Wallets {
+    /// Get the balance of a currency.
+    ///
+    /// # Parameters
+    ///
+    /// - `balance_type` - The currency, as in [`Balance::balance_type`].
+    ///
+    /// # Returns
+    ///
+    /// The balance, or `None` if the account has no wallet for the currency.
+    pub fn balance(&self, balance_type: &str) -> Option<i64> {
+        self.wallets
+            .iter()
+            .find(|wallet| wallet.balance.balance_type == balance_type)
+            .map(|wallet| wallet.balance.amount)
+    }
+
+    /// Get the crafting materials balance of the account.
+    pub fn materials(&self) -> Materials {
+        Materials {
+            plasteel: self.balance("plasteel").unwrap_or_default(),
+            diamantine: self.balance("diamantine").unwrap_or_default(),
+        }
+    }
+}
+
+/// Crafting materials balance of an account
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Materials {
+    pub plasteel: i64,
+    pub diamantine: i64,
+}
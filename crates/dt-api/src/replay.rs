@@ -0,0 +1,164 @@
+//! Replay of captured responses, for running without network access.
+//!
+//! An [`Api`](crate::Api) created with [`Api::replay`](crate::Api::replay)
+//! reads the response of every endpoint from a JSON file in a fixture
+//! directory instead of requesting it:
+//!
+//! | Endpoint     | File                                                               |
+//! | ------------ | ------------------------------------------------------------------ |
+//! | Summary      | `summary.json`                                                     |
+//! | Store        | `stores/<currency type>/<character id>.json`                       |
+//! | Public store | `stores/<currency type>/public/<archetype>.json`                   |
+//! | Inventory    | `inventories/<character id>.json`                                  |
+//! | Wallets      | `wallets.json`                                                     |
+//! | Master data  | `master_data.json`                                                 |
+//! | Page         | `pages/<path>.json`, or `pages/<path>/<continuation token>.json`   |
+//!
+//! Files in the directory of an account, named by [`account_dir`], take
+//! precedence over those at the root, so fixtures can be shared by every
+//! account or be specific to one.
+
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use serde::de::DeserializeOwned;
+use tracing::{debug, info, instrument};
+
+use crate::{models::AccountId, Auth, Endpoint, Error, Result};
+
+/// Fixture directory read by a replaying [`Api`](crate::Api).
+#[derive(Clone, Debug)]
+pub struct Replay {
+    dir: PathBuf,
+}
+
+impl Replay {
+    /// Creates a replay of the fixtures in `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The fixture directory.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Reads the fixture of an endpoint, preferring the one of the account.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if there is no fixture for the endpoint or it
+    /// doesn't match the model.
+    #[instrument(skip(self))]
+    pub(crate) async fn send<T: DeserializeOwned + std::fmt::Debug>(
+        &self,
+        auth: &Auth,
+        endpoint: Endpoint<'_>,
+    ) -> Result<T> {
+        let file = fixture_path(endpoint);
+        let candidates = [
+            self.dir.join(account_dir(auth.sub)).join(&file),
+            self.dir.join(&file),
+        ];
+        for path in candidates {
+            let contents = match tokio::fs::read(&path).await {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(Error::ReadFixture { path, source: e }),
+            };
+            let data = serde_json::from_slice::<T>(&contents)
+                .map_err(|e| Error::InvalidFixture { path, source: e })?;
+            info!("Replayed {}", endpoint);
+            debug!(data = ?data);
+            return Ok(data);
+        }
+        tracing::error!(path = %self.dir.join(&file).display(), "No fixture for {}", endpoint);
+        Err(Error::MissingFixture {
+            endpoint: endpoint.to_string(),
+            path: self.dir.join(file),
+        })
+    }
+
+    /// Refreshes an auth without a request, keeping its tokens.
+    pub(crate) fn refresh_auth(&self, auth: &Auth) -> Auth {
+        let refresh_at = chrono::Duration::from_std(auth.expires_in)
+            .ok()
+            .and_then(|expires_in| Utc::now().checked_add_signed(expires_in));
+        Auth {
+            refresh_at,
+            ..auth.clone()
+        }
+    }
+}
+
+/// Name of the fixture directory of an account.
+///
+/// This is a hash of the account id, so fixtures can be shared without
+/// revealing it.
+pub fn account_dir(sub: AccountId) -> String {
+    // FNV-1a, as it is stable across releases and platforms.
+    let hash = sub
+        .0
+        .as_bytes()
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    format!("{:016x}", hash)
+}
+
+/// Path of the fixture of an endpoint, relative to the fixture directory or
+/// an account directory.
+pub fn fixture_path(endpoint: Endpoint<'_>) -> PathBuf {
+    match endpoint {
+        Endpoint::Summary => PathBuf::from("summary.json"),
+        Endpoint::Store {
+            currency_type,
+            character,
+        } => PathBuf::from("stores")
+            .join(currency_type.to_string())
+            .join(format!("{}.json", character.id)),
+        Endpoint::PublicStore {
+            currency_type,
+            archetype,
+        } => PathBuf::from("stores")
+            .join(currency_type.to_string())
+            .join("public")
+            .join(format!("{}.json", sanitize(archetype))),
+        Endpoint::Inventory { character } => {
+            PathBuf::from("inventories").join(format!("{}.json", character.id))
+        }
+        Endpoint::Wallets => PathBuf::from("wallets.json"),
+        Endpoint::MasterData => PathBuf::from("master_data.json"),
+        Endpoint::Page {
+            path,
+            continuation_token,
+        } => {
+            let mut file = PathBuf::from("pages");
+            let segments = path.split('/').filter(|segment| !segment.is_empty());
+            file.extend(segments.map(sanitize));
+            match continuation_token {
+                Some(token) => {
+                    file.push(format!("{}.json", sanitize(token)));
+                    file
+                }
+                None => file.with_extension("json"),
+            }
+        }
+    }
+}
+
+/// Replace characters that aren't safe in file names, including the dots of
+/// `..`, so paths can't leave the fixture directory.
+fn sanitize(segment: &str) -> String {
+    segment
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
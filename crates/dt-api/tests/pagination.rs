@@ -0,0 +1,74 @@
+//! Tests of following continuation tokens, served from replay fixtures.
+#![cfg(feature = "replay")]
+
+use std::path::Path;
+
+use dt_api::{Api, Auth, Error};
+use futures::TryStreamExt;
+use serde_json::{json, Value};
+
+fn auth() -> Auth {
+    serde_json::from_value(json!({
+        "AccessToken": "access",
+        "AccountName": "account",
+        "ExpiresIn": 3600,
+        "RefreshToken": "refresh",
+        "Sub": "00000000-0000-0000-0000-000000000001",
+    }))
+    .unwrap()
+}
+
+/// Write a page of `/things` with `item`, continued by `next`.
+fn write_page(dir: &Path, token: Option<&str>, item: u32, next: Option<&str>) {
+    let file = match token {
+        Some(token) => dir.join("pages/things").join(format!("{token}.json")),
+        None => dir.join("pages/things.json"),
+    };
+    std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+    let page = json!({ "items": [item], "continuationToken": next });
+    std::fs::write(file, page.to_string()).unwrap();
+}
+
+async fn fetch(api: &Api) -> Result<Vec<Value>, Error> {
+    let auth = auth();
+    api.fetch_all_pages(&auth, "/things").try_collect().await
+}
+
+#[tokio::test]
+async fn follows_continuation_tokens() {
+    let dir = tempfile::tempdir().unwrap();
+    write_page(dir.path(), None, 1, Some("a"));
+    write_page(dir.path(), Some("a"), 2, Some("b"));
+    write_page(dir.path(), Some("b"), 3, None);
+
+    let items = fetch(&Api::replay(dir.path())).await.unwrap();
+    assert_eq!(items, vec![json!(1), json!(2), json!(3)]);
+}
+
+#[tokio::test]
+async fn stops_at_repeated_continuation_token() {
+    let dir = tempfile::tempdir().unwrap();
+    write_page(dir.path(), None, 1, Some("a"));
+    write_page(dir.path(), Some("a"), 2, Some("b"));
+    write_page(dir.path(), Some("b"), 3, Some("a"));
+
+    let result = fetch(&Api::replay(dir.path())).await;
+    assert!(matches!(
+        result,
+        Err(Error::RepeatedContinuationToken { .. })
+    ));
+}
+
+#[tokio::test]
+async fn stops_at_max_pages() {
+    let dir = tempfile::tempdir().unwrap();
+    write_page(dir.path(), None, 1, Some("a"));
+    write_page(dir.path(), Some("a"), 2, Some("b"));
+    write_page(dir.path(), Some("b"), 3, None);
+
+    let api = Api::replay(dir.path()).with_max_pages(2);
+    let result = fetch(&api).await;
+    assert!(matches!(result, Err(Error::TooManyPages { max: 2, .. })));
+    let api = Api::replay(dir.path()).with_max_pages(3);
+    assert_eq!(fetch(&api).await.unwrap().len(), 3);
+}
@@ -0,0 +1,485 @@
+//! Property-based round-trip tests of the models.
+//!
+//! Every model generated here must serialize to JSON that deserializes back to
+//! a model serializing to the same JSON, so the serde attributes and adapters
+//! can't lose or change data.
+
+use std::{collections::HashMap, time::Duration};
+
+use chrono::{DateTime, TimeZone, Timelike, Utc};
+use dt_api::{models::*, Auth};
+use proptest::{collection::vec, option, prelude::*};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+/// Serialize `model`, deserialize it back and check that it serializes to the
+/// same JSON, both as a `Value` and through text.
+fn assert_round_trip<T: Serialize + DeserializeOwned>(model: &T) -> Result<(), TestCaseError> {
+    let json = serde_json::to_value(model).expect("model serializes");
+    let parsed: T = serde_json::from_value(json.clone())
+        .map_err(|e| TestCaseError::fail(format!("{e}: {json}")))?;
+    prop_assert_eq!(&serde_json::to_value(&parsed).unwrap(), &json);
+
+    let text = serde_json::to_string(model).unwrap();
+    let parsed: T =
+        serde_json::from_str(&text).map_err(|e| TestCaseError::fail(format!("{e}: {text}")))?;
+    prop_assert_eq!(serde_json::to_value(&parsed).unwrap(), json);
+    Ok(())
+}
+
+mod strategies {
+    use super::*;
+
+    pub fn uuid() -> impl Strategy<Value = Uuid> {
+        any::<u128>().prop_map(Uuid::from_u128)
+    }
+
+    /// Times with millisecond precision, the precision of the timestamp
+    /// adapters.
+    pub fn time_millis() -> impl Strategy<Value = DateTime<Utc>> {
+        // Years 1970 to 2200.
+        (0..7_258_118_400_000_i64).prop_map(|ms| Utc.timestamp_millis_opt(ms).unwrap())
+    }
+
+    pub fn text() -> impl Strategy<Value = String> {
+        "[a-zA-Z0-9_/ .-]{0,16}"
+    }
+
+    pub fn finite_f64() -> impl Strategy<Value = f64> {
+        prop::num::f64::NORMAL | prop::num::f64::ZERO | prop::num::f64::SUBNORMAL
+    }
+
+    pub fn json() -> impl Strategy<Value = Value> {
+        let leaf = prop_oneof![
+            Just(Value::Null),
+            any::<bool>().prop_map(Value::from),
+            any::<i64>().prop_map(Value::from),
+            finite_f64().prop_map(Value::from),
+            text().prop_map(Value::from),
+        ];
+        leaf.prop_recursive(3, 16, 4, |inner| {
+            prop_oneof![
+                vec(inner.clone(), 0..4).prop_map(Value::from),
+                prop::collection::hash_map(text(), inner, 0..4)
+                    .prop_map(|map| Value::Object(map.into_iter().collect())),
+            ]
+        })
+    }
+
+    pub fn links() -> impl Strategy<Value = HashMap<String, Link>> {
+        prop::collection::hash_map(text(), text().prop_map(|href| Link { href }), 0..3)
+    }
+
+    pub fn currency_type() -> impl Strategy<Value = CurrencyType> {
+        prop_oneof![Just(CurrencyType::Marks), Just(CurrencyType::Credits)]
+    }
+
+    prop_compose! {
+        pub fn character()(
+            id in uuid(),
+            name in text(),
+            female in any::<bool>(),
+            archetype in text(),
+            specialization in text(),
+            level in any::<u32>(),
+        ) -> Character {
+            Character {
+                id: CharacterId(id),
+                name,
+                gender: if female { Gender::Female } else { Gender::Male },
+                archetype,
+                specialization,
+                level,
+            }
+        }
+    }
+
+    prop_compose! {
+        pub fn summary()(
+            links in links(),
+            username in text(),
+            name in text(),
+            discriminator in text(),
+            allow_rename in any::<bool>(),
+            characters in vec(character(), 0..5),
+            verified in any::<bool>(),
+            (steam, twitch) in (text(), text()),
+            (newsletter_subscribe, opt_in, terms_agreed) in any::<(bool, bool, bool)>(),
+        ) -> Summary {
+            Summary {
+                links,
+                username,
+                name,
+                discriminator,
+                allow_rename,
+                characters,
+                email: Email { verified },
+                linked_accounts: LinkedAccounts { steam, twitch },
+                marketing_preferences: MarketingPreferences {
+                    newsletter_subscribe,
+                    opt_in,
+                    terms_agreed,
+                },
+            }
+        }
+    }
+
+    prop_compose! {
+        pub fn item_override()(
+            ver in any::<i32>(),
+            rarity in any::<i32>(),
+            character_level in any::<i32>(),
+            item_level in any::<i32>(),
+            base_item_level in any::<i32>(),
+            traits in vec(
+                (text(), any::<i32>(), option::of(finite_f64()))
+                    .prop_map(|(id, rarity, value)| Trait { id, rarity, value }),
+                0..4,
+            ),
+            perks in vec((text(), any::<i32>()).prop_map(|(id, rarity)| Perk { id, rarity }), 0..3),
+        ) -> Override {
+            Override {
+                ver,
+                rarity,
+                character_level,
+                item_level,
+                base_item_level,
+                traits,
+                perks,
+            }
+        }
+    }
+
+    pub fn overrides() -> impl Strategy<Value = Overrides> {
+        prop_oneof![
+            (
+                item_override(),
+                vec(
+                    (text(), finite_f64()).prop_map(|(name, value)| Stat { name, value }),
+                    0..5
+                )
+            )
+                .prop_map(|(overrides, base_stats)| Overrides::Weapon(
+                    WeaponOverride {
+                        overrides,
+                        base_stats
+                    }
+                )),
+            item_override().prop_map(Overrides::Gadget),
+            vec(text(), 0..3).prop_map(|slots| Overrides::RandomItem { slots }),
+            Just(Overrides::None {}),
+        ]
+    }
+
+    prop_compose! {
+        pub fn offer()(
+            (offer_id, sku_id, entitlement_id, price_id, gear_id) in
+                (uuid(), uuid(), uuid(), uuid(), uuid()),
+            (display_priority, internal_name, name, description, category, asset_id) in
+                (any::<i32>(), text(), text(), text(), text(), text()),
+            (tags, dlc_req) in (vec(text(), 0..3), vec(text(), 0..2)),
+            (limit, entitlement_type) in (any::<i32>(), text()),
+            (amount, amount_type, priority, price_formula) in
+                (any::<i32>(), currency_type(), any::<i32>(), option::of(text())),
+            state in text(),
+            (id, rotation, description_type) in (text(), text(), text()),
+            properties in prop::collection::hash_map(text(), json(), 0..3),
+            overrides in overrides(),
+            media in vec(json(), 0..2),
+        ) -> Offer {
+            Offer {
+                offer_id: OfferId(offer_id),
+                sku: Sku {
+                    id: SkuId(sku_id),
+                    display_priority,
+                    internal_name,
+                    name,
+                    description,
+                    category,
+                    asset_id,
+                    tags,
+                    dlc_req,
+                },
+                entitlement: Entitlement {
+                    id: EntitlementId(entitlement_id),
+                    limit,
+                    entitlement_type,
+                },
+                price: Price {
+                    amount: Amount { amount, amount_type },
+                    id: PriceId(price_id),
+                    priority,
+                    price_formula,
+                },
+                state,
+                description: Description {
+                    id,
+                    gear_id: GearId(gear_id),
+                    rotation,
+                    description_type,
+                    properties,
+                    overrides,
+                },
+                media,
+            }
+        }
+    }
+
+    prop_compose! {
+        pub fn store()(
+            links in links(),
+            (catalog_id, catalog_name, generation, layout_ref, valid_from, valid_to) in
+                (uuid(), text(), any::<i32>(), option::of(text()), time_millis(), time_millis()),
+            name in text(),
+            public in vec(offer(), 0..3),
+            personal in vec(offer(), 0..3),
+            rerolls_this_rotation in any::<i32>(),
+            current_rotation_end in time_millis(),
+        ) -> Store {
+            Store {
+                links,
+                catalog: Catalog {
+                    id: CatalogId(catalog_id),
+                    name: catalog_name,
+                    generation,
+                    layout_ref,
+                    valid_from,
+                    valid_to,
+                },
+                name,
+                public,
+                personal,
+                rerolls_this_rotation,
+                current_rotation_end,
+            }
+        }
+    }
+
+    prop_compose! {
+        pub fn master_data()(links in links(), href in text(), version in text()) -> MasterData {
+            MasterData {
+                links,
+                player_items: PlayerItems { href, version },
+            }
+        }
+    }
+
+    prop_compose! {
+        pub fn auth()(
+            (access_token, account_name, refresh_token) in (text(), text(), text()),
+            expires_in in any::<u64>().prop_map(Duration::from_secs),
+            refresh_at in option::of(time_millis()),
+            sub in uuid(),
+            base_url in option::of(text()),
+        ) -> Auth {
+            Auth {
+                access_token,
+                account_name,
+                expires_in,
+                refresh_at,
+                refresh_token,
+                sub: AccountId(sub),
+                base_url,
+            }
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn summary_round_trips(summary in strategies::summary()) {
+        assert_round_trip(&summary)?;
+    }
+
+    #[test]
+    fn summary_projects_to_its_characters(summary in strategies::summary()) {
+        let json = serde_json::to_value(&summary).unwrap();
+        let characters: Characters = serde_json::from_value(json).unwrap();
+        prop_assert_eq!(
+            serde_json::to_value(&characters.characters).unwrap(),
+            serde_json::to_value(&summary.characters).unwrap()
+        );
+    }
+
+    #[test]
+    fn store_round_trips(store in strategies::store()) {
+        assert_round_trip(&store)?;
+    }
+
+    #[test]
+    fn overrides_keep_their_variant(overrides in strategies::overrides()) {
+        let json = serde_json::to_value(&overrides).unwrap();
+        let parsed: Overrides = serde_json::from_value(json).unwrap();
+        prop_assert_eq!(
+            std::mem::discriminant(&parsed),
+            std::mem::discriminant(&overrides)
+        );
+    }
+
+    #[test]
+    fn master_data_round_trips(master_data in strategies::master_data()) {
+        assert_round_trip(&master_data)?;
+    }
+
+    #[test]
+    fn auth_round_trips(auth in strategies::auth()) {
+        assert_round_trip(&auth)?;
+        let parsed: Auth = serde_json::from_str(&serde_json::to_string(&auth).unwrap()).unwrap();
+        prop_assert_eq!(parsed.refresh_at, auth.refresh_at);
+        prop_assert_eq!(parsed.expires_in, auth.expires_in);
+    }
+
+    #[test]
+    fn refresh_at_is_in_milliseconds(ms in 0..7_258_118_400_000_i64) {
+        let json = serde_json::json!({
+            "AccessToken": "a",
+            "AccountName": "n",
+            "ExpiresIn": 3600,
+            "RefreshAt": ms,
+            "RefreshToken": "r",
+            "Sub": Uuid::nil(),
+        });
+        let auth: Auth = serde_json::from_value(json).unwrap();
+        prop_assert_eq!(auth.refresh_at.unwrap().timestamp_millis(), ms);
+        prop_assert_eq!(auth.expires_in, Duration::from_secs(3600));
+        prop_assert_eq!(&serde_json::to_value(&auth).unwrap()["RefreshAt"], &Value::from(ms));
+    }
+
+    #[test]
+    fn retry_after_is_seconds_or_a_date(now in strategies::time_millis(), secs in 0..86_400_u64) {
+        let now = now.with_nanosecond(0).unwrap();
+        let delay = Duration::from_secs(secs);
+        prop_assert_eq!(dt_api::parse_retry_after(&secs.to_string(), now), Some(delay));
+        let at = now + chrono::Duration::seconds(secs as i64);
+        let date = at.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        prop_assert_eq!(dt_api::parse_retry_after(&date, now), Some(delay));
+        let past = now - chrono::Duration::seconds(secs as i64);
+        let date = past.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        prop_assert_eq!(dt_api::parse_retry_after(&date, now), Some(Duration::ZERO));
+        prop_assert_eq!(dt_api::parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn rotation_end_is_a_millisecond_string(store in strategies::store()) {
+        let json = serde_json::to_value(&store).unwrap();
+        prop_assert_eq!(
+            &json["currentRotationEnd"],
+            &Value::from(store.current_rotation_end.timestamp_millis().to_string())
+        );
+        prop_assert_eq!(
+            &json["catalog"]["validFrom"],
+            &Value::from(store.catalog.valid_from.timestamp_millis().to_string())
+        );
+        prop_assert_eq!(
+            &json["catalog"]["validTo"],
+            &Value::from(store.catalog.valid_to.timestamp_millis().to_string())
+        );
+    }
+
+    #[test]
+    fn store_rotates_within_a_consistent_catalog(mut store in strategies::store()) {
+        let rotates_at = store.rotates_at();
+        prop_assert!(rotates_at <= store.current_rotation_end);
+        match store.rotation_issue() {
+            None => {
+                prop_assert!(store.catalog.is_valid_at(store.catalog.valid_from));
+                prop_assert_eq!(rotates_at, store.current_rotation_end);
+            }
+            Some(RotationIssue::EmptyWindow) => {
+                prop_assert_eq!(rotates_at, store.current_rotation_end);
+            }
+            Some(_) => {
+                prop_assert_eq!(rotates_at, store.current_rotation_end.min(store.catalog.valid_to));
+            }
+        }
+
+        store.catalog.valid_from = store.current_rotation_end - chrono::Duration::hours(1);
+        store.catalog.valid_to = store.current_rotation_end;
+        prop_assert_eq!(store.rotation_issue(), None);
+    }
+
+    #[test]
+    fn link_path_drops_scheme_and_host(path in "(/[a-z0-9-]{1,12}){1,4}(\\?[a-z]=[0-9])?") {
+        let absolute = Link { href: format!("https://bsp-td-prod.atoma.cloud{path}") };
+        prop_assert_eq!(absolute.path(), path.as_str());
+        let relative = Link { href: path.clone() };
+        prop_assert_eq!(relative.path(), path.as_str());
+    }
+
+    #[test]
+    fn known_properties_are_typed(
+        mut offer in strategies::offer(),
+        level in any::<u32>(),
+        (min, max) in (any::<u32>(), any::<u32>()),
+        skin in option::of(strategies::text()),
+        tags in vec(strategies::text(), 0..3),
+    ) {
+        let properties = &mut offer.description.properties;
+        properties.clear();
+        properties.insert("level".into(), Value::from(level));
+        properties.insert("minItemLevel".into(), Value::from(min));
+        properties.insert("maxItemLevel".into(), Value::from(max));
+        properties.insert("skin".into(), Value::from(skin.clone()));
+        properties.insert("tags".into(), Value::from(tags.clone()));
+        properties.insert("unknown".into(), Value::from("kept"));
+
+        let known = offer.description.known_properties();
+        prop_assert_eq!(known.level, Some(level));
+        prop_assert_eq!(known.item_level, Some(ItemLevelRange { min, max }));
+        prop_assert_eq!(known.skin, skin);
+        prop_assert_eq!(known.tags, tags);
+        prop_assert_eq!(offer.description.property::<String>("unknown"), Some("kept".to_string()));
+
+        offer.description.properties.insert("level".into(), Value::from("thirty"));
+        prop_assert_eq!(offer.description.known_properties().level, None);
+        prop_assert!(offer.description.known_properties().item_level.is_some());
+    }
+
+    #[test]
+    fn amount_is_formatted_in_groups_of_three(amount in any::<i32>(), amount_type in strategies::currency_type()) {
+        let formatted = Amount { amount, amount_type }.formatted();
+        prop_assert_eq!(formatted.replace(',', "").parse::<i32>().unwrap(), amount);
+        let digits = formatted.trim_start_matches('-');
+        let groups: Vec<_> = digits.split(',').collect();
+        prop_assert!((1..=3).contains(&groups[0].len()));
+        prop_assert!(groups[1..].iter().all(|group| group.len() == 3));
+    }
+
+    #[test]
+    fn character_level_is_the_highest_of_the_personal_items(store in strategies::store()) {
+        let levels: Vec<i32> = store
+            .personal
+            .iter()
+            .filter_map(|offer| offer.description.overrides.item())
+            .map(|item| item.character_level)
+            .collect();
+        match store.character_level() {
+            Some(level) => prop_assert!(levels.iter().all(|&l| l <= level as i32)
+                && levels.contains(&(level as i32))),
+            None => prop_assert!(levels.iter().all(|&l| l < 0)),
+        }
+    }
+
+    #[test]
+    fn stat_percent_is_a_whole_share_of_the_maximum(value in any::<f64>()) {
+        let percent = Stat { name: "damage".to_string(), value }.percent();
+        prop_assert!(percent <= Stat::MAX_PERCENT);
+        if (0.0..=1.0).contains(&value) {
+            prop_assert!((f64::from(percent) - value * 80.0).abs() <= 0.5);
+        }
+    }
+
+    #[test]
+    fn cheapest_offer_has_the_lowest_price_of_its_category(store in strategies::store()) {
+        for offer in store.offers() {
+            let category = &offer.sku.category;
+            let cheapest = store.cheapest(&category.to_uppercase()).unwrap();
+            prop_assert!(cheapest.sku.category.eq_ignore_ascii_case(category));
+            prop_assert!(store
+                .offers()
+                .filter(|other| other.sku.category.eq_ignore_ascii_case(category))
+                .all(|other| other.price.amount.amount >= cheapest.price.amount.amount));
+        }
+    }
+}
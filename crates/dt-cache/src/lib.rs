@@ -0,0 +1,244 @@
+//! Cached resources that are fetched on a miss and refreshed one at a time.
+//!
+//! A [`CachedResource`] holds the last fetched value of a resource, such as
+//! the summary of an account, with when it was fetched. Concurrent refreshes
+//! are coalesced into a single fetch, and subscribers are notified of every
+//! new value.
+
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{sync::watch, time::Instant};
+
+/// The last fetched value of a resource. Clones share the value.
+pub struct CachedResource<T> {
+    inner: Arc<Inner<T>>,
+}
+
+struct Inner<T> {
+    value: watch::Sender<Option<T>>,
+    state: Mutex<State>,
+    /// Held while fetching, so refreshes wait for the one in flight.
+    refreshing: tokio::sync::Mutex<()>,
+}
+
+#[derive(Default)]
+struct State {
+    fetched_at: Option<Instant>,
+    /// Incremented whenever a value is cached.
+    generation: u64,
+}
+
+impl<T: Clone> CachedResource<T> {
+    /// A resource caching `value`, as fetched now.
+    pub fn new(value: Option<T>) -> Self {
+        let state = State {
+            fetched_at: value.is_some().then(Instant::now),
+            generation: 0,
+        };
+        Self {
+            inner: Arc::new(Inner {
+                value: watch::Sender::new(value),
+                state: Mutex::new(state),
+                refreshing: tokio::sync::Mutex::new(()),
+            }),
+        }
+    }
+
+    /// The cached value, however old.
+    pub fn peek(&self) -> Option<T> {
+        self.inner.value.borrow().clone()
+    }
+
+    pub fn is_cached(&self) -> bool {
+        self.inner.value.borrow().is_some()
+    }
+
+    /// Time since the cached value was fetched.
+    pub fn age(&self) -> Option<Duration> {
+        self.state().fetched_at.map(|at| at.elapsed())
+    }
+
+    /// The cached value, if it was fetched less than `max_age` ago.
+    pub fn fresh(&self, max_age: Duration) -> Option<T> {
+        if self.age()? < max_age {
+            self.peek()
+        } else {
+            None
+        }
+    }
+
+    /// Cache `value` as fetched now, returning the value it replaces.
+    pub fn set(&self, value: T) -> Option<T> {
+        let mut state = self.state();
+        state.fetched_at = Some(Instant::now());
+        state.generation += 1;
+        self.inner.value.send_replace(Some(value))
+    }
+
+    /// Cache `value` unless a value is cached, returning whether it was.
+    pub fn set_if_empty(&self, value: T) -> bool {
+        let mut state = self.state();
+        let set = self.inner.value.send_if_modified(|current| {
+            if current.is_some() {
+                return false;
+            }
+            *current = Some(value);
+            true
+        });
+        if set {
+            state.fetched_at = Some(Instant::now());
+            state.generation += 1;
+        }
+        set
+    }
+
+    /// Watch the cached value, which changes whenever a value is cached.
+    pub fn subscribe(&self) -> watch::Receiver<Option<T>> {
+        self.inner.value.subscribe()
+    }
+
+    /// The cached value, or a fetched one if none is cached or the cached one
+    /// is at least `max_age` old.
+    pub async fn get<F, Fut, E>(&self, max_age: Option<Duration>, fetch: F) -> Result<T, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let generation = self.state().generation;
+        let cached = match max_age {
+            Some(max_age) => self.fresh(max_age),
+            None => self.peek(),
+        };
+        match cached {
+            Some(value) => Ok(value),
+            None => self.refresh_since(generation, fetch).await,
+        }
+    }
+
+    /// Fetch and cache the value.
+    ///
+    /// A refresh that starts while another is in flight waits for it and
+    /// returns its value instead of fetching again. Nothing is cached if the
+    /// fetch fails.
+    pub async fn refresh<F, Fut, E>(&self, fetch: F) -> Result<T, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let generation = self.state().generation;
+        self.refresh_since(generation, fetch).await
+    }
+
+    /// Fetch and cache the value, unless a value was cached since
+    /// `generation`.
+    async fn refresh_since<F, Fut, E>(&self, generation: u64, fetch: F) -> Result<T, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let _refreshing = self.inner.refreshing.lock().await;
+        if self.state().generation != generation {
+            if let Some(value) = self.peek() {
+                return Ok(value);
+            }
+        }
+        let value = fetch().await?;
+        self.set(value.clone());
+        Ok(value)
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        // The state is only assigned while locked, so it is consistent even
+        // if a holder panicked.
+        self.inner
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<T: Clone> Default for CachedResource<T> {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl<T> Clone for CachedResource<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for CachedResource<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let age = self
+            .inner
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .fetched_at
+            .map(|at| at.elapsed());
+        f.debug_struct("CachedResource")
+            .field("value", &*self.inner.value.borrow())
+            .field("age", &age)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn concurrent_refreshes_fetch_once() {
+        let resource = CachedResource::<u32>::default();
+        let fetches = AtomicUsize::new(0);
+        let fetch = || async {
+            fetches.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Ok::<_, ()>(7)
+        };
+        let (first, second) = tokio::join!(resource.refresh(fetch), resource.refresh(fetch));
+        assert_eq!((first, second), (Ok(7), Ok(7)));
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn get_fetches_once_stale() {
+        let resource = CachedResource::new(Some(1));
+        let max_age = Some(Duration::from_secs(10));
+        assert_eq!(resource.get(max_age, || async { Err(()) }).await, Ok(1));
+
+        tokio::time::advance(Duration::from_secs(11)).await;
+        assert_eq!(
+            resource.get(max_age, || async { Ok::<_, ()>(2) }).await,
+            Ok(2)
+        );
+        assert_eq!(resource.age(), Some(Duration::ZERO));
+    }
+
+    #[tokio::test]
+    async fn failed_refresh_keeps_value() {
+        let resource = CachedResource::new(Some(1));
+        let mut updates = resource.subscribe();
+        assert_eq!(
+            resource.refresh(|| async { Err("down") }).await,
+            Err("down")
+        );
+        assert_eq!(resource.peek(), Some(1));
+        assert!(!updates.has_changed().unwrap());
+
+        resource.refresh(|| async { Ok::<_, ()>(2) }).await.unwrap();
+        assert!(updates.has_changed().unwrap());
+        assert_eq!(*updates.borrow_and_update(), Some(2));
+        assert!(!resource.set_if_empty(3));
+    }
+}
@@ -0,0 +1,321 @@
+//! Throughput and latency of concurrent `/store` requests against a populated
+//! cache.
+//!
+//! The bench runs the dt-fetcher binary with `--replay` fixtures generated
+//! here, so no upstream is involved, fills the cache of one account and then
+//! sends batches of concurrent requests for the stores of its characters. The
+//! p99 latency of each batch size is printed after it is measured.
+//!
+//! Save a baseline before changing the cache layer and compare against it
+//! afterwards:
+//!
+//! ```console
+//! cargo bench -p dt-fetcher --bench store -- --save-baseline before
+//! cargo bench -p dt-fetcher --bench store -- --baseline before
+//! ```
+
+use std::{
+    collections::HashMap,
+    net::{SocketAddr, TcpListener},
+    path::Path,
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use dt_api::{models::*, Auth};
+use uuid::Uuid;
+
+/// Characters of the account, each with a store per currency.
+const CHARACTERS: usize = 8;
+/// Offers in each store.
+const OFFERS: usize = 40;
+/// Concurrent requests sent in each iteration.
+const CONCURRENCY: &[usize] = &[1, 8, 32, 128];
+
+/// The dt-fetcher process, killed when dropped.
+struct Server {
+    child: Child,
+    addr: SocketAddr,
+}
+
+impl Server {
+    fn start(fixtures: &Path) -> Self {
+        // Let the OS pick a free port, then release it for the server.
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("a free port");
+        let child = Command::new(env!("CARGO_BIN_EXE_dt-fetcher"))
+            .arg("--listen-addr")
+            .arg(addr.to_string())
+            .arg("--replay")
+            .arg(fixtures)
+            .env("RUST_LOG", "error")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("dt-fetcher starts");
+        Self { child, addr }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn character(index: usize) -> Character {
+    Character {
+        id: CharacterId(Uuid::from_u128(index as u128 + 1)),
+        name: format!("character-{index}"),
+        gender: Gender::Female,
+        archetype: ["veteran", "zealot", "psyker", "ogryn"][index % 4].to_string(),
+        specialization: String::new(),
+        level: 30,
+    }
+}
+
+fn summary(characters: Vec<Character>) -> Summary {
+    Summary {
+        links: HashMap::new(),
+        username: "account".to_string(),
+        name: "account".to_string(),
+        discriminator: "0000".to_string(),
+        allow_rename: false,
+        characters,
+        email: Email { verified: true },
+        linked_accounts: LinkedAccounts {
+            steam: String::new(),
+            twitch: String::new(),
+        },
+        marketing_preferences: MarketingPreferences {
+            newsletter_subscribe: false,
+            opt_in: false,
+            terms_agreed: true,
+        },
+    }
+}
+
+fn offer(index: usize, currency_type: CurrencyType) -> Offer {
+    let overrides = Override {
+        ver: 1,
+        rarity: 1 + (index % 5) as i32,
+        character_level: 30,
+        item_level: 300 + index as i32,
+        base_item_level: 300,
+        traits: vec![Trait {
+            id: format!("trait_{index}"),
+            rarity: 3,
+            value: Some(0.1),
+        }],
+        perks: vec![Perk {
+            id: format!("perk_{index}"),
+            rarity: 2,
+        }],
+    };
+    Offer {
+        offer_id: OfferId(Uuid::new_v4()),
+        sku: Sku {
+            id: SkuId(Uuid::new_v4()),
+            display_priority: index as i32,
+            internal_name: format!("item_{index}"),
+            name: format!("Item {index}"),
+            description: String::new(),
+            category: "weapon".to_string(),
+            asset_id: String::new(),
+            tags: Vec::new(),
+            dlc_req: Vec::new(),
+        },
+        entitlement: Entitlement {
+            id: EntitlementId(Uuid::new_v4()),
+            limit: 1,
+            entitlement_type: "GearInstance".to_string(),
+        },
+        price: Price {
+            amount: Amount {
+                amount: 1000 + index as i32,
+                amount_type: currency_type,
+            },
+            id: PriceId(Uuid::new_v4()),
+            priority: 0,
+            price_formula: None,
+        },
+        state: "active".to_string(),
+        description: Description {
+            id: format!("content/items/weapons/item_{index}"),
+            gear_id: GearId(Uuid::new_v4()),
+            rotation: "daily".to_string(),
+            description_type: "weapon".to_string(),
+            properties: HashMap::new(),
+            overrides: Overrides::Weapon(WeaponOverride {
+                overrides,
+                base_stats: vec![Stat {
+                    name: "damage".to_string(),
+                    value: 0.5,
+                }],
+            }),
+        },
+        media: Vec::new(),
+    }
+}
+
+fn store(currency_type: CurrencyType) -> Store {
+    let offers = (0..OFFERS).map(|i| offer(i, currency_type));
+    // Far enough away that the cached stores never expire during a run.
+    let rotation_end = Utc::now() + chrono::Duration::days(1);
+    Store {
+        links: HashMap::new(),
+        catalog: Catalog {
+            id: CatalogId(Uuid::new_v4()),
+            name: "catalog".to_string(),
+            generation: 1,
+            layout_ref: None,
+            valid_from: rotation_end - chrono::Duration::days(1),
+            valid_to: rotation_end,
+        },
+        name: currency_type.to_string(),
+        public: offers.clone().take(OFFERS / 2).collect(),
+        personal: offers.skip(OFFERS / 2).collect(),
+        rerolls_this_rotation: 0,
+        current_rotation_end: rotation_end,
+    }
+}
+
+fn write_json(path: &Path, value: &impl serde::Serialize) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, serde_json::to_vec(value).unwrap()).unwrap();
+}
+
+/// Write the replay fixtures of an account with [`CHARACTERS`] characters.
+fn write_fixtures(dir: &Path) -> Vec<Character> {
+    let characters: Vec<_> = (0..CHARACTERS).map(character).collect();
+    write_json(&dir.join("summary.json"), &summary(characters.clone()));
+    for character in &characters {
+        for currency_type in [CurrencyType::Marks, CurrencyType::Credits] {
+            let path = dir
+                .join("stores")
+                .join(currency_type.to_string())
+                .join(format!("{}.json", character.id));
+            write_json(&path, &store(currency_type));
+        }
+    }
+    characters
+}
+
+/// Add the auth of the account and wait until its summary is cached.
+async fn add_account(client: &reqwest::Client, server: &Server, sub: AccountId) {
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while client.get(server.url("/version")).send().await.is_err() {
+        assert!(Instant::now() < deadline, "dt-fetcher didn't start");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let auth = Auth {
+        access_token: "bench".to_string(),
+        account_name: "account".to_string(),
+        expires_in: Duration::from_secs(3600),
+        refresh_at: Some(Utc::now() + chrono::Duration::days(1)),
+        refresh_token: "bench".to_string(),
+        sub,
+        base_url: None,
+    };
+    client
+        .put(server.url(&format!("/auth/{}", sub.0)))
+        .json(&auth)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .expect("auth is added");
+    loop {
+        let res = client
+            .get(server.url(&format!("/summary/{}", sub.0)))
+            .send()
+            .await;
+        if res.is_ok_and(|res| res.status().is_success()) {
+            break;
+        }
+        assert!(Instant::now() < deadline, "summary wasn't cached");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// Request a store and return how long it took.
+async fn request(client: &reqwest::Client, url: &str) -> Duration {
+    let start = Instant::now();
+    let res = client.get(url).send().await.expect("request succeeds");
+    assert!(res.status().is_success(), "{url}: {}", res.status());
+    res.bytes().await.expect("body is read");
+    start.elapsed()
+}
+
+fn percentile(latencies: &mut [Duration], percentile: f64) -> Duration {
+    latencies.sort_unstable();
+    let rank = (latencies.len() as f64 * percentile).ceil() as usize;
+    latencies[rank.saturating_sub(1)]
+}
+
+fn concurrent_stores(c: &mut Criterion) {
+    let fixtures = tempfile::tempdir().unwrap();
+    let characters = write_fixtures(fixtures.path());
+    let server = Server::start(fixtures.path());
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let client = reqwest::Client::new();
+    let sub = AccountId(Uuid::from_u128(0xbe9c));
+
+    let urls: Vec<_> = characters
+        .iter()
+        .flat_map(|character| {
+            ["marks", "credits"].map(|currency_type| {
+                server.url(&format!(
+                    "/store/{}?characterId={}&currencyType={}",
+                    sub.0, character.id, currency_type
+                ))
+            })
+        })
+        .collect();
+    runtime.block_on(async {
+        add_account(&client, &server, sub).await;
+        // Fill the cache, so only cached stores are measured.
+        for url in &urls {
+            request(&client, url).await;
+        }
+    });
+
+    let mut group = c.benchmark_group("store");
+    for &concurrency in CONCURRENCY {
+        let mut latencies = Vec::new();
+        group.throughput(Throughput::Elements(concurrency as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(concurrency),
+            &concurrency,
+            |b, &concurrency| {
+                b.iter_custom(|iters| {
+                    runtime.block_on(async {
+                        let start = Instant::now();
+                        for _ in 0..iters {
+                            let batch =
+                                (0..concurrency).map(|i| request(&client, &urls[i % urls.len()]));
+                            latencies.extend(futures::future::join_all(batch).await);
+                        }
+                        start.elapsed()
+                    })
+                })
+            },
+        );
+        println!(
+            "store/{concurrency}: p99 {:?} over {} requests",
+            percentile(&mut latencies, 0.99),
+            latencies.len()
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, concurrent_stores);
+criterion_main!(benches);
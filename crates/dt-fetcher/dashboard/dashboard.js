@@ -0,0 +1,143 @@
+"use strict";
+
+// Uses relative URLs so the dashboard also works behind a path prefix.
+const CURRENCIES = ["marks", "credits"];
+
+function el(tag, attrs = {}, ...children) {
+  const node = document.createElement(tag);
+  for (const [key, value] of Object.entries(attrs)) {
+    node.setAttribute(key, value);
+  }
+  for (const child of children) {
+    node.append(child);
+  }
+  return node;
+}
+
+async function getJson(url) {
+  const response = await fetch(url);
+  if (!response.ok) {
+    throw new Error(`${url}: ${response.status}`);
+  }
+  return response.json();
+}
+
+function status(label, value) {
+  return el("span", { class: `status ${value}` }, `${label}: ${value}`);
+}
+
+async function authStatus(id) {
+  try {
+    const response = await fetch(`auth/${id}`);
+    return response.ok ? "ok" : response.status === 404 ? "missing" : "error";
+  } catch (e) {
+    return "error";
+  }
+}
+
+function countdown(end) {
+  const remaining = Math.max(0, Math.floor((end - Date.now()) / 1000));
+  const hours = Math.floor(remaining / 3600);
+  const minutes = Math.floor((remaining % 3600) / 60);
+  const seconds = remaining % 60;
+  return `${hours}h ${String(minutes).padStart(2, "0")}m ${String(seconds).padStart(2, "0")}s`;
+}
+
+function offerRow(offer) {
+  const overrides = offer.description.overrides || {};
+  const traits = (overrides.traits || []).map((t) => `${t.id} (${t.rarity})`).join(", ");
+  return el(
+    "tr",
+    {},
+    el("td", {}, offer.sku.name),
+    el("td", {}, offer.sku.category),
+    el("td", {}, overrides.rarity === undefined ? "" : String(overrides.rarity)),
+    el("td", {}, `${offer.price.amount.amount} ${offer.price.amount.type}`),
+    el("td", {}, traits),
+  );
+}
+
+async function storeSection(accountId, character, currency) {
+  const section = el("div", {}, el("h4", {}, currency));
+  try {
+    const store = await getJson(
+      `store/${accountId}?characterId=${character.id}&currencyType=${currency}`,
+    );
+    const end = Number(store.currentRotationEnd);
+    const timer = el("span", { class: "muted countdown", "data-end": String(end) }, countdown(end));
+    section.querySelector("h4").append(" ", timer);
+    const table = el(
+      "table",
+      {},
+      el(
+        "tr",
+        {},
+        el("th", {}, "Name"),
+        el("th", {}, "Category"),
+        el("th", {}, "Rarity"),
+        el("th", {}, "Price"),
+        el("th", {}, "Traits"),
+      ),
+    );
+    for (const offer of store.personal.concat(store.public)) {
+      table.append(offerRow(offer));
+    }
+    section.append(table);
+  } catch (e) {
+    section.append(el("p", { class: "muted" }, `Failed to load store: ${e.message}`));
+  }
+  return section;
+}
+
+async function accountSection(account) {
+  const heading = el("h2", {}, account.id);
+  heading.append(status("auth", await authStatus(account.id)));
+  for (const [name, value] of Object.entries(account.sections)) {
+    heading.append(status(name, value));
+  }
+  const section = el(
+    "section",
+    { class: "account" },
+    heading,
+    el("p", { class: "muted" }, `Last updated ${new Date(account.lastUpdated).toLocaleString()}`),
+  );
+  let summary;
+  try {
+    summary = await getJson(`summary/${account.id}`);
+  } catch (e) {
+    section.append(el("p", { class: "muted" }, `Failed to load summary: ${e.message}`));
+    return section;
+  }
+  for (const character of summary.characters) {
+    section.append(
+      el("h3", {}, `${character.name} `, el("span", { class: "muted" }, `${character.archetype}, level ${character.level}`)),
+    );
+    for (const currency of CURRENCIES) {
+      section.append(await storeSection(account.id, character, currency));
+    }
+  }
+  return section;
+}
+
+async function load() {
+  const main = document.getElementById("accounts");
+  try {
+    const accounts = await getJson("accounts");
+    const sections = await Promise.all(accounts.map(accountSection));
+    main.replaceChildren(...sections);
+    if (sections.length === 0) {
+      main.append(el("p", { class: "muted" }, "No accounts."));
+    }
+  } catch (e) {
+    main.replaceChildren(el("p", { class: "muted" }, `Failed to load accounts: ${e.message}`));
+  }
+}
+
+setInterval(() => {
+  for (const timer of document.querySelectorAll(".countdown")) {
+    timer.textContent = countdown(Number(timer.dataset.end));
+  }
+}, 1000);
+
+document.getElementById("refresh").addEventListener("click", load);
+load();
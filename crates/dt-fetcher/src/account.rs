@@ -1,121 +1,673 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
-use dt_api::models::{AccountId, CharacterId, MasterData, Store};
+use dt_api::{
+    models::{
+        AccountId, Character, CharacterId, CurrencyType, Inventory, ItemCatalog, MasterData,
+        Materials, Offer, Store, Summary,
+    },
+    Auth,
+};
+use dt_cache::CachedResource;
 use futures::stream::{FuturesOrdered, StreamExt};
-use tokio::sync::RwLock;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, watch, RwLock};
+use tokio_util::sync::CancellationToken;
 use tracing::error;
 use tracing::{info, instrument};
 
+use crate::{
+    cached::Cached,
+    clock::{Clock, ErasedClock, SystemClock},
+    config::Config,
+    store_metrics::{StoreGauges, StoreMetrics},
+    stores::{StoreRequest, Stores},
+    timeline::{Resource, TimelineEvent},
+    upstream::Upstream,
+};
+
+/// Population status of a single section of cached account data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum SectionStatus {
+    Ok,
+    Partial,
+    Missing,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AccountStatus {
+    pub summary: SectionStatus,
+    pub master_data: SectionStatus,
+    pub marks_store: SectionStatus,
+    pub credits_store: SectionStatus,
+}
+
+/// Snapshot of the cached data for an account, without credentials.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AccountBundle {
+    pub id: AccountId,
+    pub exported_at: DateTime<Utc>,
+    pub last_updated: DateTime<Utc>,
+    pub summary: Option<Summary>,
+    pub master_data: Option<MasterData>,
+    pub marks_store: HashMap<CharacterId, Store>,
+    pub credits_store: HashMap<CharacterId, Store>,
+}
+
+/// Cached offer with the account, character and currency it was offered to.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OfferMatch {
+    pub account_id: AccountId,
+    pub character_id: CharacterId,
+    pub character_name: Option<String>,
+    pub currency_type: CurrencyType,
+    pub offer: Offer,
+}
+
+/// Inventory of a character, with when it was fetched.
+#[derive(Debug, Clone)]
+pub(crate) struct CachedInventory {
+    pub fetched_at: DateTime<Utc>,
+    pub inventory: Inventory,
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct AccountData {
     pub last_updated: DateTime<Utc>,
-    pub summary: Arc<RwLock<dt_api::models::Summary>>,
-    pub marks_store: Arc<RwLock<HashMap<CharacterId, dt_api::models::Store>>>,
-    pub credits_store: Arc<RwLock<HashMap<CharacterId, dt_api::models::Store>>>,
-    pub master_data: Arc<RwLock<dt_api::models::MasterData>>,
+    pub summary: CachedResource<Cached<Summary>>,
+    pub marks_store: Arc<RwLock<Stores>>,
+    pub credits_store: Arc<RwLock<Stores>>,
+    pub master_data: CachedResource<MasterData>,
+    /// Fetched lazily, as only some clients need them.
+    pub inventories: Arc<RwLock<HashMap<CharacterId, CachedInventory>>>,
+    /// Refreshed alongside the summary.
+    pub materials: CachedResource<Materials>,
+    /// Fetched lazily, to expand random item offers.
+    pub item_catalog: CachedResource<ItemCatalog>,
 }
 
 impl AccountData {
     pub fn new(
-        summary: dt_api::models::Summary,
+        summary: Option<Summary>,
         marks_store: HashMap<CharacterId, Store>,
         credits_store: HashMap<CharacterId, Store>,
-        master_data: MasterData,
+        master_data: Option<MasterData>,
     ) -> Self {
         Self {
             last_updated: Utc::now(),
-            summary: Arc::new(RwLock::new(summary)),
-            marks_store: Arc::new(RwLock::new(marks_store)),
-            credits_store: Arc::new(RwLock::new(credits_store)),
-            master_data: Arc::new(RwLock::new(master_data)),
+            summary: CachedResource::new(summary.map(Cached::new)),
+            marks_store: Arc::new(RwLock::new(marks_store.into_iter().collect())),
+            credits_store: Arc::new(RwLock::new(credits_store.into_iter().collect())),
+            master_data: CachedResource::new(master_data),
+            inventories: Default::default(),
+            materials: Default::default(),
+            item_catalog: Default::default(),
         }
     }
 
+    /// Fetch all account data, keeping whichever sections succeeded.
+    ///
+    /// Missing sections are left empty and fetched lazily by the handlers.
+    /// Only the public stores are fetched unless `personal`.
     #[instrument]
-    pub async fn fetch(api: &dt_api::Api, auth: &dt_api::Auth) -> Result<AccountData> {
-        let summary = api.get_summary(auth).await?;
+    pub async fn fetch(api: &Upstream, auth: &Auth, personal: bool) -> AccountData {
+        let (summary, master_data, wallets) = tokio::join!(
+            api.get_summary(auth),
+            api.get_master_data(auth),
+            api.get_wallets(auth)
+        );
+
+        let master_data = match master_data {
+            Ok(master_data) => Some(master_data),
+            Err(e) => {
+                error!(error = %e, "Failed to get master data");
+                None
+            }
+        };
+
+        let materials = match wallets {
+            Ok(wallets) => Some(wallets.materials()),
+            Err(e) => {
+                error!(error = %e, "Failed to get wallets");
+                None
+            }
+        };
+
+        let summary = match summary {
+            Ok(summary) => summary,
+            Err(e) => {
+                error!(error = %e, "Failed to get summary");
+                api.timeline()
+                    .record_failure(auth.sub, Resource::Summary, &e);
+                let account_data = Self::new(None, HashMap::new(), HashMap::new(), master_data);
+                if let Some(materials) = materials {
+                    account_data.materials.set(materials);
+                }
+                return account_data;
+            }
+        };
 
         info!(
             "Fetching stores for {} characters",
             summary.characters.len()
         );
 
-        let marks_store = summary
-            .characters
-            .iter()
-            .map(|c| api.get_store(auth, dt_api::models::CurrencyType::Marks, c))
-            .collect::<FuturesOrdered<_>>()
-            .collect::<Vec<_>>();
+        let (marks_store, credits_store) = tokio::join!(
+            Self::fetch_stores(
+                api,
+                auth,
+                CurrencyType::Marks,
+                &summary.characters,
+                personal
+            ),
+            Self::fetch_stores(
+                api,
+                auth,
+                CurrencyType::Credits,
+                &summary.characters,
+                personal
+            )
+        );
 
-        let credits_store = summary
-            .characters
+        let account_data = Self::new(Some(summary), marks_store, credits_store, master_data);
+        if let Some(materials) = materials {
+            account_data.materials.set(materials);
+        }
+        account_data
+    }
+
+    /// Fetch the stores of `characters` for `currency_type`, leaving out
+    /// those that failed.
+    async fn fetch_stores(
+        api: &Upstream,
+        auth: &Auth,
+        currency_type: CurrencyType,
+        characters: &[Character],
+        personal: bool,
+    ) -> HashMap<CharacterId, Store> {
+        let requests = StoreRequest::plan(characters, personal);
+        let stores = requests
             .iter()
-            .map(|c| api.get_store(auth, dt_api::models::CurrencyType::Credits, c))
+            .map(|request| api.get_requested_store(auth, currency_type, request))
             .collect::<FuturesOrdered<_>>()
-            .collect::<Vec<_>>();
+            .collect::<Vec<_>>()
+            .await;
+        let mut fetched = HashMap::new();
+        for (request, store) in requests.iter().zip(stores) {
+            match store {
+                Ok(store) => fetched.extend(
+                    request
+                        .characters()
+                        .iter()
+                        .map(|character| (character.id, store.clone())),
+                ),
+                Err(e) => {
+                    error!("Failed to get {} store: {}", currency_type, e);
+                    api.timeline().record_failure(auth.sub, Resource::Store, &e);
+                }
+            }
+        }
+        fetched
+    }
 
-        let (marks_store, credits_store) = tokio::join!(marks_store, credits_store);
+    /// Cached stores for `currency_type`, keyed by character.
+    pub fn stores(&self, currency_type: CurrencyType) -> &RwLock<Stores> {
+        match currency_type {
+            CurrencyType::Marks => &self.marks_store,
+            CurrencyType::Credits => &self.credits_store,
+        }
+    }
 
-        let marks_store = summary
-            .characters
-            .iter()
-            .zip(marks_store.into_iter())
-            .filter_map(|(c, s)| match s {
-                Ok(s) => Some((c.id, s)),
-                Err(e) => {
-                    error!("Failed to get marks store: {}", e);
-                    None
+    /// Seed account data from an exported bundle.
+    ///
+    /// The data is treated as freshly fetched, so it is served until the
+    /// normal refresh interval elapses or the account is fetched again.
+    pub fn from_bundle(bundle: AccountBundle) -> Self {
+        Self::new(
+            bundle.summary,
+            bundle.marks_store,
+            bundle.credits_store,
+            bundle.master_data,
+        )
+    }
+
+    /// Fill sections that failed to fetch with the data cached in `previous`.
+    #[instrument(skip_all)]
+    pub async fn fill_missing(&self, previous: &AccountData) {
+        if let Some(summary) = previous.summary.peek() {
+            self.summary.set_if_empty(summary);
+        }
+        if let Some(master_data) = previous.master_data.peek() {
+            self.master_data.set_if_empty(master_data);
+        }
+        if let Some(materials) = previous.materials.peek() {
+            self.materials.set_if_empty(materials);
+        }
+        if let Some(item_catalog) = previous.item_catalog.peek() {
+            self.item_catalog.set_if_empty(item_catalog);
+        }
+        for (stores, previous) in [
+            (&self.marks_store, &previous.marks_store),
+            (&self.credits_store, &previous.credits_store),
+        ] {
+            stores.write().await.fill_missing(&*previous.read().await);
+        }
+        let mut inventories = self.inventories.write().await;
+        for (id, inventory) in previous.inventories.read().await.iter() {
+            inventories.entry(*id).or_insert_with(|| inventory.clone());
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn bundle(&self, id: AccountId) -> AccountBundle {
+        AccountBundle {
+            id,
+            exported_at: Utc::now(),
+            last_updated: self.last_updated,
+            summary: self.summary.peek().as_deref().cloned(),
+            master_data: self.master_data.peek(),
+            marks_store: self.marks_store.read().await.to_map(),
+            credits_store: self.credits_store.read().await.to_map(),
+        }
+    }
+
+    /// Collect the offers in the cached stores that match `filter`.
+    #[instrument(skip(self, filter))]
+    pub async fn find_offers(
+        &self,
+        account_id: AccountId,
+        filter: impl Fn(&Offer) -> bool,
+    ) -> Vec<OfferMatch> {
+        let mut matches = Vec::new();
+        for (currency_type, stores) in [
+            (CurrencyType::Marks, &self.marks_store),
+            (CurrencyType::Credits, &self.credits_store),
+        ] {
+            for (character_id, store) in stores.read().await.iter() {
+                for offer in store.public().iter().chain(store.personal()) {
+                    if filter(offer) {
+                        matches.push(OfferMatch {
+                            account_id,
+                            character_id,
+                            character_name: None,
+                            currency_type,
+                            offer: offer.clone(),
+                        });
+                    }
                 }
-            })
-            .collect::<HashMap<CharacterId, Store>>();
+            }
+        }
+        if !matches.is_empty() {
+            if let Some(summary) = self.summary.peek() {
+                for offer_match in &mut matches {
+                    offer_match.character_name = summary
+                        .characters
+                        .iter()
+                        .find(|c| c.id == offer_match.character_id)
+                        .map(|c| c.name.clone());
+                }
+            }
+        }
+        matches
+    }
 
-        let credits_store = summary
-            .characters
-            .iter()
-            .zip(credits_store.into_iter())
-            .filter_map(|(c, s)| match s {
-                Ok(s) => Some((c.id, s)),
-                Err(e) => {
-                    error!("Failed to get credits store: {}", e);
-                    None
+    #[instrument(skip(self))]
+    pub async fn status(&self) -> AccountStatus {
+        let summary = self.summary.peek();
+        let store_status = |stores: &Stores| match summary.as_ref() {
+            Some(summary) => {
+                let cached = summary
+                    .characters
+                    .iter()
+                    .filter(|c| stores.contains_key(&c.id))
+                    .count();
+                if cached == summary.characters.len() {
+                    SectionStatus::Ok
+                } else if cached == 0 {
+                    SectionStatus::Missing
+                } else {
+                    SectionStatus::Partial
                 }
-            })
-            .collect::<HashMap<CharacterId, Store>>();
+            }
+            None if stores.is_empty() => SectionStatus::Missing,
+            None => SectionStatus::Partial,
+        };
+        let present = |present: bool| {
+            if present {
+                SectionStatus::Ok
+            } else {
+                SectionStatus::Missing
+            }
+        };
+        AccountStatus {
+            summary: present(summary.is_some()),
+            master_data: present(self.master_data.is_cached()),
+            marks_store: store_status(&*self.marks_store.read().await),
+            credits_store: store_status(&*self.credits_store.read().await),
+        }
+    }
+}
 
-        let master_data = api.get_master_data(auth).await?;
+/// Characters created or deleted in game, found by comparing a refreshed
+/// summary with the cached one.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CharacterChanges {
+    pub account_id: AccountId,
+    pub added: Vec<Character>,
+    pub removed: Vec<Character>,
+}
 
-        Ok(Self::new(summary, marks_store, credits_store, master_data))
+impl CharacterChanges {
+    fn between(account_id: AccountId, previous: &Summary, current: &Summary) -> Self {
+        let missing_from = |summary: &Summary, characters: &[Character]| {
+            characters
+                .iter()
+                .filter(|character| !summary.characters.iter().any(|c| c.id == character.id))
+                .cloned()
+                .collect()
+        };
+        Self {
+            account_id,
+            added: missing_from(previous, &current.characters),
+            removed: missing_from(current, &previous.characters),
+        }
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// How many character changes subscribers may fall behind by.
+const CHANGES_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Accounts {
+    data: Arc<RwLock<HashMap<AccountId, AccountData>>>,
+    changes: broadcast::Sender<CharacterChanges>,
+    clock: ErasedClock,
 }
 
-#[derive(Debug, Clone, Default)]
-pub(crate) struct Accounts(Arc<RwLock<HashMap<AccountId, AccountData>>>);
+impl Default for Accounts {
+    fn default() -> Self {
+        Self::with_clock(SystemClock)
+    }
+}
 
 impl Accounts {
+    /// Accounts whose TTLs and refreshes are timed by `clock`.
+    pub fn with_clock(clock: impl Into<ErasedClock>) -> Self {
+        Self {
+            data: Default::default(),
+            changes: broadcast::channel(CHANGES_CAPACITY).0,
+            clock: clock.into(),
+        }
+    }
+
+    /// The clock that cache TTLs, rotations and refreshes are checked
+    /// against.
+    pub fn clock(&self) -> &ErasedClock {
+        &self.clock
+    }
+
     #[instrument]
     pub async fn get(&self, id: &AccountId) -> Option<AccountData> {
-        self.0.read().await.get(id).cloned()
+        self.data.read().await.get(id).cloned()
+    }
+
+    #[instrument]
+    pub async fn list(&self) -> Vec<(AccountId, AccountData)> {
+        self.data
+            .read()
+            .await
+            .iter()
+            .map(|(id, data)| (*id, data.clone()))
+            .collect()
     }
 
     #[instrument]
     pub async fn insert(&self, id: AccountId, data: AccountData) {
-        self.0.write().await.insert(id, data);
+        self.data.write().await.insert(id, data);
+    }
+
+    /// Subscribe to the characters created or deleted in game, as found when
+    /// refreshing summaries.
+    pub fn subscribe(&self) -> broadcast::Receiver<CharacterChanges> {
+        self.changes.subscribe()
+    }
+
+    /// Fetch the summary and crafting materials of a cached account and cache
+    /// them. Failing to get the materials keeps the cached ones.
+    ///
+    /// Concurrent refreshes of an account fetch once. Stores and inventories
+    /// of deleted characters are dropped from the cache, and the changes are
+    /// sent to subscribers.
+    #[instrument(skip(self, api, auth), fields(sub = ?auth.sub))]
+    pub async fn refresh_summary(&self, api: &Upstream, auth: &Auth) -> Result<Cached<Summary>> {
+        let account_data = self
+            .get(&auth.sub)
+            .await
+            .context("Account data not found")?;
+        let mut previous = None;
+        let summary = account_data
+            .summary
+            .refresh(|| async {
+                let (summary, wallets) = tokio::join!(api.get_summary(auth), api.get_wallets(auth));
+                match wallets {
+                    Ok(wallets) => {
+                        account_data.materials.set(wallets.materials());
+                    }
+                    Err(e) => error!(error = %e, "Failed to get wallets"),
+                }
+                let summary = match summary {
+                    Ok(summary) => summary,
+                    Err(e) => {
+                        api.timeline()
+                            .record_failure(auth.sub, Resource::Summary, &e);
+                        return Err(e).context("Failed to get summary");
+                    }
+                };
+                api.timeline()
+                    .record(auth.sub, TimelineEvent::SummaryRefreshed);
+                previous = account_data.summary.peek();
+                anyhow::Ok(Cached::new(summary))
+            })
+            .await?;
+        self.update_timestamp(&auth.sub).await;
+        // Only the refresh that fetched has the summary it replaced.
+        let Some(previous) = previous else {
+            return Ok(summary);
+        };
+        let changes = CharacterChanges::between(auth.sub, &previous, &summary);
+        if changes.is_empty() {
+            return Ok(summary);
+        }
+        info!(
+            added = changes.added.len(),
+            removed = changes.removed.len(),
+            "Characters changed"
+        );
+        for character in &changes.removed {
+            for currency_type in [CurrencyType::Marks, CurrencyType::Credits] {
+                account_data
+                    .stores(currency_type)
+                    .write()
+                    .await
+                    .remove(&character.id);
+            }
+            account_data.inventories.write().await.remove(&character.id);
+        }
+        // Only fails if nobody is subscribed.
+        let _ = self.changes.send(changes);
+        Ok(summary)
+    }
+
+    /// Check the characters of a cached account without fetching its whole
+    /// summary, and refresh the summary if they changed.
+    ///
+    /// Returns whether they changed.
+    #[instrument(skip(self, api, auth), fields(sub = ?auth.sub))]
+    pub async fn confirm_characters(&self, api: &Upstream, auth: &Auth) -> Result<bool> {
+        let account_data = self
+            .get(&auth.sub)
+            .await
+            .context("Account data not found")?;
+        let summary = account_data.summary.peek();
+        let characters = api
+            .get_characters(auth, summary.as_deref())
+            .await
+            .context("Failed to get characters")?;
+        let ids = |characters: &[Character]| {
+            let mut ids: Vec<_> = characters.iter().map(|c| c.id).collect();
+            ids.sort_by_key(|id| id.0);
+            ids
+        };
+        let unchanged =
+            summary.is_some_and(|summary| ids(&summary.characters) == ids(&characters.characters));
+        if unchanged {
+            return Ok(false);
+        }
+        info!("Characters changed, refreshing summary");
+        self.refresh_summary(api, auth).await?;
+        Ok(true)
+    }
+
+    /// Refresh the summary of an account if `character` levelled up since it
+    /// was fetched, going by the level the personal offers of `store` were
+    /// made for.
+    ///
+    /// Returns whether it levelled up.
+    #[instrument(skip(self, api, auth, character, store), fields(sub = ?auth.sub, character.id = %character.id))]
+    pub async fn refresh_on_level_up(
+        &self,
+        api: &Upstream,
+        auth: &Auth,
+        character: &Character,
+        store: &Store,
+    ) -> Result<bool> {
+        let level = match store.character_level() {
+            Some(level) if level > character.level => level,
+            _ => return Ok(false),
+        };
+        info!(
+            from = character.level,
+            to = level,
+            "Character levelled up, refreshing summary"
+        );
+        self.refresh_summary(api, auth).await?;
+        Ok(true)
+    }
+
+    /// Evict the least recently served stores until the cached stores fit in
+    /// `budget` bytes, measured by the size of their JSON, and record the
+    /// cache size. Evicted stores are fetched again when next requested.
+    #[instrument(skip(self))]
+    pub async fn evict_stores(&self, budget: Option<u64>) {
+        let mut entries = Vec::new();
+        let mut size: u64 = 0;
+        for (id, account_data) in self.list().await {
+            for currency_type in [CurrencyType::Marks, CurrencyType::Credits] {
+                let stores = account_data.stores(currency_type).read().await;
+                size += stores.size() as u64;
+                entries.extend(
+                    stores.entries().map(|(character_id, store)| {
+                        (store.clone(), id, currency_type, character_id)
+                    }),
+                );
+            }
+        }
+        let mut count = entries.len();
+        if let Some(budget) = budget.filter(|budget| size > *budget) {
+            entries.sort_by_key(|(store, ..)| store.last_served());
+            let mut evicted = 0;
+            for (store, id, currency_type, character_id) in entries {
+                if size <= budget {
+                    break;
+                }
+                let Some(account_data) = self.get(&id).await else {
+                    continue;
+                };
+                let mut stores = account_data.stores(currency_type).write().await;
+                let before = stores.size();
+                // Keep stores replaced since they were listed.
+                if stores.remove_if_cached(&character_id, &store) {
+                    size -= (before - stores.size()) as u64;
+                    count -= 1;
+                    evicted += 1;
+                }
+            }
+            info!(
+                evicted,
+                size, budget, "Evicted least recently served stores"
+            );
+            metrics::counter!("dt_fetcher_cache_evictions_total").increment(evicted);
+        }
+        metrics::gauge!("dt_fetcher_cache_store_bytes").set(size as f64);
+        metrics::gauge!("dt_fetcher_cache_stores").set(count as f64);
     }
 
     #[instrument]
     pub async fn update_timestamp(&self, id: &AccountId) {
-        if let Some(account_data) = self.0.write().await.get_mut(id) {
-            account_data.last_updated = Utc::now();
+        if let Some(account_data) = self.data.write().await.get_mut(id) {
+            account_data.last_updated = self.clock.now();
         }
     }
 
     #[instrument]
     pub async fn timestamp(&self, id: &AccountId) -> Option<DateTime<Utc>> {
-        if let Some(account_data) = self.0.read().await.get(id) {
+        if let Some(account_data) = self.data.read().await.get(id) {
             return Some(account_data.last_updated);
         }
         None
     }
 }
+
+/// How often the cache size is recorded and the budget enforced, besides
+/// whenever a store is cached.
+const EVICTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Periodically enforces the cache budget, so that stores cached by the auth
+/// manager and budget changes are accounted for, and records the contents of
+/// the cached stores.
+pub(crate) struct CacheMonitor {
+    accounts: Accounts,
+    config: watch::Receiver<Config>,
+    store_metrics: StoreMetrics,
+}
+
+impl CacheMonitor {
+    pub fn new(accounts: Accounts, config: watch::Receiver<Config>) -> Self {
+        Self {
+            accounts,
+            config,
+            store_metrics: StoreMetrics::default(),
+        }
+    }
+
+    #[instrument(skip_all)]
+    pub async fn start(mut self, token: CancellationToken) -> Result<()> {
+        let mut interval = tokio::time::interval(EVICTION_INTERVAL);
+        loop {
+            tokio::select! {
+                // The config channel closes during shutdown, which isn't an error.
+                biased;
+                _ = token.cancelled() => {
+                    info!("Shutting down cache monitor");
+                    return Ok(());
+                }
+                res = self.config.changed() => res?,
+                _ = interval.tick() => {}
+            }
+            let budget = self.config.borrow_and_update().cache_budget_bytes();
+            self.accounts.evict_stores(budget).await;
+            self.store_metrics
+                .record(StoreGauges::collect(&self.accounts).await);
+        }
+    }
+}
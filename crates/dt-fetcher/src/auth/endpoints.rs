@@ -1,38 +1,113 @@
 use axum::{
     extract::{Path, State},
-    http::StatusCode,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
+use chrono::{DateTime, Utc};
 use dt_api::models::AccountId;
-use tracing::{error, instrument};
+use serde::Serialize;
+use tokio::sync::watch;
+use tracing::{error, info, instrument, warn};
 
-use super::{AuthData, AuthStorage};
+use super::{AddPending, AuthData, NotServed, QueueFull, ENQUEUE_TIMEOUT};
+use crate::config::Config;
 
-#[instrument(skip(state))]
-pub(crate) async fn put_auth<T: AuthStorage>(
+#[instrument(skip(state, config))]
+pub(crate) async fn put_auth(
     Path(id): Path<AccountId>,
-    State(state): State<AuthData<T>>,
+    State(state): State<AuthData>,
+    State(config): State<watch::Receiver<Config>>,
     Json(auth): Json<dt_api::Auth>,
-) -> StatusCode {
+) -> Response {
+    if auth.sub != id {
+        warn!(sub = %auth.sub, "Auth is for another account");
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    if let Some(base_url) = &auth.base_url {
+        if !config.borrow().allows_base_url(base_url) {
+            warn!(base_url, "Base URL is not in allowedBaseUrls");
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+    }
     let result = state.contains(&id);
     if let Ok(true) = result {
-        return StatusCode::OK;
+        if !state.needs_reauth(&id).await {
+            return StatusCode::OK.into_response();
+        }
+        info!("Replacing auth with rejected refresh token");
     }
     if let Err(e) = result {
         error!("Failed to check if auth exists: {}", e);
-        return StatusCode::INTERNAL_SERVER_ERROR;
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
     }
     if let Err(e) = state.add_auth(auth).await {
+        if e.is::<QueueFull>() {
+            return queue_full();
+        }
+        if e.is::<NotServed>() {
+            warn!("{}", e);
+            return StatusCode::MISDIRECTED_REQUEST.into_response();
+        }
+        if e.is::<AddPending>() {
+            warn!("{}", e);
+            return StatusCode::CONFLICT.into_response();
+        }
         error!("Failed to add auth: {}", e);
-        return StatusCode::INTERNAL_SERVER_ERROR;
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    StatusCode::CREATED.into_response()
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Refreshed {
+    refresh_at: DateTime<Utc>,
+}
+
+#[instrument(skip(state))]
+pub(crate) async fn refresh_auth(
+    Path(id): Path<AccountId>,
+    State(state): State<AuthData>,
+) -> Response {
+    match state.contains(&id) {
+        Ok(true) => {}
+        Ok(false) => {
+            error!("Auth not found");
+            return StatusCode::NOT_FOUND.into_response();
+        }
+        Err(e) => {
+            error!("Failed to check if auth exists: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
     }
-    return StatusCode::CREATED;
+    match state.refresh(id).await {
+        Ok(Some(refresh_at)) => Json(Refreshed { refresh_at }).into_response(),
+        Ok(None) => {
+            error!("Auth needs reauth");
+            StatusCode::CONFLICT.into_response()
+        }
+        Err(e) if e.is::<QueueFull>() => queue_full(),
+        Err(e) => {
+            error!("Failed to refresh auth: {}", e);
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+    }
+}
+
+fn queue_full() -> Response {
+    let retry_after = ENQUEUE_TIMEOUT.as_secs().to_string();
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER, retry_after)],
+    )
+        .into_response()
 }
 
 #[instrument(skip(state))]
-pub(crate) async fn get_auth<T: AuthStorage>(
+pub(crate) async fn get_auth(
     Path(id): Path<AccountId>,
-    State(state): State<AuthData<T>>,
+    State(state): State<AuthData>,
 ) -> StatusCode {
     let result = state.contains(&id);
     if let Ok(true) = result {
@@ -45,3 +120,86 @@ pub(crate) async fn get_auth<T: AuthStorage>(
         StatusCode::NOT_FOUND
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        account::Accounts,
+        auth::AuthManager,
+        coordination::Coordinator,
+        history::{History, InMemoryHistoryStorage},
+        notify::Notifiers,
+        settings::{InMemorySettingsStorage, Settings},
+        upstream::Upstream,
+    };
+
+    fn manager(config: &watch::Receiver<Config>) -> AuthManager {
+        let api = Upstream::new(
+            dt_api::Api::new(),
+            Coordinator::local(None),
+            History::new(InMemoryHistoryStorage::default().into()),
+        );
+        AuthManager::new(
+            api,
+            Accounts::default(),
+            Notifiers::new(config.clone()),
+            Settings::new(InMemorySettingsStorage::default().into()),
+            config.clone(),
+        )
+    }
+
+    fn auth(base_url: Option<&str>) -> dt_api::Auth {
+        serde_json::from_value(serde_json::json!({
+            "AccessToken": "access",
+            "AccountName": "account",
+            "ExpiresIn": 3600,
+            "RefreshToken": "refresh",
+            "Sub": "00000000-0000-0000-0000-000000000001",
+            "BaseUrl": base_url,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn rejects_base_urls_not_allowed() {
+        let (_tx, config) = watch::channel(Config {
+            allowed_base_urls: vec!["https://backend.example.com".to_string()],
+            ..Config::default()
+        });
+        let manager = manager(&config);
+        let put = |auth: dt_api::Auth| {
+            put_auth(
+                Path(auth.sub),
+                State(manager.auth_data()),
+                State(config.clone()),
+                Json(auth),
+            )
+        };
+
+        let response = put(auth(Some("http://169.254.169.254"))).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(!manager.auth_data().contains(&auth(None).sub).unwrap());
+
+        let response = put(auth(Some("https://backend.example.com"))).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn rejects_auth_for_another_account() {
+        let (_tx, config) = watch::channel(Config::default());
+        let manager = manager(&config);
+        let put = |id: AccountId| {
+            put_auth(
+                Path(id),
+                State(manager.auth_data()),
+                State(config.clone()),
+                Json(auth(None)),
+            )
+        };
+
+        let other = AccountId(uuid::Uuid::from_u128(2));
+        assert_eq!(put(other).await.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(put(auth(None).sub).await.status(), StatusCode::CREATED);
+    }
+}
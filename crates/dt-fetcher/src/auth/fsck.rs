@@ -0,0 +1,44 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use tracing::{error, info, instrument};
+
+use super::{storage::is_corrupt, SledDbAuthStorage};
+
+/// Validate the auth database at `path`.
+///
+/// With `repair`, a corrupt database is restored from the newest usable
+/// backup and invalid records are quarantined; otherwise any problem is an
+/// error. A database that can't be opened for another reason, e.g. because
+/// dt-fetcher is running, is never touched.
+#[instrument]
+pub(crate) fn fsck(path: &Path, repair: bool) -> Result<()> {
+    if !path.exists() {
+        bail!("No database at {}", path.display());
+    }
+    let storage = match SledDbAuthStorage::open(path) {
+        Ok(storage) => storage,
+        Err(e) if repair && is_corrupt(&e) => {
+            error!(error = ?e, "Database is unreadable; recovering");
+            SledDbAuthStorage::recover(path)?
+        }
+        Err(e) if is_corrupt(&e) => {
+            return Err(e.context("Database is unreadable; run with --repair to restore a backup"))
+        }
+        Err(e) => return Err(e.context("Failed to open database; is it in use?")),
+    };
+    let invalid = storage.invalid_records()?;
+    if invalid.is_empty() {
+        info!("No invalid auth records found");
+        return Ok(());
+    }
+    if !repair {
+        bail!(
+            "Found {} invalid auth records; run with --repair to quarantine them",
+            invalid.len()
+        );
+    }
+    storage.quarantine(&invalid)?;
+    info!(count = invalid.len(), "Quarantined invalid auth records");
+    Ok(())
+}
@@ -1,37 +1,104 @@
 use std::{
-    collections::BinaryHeap,
-    time::{Duration, SystemTime},
+    collections::{BinaryHeap, HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
-use anyhow::{anyhow, bail, Context as _, Result};
+use anyhow::{anyhow, Context as _, Result};
 use chrono::{DateTime, Utc};
 use dt_api::{models::AccountId, Auth};
 use futures_util::future::{self, Either};
-use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::{
+    sync::{
+        mpsc::{channel, error::SendTimeoutError, Receiver, Sender},
+        oneshot, watch, RwLock,
+    },
+    time::Instant,
+};
 use tokio_util::sync::CancellationToken;
 use tracing::{error, info, instrument, warn};
 
-use crate::account::{AccountData, Accounts};
+use crate::{
+    account::{AccountData, Accounts},
+    clock::Clock,
+    config::Config,
+    notify::{Event, Notifiers},
+    settings::Settings,
+    timeline::{Resource, TimelineEvent},
+    upstream::Upstream,
+};
 
-use super::AuthStorage;
+use super::{AuthStorage, ErasedAuthStorage, InMemoryAuthStorage};
 
 const REFRESH_BUFFER: Duration = Duration::from_secs(300);
+/// How long an instance may take to refresh an auth before another takes over.
+const LEASE_TTL: Duration = Duration::from_secs(60);
+/// How long to wait before checking for an auth refreshed by another instance.
+const FOLLOWER_RETRY: Duration = Duration::from_secs(60);
+/// Capacity of the command queue of the auth manager.
+const QUEUE_CAPACITY: usize = 100;
+/// How long to wait for room in a full command queue before giving up.
+pub(crate) const ENQUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait before retrying a refresh that failed for reasons other
+/// than a rejected refresh token, unless the auth server asks for longer.
+const REFRESH_RETRY: Duration = Duration::from_secs(60);
 
+/// A scheduled auth refresh.
+///
+/// `refresh_at` is the wall clock time stored with the auth, while refreshes
+/// are scheduled by the monotonic `deadline`, so adjustments of the system
+/// clock don't move refreshes that are already scheduled.
 #[derive(PartialEq, Eq)]
 struct RefreshAuth {
     id: AccountId,
     refresh_at: DateTime<Utc>,
+    deadline: Instant,
 }
 
 impl RefreshAuth {
-    fn new(auth: &Auth) -> Self {
+    fn new(auth: &Auth, clock: &impl Clock) -> Self {
+        Self::new_at(auth, clock.now(), Instant::now())
+    }
+
+    /// Schedule the refresh of `auth` as of the wall clock time `now` and the
+    /// monotonic time `instant`.
+    fn new_at(auth: &Auth, now: DateTime<Utc>, instant: Instant) -> Self {
+        let refresh_at = auth
+            .refresh_at
+            .unwrap_or_else(|| saturating_add(now, auth.expires_in.saturating_sub(REFRESH_BUFFER)));
         Self {
             id: auth.sub,
-            refresh_at: auth.refresh_at.unwrap_or(
-                DateTime::from(SystemTime::now()) + auth.expires_in.saturating_sub(REFRESH_BUFFER),
-            ),
+            refresh_at,
+            deadline: instant + duration_until(refresh_at, now),
         }
     }
+
+    /// Schedule a refresh of the account after `delay`.
+    fn after(id: AccountId, delay: Duration, clock: &impl Clock) -> Self {
+        Self::after_at(id, delay, clock.now(), Instant::now())
+    }
+
+    fn after_at(id: AccountId, delay: Duration, now: DateTime<Utc>, instant: Instant) -> Self {
+        Self {
+            id,
+            refresh_at: saturating_add(now, delay),
+            deadline: instant + delay,
+        }
+    }
+}
+
+/// Time from `now` until `at`, or zero if `at` has passed, e.g. because the
+/// clock jumped forward.
+fn duration_until(at: DateTime<Utc>, now: DateTime<Utc>) -> Duration {
+    (at - now).to_std().unwrap_or(Duration::ZERO)
+}
+
+/// `now` plus `delay`, or the latest representable time if that is later.
+fn saturating_add(now: DateTime<Utc>, delay: Duration) -> DateTime<Utc> {
+    chrono::Duration::from_std(delay)
+        .ok()
+        .and_then(|delay| now.checked_add_signed(delay))
+        .unwrap_or(DateTime::<Utc>::MAX_UTC)
 }
 
 impl PartialOrd for RefreshAuth {
@@ -42,53 +109,117 @@ impl PartialOrd for RefreshAuth {
 
 impl Ord for RefreshAuth {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        other.refresh_at.cmp(&self.refresh_at)
+        other.deadline.cmp(&self.deadline)
     }
 }
 
 #[derive(Debug)]
 pub(crate) enum AuthCommand {
     NewAuth(Auth),
+    /// Refresh the auth of an account now, replying with its next refresh time.
+    Refresh(AccountId, oneshot::Sender<Result<Option<DateTime<Utc>>>>),
+    /// Reply as soon as the command is processed, to check that the manager
+    /// is responsive.
+    Ping(oneshot::Sender<()>),
+}
+
+/// The auth manager's command queue stayed full for [`ENQUEUE_TIMEOUT`].
+#[derive(Debug)]
+pub(crate) struct QueueFull;
+
+impl std::fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Auth manager queue is full")
+    }
 }
 
+impl std::error::Error for QueueFull {}
+
+/// The account isn't among those served by this instance.
+#[derive(Debug)]
+pub(crate) struct NotServed(pub AccountId);
+
+impl std::fmt::Display for NotServed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Account {} isn't served by this instance", self.0 .0)
+    }
+}
+
+impl std::error::Error for NotServed {}
+
+/// A different auth for the account is already queued to be added.
 #[derive(Debug)]
-pub(crate) struct AuthManager<T: AuthStorage + Clone> {
-    api: dt_api::Api,
-    auth_data: AuthData<T>,
+pub(crate) struct AddPending(pub AccountId);
+
+impl std::fmt::Display for AddPending {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Another auth for account {} is being added", self.0 .0)
+    }
+}
+
+impl std::error::Error for AddPending {}
+
+/// Clones share the command queue, so a clone can take over from a manager
+/// that panicked.
+#[derive(Debug, Clone)]
+pub(crate) struct AuthManager {
+    api: Upstream,
+    auth_data: AuthData,
     accounts: Accounts,
-    rx: Receiver<AuthCommand>,
+    notifiers: Notifiers,
+    settings: Settings,
+    config: watch::Receiver<Config>,
+    rx: Arc<tokio::sync::Mutex<Receiver<AuthCommand>>>,
 }
 
-impl<T: AuthStorage + Default + Clone> AuthManager<T> {
+impl AuthManager {
+    /// A manager keeping auths in memory.
     #[instrument(skip_all)]
-    pub fn new(api: dt_api::Api, accounts: Accounts) -> Self {
-        let (tx, rx) = channel(100);
-        AuthManager {
-            auth_data: AuthData {
-                auths: Default::default(),
-                tx,
-            },
-            rx,
+    pub fn new(
+        api: Upstream,
+        accounts: Accounts,
+        notifiers: Notifiers,
+        settings: Settings,
+        config: watch::Receiver<Config>,
+    ) -> Self {
+        Self::new_with_storage(
             api,
             accounts,
-        }
+            InMemoryAuthStorage::default(),
+            notifiers,
+            settings,
+            config,
+        )
     }
-}
 
-impl<T: AuthStorage + Clone> AuthManager<T> {
     #[instrument(skip_all)]
-    pub fn new_with_storage(api: dt_api::Api, accounts: Accounts, storage: T) -> Self {
-        let (tx, rx) = channel(100);
+    pub fn new_with_storage(
+        api: Upstream,
+        accounts: Accounts,
+        storage: impl Into<ErasedAuthStorage>,
+        notifiers: Notifiers,
+        settings: Settings,
+        config: watch::Receiver<Config>,
+    ) -> Self {
+        let (tx, rx) = channel(QUEUE_CAPACITY);
         AuthManager {
-            auth_data: AuthData { auths: storage, tx },
-            rx,
+            auth_data: AuthData {
+                auths: storage.into(),
+                tx,
+                needs_reauth: Default::default(),
+                pending: Default::default(),
+            },
+            rx: Arc::new(tokio::sync::Mutex::new(rx)),
             api,
             accounts,
+            notifiers,
+            settings,
+            config,
         }
     }
 
     #[instrument(skip_all)]
-    pub fn auth_data(&self) -> AuthData<T> {
+    pub fn auth_data(&self) -> AuthData {
         self.auth_data.clone()
     }
 
@@ -99,84 +230,127 @@ impl<T: AuthStorage + Clone> AuthManager<T> {
         auth: Auth,
     ) -> Result<()> {
         info!(auth = ?auth, "Adding new auth");
-        if self.auth_data.contains(&auth.sub)? {
-            error!(auth = ?auth, "Auth already exists");
-            bail!("Auth already exists");
+        if self.auth_data.needs_reauth(&auth.sub).await {
+            info!(sub = ?auth.sub, "Replacing rejected auth; resuming refreshes");
+        } else if self.auth_data.contains(&auth.sub)? {
+            warn!(sub = ?auth.sub, "Auth already exists; ignoring");
+            return Ok(());
         }
-        Self::insert_new_refresh_auth(auths, &auth).await;
-        Self::populate_account_data(&self.api, &mut self.accounts, &auth).await?;
-        if let Err(e) = self.auth_data.insert(auth.sub, auth).await {
+        Self::insert_new_refresh_auth(auths, &auth, self.accounts.clock()).await;
+        let personal = self.personal_stores(auth.sub);
+        Self::populate_account_data(&self.api, &mut self.accounts, &auth, personal).await;
+        // Lets the instance owning the account in a cluster claim it.
+        if let Err(e) = self.api.coordinator().publish_auth(&auth).await {
+            warn!(error = %e, "Failed to publish auth");
+        }
+        let sub = auth.sub;
+        if let Err(e) = self.auth_data.insert(sub, auth).await {
             error!(error = %e, "Failed to insert auth");
             Err(e).context("Failed to insert auth")?;
         }
+        self.auth_data.needs_reauth.write().await.remove(&sub);
 
         Ok(())
     }
 
-    async fn insert_new_refresh_auth(auths: &mut BinaryHeap<RefreshAuth>, auth: &Auth) {
-        auths.push(RefreshAuth::new(auth));
+    async fn insert_new_refresh_auth(
+        auths: &mut BinaryHeap<RefreshAuth>,
+        auth: &Auth,
+        clock: &impl Clock,
+    ) {
+        auths.push(RefreshAuth::new(auth, clock));
+    }
+
+    fn personal_stores(&self, account: AccountId) -> bool {
+        let default = self.config.borrow().personal_stores;
+        self.settings.personal_stores(account, default)
     }
 
     #[instrument(skip(api, accounts))]
     async fn populate_account_data(
-        api: &dt_api::Api,
+        api: &Upstream,
         accounts: &mut Accounts,
         auth: &Auth,
-    ) -> Result<()> {
-        if let Ok(account) = AccountData::fetch(api, auth).await {
-            info!(sub = ?auth.sub, "Adding new account data");
-            accounts.insert(auth.sub, account).await;
-        } else {
-            error!(auth = ?auth, "Failed to fetch account data");
-            bail!("Failed to fetch account data");
+        personal: bool,
+    ) {
+        let account = AccountData::fetch(api, auth, personal).await;
+        if let Some(previous) = accounts.get(&auth.sub).await {
+            account.fill_missing(&previous).await;
         }
-        Ok(())
+        let status = account.status().await;
+        info!(sub = ?auth.sub, status = ?status, "Adding new account data");
+        accounts.insert(auth.sub, account).await;
     }
 
     #[instrument(skip_all)]
     pub async fn start(mut self, token: CancellationToken) -> Result<()> {
+        let mut rx = self.rx.clone().lock_owned().await;
+        // A previous manager may have panicked while adding an auth, which
+        // would otherwise be deduplicated forever.
+        self.auth_data
+            .pending
+            .lock()
+            .expect("pending lock poisoned")
+            .clear();
         let mut auths: BinaryHeap<RefreshAuth> = BinaryHeap::new();
         for auth in self.auth_data.auths.iter() {
             match auth {
                 Ok((_, auth)) => {
                     if auth.expired(REFRESH_BUFFER) {
                         warn!(sub = ?auth.sub, "Auth expired, removing");
-                        self.auth_data.auths.remove(&auth.sub)?;
+                        if let Err(e) = self.auth_data.auths.remove(&auth.sub) {
+                            error!(sub = ?auth.sub, error = %e, "Failed to remove expired auth");
+                        }
                     } else {
                         info!(sub = ?auth.sub, "Adding auth");
-                        Self::insert_new_refresh_auth(&mut auths, &auth).await;
-                        Self::populate_account_data(&self.api, &mut self.accounts, &auth).await?;
+                        Self::insert_new_refresh_auth(&mut auths, &auth, self.accounts.clock())
+                            .await;
+                        let personal = self.personal_stores(auth.sub);
+                        Self::populate_account_data(&self.api, &mut self.accounts, &auth, personal)
+                            .await;
                     }
                 }
                 Err(e) => {
-                    error!(error = %e, "Failed to get auth");
+                    error!(error = ?e, "Failed to get auth; skipping");
                 }
             }
         }
         let mut shutdown = false;
         loop {
             let sleep = if let Some(refresh_auth) = auths.peek() {
-                let duration = (refresh_auth.refresh_at - DateTime::from(SystemTime::now()))
-                    .max(chrono::Duration::zero())
-                    .to_std()
-                    .expect("Duration was less than 0");
+                let duration = refresh_auth
+                    .deadline
+                    .saturating_duration_since(Instant::now());
                 info!(
                     duration = ?duration,
                     refresh_at = ?refresh_auth.refresh_at,
                     "Sleeping until next auth refresh");
-                Either::Left(tokio::time::sleep(
-                    (refresh_auth.refresh_at - DateTime::from(SystemTime::now()))
-                        .max(chrono::Duration::zero())
-                        .to_std()
-                        .expect("Duration was less than 0"),
-                ))
+                Either::Left(tokio::time::sleep_until(refresh_auth.deadline))
             } else {
                 info!("No auths, sleeping");
                 Either::Right(future::pending())
             };
             tokio::select! {
-                command = self.rx.recv() => match command {
-                    Some(AuthCommand::NewAuth(auth)) => self.insert_new_auth(&mut auths, auth).await?,
+                command = rx.recv() => match command {
+                    Some(AuthCommand::NewAuth(auth)) => {
+                        record_queue_depth(&self.auth_data.tx);
+                        let sub = auth.sub;
+                        let result = self.insert_new_auth(&mut auths, auth).await;
+                        self.auth_data.pending_done(&sub);
+                        result?
+                    }
+                    Some(AuthCommand::Refresh(id, reply)) => {
+                        record_queue_depth(&self.auth_data.tx);
+                        let result = self.refresh_now(&mut auths, id).await;
+                        if let Err(e) = &result {
+                            error!(sub = ?id, error = %e, "Failed to refresh auth");
+                        }
+                        let _ = reply.send(result);
+                    }
+                    Some(AuthCommand::Ping(reply)) => {
+                        record_queue_depth(&self.auth_data.tx);
+                        let _ = reply.send(());
+                    }
                     None => {
                         if shutdown {
                             info!("Auth manager channel closed");
@@ -189,7 +363,7 @@ impl<T: AuthStorage + Clone> AuthManager<T> {
                 _ = token.cancelled() => {
                     info!("Shutting down auth manager");
                     shutdown = true;
-                    self.rx.close();
+                    rx.close();
                 }
                 _ = sleep => {
                     if let Err(e) = self.refresh_auth(&mut auths).await {
@@ -203,44 +377,224 @@ impl<T: AuthStorage + Clone> AuthManager<T> {
     #[instrument(skip_all)]
     async fn refresh_auth(&mut self, auths: &mut BinaryHeap<RefreshAuth>) -> Result<()> {
         if let Some(refresh_auth) = auths.pop() {
-            if let Some(auth) = self.auth_data.get(refresh_auth.id)? {
-                info!(sub = ?refresh_auth.id, "Refreshing auth");
-                let mut auth = self
-                    .api
-                    .refresh_auth(&auth)
-                    .await
-                    .context("failed to refresh auth")?;
-                let refresh_auth = RefreshAuth::new(&auth);
-                auth.refresh_at = Some(refresh_auth.refresh_at);
-                info!(auth = ?auth, "Auth refreshed");
-                if let Err(e) = self.auth_data.insert(refresh_auth.id, auth).await {
-                    error!(error = %e, "Failed to insert auth, removing");
-                    self.auth_data.auths.remove(&refresh_auth.id)?;
-                    return Err(e);
+            self.refresh(auths, refresh_auth.id).await?;
+        }
+        Ok(())
+    }
+
+    /// Refresh the auth of the account now, replacing its scheduled refresh.
+    ///
+    /// Returns when the account is next due for a refresh, if it is scheduled.
+    #[instrument(skip(self, auths))]
+    async fn refresh_now(
+        &mut self,
+        auths: &mut BinaryHeap<RefreshAuth>,
+        id: AccountId,
+    ) -> Result<Option<DateTime<Utc>>> {
+        auths.retain(|refresh_auth| refresh_auth.id != id);
+        self.refresh(auths, id).await?;
+        Ok(auths
+            .iter()
+            .find(|refresh_auth| refresh_auth.id == id)
+            .map(|refresh_auth| refresh_auth.refresh_at))
+    }
+
+    async fn refresh(&mut self, auths: &mut BinaryHeap<RefreshAuth>, id: AccountId) -> Result<()> {
+        if let Some(auth) = self.auth_data.get(id)? {
+            let coordinator = self.api.coordinator().clone();
+            let leader = coordinator
+                .acquire_lease(id, LEASE_TTL)
+                .await
+                .unwrap_or_else(|e| {
+                    warn!(error = %e, "Failed to acquire lease, refreshing anyway");
+                    true
+                });
+            if !leader {
+                return self.adopt_auth(auths, auth).await;
+            }
+            info!(sub = ?id, "Refreshing auth");
+            let mut auth = match self.api.refresh_auth(&auth).await {
+                Ok(auth) => auth,
+                Err(dt_api::Error::RefreshAuth { status, .. }) if status.is_client_error() => {
+                    warn!(
+                        sub = ?id,
+                        status = %status,
+                        "Refresh token rejected; account needs reauth"
+                    );
+                    #[cfg(feature = "sentry")]
+                    crate::error_report::report_refresh_failure(
+                        &auth,
+                        &format!("Refresh token rejected with {status}"),
+                    );
+                    self.auth_data.needs_reauth.write().await.insert(id);
+                    self.api.timeline().record_failure(
+                        id,
+                        Resource::Auth,
+                        &format!("Refresh token rejected with {status}"),
+                    );
+                    self.notifiers
+                        .notify(&Event::NeedsReauth { account_id: id })
+                        .await;
+                    return Ok(());
                 }
+                Err(e) => {
+                    #[cfg(feature = "sentry")]
+                    crate::error_report::report_refresh_failure(&auth, &e);
+                    self.api.timeline().record_failure(id, Resource::Auth, &e);
+                    let delay = e
+                        .retry_after()
+                        .map_or(REFRESH_RETRY, |d| d.max(REFRESH_RETRY));
+                    auths.push(RefreshAuth::after(id, delay, self.accounts.clock()));
+                    return Err(e).context("failed to refresh auth");
+                }
+            };
+            let refresh_auth = RefreshAuth::new(&auth, self.accounts.clock());
+            auth.refresh_at = Some(refresh_auth.refresh_at);
+            info!(auth = ?auth, "Auth refreshed");
+            self.api.timeline().record(id, TimelineEvent::AuthRefreshed);
+            if let Err(e) = coordinator.publish_auth(&auth).await {
+                warn!(error = %e, "Failed to publish auth");
+            }
+            let lease_ttl = refresh_auth
+                .deadline
+                .saturating_duration_since(Instant::now())
+                + REFRESH_BUFFER;
+            if let Err(e) = coordinator.acquire_lease(id, lease_ttl).await {
+                warn!(error = %e, "Failed to renew lease");
+            }
+            if let Err(e) = self.auth_data.insert(id, auth).await {
+                error!(error = %e, "Failed to insert auth, removing");
+                self.auth_data.auths.remove(&id)?;
+                return Err(e);
+            }
+            auths.push(refresh_auth);
+        } else {
+            warn!(sub = ?id, "Auth not found, removing");
+            self.auth_data.auths.remove(&id)?;
+        }
+        Ok(())
+    }
+
+    /// Adopt the auth refreshed by the instance holding the lease for the account.
+    #[instrument(skip_all, fields(sub = ?auth.sub))]
+    async fn adopt_auth(&mut self, auths: &mut BinaryHeap<RefreshAuth>, auth: Auth) -> Result<()> {
+        match self.api.coordinator().latest_auth(auth.sub).await {
+            Ok(Some(latest)) if latest.refresh_at > auth.refresh_at => {
+                info!("Adopting auth refreshed by another instance");
+                let refresh_auth = RefreshAuth::new(&latest, self.accounts.clock());
+                self.auth_data.insert(latest.sub, latest).await?;
                 auths.push(refresh_auth);
-            } else {
-                warn!(sub = ?refresh_auth.id, "Auth not found, removing");
-                self.auth_data.auths.remove(&refresh_auth.id)?;
+            }
+            result => {
+                if let Err(e) = result {
+                    warn!(error = %e, "Failed to get published auth");
+                }
+                info!("Auth not refreshed by lease holder yet, retrying later");
+                auths.push(RefreshAuth::after(
+                    auth.sub,
+                    FOLLOWER_RETRY,
+                    self.accounts.clock(),
+                ));
             }
         }
         Ok(())
     }
 }
 
+fn record_queue_depth(tx: &Sender<AuthCommand>) {
+    metrics::gauge!("dt_fetcher_auth_queue_depth").set((tx.max_capacity() - tx.capacity()) as f64);
+}
+
+/// Resolution of the account served by the single-account endpoints.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum SingleAccount {
+    Found(AccountId),
+    /// There is no auth, or none for the default account.
+    Missing,
+    /// There are several auths, listed here, and no default account.
+    Ambiguous(Vec<AccountId>),
+}
+
 #[derive(Debug, Clone)]
-pub(crate) struct AuthData<A: AuthStorage> {
-    auths: A,
+pub(crate) struct AuthData {
+    auths: ErasedAuthStorage,
     tx: Sender<AuthCommand>,
+    /// Accounts whose refresh token was rejected, kept until a new auth is added.
+    needs_reauth: Arc<RwLock<HashSet<AccountId>>>,
+    /// Refresh tokens of the queued `NewAuth` commands by account, so that
+    /// concurrent adds of the same auth are only queued once.
+    pending: Arc<Mutex<HashMap<AccountId, String>>>,
 }
 
-impl<T: AuthStorage> AuthData<T> {
+impl AuthData {
+    /// Queue `auth` to be added. Fails with [`AddPending`] if a different
+    /// auth for the account is queued, so that its token isn't dropped
+    /// silently.
     #[instrument(skip(self))]
     pub async fn add_auth(&self, auth: Auth) -> Result<()> {
-        self.tx
-            .send(AuthCommand::NewAuth(auth))
-            .await
-            .context("Failed to send auth")
+        let sub = auth.sub;
+        if !self.auths.serves(&sub) {
+            return Err(NotServed(sub).into());
+        }
+        {
+            let mut pending = self.pending.lock().expect("pending lock poisoned");
+            match pending.get(&sub) {
+                Some(token) if *token == auth.refresh_token => {
+                    info!(sub = ?sub, "Auth already being added; ignoring");
+                    return Ok(());
+                }
+                Some(_) => return Err(AddPending(sub).into()),
+                None => {
+                    pending.insert(sub, auth.refresh_token.clone());
+                }
+            }
+        }
+        let result = self.send(AuthCommand::NewAuth(auth)).await;
+        if result.is_err() {
+            self.pending_done(&sub);
+        }
+        result
+    }
+
+    /// Refresh the auth of the account now.
+    ///
+    /// Returns when the account is next due for a refresh, or `None` if it
+    /// isn't scheduled, e.g. because it needs reauthentication.
+    #[instrument(skip(self))]
+    pub async fn refresh(&self, id: AccountId) -> Result<Option<DateTime<Utc>>> {
+        let (reply, response) = oneshot::channel();
+        self.send(AuthCommand::Refresh(id, reply)).await?;
+        response.await.context("Auth manager dropped refresh")?
+    }
+
+    /// Check that the auth manager is processing commands.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    #[instrument(skip(self))]
+    pub async fn ping(&self) -> Result<()> {
+        let (reply, response) = oneshot::channel();
+        self.send(AuthCommand::Ping(reply)).await?;
+        response.await.context("Auth manager dropped ping")
+    }
+
+    async fn send(&self, command: AuthCommand) -> Result<()> {
+        let result = match self.tx.send_timeout(command, ENQUEUE_TIMEOUT).await {
+            Ok(()) => Ok(()),
+            Err(SendTimeoutError::Timeout(_)) => {
+                warn!("Auth manager queue is full");
+                metrics::counter!("dt_fetcher_auth_queue_rejected_total").increment(1);
+                Err(QueueFull.into())
+            }
+            Err(e @ SendTimeoutError::Closed(_)) => Err(e).context("Failed to send command"),
+        };
+        record_queue_depth(&self.tx);
+        result
+    }
+
+    fn pending_done(&self, id: &AccountId) {
+        self.pending
+            .lock()
+            .expect("pending lock poisoned")
+            .remove(id);
     }
 
     #[instrument(skip(self))]
@@ -248,9 +602,41 @@ impl<T: AuthStorage> AuthData<T> {
         self.auths.get(id)
     }
 
+    /// The account served by the single-account endpoints: `default` if set,
+    /// otherwise the only account.
+    #[instrument(skip(self))]
+    pub fn get_single(&self, default: Option<AccountId>) -> Result<SingleAccount> {
+        if let Some(id) = default {
+            return Ok(if self.auths.contains(&id)? {
+                SingleAccount::Found(id)
+            } else {
+                SingleAccount::Missing
+            });
+        }
+        let mut ids = self
+            .auths
+            .iter()
+            .map(|result| result.map(|(id, _)| id))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(match ids.len() {
+            0 => SingleAccount::Missing,
+            1 => SingleAccount::Found(ids[0]),
+            _ => {
+                ids.sort_by_key(|id| id.0);
+                SingleAccount::Ambiguous(ids)
+            }
+        })
+    }
+
+    /// Whether the refresh token of the account was rejected.
     #[instrument(skip(self))]
-    pub fn get_single(&self) -> Result<Option<AccountId>> {
-        self.auths.get_single()
+    pub async fn needs_reauth(&self, id: &AccountId) -> bool {
+        self.needs_reauth.read().await.contains(id)
+    }
+
+    /// Name of the auth storage backend.
+    pub fn backend(&self) -> &'static str {
+        self.auths.backend()
     }
 
     #[instrument(skip(self))]
@@ -263,3 +649,157 @@ impl<T: AuthStorage> AuthData<T> {
         self.auths.insert(id, auth)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::MockClock,
+        coordination::Coordinator,
+        history::{History, InMemoryHistoryStorage},
+        settings::InMemorySettingsStorage,
+    };
+
+    fn manager() -> AuthManager {
+        manager_with(dt_api::Api::new(), Accounts::default())
+    }
+
+    fn manager_with(api: dt_api::Api, accounts: Accounts) -> AuthManager {
+        let api = Upstream::new(
+            api,
+            Coordinator::local(None),
+            History::new(InMemoryHistoryStorage::default().into()),
+        );
+        let (_, config) = watch::channel(Config::default());
+        AuthManager::new(
+            api,
+            accounts,
+            Notifiers::new(config.clone()),
+            Settings::new(InMemorySettingsStorage::default().into()),
+            config,
+        )
+    }
+
+    fn auth() -> Auth {
+        serde_json::from_value(serde_json::json!({
+            "AccessToken": "access",
+            "AccountName": "account",
+            "ExpiresIn": 3600,
+            "RefreshToken": "refresh",
+            "Sub": "00000000-0000-0000-0000-000000000001",
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn concurrent_adds_queue_one_command() {
+        let manager = manager();
+        let mut rx = manager.rx.lock().await;
+        let (first, second) = (manager.auth_data(), manager.auth_data());
+        let (a, b) = tokio::join!(first.add_auth(auth()), second.add_auth(auth()));
+        a.unwrap();
+        b.unwrap();
+        let newer = Auth {
+            refresh_token: "newer".to_string(),
+            ..auth()
+        };
+        let error = first.add_auth(newer).await.unwrap_err();
+        assert!(error.is::<AddPending>());
+
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(AuthCommand::NewAuth(auth)) if auth.sub == self::auth().sub
+        ));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn add_is_queued_again_once_processed() {
+        let manager = manager();
+        let mut rx = manager.rx.lock().await;
+        let auth_data = manager.auth_data();
+        auth_data.add_auth(auth()).await.unwrap();
+        assert!(rx.try_recv().is_ok());
+        auth_data.pending_done(&auth().sub);
+
+        auth_data.add_auth(auth()).await.unwrap();
+        assert!(rx.try_recv().is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refreshes_auth_when_due() {
+        let start = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let fixtures = tempfile::tempdir().unwrap();
+        let manager = manager_with(
+            dt_api::Api::replay(fixtures.path()),
+            Accounts::with_clock(MockClock::at(start)),
+        );
+        let auth_data = manager.auth_data();
+        let token = CancellationToken::new();
+        let handle = tokio::spawn(manager.start(token.clone()));
+        auth_data.add_auth(auth()).await.unwrap();
+        auth_data.ping().await.unwrap();
+        let refresh_at = || auth_data.get(auth().sub).unwrap().unwrap().refresh_at;
+
+        // The auth expires in an hour, and is refreshed five minutes before.
+        tokio::time::advance(Duration::from_secs(3299)).await;
+        auth_data.ping().await.unwrap();
+        assert_eq!(refresh_at(), None);
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        auth_data.ping().await.unwrap();
+        assert!(refresh_at().is_some());
+
+        token.cancel();
+        handle.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn refresh_in_the_past_is_due_now() {
+        // The clock jumped forward past the stored refresh time.
+        let now = Utc::now();
+        let instant = Instant::now();
+        let mut auth = auth();
+        auth.refresh_at = Some(now - chrono::Duration::hours(1));
+        let refresh_auth = RefreshAuth::new_at(&auth, now, instant);
+        assert_eq!(refresh_auth.deadline, instant);
+    }
+
+    #[test]
+    fn backwards_clock_jump_keeps_scheduled_order() {
+        let now = Utc::now();
+        let instant = Instant::now();
+        let mut scheduled = auth();
+        scheduled.refresh_at = Some(now + chrono::Duration::minutes(5));
+        let mut auths = BinaryHeap::new();
+        auths.push(RefreshAuth::new_at(&scheduled, now, instant));
+        // A minute later the clock is set back an hour, and a retry is
+        // scheduled 10 minutes out: before the other refresh by the wall
+        // clock, but after it in real time.
+        let retry_id = AccountId(uuid::Uuid::from_u128(2));
+        auths.push(RefreshAuth::after_at(
+            retry_id,
+            Duration::from_secs(600),
+            now - chrono::Duration::hours(1) + chrono::Duration::minutes(1),
+            instant + Duration::from_secs(60),
+        ));
+
+        let first = auths.pop().unwrap();
+        assert_eq!(first.id, scheduled.sub);
+        assert_eq!(first.deadline, instant + Duration::from_secs(300));
+        let second = auths.pop().unwrap();
+        assert_eq!(second.id, retry_id);
+        assert!(second.refresh_at < first.refresh_at);
+        assert_eq!(second.deadline, instant + Duration::from_secs(660));
+    }
+
+    #[test]
+    fn far_future_expiry_saturates() {
+        let mut auth = auth();
+        auth.expires_in = Duration::MAX;
+        let refresh_auth = RefreshAuth::new_at(&auth, Utc::now(), Instant::now());
+        assert_eq!(refresh_auth.refresh_at, DateTime::<Utc>::MAX_UTC);
+    }
+}
@@ -1,8 +1,15 @@
 mod endpoints;
-pub(crate) use endpoints::{get_auth, put_auth};
+pub(crate) use endpoints::{get_auth, put_auth, refresh_auth};
+
+mod fsck;
+pub(crate) use fsck::fsck;
 
 mod storage;
-pub(crate) use storage::{AuthStorage, ErasedAuthStorage, InMemoryAuthStorage, SledDbAuthStorage};
+pub(crate) use storage::{
+    AuthStorage, ErasedAuthStorage, FilteredAuthStorage, InMemoryAuthStorage, SledDbAuthStorage,
+};
 
 mod manager;
-pub(crate) use manager::{AuthData, AuthManager};
+pub(crate) use manager::{
+    AddPending, AuthData, AuthManager, NotServed, QueueFull, SingleAccount, ENQUEUE_TIMEOUT,
+};
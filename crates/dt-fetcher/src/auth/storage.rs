@@ -1,17 +1,25 @@
-use std::path::Path;
-
-use anyhow::{Context, Result};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
 use dyn_clone::DynClone;
 use im::HashMap;
-use tracing::instrument;
+use serde::{Deserialize, Serialize};
+use serde_with::{formats::Strict, serde_as, DurationSeconds, TimestampMilliSeconds};
+use tracing::{error, info, instrument, warn};
 
 use dt_api::{models::AccountId, Auth};
 
+/// A backend for auths. New backends implement this and convert into
+/// [`ErasedAuthStorage`], which is what the rest of the crate uses.
 pub(crate) trait AuthStorage: Send + Sync + DynClone + 'static {
     fn get(&self, id: AccountId) -> Result<Option<Auth>>;
 
-    fn get_single(&self) -> Result<Option<AccountId>>;
-
     fn contains(&self, id: &AccountId) -> Result<bool>;
 
     fn insert(&mut self, id: AccountId, auth: Auth) -> Result<()>;
@@ -19,13 +27,22 @@ pub(crate) trait AuthStorage: Send + Sync + DynClone + 'static {
     fn remove(&mut self, id: &AccountId) -> Result<()>;
 
     fn iter(&self) -> ErasedAuthStorageIter;
+
+    /// Name of the storage backend, for diagnostics.
+    fn backend(&self) -> &'static str;
+
+    /// Whether auths for the account may be stored.
+    fn serves(&self, _id: &AccountId) -> bool {
+        true
+    }
 }
 
 dyn_clone::clone_trait_object!(AuthStorage);
 
+/// Auths kept in memory, shared between clones.
 #[derive(Debug, Clone, Default)]
 pub struct InMemoryAuthStorage {
-    auths: HashMap<AccountId, Auth>,
+    auths: Arc<RwLock<HashMap<AccountId, Auth>>>,
 }
 
 pub struct InMemoryAuthStorageIter {
@@ -51,65 +68,442 @@ impl Iterator for InMemoryAuthStorageIter {
 impl AuthStorage for InMemoryAuthStorage {
     #[instrument(skip(self))]
     fn get(&self, id: AccountId) -> Result<Option<Auth>> {
-        Ok(self.auths.get(&id).cloned())
-    }
-
-    #[instrument(skip(self))]
-    fn get_single(&self) -> Result<Option<AccountId>> {
-        Ok(self.auths.keys().next().copied())
+        let auths = self.auths.read().expect("Auths poisoned");
+        Ok(auths.get(&id).cloned())
     }
 
     #[instrument(skip(self))]
     fn contains(&self, id: &AccountId) -> Result<bool> {
-        Ok(self.auths.contains_key(id))
+        let auths = self.auths.read().expect("Auths poisoned");
+        Ok(auths.contains_key(id))
     }
 
     #[instrument(skip(self))]
     fn insert(&mut self, id: AccountId, auth: Auth) -> Result<()> {
-        self.auths.insert(id, auth);
+        let mut auths = self.auths.write().expect("Auths poisoned");
+        auths.insert(id, auth);
         Ok(())
     }
 
     #[instrument(skip(self))]
     fn remove(&mut self, id: &AccountId) -> Result<()> {
-        self.auths.remove(id);
+        let mut auths = self.auths.write().expect("Auths poisoned");
+        auths.remove(id);
         Ok(())
     }
 
     #[instrument(skip(self))]
     fn iter(&self) -> ErasedAuthStorageIter {
-        InMemoryAuthStorageIter::new(&self.auths).into()
+        let auths = self.auths.read().expect("Auths poisoned");
+        InMemoryAuthStorageIter::new(&auths).into()
+    }
+
+    fn backend(&self) -> &'static str {
+        "memory"
+    }
+}
+
+/// Database record of an [`Auth`].
+///
+/// `Auth` skips unset optional fields when serializing, which the postcard
+/// format cannot represent, so records are written through this type instead.
+#[serde_as]
+#[derive(Serialize, Deserialize)]
+struct AuthRecord {
+    access_token: String,
+    account_name: String,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    expires_in: Duration,
+    #[serde_as(as = "Option<TimestampMilliSeconds<i64, Strict>>")]
+    refresh_at: Option<DateTime<Utc>>,
+    refresh_token: String,
+    sub: AccountId,
+    base_url: Option<String>,
+}
+
+/// Record layout written before base URL overrides were supported.
+#[serde_as]
+#[cfg_attr(test, derive(Serialize))]
+#[derive(Deserialize)]
+struct LegacyAuthRecord {
+    access_token: String,
+    account_name: String,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    expires_in: Duration,
+    #[serde_as(as = "Option<TimestampMilliSeconds<i64, Strict>>")]
+    refresh_at: Option<DateTime<Utc>>,
+    refresh_token: String,
+    sub: AccountId,
+}
+
+fn encode_auth(auth: &Auth) -> Result<Vec<u8>> {
+    let record = AuthRecord {
+        access_token: auth.access_token.clone(),
+        account_name: auth.account_name.clone(),
+        expires_in: auth.expires_in,
+        refresh_at: auth.refresh_at,
+        refresh_token: auth.refresh_token.clone(),
+        sub: auth.sub,
+        base_url: auth.base_url.clone(),
+    };
+    postcard::to_stdvec(&record).context("Failed to serialize auth")
+}
+
+fn decode_auth(bytes: &[u8]) -> Result<Auth> {
+    if let Ok(record) = postcard::from_bytes::<AuthRecord>(bytes) {
+        return Ok(Auth {
+            access_token: record.access_token,
+            account_name: record.account_name,
+            expires_in: record.expires_in,
+            refresh_at: record.refresh_at,
+            refresh_token: record.refresh_token,
+            sub: record.sub,
+            base_url: record.base_url,
+        });
     }
+    let record =
+        postcard::from_bytes::<LegacyAuthRecord>(bytes).context("Failed to deserialize auth")?;
+    Ok(Auth {
+        access_token: record.access_token,
+        account_name: record.account_name,
+        expires_in: record.expires_in,
+        refresh_at: record.refresh_at,
+        refresh_token: record.refresh_token,
+        sub: record.sub,
+        base_url: None,
+    })
 }
 
 // 1MB cache size, more than enough to keep the whole DB in memory.
 const SLED_DB_CACHE_SIZE_BYTES: u64 = 1024 * 1024;
 
+/// Tree that auths are stored in, keyed by account id.
+const AUTHS_TREE: &str = "auths";
+/// Tree that records which failed validation are moved to.
+const QUARANTINE_TREE: &str = "quarantine";
+/// Tree for database metadata.
+const META_TREE: &str = "meta";
+/// Key in [`META_TREE`] of the layout version of the database.
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+/// Current layout version of the database.
+///
+/// * 0: auths in the default tree, no schema version.
+/// * 1: auths in [`AUTHS_TREE`], the default tree is unused.
+const SCHEMA_VERSION: u32 = 1;
+
+fn validate_record(key: &[u8], value: &[u8]) -> Result<(AccountId, Auth)> {
+    let id = AccountId(uuid::Uuid::from_slice(key).context("Failed to deserialize uuid")?);
+    let auth = decode_auth(value)?;
+    if auth.sub != id {
+        anyhow::bail!("Auth for {} is stored under {}", auth.sub, id);
+    }
+    Ok((id, auth))
+}
+
+fn open_db(path: &Path) -> Result<sled::Db> {
+    sled::Config::new()
+        .path(path)
+        .cache_capacity(SLED_DB_CACHE_SIZE_BYTES)
+        .flush_every_ms(None)
+        .open()
+        .context("Failed to open db")
+}
+
+/// Whether opening a database failed because its contents are damaged.
+///
+/// Errors that say nothing about the contents, such as the database being
+/// locked by another process or not being readable by this one, are not
+/// corruption, and recovering from them would throw away a healthy database.
+pub(crate) fn is_corrupt(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<sled::Error>() {
+            return match e {
+                sled::Error::Corruption { .. } => true,
+                sled::Error::Io(e) => is_corrupt_io(e),
+                _ => false,
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            return is_corrupt_io(e);
+        }
+        cause.is::<std::array::TryFromSliceError>()
+    })
+}
+
+fn is_corrupt_io(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::InvalidData | std::io::ErrorKind::UnexpectedEof
+    )
+}
+
+/// Copy every tree of `from` into `to`.
+fn copy_db(from: &sled::Db, to: &sled::Db) -> Result<()> {
+    for name in from.tree_names() {
+        let source = from.open_tree(&name).context("Failed to open tree")?;
+        let destination = to.open_tree(&name).context("Failed to open tree")?;
+        for result in source.iter() {
+            let (key, value) = result.context("Failed to read record")?;
+            destination
+                .insert(key, value)
+                .context("Failed to write record")?;
+        }
+    }
+    to.flush().context("Failed to flush")?;
+    Ok(())
+}
+
+/// `path` with a timestamped suffix, e.g. `auth.db.backup-20240101T000000.000Z`.
+fn timestamped_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(
+        ".{suffix}-{}",
+        Utc::now().format("%Y%m%dT%H%M%S%.3fZ")
+    ));
+    PathBuf::from(name)
+}
+
+/// Existing backups of the database at `path`, newest first.
+fn backups(path: &Path) -> Vec<PathBuf> {
+    let (Some(parent), Some(file_name)) = (path.parent(), path.file_name()) else {
+        return Vec::new();
+    };
+    let parent = if parent.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        parent
+    };
+    let prefix = format!("{}.backup-", file_name.to_string_lossy());
+    let mut backups = std::fs::read_dir(parent)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    backups.sort();
+    backups.reverse();
+    backups
+}
+
 #[derive(Debug, Clone)]
 pub struct SledDbAuthStorage {
     db: sled::Db,
+    auths: sled::Tree,
+    path: PathBuf,
 }
 
 impl SledDbAuthStorage {
-    pub fn new<P: AsRef<Path>>(db: P) -> Result<Self> {
-        Ok(Self {
-            db: sled::Config::new()
-                .path(db)
-                .cache_capacity(SLED_DB_CACHE_SIZE_BYTES)
-                .flush_every_ms(None)
-                .open()
-                .context("Failed to open db")?,
-        })
+    /// Open the database, recovering from corruption.
+    ///
+    /// A corrupt database is moved aside and restored from the newest usable
+    /// backup, or recreated empty if there is none. Records that fail to
+    /// decode are quarantined. Other errors, such as the database being locked
+    /// by another process, are returned as is.
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let storage = match Self::open(&path) {
+            Ok(storage) => storage,
+            Err(e) if is_corrupt(&e) => {
+                error!(error = ?e, "Database is unreadable; recovering");
+                Self::recover(path)?
+            }
+            Err(e) => return Err(e),
+        };
+        if let Err(e) = storage
+            .invalid_records()
+            .and_then(|invalid| storage.quarantine(&invalid))
+        {
+            error!(error = ?e, "Failed to quarantine invalid auths; they will be skipped");
+        }
+        Ok(storage)
+    }
+
+    /// Open the database without attempting any recovery.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        Self::from_db(open_db(&path)?, path)
+    }
+
+    /// Wrap an opened database, migrating it to the current layout.
+    fn from_db(db: sled::Db, path: PathBuf) -> Result<Self> {
+        let storage = Self {
+            auths: db.open_tree(AUTHS_TREE).context("Failed to open auths")?,
+            db,
+            path,
+        };
+        storage.migrate()?;
+        Ok(storage)
+    }
+
+    /// Migrate the database to [`SCHEMA_VERSION`].
+    ///
+    /// Databases without a schema version keep their auths in the default
+    /// tree; they are backed up and the auths moved to [`AUTHS_TREE`]. Each
+    /// step is safe to repeat if interrupted.
+    #[instrument(skip(self))]
+    fn migrate(&self) -> Result<()> {
+        let meta = self
+            .db
+            .open_tree(META_TREE)
+            .context("Failed to open meta")?;
+        let version = match meta
+            .get(SCHEMA_VERSION_KEY)
+            .context("Failed to get schema version")?
+        {
+            Some(bytes) => u32::from_be_bytes(
+                bytes
+                    .as_ref()
+                    .try_into()
+                    .context("Invalid schema version")?,
+            ),
+            None => 0,
+        };
+        if version == SCHEMA_VERSION {
+            return Ok(());
+        }
+        if version > SCHEMA_VERSION {
+            bail!(
+                "Database has schema version {version}, newer than the supported version {SCHEMA_VERSION}"
+            );
+        }
+        if !self.db.is_empty() {
+            info!(from = version, to = SCHEMA_VERSION, "Migrating db");
+            self.backup()?;
+            for result in self.db.iter() {
+                let (key, value) = result.context("Failed to read record")?;
+                self.auths
+                    .insert(key, value)
+                    .context("Failed to write auth")?;
+            }
+            self.db.clear().context("Failed to clear default tree")?;
+        }
+        meta.insert(SCHEMA_VERSION_KEY, &SCHEMA_VERSION.to_be_bytes())
+            .context("Failed to set schema version")?;
+        self.db.flush().context("Failed to flush")?;
+        Ok(())
+    }
+
+    /// Move an unreadable database aside and restore the newest backup that can be read.
+    #[instrument(skip_all, fields(path = %path.as_ref().display()))]
+    pub fn recover<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if path.exists() {
+            let corrupt_path = timestamped_path(path, "corrupt");
+            std::fs::rename(path, &corrupt_path).context("Failed to move corrupt db aside")?;
+            warn!(corrupt_path = %corrupt_path.display(), "Moved corrupt db aside");
+        }
+        for backup_path in backups(path) {
+            let restored = open_db(&backup_path).and_then(|backup| {
+                let db = open_db(path)?;
+                copy_db(&backup, &db)?;
+                Self::from_db(db, path.to_path_buf())
+            });
+            match restored {
+                Ok(storage) => {
+                    warn!(backup_path = %backup_path.display(), "Restored db from backup");
+                    return Ok(storage);
+                }
+                Err(e) => {
+                    error!(backup_path = %backup_path.display(), error = ?e, "Failed to restore backup");
+                    if path.exists() {
+                        std::fs::remove_dir_all(path)
+                            .context("Failed to remove partially restored db")?;
+                    }
+                }
+            }
+        }
+        warn!("No usable backup found; starting with an empty db");
+        Self::open(path)
+    }
+
+    /// Rewrite the database at `path` into a fresh directory to reclaim space.
+    ///
+    /// The original database is kept as a backup. The database must not be in
+    /// use.
+    #[instrument(skip_all, fields(path = %path.as_ref().display()))]
+    pub fn compact<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
+        let path = path.as_ref();
+        let compacted_path = timestamped_path(path, "compacting");
+        {
+            let db = open_db(path)?;
+            let compacted = open_db(&compacted_path).context("Failed to create compacted db")?;
+            copy_db(&db, &compacted).context("Failed to write compacted db")?;
+        }
+        let backup_path = timestamped_path(path, "backup");
+        std::fs::rename(path, &backup_path).context("Failed to move db to backup")?;
+        std::fs::rename(&compacted_path, path).context("Failed to move compacted db")?;
+        info!(backup_path = %backup_path.display(), "Compacted db");
+        Ok(backup_path)
+    }
+
+    /// Database shared with the other sled storages.
+    pub fn db(&self) -> &sled::Db {
+        &self.db
+    }
+
+    /// Copy the database to a timestamped backup next to it.
+    #[instrument(skip(self))]
+    pub fn backup(&self) -> Result<PathBuf> {
+        let backup_path = timestamped_path(&self.path, "backup");
+        let backup = open_db(&backup_path).context("Failed to create backup")?;
+        copy_db(&self.db, &backup).context("Failed to write backup")?;
+        info!(backup_path = %backup_path.display(), "Backed up db");
+        Ok(backup_path)
+    }
+
+    /// Keys of stored auths that fail to decode or are stored under the wrong account.
+    #[instrument(skip(self))]
+    pub fn invalid_records(&self) -> Result<Vec<sled::IVec>> {
+        let mut invalid = Vec::new();
+        for result in self.auths.iter() {
+            let (key, value) = result.context("Failed to read db")?;
+            if let Err(reason) = validate_record(&key, &value) {
+                warn!(key = ?key, error = %reason, "Invalid auth record");
+                invalid.push(key);
+            }
+        }
+        Ok(invalid)
+    }
+
+    /// Back up the database, then move the records at `keys` into the quarantine tree.
+    #[instrument(skip_all)]
+    pub fn quarantine(&self, keys: &[sled::IVec]) -> Result<()> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        self.backup()?;
+        let quarantine = self
+            .db
+            .open_tree(QUARANTINE_TREE)
+            .context("Failed to open quarantine")?;
+        for key in keys {
+            if let Some(value) = self.auths.remove(key).context("Failed to remove auth")? {
+                quarantine
+                    .insert(key, value)
+                    .context("Failed to quarantine auth")?;
+                warn!(key = ?key, "Quarantined auth record");
+            }
+        }
+        self.db.flush().context("Failed to flush")?;
+        Ok(())
     }
 }
 
+/// Iterator over stored auths.
+///
+/// Records that fail validation are yielded as errors. A read error ends the
+/// iteration after it is yielded.
 pub struct SledDbAuthStorageIter {
-    inner: sled::Iter,
+    inner: std::iter::Fuse<sled::Iter>,
+    failed: bool,
 }
 
 impl SledDbAuthStorageIter {
-    fn new(db: &sled::Db) -> Self {
-        Self { inner: db.iter() }
+    fn new(auths: &sled::Tree) -> Self {
+        Self {
+            inner: auths.iter().fuse(),
+            failed: false,
+        }
     }
 }
 
@@ -117,12 +511,15 @@ impl Iterator for SledDbAuthStorageIter {
     type Item = Result<(AccountId, Auth)>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next().map(|result| {
-            let (id, auth) = result.expect("Failed to get key/value pair");
-            Ok((
-                AccountId(uuid::Uuid::from_slice(&id).context("Failed to deserialize uuid")?),
-                postcard::from_bytes(&auth).context("Failed to deserialize auth")?,
-            ))
+        if self.failed {
+            return None;
+        }
+        Some(match self.inner.next()? {
+            Ok((id, auth)) => validate_record(&id, &auth),
+            Err(e) => {
+                self.failed = true;
+                Err(e).context("Failed to get key/value pair")
+            }
         })
     }
 }
@@ -130,57 +527,45 @@ impl Iterator for SledDbAuthStorageIter {
 impl AuthStorage for SledDbAuthStorage {
     #[instrument(skip(self))]
     fn get(&self, id: AccountId) -> Result<Option<Auth>> {
-        let result = self.db.get(id.0.as_bytes()).context("Failed to get auth")?;
-        result
-            .map(|auth| postcard::from_bytes::<Auth>(&auth).context("Failed to deserialize auth"))
-            .transpose()
-    }
-
-    #[instrument(skip(self))]
-    fn get_single(&self) -> Result<Option<AccountId>> {
-        let result = self.db.first().context("Failed to get auth")?;
-        result
-            .map(|(id, _)| {
-                uuid::Uuid::from_slice(&id)
-                    .context("Failed to deserialize uuid")
-                    .map(AccountId)
-            })
-            .transpose()
+        let result = self
+            .auths
+            .get(id.0.as_bytes())
+            .context("Failed to get auth")?;
+        result.map(|auth| decode_auth(&auth)).transpose()
     }
 
     #[instrument(skip(self))]
     fn contains(&self, id: &AccountId) -> Result<bool> {
-        self.db
+        self.auths
             .contains_key(id.0.as_bytes())
             .context("Failed to get auth")
     }
 
     #[instrument(skip(self))]
     fn insert(&mut self, id: AccountId, auth: Auth) -> Result<()> {
-        self.db
-            .insert(
-                id.0.as_bytes(),
-                postcard::to_vec::<Auth, 1024>(&auth)
-                    .context("Failed to serialize auth")?
-                    .as_slice(),
-            )
+        self.auths
+            .insert(id.0.as_bytes(), encode_auth(&auth)?)
             .context("Failed to insert")?;
-        self.db.flush().context("Failed to flush")?;
+        self.auths.flush().context("Failed to flush")?;
         Ok(())
     }
 
     #[instrument(skip(self))]
     fn remove(&mut self, id: &AccountId) -> Result<()> {
-        self.db
+        self.auths
             .remove(id.0.as_bytes())
             .context("Failed to remove auth")?;
-        self.db.flush().context("Failed to flush")?;
+        self.auths.flush().context("Failed to flush")?;
         Ok(())
     }
 
     #[instrument(skip(self))]
     fn iter(&self) -> ErasedAuthStorageIter {
-        SledDbAuthStorageIter::new(&self.db).into()
+        SledDbAuthStorageIter::new(&self.auths).into()
+    }
+
+    fn backend(&self) -> &'static str {
+        "sled"
     }
 }
 
@@ -198,20 +583,87 @@ impl From<SledDbAuthStorageIter> for ErasedAuthStorageIter {
     }
 }
 
+/// Only the auths of some accounts in another storage, so instances sharing
+/// a storage backend can each serve a subset of its accounts.
+///
+/// Auths of other accounts are left alone and can't be added.
+#[derive(Clone)]
+pub struct FilteredAuthStorage {
+    inner: ErasedAuthStorage,
+    accounts: Arc<HashSet<AccountId>>,
+}
+
+impl FilteredAuthStorage {
+    pub fn new(inner: ErasedAuthStorage, accounts: impl IntoIterator<Item = AccountId>) -> Self {
+        Self {
+            inner,
+            accounts: Arc::new(accounts.into_iter().collect()),
+        }
+    }
+}
+
+impl AuthStorage for FilteredAuthStorage {
+    fn get(&self, id: AccountId) -> Result<Option<Auth>> {
+        if !self.serves(&id) {
+            return Ok(None);
+        }
+        self.inner.get(id)
+    }
+
+    fn contains(&self, id: &AccountId) -> Result<bool> {
+        Ok(self.serves(id) && self.inner.contains(id)?)
+    }
+
+    fn insert(&mut self, id: AccountId, auth: Auth) -> Result<()> {
+        if !self.serves(&id) {
+            bail!("Account {} isn't served by this instance", id.0);
+        }
+        self.inner.insert(id, auth)
+    }
+
+    fn remove(&mut self, id: &AccountId) -> Result<()> {
+        if !self.serves(id) {
+            return Ok(());
+        }
+        self.inner.remove(id)
+    }
+
+    fn iter(&self) -> ErasedAuthStorageIter {
+        let accounts = self.accounts.clone();
+        Box::new(self.inner.iter().filter(move |result| match result {
+            Ok((id, _)) => accounts.contains(id),
+            Err(_) => true,
+        }))
+    }
+
+    fn backend(&self) -> &'static str {
+        self.inner.backend()
+    }
+
+    fn serves(&self, id: &AccountId) -> bool {
+        self.accounts.contains(id)
+    }
+}
+
+/// Any auth storage, so the manager and the server aren't generic over the
+/// backend chosen at startup.
 #[derive(Clone)]
 pub struct ErasedAuthStorage(Box<dyn AuthStorage>);
 
+impl std::fmt::Debug for ErasedAuthStorage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ErasedAuthStorage")
+            .field(&self.0.backend())
+            .finish()
+    }
+}
+
 impl AuthStorage for ErasedAuthStorage {
     #[instrument(skip(self))]
     fn get(&self, id: AccountId) -> Result<Option<Auth>> {
         self.0.get(id)
     }
 
-    #[instrument(skip(self))]
-    fn get_single(&self) -> Result<Option<AccountId>> {
-        self.0.get_single()
-    }
-
     #[instrument(skip(self))]
     fn contains(&self, id: &AccountId) -> Result<bool> {
         self.0.contains(id)
@@ -231,6 +683,14 @@ impl AuthStorage for ErasedAuthStorage {
     fn iter(&self) -> ErasedAuthStorageIter {
         Box::new(self.0.iter())
     }
+
+    fn backend(&self) -> &'static str {
+        self.0.backend()
+    }
+
+    fn serves(&self, id: &AccountId) -> bool {
+        self.0.serves(id)
+    }
 }
 
 impl From<InMemoryAuthStorage> for ErasedAuthStorage {
@@ -244,3 +704,132 @@ impl From<SledDbAuthStorage> for ErasedAuthStorage {
         Self(Box::new(value))
     }
 }
+
+impl From<FilteredAuthStorage> for ErasedAuthStorage {
+    fn from(value: FilteredAuthStorage) -> Self {
+        Self(Box::new(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth() -> Auth {
+        serde_json::from_value(serde_json::json!({
+            "AccessToken": "access",
+            "AccountName": "account",
+            "ExpiresIn": 3600,
+            "RefreshToken": "refresh",
+            "Sub": "00000000-0000-0000-0000-000000000001",
+        }))
+        .unwrap()
+    }
+
+    /// Names of the files next to the database at `path` containing `suffix`.
+    fn siblings(path: &Path, suffix: &str) -> Vec<String> {
+        std::fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.contains(suffix))
+            .collect()
+    }
+
+    fn storage_with_auth(path: &Path) -> SledDbAuthStorage {
+        let mut storage = SledDbAuthStorage::new(path).unwrap();
+        storage.insert(auth().sub, auth()).unwrap();
+        storage
+    }
+
+    #[test]
+    fn locked_db_is_not_recovered() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("auth.db");
+        let storage = storage_with_auth(&path);
+
+        let e = SledDbAuthStorage::new(&path).unwrap_err();
+        assert!(!is_corrupt(&e), "{e:?}");
+        assert!(siblings(&path, ".corrupt-").is_empty());
+        assert!(storage.contains(&auth().sub).unwrap());
+    }
+
+    #[test]
+    fn corrupt_db_is_restored_from_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("auth.db");
+        storage_with_auth(&path).backup().unwrap();
+        std::fs::write(path.join("conf"), "not a sled config\n").unwrap();
+
+        let storage = SledDbAuthStorage::new(&path).unwrap();
+        let restored = storage.get(auth().sub).unwrap().unwrap();
+        assert_eq!(restored.refresh_token, "refresh");
+        assert_eq!(siblings(&path, ".corrupt-").len(), 1);
+    }
+
+    #[test]
+    fn recover_without_backup_starts_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("auth.db");
+        drop(storage_with_auth(&path));
+
+        let storage = SledDbAuthStorage::recover(&path).unwrap();
+        assert!(!storage.contains(&auth().sub).unwrap());
+        assert_eq!(siblings(&path, ".corrupt-").len(), 1);
+    }
+
+    #[test]
+    fn invalid_records_are_quarantined() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("auth.db");
+        let storage = storage_with_auth(&path);
+        let other = uuid::Uuid::from_u128(2);
+        storage
+            .auths
+            .insert(other.as_bytes(), encode_auth(&auth()).unwrap())
+            .unwrap();
+        storage.auths.insert(b"not a uuid", b"garbage").unwrap();
+        drop(storage);
+
+        let storage = SledDbAuthStorage::new(&path).unwrap();
+        assert!(storage.invalid_records().unwrap().is_empty());
+        assert!(storage.contains(&auth().sub).unwrap());
+        let quarantine = storage.db.open_tree(QUARANTINE_TREE).unwrap();
+        assert_eq!(quarantine.len(), 2);
+        assert!(quarantine.contains_key(other.as_bytes()).unwrap());
+        assert_eq!(siblings(&path, ".backup-").len(), 1);
+    }
+
+    #[test]
+    fn legacy_db_is_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("auth.db");
+        let auth = auth();
+        let record = LegacyAuthRecord {
+            access_token: auth.access_token.clone(),
+            account_name: auth.account_name.clone(),
+            expires_in: auth.expires_in,
+            refresh_at: auth.refresh_at,
+            refresh_token: auth.refresh_token.clone(),
+            sub: auth.sub,
+        };
+        {
+            let db = open_db(&path).unwrap();
+            db.insert(auth.sub.0.as_bytes(), postcard::to_stdvec(&record).unwrap())
+                .unwrap();
+            db.flush().unwrap();
+        }
+
+        let storage = SledDbAuthStorage::new(&path).unwrap();
+        let migrated = storage.get(auth.sub).unwrap().unwrap();
+        assert_eq!(migrated.sub, auth.sub);
+        assert_eq!(migrated.refresh_token, auth.refresh_token);
+        assert_eq!(migrated.base_url, None);
+        assert!(storage.db.is_empty());
+        let meta = storage.db.open_tree(META_TREE).unwrap();
+        assert_eq!(
+            meta.get(SCHEMA_VERSION_KEY).unwrap().unwrap().as_ref(),
+            SCHEMA_VERSION.to_be_bytes()
+        );
+        assert_eq!(siblings(&path, ".backup-").len(), 1);
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Deref,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+};
+
+use axum::{
+    body::Bytes,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use serde::{Serialize, Serializer};
+
+/// Cached model with its JSON encoding, so it can be served as is instead of
+/// being cloned and serialized again for every request.
+///
+/// The encoding is made once, when the model is cached. Clones share the
+/// model, the encoding and when it was last served.
+pub(crate) struct Cached<T> {
+    value: Arc<T>,
+    json: Bytes,
+    etag: HeaderValue,
+    /// Unix time in milliseconds, for evicting the least recently served.
+    last_served: Arc<AtomicI64>,
+}
+
+impl<T: Serialize> Cached<T> {
+    pub fn new(value: T) -> Self {
+        // The models have no maps with non-string keys, which is the only way
+        // they could fail to serialize.
+        let json = Bytes::from(serde_json::to_vec(&value).expect("models serialize to JSON"));
+        let etag = etag(&json);
+        Self {
+            value: Arc::new(value),
+            json,
+            etag,
+            last_served: Arc::new(AtomicI64::new(Utc::now().timestamp_millis())),
+        }
+    }
+}
+
+impl<T> Cached<T> {
+    /// The JSON encoding of the model.
+    pub fn json(&self) -> Bytes {
+        self.json.clone()
+    }
+
+    /// Size of the JSON encoding in bytes, as an estimate of the memory used.
+    pub fn size(&self) -> usize {
+        self.json.len()
+    }
+
+    /// Record that the model was served.
+    pub fn touch(&self) {
+        self.last_served
+            .store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+    }
+
+    /// When the model was last served, or cached if it hasn't been.
+    pub fn last_served(&self) -> i64 {
+        self.last_served.load(Ordering::Relaxed)
+    }
+
+    /// Whether both are clones of the same cached model.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.value, &other.value)
+    }
+
+    /// Respond with the JSON encoding, or with `304 Not Modified` if the
+    /// request's `If-None-Match` lists the entity tag.
+    pub fn respond(&self, headers: &HeaderMap) -> Response {
+        respond_json(self.json(), &self.etag, headers)
+    }
+}
+
+/// Respond with the JSON encoding of a value derived from a cached model, such
+/// as a projection of it, tagged and revalidated like [`Cached::respond`].
+pub(crate) fn respond(value: &serde_json::Value, headers: &HeaderMap) -> Response {
+    let json = Bytes::from(serde_json::to_vec(value).expect("JSON values serialize"));
+    let etag = etag(&json);
+    respond_json(json, &etag, headers)
+}
+
+fn respond_json(json: Bytes, etag: &HeaderValue, headers: &HeaderMap) -> Response {
+    let etag_header = (header::ETAG, etag.clone());
+    if not_modified(headers, etag) {
+        return (StatusCode::NOT_MODIFIED, [etag_header]).into_response();
+    }
+    (
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            ),
+            etag_header,
+        ],
+        json,
+    )
+        .into_response()
+}
+
+/// Entity tag of a JSON encoding.
+fn etag(json: &Bytes) -> HeaderValue {
+    // `DefaultHasher::new` is the same in every instance of a build, so
+    // clients can revalidate against any instance of a deployment.
+    let mut hasher = DefaultHasher::new();
+    json.hash(&mut hasher);
+    HeaderValue::try_from(format!("\"{:016x}\"", hasher.finish()))
+        .expect("hex is a valid header value")
+}
+
+fn not_modified(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag.as_bytes() == etag.as_bytes())
+}
+
+impl<T> Clone for Cached<T> {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+            json: self.json.clone(),
+            etag: self.etag.clone(),
+            last_served: self.last_served.clone(),
+        }
+    }
+}
+
+impl<T> Deref for Cached<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Cached<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cached")
+            .field("value", &self.value)
+            .field("etag", &self.etag)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Serialize> Serialize for Cached<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn derived_values_are_revalidated() {
+        let value = json!({ "name": "a" });
+        let response = respond(&value, &HeaderMap::new());
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[header::ETAG].clone();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, etag.clone());
+        assert_eq!(respond(&value, &headers).status(), StatusCode::NOT_MODIFIED);
+        let changed = respond(&json!({ "name": "b" }), &headers);
+        assert_eq!(changed.status(), StatusCode::OK);
+        assert_ne!(changed.headers()[header::ETAG], etag);
+    }
+}
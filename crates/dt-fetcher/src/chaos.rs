@@ -0,0 +1,119 @@
+//! Faults injected into upstream calls, to check that retries, error budgets
+//! and serving stale data work before relying on them.
+//!
+//! Faults are set per endpoint with `PUT /admin/chaos` and apply until
+//! replaced; nothing is injected by default.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::upstream::UpstreamCall;
+
+/// Faults injected into calls to one upstream endpoint.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct Faults {
+    /// Milliseconds added before every call.
+    pub latency_ms: u64,
+    /// Share of calls, from 0 to 1, that fail with `503 Service Unavailable`.
+    pub error_rate: f64,
+    /// Share of calls, from 0 to 1, whose response fails to parse.
+    pub malformed_rate: f64,
+}
+
+impl Faults {
+    pub fn is_valid(&self) -> bool {
+        (0.0..=1.0).contains(&self.error_rate) && (0.0..=1.0).contains(&self.malformed_rate)
+    }
+}
+
+/// Faults of every endpoint. Clones share the faults.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Chaos {
+    faults: Arc<Mutex<HashMap<UpstreamCall, Faults>>>,
+}
+
+impl Chaos {
+    pub fn get(&self) -> HashMap<UpstreamCall, Faults> {
+        self.lock().clone()
+    }
+
+    pub fn set(&self, faults: HashMap<UpstreamCall, Faults>) {
+        *self.lock() = faults;
+    }
+
+    /// Delay a call to `call` and pick whether it fails, returning the error
+    /// it fails with.
+    pub async fn inject(&self, call: UpstreamCall) -> Option<dt_api::Error> {
+        let faults = self.lock().get(&call).cloned()?;
+        if faults.latency_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(faults.latency_ms)).await;
+        }
+        let roll = rand::random::<f64>();
+        if roll < faults.error_rate {
+            warn!(call = ?call, "Injecting upstream error");
+            metrics::counter!("dt_fetcher_chaos_faults_total", "fault" => "error").increment(1);
+            return Some(dt_api::Error::GetPage {
+                status: StatusCode::SERVICE_UNAVAILABLE,
+                error: serde_json::json!("Injected by chaos testing"),
+                path: format!("{call:?}"),
+            });
+        }
+        if roll < faults.error_rate + faults.malformed_rate {
+            warn!(call = ?call, "Injecting malformed upstream response");
+            metrics::counter!("dt_fetcher_chaos_faults_total", "fault" => "malformed").increment(1);
+            let error = serde_json::from_str::<serde_json::Value>(r#"{"truncated":"#)
+                .expect_err("truncated JSON fails to parse");
+            return Some(dt_api::Error::InvalidJson(error));
+        }
+        None
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<UpstreamCall, Faults>> {
+        self.faults.lock().expect("chaos lock is not poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn injects_only_configured_faults() {
+        let chaos = Chaos::default();
+        assert!(chaos.inject(UpstreamCall::Summary).await.is_none());
+
+        chaos.set(HashMap::from([
+            (
+                UpstreamCall::Summary,
+                Faults {
+                    error_rate: 1.0,
+                    ..Faults::default()
+                },
+            ),
+            (
+                UpstreamCall::Store,
+                Faults {
+                    malformed_rate: 1.0,
+                    ..Faults::default()
+                },
+            ),
+        ]));
+        assert!(matches!(
+            chaos.inject(UpstreamCall::Summary).await,
+            Some(dt_api::Error::GetPage { status, .. }) if status == StatusCode::SERVICE_UNAVAILABLE
+        ));
+        assert!(matches!(
+            chaos.inject(UpstreamCall::Store).await,
+            Some(dt_api::Error::InvalidJson(_))
+        ));
+        assert!(chaos.inject(UpstreamCall::Wallets).await.is_none());
+    }
+}
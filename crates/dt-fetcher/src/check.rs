@@ -0,0 +1,160 @@
+//! Self-test run with `--check`, for deployment pipelines to validate an
+//! instance before it replaces the running one.
+
+use anyhow::{bail, Context, Result};
+use dt_api::{models::AccountId, Auth};
+use nu_ansi_term::{Color, Style};
+
+use crate::{
+    auth::{AuthStorage, SledDbAuthStorage},
+    cli::Args,
+    config::Config,
+    present::{self, Cell, Table},
+};
+
+/// Outcome of one step of the self-test.
+#[derive(Debug)]
+enum Outcome {
+    Ok(String),
+    Failed(anyhow::Error),
+    Skipped(&'static str),
+}
+
+impl Outcome {
+    fn from_result(result: Result<String>) -> Self {
+        match result {
+            Ok(detail) => Outcome::Ok(detail),
+            Err(e) => Outcome::Failed(e),
+        }
+    }
+
+    fn cells(&self) -> [Cell; 2] {
+        match self {
+            Outcome::Ok(detail) => [
+                Cell::styled("ok", Color::Green.normal()),
+                detail.as_str().into(),
+            ],
+            // `{:#}` includes the causes, which are what is worth fixing.
+            Outcome::Failed(e) => [
+                Cell::styled("failed", Color::Red.bold()),
+                format!("{e:#}").into(),
+            ],
+            Outcome::Skipped(reason) => [
+                Cell::styled("skipped", Style::new().dimmed()),
+                (*reason).into(),
+            ],
+        }
+    }
+}
+
+/// Validate the configuration, open the storage and optionally probe the
+/// upstream, print a report and fail if any step failed.
+pub(crate) async fn run(args: &Args) -> Result<()> {
+    let (config_outcome, config) = match load_config(args) {
+        Ok(config) => (Outcome::Ok(describe_config(args)), Some(config)),
+        Err(e) => (Outcome::Failed(e), None),
+    };
+    let (storage_outcome, storage) = match &args.db_path {
+        Some(path) => match open_storage(path) {
+            Ok((detail, storage)) => (Outcome::Ok(detail), Some(storage)),
+            Err(e) => (Outcome::Failed(e), None),
+        },
+        None => (Outcome::Ok("in memory, nothing to open".to_string()), None),
+    };
+    let upstream_outcome = match (args.check_upstream, &config) {
+        (None, _) => Outcome::Skipped("pass --check-upstream to probe"),
+        (Some(_), None) => Outcome::Skipped("config is invalid"),
+        (Some(account), Some(config)) => {
+            Outcome::from_result(probe(args, config, account, storage.as_ref()).await)
+        }
+    };
+    let steps = [
+        ("config", config_outcome),
+        ("storage", storage_outcome),
+        ("upstream", upstream_outcome),
+    ];
+
+    let failed = steps
+        .iter()
+        .filter(|(_, outcome)| matches!(outcome, Outcome::Failed(_)))
+        .count();
+    let rows = steps
+        .iter()
+        .map(|(step, outcome)| {
+            let [result, detail] = outcome.cells();
+            vec![(*step).into(), result, detail]
+        })
+        .collect();
+    println!(
+        "{}",
+        Table::new(&["Check", "Result", "Detail"], rows).with_color(present::use_color())
+    );
+    if failed > 0 {
+        bail!("{failed} of {} checks failed", steps.len());
+    }
+    Ok(())
+}
+
+fn load_config(args: &Args) -> Result<Config> {
+    if let Some(path) = &args.config {
+        // A missing file would otherwise load as an empty one.
+        if !path.exists() {
+            bail!("No config file at {}", path.display());
+        }
+    }
+    let config = Config::load(&args.base_config(), args.config.as_ref())?;
+    config.log_filter()?;
+    Ok(config)
+}
+
+fn describe_config(args: &Args) -> String {
+    match &args.config {
+        Some(path) => format!("loaded {}", path.display()),
+        None => "command line only".to_string(),
+    }
+}
+
+/// Open the database and validate its auths, describing what it holds.
+fn open_storage(path: &std::path::Path) -> Result<(String, SledDbAuthStorage)> {
+    if !path.exists() {
+        bail!("No database at {}", path.display());
+    }
+    let storage =
+        SledDbAuthStorage::open(path).context("Failed to open database; is it in use?")?;
+    let invalid = storage.invalid_records()?;
+    if !invalid.is_empty() {
+        bail!(
+            "{} invalid auth records; run fsck-auth --repair",
+            invalid.len()
+        );
+    }
+    let auths = storage.iter().collect::<Result<Vec<_>>>()?;
+    Ok((format!("sled, {} auths", auths.len()), storage))
+}
+
+/// Probe the upstream, authenticated as `account` if given.
+async fn probe(
+    args: &Args,
+    config: &Config,
+    account: Option<uuid::Uuid>,
+    storage: Option<&SledDbAuthStorage>,
+) -> Result<String> {
+    let api = args.api(&config.upstream)?;
+    let Some(account) = account.map(AccountId) else {
+        let status = api.ping().await.context("Upstream is unreachable")?;
+        return Ok(format!("reachable, responded with {status}"));
+    };
+    let storage = storage.context("Probing with an account requires a readable --db-path")?;
+    let auth: Auth = storage
+        .get(account)?
+        .with_context(|| format!("No auth for {}", account.0))?;
+    let summary = api
+        .get_summary(&auth)
+        .await
+        .context("Failed to get the summary")?;
+    Ok(format!(
+        "got summary of {} with {} characters",
+        account.0,
+        summary.characters.len()
+    ))
+}
@@ -0,0 +1,558 @@
+//! The command line interface of the `dt-fetcher` binary.
+
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use dt_api::models::AccountId;
+use figment::{providers::Format, Figment};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+use tracing_subscriber::{prelude::*, reload, EnvFilter};
+
+#[cfg(feature = "sentry")]
+use crate::error_report;
+#[cfg(feature = "s3")]
+use crate::history::{S3HistoryStorage, S3Location};
+#[cfg(windows)]
+use crate::windows;
+use crate::{
+    account::{AccountBundle, AccountData},
+    auth, check, client,
+    config::{Config, ConfigWatcher, LogHandle},
+    coordination::{self, Coordinator},
+    database, diff,
+    fetcher::{Fetcher, Storage, Tasks},
+    history::{History, InMemoryHistoryStorage},
+    scrub, supervisor,
+    supervisor::Supervisor,
+    systemd,
+    tabular::{bundle_rows, Delimited},
+    telemetry,
+    upstream::{Upstream, UpstreamConfig},
+};
+
+#[derive(Parser, Debug)]
+pub(crate) struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Path to auth json file
+    #[arg(
+        long,
+        global = true,
+        value_parser = clap::value_parser!(PathBuf),
+    )]
+    auth: Option<PathBuf>,
+    /// Path to reloadable config json file
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(PathBuf),
+    )]
+    pub(crate) config: Option<PathBuf>,
+    /// Host and port to listen on
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(SocketAddr),
+        default_value = "0.0.0.0:3000"
+    )]
+    listen_addr: SocketAddr,
+    /// Output logs directly to systemd
+    #[arg(long, default_value = "false")]
+    log_to_systemd: bool,
+    /// Output logs to the Windows event log
+    #[cfg(windows)]
+    #[arg(long, default_value = "false")]
+    log_to_eventlog: bool,
+    /// Path to database
+    #[arg(long, global = true, value_parser = clap::value_parser!(PathBuf))]
+    pub(crate) db_path: Option<PathBuf>,
+    /// Disable `single` endpoint variants
+    #[arg(long, default_value = "false")]
+    disable_single: bool,
+    /// Account served by the `single` endpoint variants
+    #[arg(long, value_name = "UUID")]
+    default_account: Option<uuid::Uuid>,
+    /// Check upstream responses for schema drift every N seconds
+    #[arg(long, value_name = "SECONDS")]
+    drift_check_interval: Option<u64>,
+    /// Maximum number of upstream requests per second
+    #[arg(long, value_name = "PER_SECOND", value_parser = parse_rate_limit)]
+    upstream_rate_limit: Option<coordination::RateLimit>,
+    /// Fetch stores as soon as they rotate
+    #[arg(long, default_value = "false")]
+    prefetch: bool,
+    /// URL to post events to as JSON
+    #[arg(long, value_name = "URL")]
+    webhook: Vec<String>,
+    /// Serve a frontend from this directory at `/`
+    #[arg(long, value_name = "DIR")]
+    serve_static: Option<PathBuf>,
+    /// Serve all routes under this path, e.g. behind a reverse proxy
+    #[arg(long, value_name = "PREFIX")]
+    path_prefix: Option<String>,
+    /// Auth manager restarts allowed per 10 minutes
+    #[arg(long, value_name = "N", default_value = "3")]
+    max_restarts: usize,
+    /// Bearer token for the `/admin` endpoints
+    #[arg(long, value_name = "TOKEN")]
+    admin_token: Option<String>,
+    /// Seed the cache from an exported account bundle
+    #[arg(long, value_name = "BUNDLE")]
+    seed_cache: Vec<PathBuf>,
+    /// Serve upstream responses from fixture files
+    #[arg(long, global = true, value_name = "DIR")]
+    replay: Option<PathBuf>,
+    /// Write upstream responses to fixture files
+    #[arg(long, global = true, value_name = "DIR", conflicts_with = "replay")]
+    capture: Option<PathBuf>,
+    /// Only serve these accounts from the auth storage
+    #[arg(long, value_name = "UUID,...", value_delimiter = ',')]
+    accounts: Vec<uuid::Uuid>,
+    /// Validate the config and storage, print a report and exit
+    #[arg(long, default_value = "false")]
+    check: bool,
+    /// With --check, probe the upstream, authenticated as this account if given
+    #[arg(long, value_name = "UUID", num_args = 0..=1, requires = "check")]
+    pub(crate) check_upstream: Option<Option<uuid::Uuid>>,
+    /// Redis URL to coordinate auth refreshes and the upstream rate limit with other instances
+    #[cfg(feature = "redis")]
+    #[arg(long)]
+    redis_url: Option<String>,
+    /// Split the accounts between the instances sharing --redis-url
+    #[cfg(feature = "redis")]
+    #[arg(long, requires = "redis_url")]
+    cluster: bool,
+    /// Report panics and errors to this Sentry DSN
+    #[cfg(feature = "sentry")]
+    #[arg(long, value_name = "DSN")]
+    sentry_dsn: Option<String>,
+    /// Archive store rotations in this S3 bucket instead of the database; credentials, region and endpoint are read from the AWS_* environment variables
+    #[cfg(feature = "s3")]
+    #[arg(long, value_name = "BUCKET")]
+    archive_s3_bucket: Option<String>,
+    /// Key prefix of the archived rotations in --archive-s3-bucket
+    #[cfg(feature = "s3")]
+    #[arg(
+        long,
+        requires = "archive_s3_bucket",
+        default_value = "dt-fetcher/history"
+    )]
+    archive_s3_prefix: String,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Validate the auth database
+    FsckAuth {
+        /// Restore an unreadable database from backup and quarantine invalid records
+        #[arg(long, default_value = "false")]
+        repair: bool,
+    },
+    /// Rewrite the database to reclaim space, keeping the original as a backup
+    CompactDb,
+    /// Fetch the data for the account in --auth and write it to a JSON bundle
+    ExportAccount {
+        /// Path to write the bundle to
+        output: PathBuf,
+        /// Write the fetched stores as rows instead of a bundle
+        #[arg(long, value_enum)]
+        format: Option<Delimited>,
+    },
+    /// Strip personal data from an exported bundle or a capture directory
+    Scrub {
+        /// JSON file or capture directory to scrub
+        input: PathBuf,
+        /// Path to write the scrubbed copy to
+        output: PathBuf,
+    },
+    /// Query a running dt-fetcher and print the result as a table
+    Client {
+        /// URL of the running dt-fetcher
+        #[arg(long, default_value = "http://localhost:3000")]
+        server: String,
+        /// Account to query; the only tracked account if unset
+        #[arg(long)]
+        account: Option<uuid::Uuid>,
+        #[command(subcommand)]
+        query: client::Query,
+    },
+    /// Print the offers added, removed and changed between two store JSON files
+    DiffStores {
+        /// Older store
+        old: PathBuf,
+        /// Newer store
+        new: PathBuf,
+    },
+    /// Register a Windows service running with the other arguments given
+    #[cfg(windows)]
+    InstallService,
+    /// Stop and remove the Windows service
+    #[cfg(windows)]
+    UninstallService,
+    /// Run as a Windows service; used by the service manager
+    #[cfg(windows)]
+    #[command(hide = true)]
+    RunService,
+}
+
+impl Args {
+    /// Settings from the command line, before applying the config file.
+    pub(crate) fn base_config(&self) -> Config {
+        Config {
+            listen_addr: self.listen_addr,
+            drift_check_interval: self.drift_check_interval,
+            prefetch: self.prefetch,
+            webhooks: self.webhook.clone(),
+            default_account: self.default_account.map(AccountId),
+            admin_token: self.admin_token.clone().map(Into::into),
+            ..Config::default()
+        }
+    }
+
+    /// The upstream API client, reading fixtures instead with `--replay` and
+    /// writing them with `--capture`.
+    pub(crate) fn api(&self, upstream: &UpstreamConfig) -> Result<dt_api::Api> {
+        let api = match &self.replay {
+            Some(dir) => {
+                info!(dir = %dir.display(), "Replaying upstream responses");
+                dt_api::Api::replay(dir)
+            }
+            None => upstream.api()?,
+        };
+        Ok(match &self.capture {
+            Some(dir) => {
+                info!(dir = %dir.display(), "Capturing upstream responses");
+                api.with_capture(dir)
+            }
+            None => api,
+        })
+    }
+}
+
+impl Args {
+    /// Running under the Windows service manager.
+    fn is_service(&self) -> bool {
+        #[cfg(windows)]
+        return matches!(self.command, Some(Command::RunService));
+        #[cfg(not(windows))]
+        false
+    }
+
+    /// Log to the Windows event log, which services have to as they have no
+    /// console.
+    #[cfg(windows)]
+    fn log_to_eventlog(&self) -> bool {
+        self.log_to_eventlog || self.is_service()
+    }
+}
+
+fn parse_rate_limit(per_second: &str) -> Result<coordination::RateLimit> {
+    coordination::RateLimit::per_second(per_second.parse()?)
+}
+
+fn init_logging(args: &Args, filter: EnvFilter) -> Result<LogHandle> {
+    let use_systemd = args.log_to_systemd;
+    let registry = tracing_subscriber::registry();
+    let layer = {
+        #[cfg(target_os = "linux")]
+        if use_systemd && libsystemd::daemon::booted() {
+            tracing_journald::layer()
+                .context("tracing_journald layer")?
+                .boxed()
+        } else {
+            tracing_subscriber::fmt::layer()
+                .pretty()
+                .with_target(true)
+                .boxed()
+        }
+        #[cfg(not(target_os = "linux"))]
+        if use_systemd {
+            return Err(anyhow::anyhow!(
+                "Systemd logging is not supported on this platform"
+            ));
+        } else {
+            #[cfg(windows)]
+            if args.log_to_eventlog() {
+                windows::EventLogLayer::new()
+                    .context("Failed to open event log")?
+                    .boxed()
+            } else {
+                tracing_subscriber::fmt::layer()
+                    .pretty()
+                    .with_target(true)
+                    .boxed()
+            }
+            #[cfg(not(windows))]
+            tracing_subscriber::fmt::layer().pretty().with_target(true)
+        }
+    };
+
+    let (filter, handle) = reload::Layer::new(filter);
+
+    registry.with(filter).with(layer).init();
+
+    Ok(handle)
+}
+
+/// Run the command given on the command line.
+pub fn main() -> Result<()> {
+    let args = Args::parse();
+
+    // These print results to stdout, so they run without the logging of the
+    // server.
+    match args.command {
+        Some(Command::Client {
+            server,
+            account,
+            query,
+        }) => return runtime()?.block_on(client::run(&server, account, query)),
+        Some(Command::DiffStores { old, new }) => return diff::diff_files(&old, &new),
+        None if args.check => return runtime()?.block_on(check::run(&args)),
+        _ => {}
+    }
+
+    #[cfg(windows)]
+    match &args.command {
+        Some(Command::InstallService) => return windows::install_service(),
+        Some(Command::UninstallService) => return windows::uninstall_service(),
+        Some(Command::RunService) => return windows::run_service(args),
+        _ => {}
+    }
+
+    runtime()?.block_on(run(args, CancellationToken::new()))
+}
+
+pub(crate) fn runtime() -> Result<tokio::runtime::Runtime> {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context("Failed to create runtime")
+}
+
+/// Run until `token` is cancelled or the process is interrupted.
+pub(crate) async fn run(args: Args, token: CancellationToken) -> Result<()> {
+    let base_config = args.base_config();
+    let config = Config::load(&base_config, args.config.as_ref())?;
+
+    let log_handle =
+        init_logging(&args, config.log_filter()?).context("Failed to initialize logging")?;
+    let service = args.is_service();
+    supervisor::install_panic_hook();
+    // Installed after the panic hook, which it chains to.
+    #[cfg(feature = "sentry")]
+    let _sentry = args.sentry_dsn.as_deref().map(error_report::init);
+
+    let rate_limit = args.upstream_rate_limit;
+    let upstream_api = args.api(&config.upstream)?;
+
+    match args.command {
+        Some(Command::FsckAuth { repair }) => {
+            let db_path = args.db_path.context("fsck-auth requires --db-path")?;
+            return auth::fsck(&db_path, repair);
+        }
+        Some(Command::CompactDb) => {
+            let db_path = args.db_path.context("compact-db requires --db-path")?;
+            return database::compact(&db_path);
+        }
+        Some(Command::Scrub { input, output }) => {
+            return scrub::scrub(&input, &output);
+        }
+        Some(Command::ExportAccount { output, format }) => {
+            let auth = args.auth.context("export-account requires --auth")?;
+            return export_account(upstream_api, &auth, &output, format, rate_limit).await;
+        }
+        Some(Command::Client { .. } | Command::DiffStores { .. }) => {
+            unreachable!("handled before initializing logging")
+        }
+        #[cfg(windows)]
+        Some(Command::InstallService | Command::UninstallService) => {
+            unreachable!("handled before starting the runtime")
+        }
+        #[cfg(windows)]
+        Some(Command::RunService) | None => {}
+        #[cfg(not(windows))]
+        None => {}
+    }
+
+    telemetry::install()?;
+
+    #[cfg(feature = "redis")]
+    let coordinator = if let Some(redis_url) = &args.redis_url {
+        info!("Coordinating with other instances via redis");
+        Coordinator::redis(redis_url, rate_limit, args.cluster).await?
+    } else {
+        Coordinator::local(rate_limit)
+    };
+    #[cfg(not(feature = "redis"))]
+    let coordinator = Coordinator::local(rate_limit);
+
+    let served = |id: &AccountId| args.accounts.is_empty() || args.accounts.contains(&id.0);
+    let storage = match args.db_path {
+        Some(db_path) => Storage::Sled(db_path),
+        None => Storage::InMemory,
+    };
+    let listen_addr = config.listen_addr;
+    let (config_tx, config_rx) = watch::channel(config);
+    let mut builder = Fetcher::builder()
+        .api(upstream_api)
+        .storage(storage)
+        .accounts(args.accounts.iter().copied())
+        .config(config_rx.clone())
+        .coordinator(coordinator)
+        .single_endpoints(!args.disable_single)
+        .max_restarts(args.max_restarts);
+    if let Some(dir) = args.serve_static {
+        builder = builder.static_dir(dir);
+    }
+    if let Some(prefix) = &args.path_prefix {
+        builder = builder.path_prefix(prefix);
+    }
+    #[cfg(feature = "s3")]
+    if let Some(bucket) = &args.archive_s3_bucket {
+        let location = S3Location {
+            bucket: bucket.clone(),
+            prefix: args.archive_s3_prefix.trim_matches('/').to_string(),
+        };
+        let (storage, uploader) = S3HistoryStorage::connect(location).await?;
+        builder = builder.s3_archive(storage, uploader);
+    }
+    let fetcher = builder.build()?;
+
+    for path in &args.seed_cache {
+        match load_bundle(path) {
+            Ok(bundle) if !served(&bundle.id) => {
+                warn!(sid = ?bundle.id, "Not seeding {}; account isn't served", path.display());
+            }
+            Ok(bundle) => {
+                info!(sid = ?bundle.id, "Seeding cache from {}", path.display());
+                fetcher
+                    .seed(bundle.id, AccountData::from_bundle(bundle))
+                    .await;
+            }
+            Err(e) => error!(error = ?e, "Failed to seed cache from {}", path.display()),
+        }
+    }
+
+    if let Some(auth) = args.auth {
+        info!("Adding auth from {}", auth.display());
+
+        let auth = Figment::new()
+            .merge(figment::providers::Json::file(auth))
+            .extract()?;
+
+        fetcher.add_auth(auth).await?;
+    }
+
+    let server = fetcher.server();
+
+    info!("Starting server");
+
+    let supervisor = Supervisor::new(token.clone(), args.max_restarts);
+    let mut tasks = Tasks::default();
+    fetcher.spawn_with(&supervisor, &mut tasks);
+    let config_watcher = ConfigWatcher::new(base_config, args.config, config_tx, log_handle);
+    tasks.push(supervisor.spawn("config watcher", config_watcher.start(token.clone())));
+    tasks.push(
+        supervisor.spawn(
+            "systemd notifier",
+            systemd::SystemdNotifier::new(fetcher.auth_data().clone(), listen_addr)
+                .start(token.clone()),
+        ),
+    );
+    tasks.push(supervisor.spawn("server", server.start(token.clone())));
+    tasks.push(supervisor.spawn("exit handler", exit_handler(token, service)));
+
+    info!("Listening on {}", listen_addr);
+
+    // Failures were logged as they happened; exit with the first one.
+    tasks.join().await?;
+    info!("Exiting");
+    Ok(())
+}
+
+async fn export_account(
+    upstream_api: dt_api::Api,
+    auth: &Path,
+    output: &Path,
+    format: Option<Delimited>,
+    rate_limit: Option<coordination::RateLimit>,
+) -> Result<()> {
+    let auth: dt_api::Auth = Figment::new()
+        .merge(figment::providers::Json::file(auth))
+        .extract()?;
+    let api = Upstream::new(
+        upstream_api,
+        Coordinator::local(rate_limit),
+        History::new(InMemoryHistoryStorage::default().into()),
+    );
+    let bundle = AccountData::fetch(&api, &auth, true)
+        .await
+        .bundle(auth.sub)
+        .await;
+    let file = std::fs::File::create(output).context("Failed to create bundle file")?;
+    match format {
+        Some(format) => format.write(file, bundle_rows(&bundle))?,
+        None => serde_json::to_writer_pretty(file, &bundle).context("Failed to write bundle")?,
+    }
+    info!("Exported account data to {}", output.display());
+    Ok(())
+}
+
+fn load_bundle(path: &Path) -> Result<AccountBundle> {
+    let file = std::fs::File::open(path).context("Failed to open bundle")?;
+    serde_json::from_reader(std::io::BufReader::new(file)).context("Failed to parse bundle")
+}
+
+/// Cancel `token` on interrupt, or on the Windows console control events.
+async fn exit_handler(
+    token: CancellationToken,
+    #[cfg_attr(not(windows), allow(unused_variables))] service: bool,
+) -> Result<()> {
+    let interrupt = {
+        #[cfg(target_family = "unix")]
+        {
+            async {
+                let mut signal =
+                    tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt())
+                        .context("Failed to create interrupt signal handler")?;
+                signal.recv().await;
+                Result::<()>::Ok(())
+            }
+        }
+        #[cfg(windows)]
+        {
+            async move {
+                use tokio::signal::windows;
+                let mut ctrl_break =
+                    windows::ctrl_break().context("Failed to handle ctrl_break")?;
+                let mut close = windows::ctrl_close().context("Failed to handle ctrl_close")?;
+                let mut logoff = windows::ctrl_logoff().context("Failed to handle ctrl_logoff")?;
+                let mut shutdown =
+                    windows::ctrl_shutdown().context("Failed to handle ctrl_shutdown")?;
+                tokio::select! {
+                    _ = ctrl_break.recv() => {},
+                    _ = close.recv() => {},
+                    // Services get the logoff event of every user, so only a
+                    // console process should stop on it.
+                    _ = logoff.recv(), if !service => {},
+                    _ = shutdown.recv() => {},
+                }
+                Result::<()>::Ok(())
+            }
+        }
+        #[cfg(not(any(target_family = "unix", windows)))]
+        futures_util::future::pending::<()>()
+    };
+    tokio::select! {
+        _ = interrupt => {},
+        res = tokio::signal::ctrl_c() => res.context("ctrl_c handler failed")?,
+        // Shutdown requested through `POST /admin/shutdown`.
+        _ = token.cancelled() => return Ok(()),
+    };
+    token.cancel();
+    Ok(())
+}
@@ -0,0 +1,176 @@
+//! Client for a running dt-fetcher, printing its responses as tables.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use dt_api::models::{AccountId, CurrencyType, Store, Summary};
+use serde::de::DeserializeOwned;
+use uuid::Uuid;
+
+use crate::present::{self, Table};
+
+/// What to query.
+#[derive(Debug, clap::Subcommand)]
+pub(crate) enum Query {
+    /// Print the offers of a character's store
+    Store {
+        /// Character ID, or archetype such as `zealot`
+        #[arg(long)]
+        character: String,
+        /// Currency of the store
+        #[arg(long, value_enum, default_value = "marks")]
+        currency: Currency,
+    },
+    /// Print the characters of the account
+    Summary,
+    /// Print the tracked accounts
+    Accounts,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub(crate) enum Currency {
+    Marks,
+    Credits,
+}
+
+impl From<Currency> for CurrencyType {
+    fn from(currency: Currency) -> Self {
+        match currency {
+            Currency::Marks => CurrencyType::Marks,
+            Currency::Credits => CurrencyType::Credits,
+        }
+    }
+}
+
+/// The fields of `GET /accounts` that are printed.
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AccountInfo {
+    id: AccountId,
+    last_updated: DateTime<Utc>,
+    needs_reauth: bool,
+}
+
+/// Client for the dt-fetcher at `server`.
+pub(crate) struct Client {
+    client: reqwest::Client,
+    server: String,
+}
+
+impl Client {
+    pub fn new(server: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            server: server.trim_end_matches('/').to_string(),
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str, query: &[(&str, String)]) -> Result<T> {
+        let url = format!("{}{}", self.server, path);
+        let response = self
+            .client
+            .get(&url)
+            .query(query)
+            .send()
+            .await
+            .with_context(|| format!("Failed to connect to {}", self.server))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            bail!("GET {path} failed with {status}: {body}");
+        }
+        response
+            .json()
+            .await
+            .with_context(|| format!("Failed to parse the response of GET {path}"))
+    }
+
+    async fn accounts(&self) -> Result<Vec<AccountInfo>> {
+        self.get("/accounts", &[]).await
+    }
+
+    /// The given account, or the only tracked one.
+    async fn account(&self, account: Option<Uuid>) -> Result<AccountId> {
+        if let Some(account) = account {
+            return Ok(AccountId(account));
+        }
+        match self.accounts().await?.as_slice() {
+            [account] => Ok(account.id),
+            [] => bail!("No accounts are tracked"),
+            accounts => bail!(
+                "{} accounts are tracked; choose one with --account",
+                accounts.len()
+            ),
+        }
+    }
+
+    async fn store(&self, id: AccountId, character: &str, currency: Currency) -> Result<Store> {
+        let currency_type = ("currencyType", CurrencyType::from(currency).to_string());
+        match character.parse::<Uuid>() {
+            Ok(character_id) => {
+                let query = [("characterId", character_id.to_string()), currency_type];
+                self.get(&format!("/store/{}", id.0), &query).await
+            }
+            Err(_) => {
+                let path = format!("/store/{}/by-archetype/{}", id.0, character);
+                self.get(&path, &[currency_type]).await
+            }
+        }
+    }
+}
+
+/// Run `query` against the dt-fetcher at `server` and print the result.
+pub(crate) async fn run(server: &str, account: Option<Uuid>, query: Query) -> Result<()> {
+    let client = Client::new(server);
+    let color = present::use_color();
+    match query {
+        Query::Store {
+            character,
+            currency,
+        } => {
+            let id = client.account(account).await?;
+            let store = client.store(id, &character, currency).await?;
+            println!("{}", present::store_table(&store).with_color(color));
+            println!("Rotates at {}", store.current_rotation_end);
+        }
+        Query::Summary => {
+            let id = client.account(account).await?;
+            let summary: Summary = client.get(&format!("/summary/{}", id.0), &[]).await?;
+            println!("{}", summary_table(&summary).with_color(color));
+        }
+        Query::Accounts => {
+            let accounts = client.accounts().await?;
+            println!("{}", accounts_table(&accounts).with_color(color));
+        }
+    }
+    Ok(())
+}
+
+fn summary_table(summary: &Summary) -> Table {
+    let rows = summary
+        .characters
+        .iter()
+        .map(|c| {
+            vec![
+                c.id.to_string().into(),
+                c.name.as_str().into(),
+                c.archetype.as_str().into(),
+                c.level.to_string().into(),
+            ]
+        })
+        .collect();
+    Table::new(&["ID", "NAME", "ARCHETYPE", "LEVEL"], rows)
+}
+
+fn accounts_table(accounts: &[AccountInfo]) -> Table {
+    let rows = accounts
+        .iter()
+        .map(|account| {
+            vec![
+                account.id.to_string().into(),
+                account.last_updated.to_string().into(),
+                if account.needs_reauth { "yes" } else { "no" }.into(),
+            ]
+        })
+        .collect();
+    Table::new(&["ID", "LAST UPDATED", "NEEDS REAUTH"], rows)
+}
@@ -0,0 +1,82 @@
+//! Wall clock time, behind a trait so tests can control it.
+//!
+//! Cache TTLs, rotation checks and refresh scheduling read the time from the
+//! [`Clock`] of [`Accounts`](crate::account::Accounts) instead of
+//! [`Utc::now`]. Monotonic deadlines use [`tokio::time::Instant`], which tests
+//! control with `tokio::time::pause`.
+
+use std::{fmt::Debug, sync::Arc};
+
+use chrono::{DateTime, Utc};
+
+/// Source of the current time.
+pub(crate) trait Clock: Send + Sync + Debug {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that starts at a fixed time and advances with the tokio clock, so
+/// with time paused it only moves with `tokio::time::advance` or sleeps.
+#[cfg(test)]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MockClock {
+    start: DateTime<Utc>,
+    started: tokio::time::Instant,
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn at(start: DateTime<Utc>) -> Self {
+        Self {
+            start,
+            started: tokio::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        let elapsed = chrono::Duration::from_std(self.started.elapsed())
+            .expect("elapsed test time fits a chrono duration");
+        self.start + elapsed
+    }
+}
+
+/// A shared clock of any type. Clones share the clock.
+#[derive(Debug, Clone)]
+pub(crate) struct ErasedClock(Arc<dyn Clock>);
+
+impl Clock for ErasedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0.now()
+    }
+}
+
+impl Default for ErasedClock {
+    fn default() -> Self {
+        SystemClock.into()
+    }
+}
+
+impl From<SystemClock> for ErasedClock {
+    fn from(value: SystemClock) -> Self {
+        Self(Arc::new(value))
+    }
+}
+
+#[cfg(test)]
+impl From<MockClock> for ErasedClock {
+    fn from(value: MockClock) -> Self {
+        Self(Arc::new(value))
+    }
+}
@@ -0,0 +1,84 @@
+//! Membership of a cluster of instances splitting accounts between them.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, instrument, warn};
+
+use crate::{auth::AuthData, coordination::Coordinator};
+
+/// How often an instance renews its membership and claims its accounts.
+const RENEW_INTERVAL: Duration = Duration::from_secs(10);
+/// How long an instance that stops renewing its membership stays a member.
+const MEMBERSHIP_TTL: Duration = Duration::from_secs(30);
+
+/// Keeps the instance in the cluster, and adds the auths of accounts it owns
+/// that were put to other instances.
+///
+/// Does nothing unless the coordinator is clustered.
+pub(crate) struct ClusterMember {
+    coordinator: Coordinator,
+    auth_data: AuthData,
+}
+
+impl ClusterMember {
+    pub fn new(coordinator: Coordinator, auth_data: AuthData) -> Self {
+        Self {
+            coordinator,
+            auth_data,
+        }
+    }
+
+    #[instrument(skip_all)]
+    pub async fn start(self, token: CancellationToken) -> Result<()> {
+        if !self.coordinator.is_clustered() {
+            token.cancelled().await;
+            return Ok(());
+        }
+        let mut interval = tokio::time::interval(RENEW_INTERVAL);
+        loop {
+            tokio::select! {
+                biased;
+                _ = token.cancelled() => {
+                    info!("Leaving cluster");
+                    if let Err(e) = self.coordinator.leave().await {
+                        warn!(error = %e, "Failed to leave cluster");
+                    }
+                    return Ok(());
+                }
+                _ = interval.tick() => self.renew().await,
+            }
+        }
+    }
+
+    async fn renew(&self) {
+        match self.coordinator.renew_membership(MEMBERSHIP_TTL).await {
+            Ok(members) => metrics::gauge!("dt_fetcher_cluster_members").set(members as f64),
+            Err(e) => {
+                error!(error = %e, "Failed to renew cluster membership");
+                return;
+            }
+        }
+        if let Err(e) = self.claim().await {
+            error!(error = %e, "Failed to claim accounts");
+        }
+    }
+
+    /// Add the published auths of owned accounts this instance doesn't have.
+    async fn claim(&self) -> Result<()> {
+        for id in self.coordinator.accounts().await? {
+            if !self.coordinator.owns(id) || self.auth_data.contains(&id)? {
+                continue;
+            }
+            let Some(auth) = self.coordinator.latest_auth(id).await? else {
+                continue;
+            };
+            info!(sub = ?id, "Claiming account");
+            if let Err(e) = self.auth_data.add_auth(auth).await {
+                warn!(sub = ?id, error = %e, "Failed to claim account");
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+//! Side-by-side comparison of two offers, e.g. of a store item with one
+//! offered to another character.
+
+use dt_api::models::{Offer, Override, Overrides, Perk, Stat, Trait, WeaponOverride};
+use serde::Serialize;
+
+use crate::account::OfferMatch;
+
+/// A value of both offers, and how much higher it is for `b`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(crate) struct Difference {
+    pub a: Option<i64>,
+    pub b: Option<i64>,
+    /// `b` minus `a`, if both offers have the value.
+    pub difference: Option<i64>,
+}
+
+impl Difference {
+    fn new(a: Option<i64>, b: Option<i64>) -> Self {
+        Self {
+            a,
+            b,
+            difference: a.zip(b).map(|(a, b)| b - a),
+        }
+    }
+}
+
+/// A base stat of either weapon, as a percentage shown in game.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct StatDifference {
+    pub name: String,
+    #[serde(flatten)]
+    pub percent: Difference,
+}
+
+/// A trait or perk of either item, by its rarity.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct ModifierDifference {
+    pub id: String,
+    #[serde(flatten)]
+    pub rarity: Difference,
+}
+
+/// How two offers differ in price and in the stats, traits and perks of their
+/// items. Values an offer doesn't have, such as the stats of a gadget, are
+/// `null`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OfferComparison {
+    pub price: Difference,
+    pub rarity: Difference,
+    pub item_level: Difference,
+    pub stats: Vec<StatDifference>,
+    pub stats_total: Difference,
+    pub traits: Vec<ModifierDifference>,
+    pub perks: Vec<ModifierDifference>,
+}
+
+impl OfferComparison {
+    pub fn new(a: &Offer, b: &Offer) -> Self {
+        let (item_a, item_b) = (
+            a.description.overrides.item(),
+            b.description.overrides.item(),
+        );
+        let (weapon_a, weapon_b) = (weapon(a), weapon(b));
+        let rarity = |item: Option<&Override>| item.map(|item| i64::from(item.rarity));
+        let item_level = |item: Option<&Override>| item.map(|item| i64::from(item.item_level));
+        let stats_total = |weapon: Option<&WeaponOverride>| weapon.map(|w| w.stats_total().into());
+        Self {
+            price: Difference::new(
+                Some(a.price.amount.amount.into()),
+                Some(b.price.amount.amount.into()),
+            ),
+            rarity: Difference::new(rarity(item_a), rarity(item_b)),
+            item_level: Difference::new(item_level(item_a), item_level(item_b)),
+            stats: by_key(
+                base_stats(weapon_a),
+                base_stats(weapon_b),
+                |s| &s.name,
+                |s| s.percent().into(),
+            )
+            .map(|(name, percent)| StatDifference { name, percent })
+            .collect(),
+            stats_total: Difference::new(stats_total(weapon_a), stats_total(weapon_b)),
+            traits: by_key(
+                traits(item_a),
+                traits(item_b),
+                |t| &t.id,
+                |t| t.rarity.into(),
+            )
+            .map(|(id, rarity)| ModifierDifference { id, rarity })
+            .collect(),
+            perks: by_key(perks(item_a), perks(item_b), |p| &p.id, |p| p.rarity.into())
+                .map(|(id, rarity)| ModifierDifference { id, rarity })
+                .collect(),
+        }
+    }
+}
+
+/// The comparison of two cached offers, with where each was offered.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Comparison {
+    pub a: OfferMatch,
+    pub b: OfferMatch,
+    #[serde(flatten)]
+    pub comparison: OfferComparison,
+}
+
+impl Comparison {
+    pub fn new(a: OfferMatch, b: OfferMatch) -> Self {
+        let comparison = OfferComparison::new(&a.offer, &b.offer);
+        Self { a, b, comparison }
+    }
+}
+
+fn weapon(offer: &Offer) -> Option<&WeaponOverride> {
+    match &offer.description.overrides {
+        Overrides::Weapon(weapon) => Some(weapon),
+        _ => None,
+    }
+}
+
+fn base_stats(weapon: Option<&WeaponOverride>) -> &[Stat] {
+    weapon.map_or(&[], |weapon| &weapon.base_stats)
+}
+
+fn traits(item: Option<&Override>) -> &[Trait] {
+    item.map_or(&[], |item| &item.traits)
+}
+
+fn perks(item: Option<&Override>) -> &[Perk] {
+    item.map_or(&[], |item| &item.perks)
+}
+
+/// Pair up the values of `a` and `b` by key, in the order of `a` and then of
+/// the keys only in `b`.
+fn by_key<'a, T>(
+    a: &'a [T],
+    b: &'a [T],
+    key: impl Fn(&T) -> &String + Copy + 'a,
+    value: impl Fn(&T) -> i64 + Copy + 'a,
+) -> impl Iterator<Item = (String, Difference)> + 'a {
+    let find = move |items: &'a [T], k: &String| items.iter().find(|item| key(item) == k);
+    let only_b = b.iter().filter(move |item| find(a, key(item)).is_none());
+    a.iter().chain(only_b).map(move |item| {
+        let k = key(item);
+        let difference = Difference::new(find(a, k).map(value), find(b, k).map(value));
+        (k.clone(), difference)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use dt_api::models::Store;
+
+    use super::*;
+
+    #[test]
+    fn compares_items_by_stat_trait_and_perk() {
+        let store: Store =
+            serde_json::from_str(include_str!("../tests/fixtures/store.json")).unwrap();
+        let comparison = OfferComparison::new(&store.personal[0], &store.personal[1]);
+
+        assert_eq!(comparison.price, Difference::new(Some(2150), Some(420)));
+        assert_eq!(comparison.price.difference, Some(-1730));
+        assert_eq!(comparison.rarity.difference, Some(-3));
+        assert_eq!(comparison.item_level.difference, Some(-290));
+        assert_eq!(
+            comparison.stats,
+            [StatDifference {
+                name: "damage".to_string(),
+                percent: Difference::new(Some(64), Some(24)),
+            }]
+        );
+        assert_eq!(comparison.stats_total.difference, Some(-40));
+        assert_eq!(comparison.traits.len(), 2);
+        assert!(comparison
+            .traits
+            .iter()
+            .all(|t| t.rarity.a.is_some() && t.rarity.b.is_none()));
+        assert_eq!(comparison.perks[0].rarity, Difference::new(Some(4), None));
+
+        // A weapon and a gadget share no stats, and a random item has no item.
+        let gadget = OfferComparison::new(&store.personal[0], &store.public[0]);
+        assert_eq!(gadget.stats_total, Difference::new(Some(64), None));
+        assert_eq!(gadget.traits.len(), 3);
+        let random = OfferComparison::new(&store.personal[2], &store.public[0]);
+        assert_eq!(random.rarity, Difference::new(None, Some(3)));
+        assert!(random.stats.is_empty());
+    }
+}
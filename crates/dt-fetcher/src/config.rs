@@ -0,0 +1,447 @@
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{ensure, Context, Result};
+use dt_api::models::AccountId;
+use figment::{
+    providers::{Format, Json, Serialized},
+    Figment,
+};
+use ipnet::IpNet;
+
+use crate::{
+    digest::DigestConfig,
+    metrics_push::MetricsPushConfig,
+    push::{NtfyConfig, PushoverConfig},
+    retention::RetentionConfig,
+    server::ForwardedHeader,
+    settings::MAX_SUMMARY_TTL_MINS,
+    slo::SloConfig,
+    telegram::TelegramConfig,
+    upstream::UpstreamConfig,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, instrument, warn};
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+/// How often the config file is checked for modifications.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+pub(crate) type LogHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Runtime configuration, read from the command line and the optional config
+/// file.
+///
+/// Everything except `listen_addr` and `upstream` can be changed without
+/// restarting.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct Config {
+    /// Host and port to listen on.
+    pub listen_addr: SocketAddr,
+    /// Minutes after which a cached summary is refreshed.
+    pub summary_refresh_interval_mins: i64,
+    /// Minutes after which a cached leaderboard is refreshed.
+    pub leaderboard_ttl_mins: i64,
+    /// Seconds between schema drift checks; disabled if `None`.
+    pub drift_check_interval: Option<u64>,
+    /// Log filter directives, e.g. `info,dt_fetcher=debug`; `RUST_LOG` if `None`.
+    pub log_level: Option<String>,
+    /// Allowed CORS origins; any origin if `None`.
+    pub cors_allowed_origins: Option<Vec<String>>,
+    /// Fetch stores as soon as they rotate and notify about watchlist matches.
+    pub prefetch: bool,
+    /// Fetch the personal offers of each character; only the public store of
+    /// each archetype if `false`.
+    pub personal_stores: bool,
+    /// Refresh the summary of an account as soon as a personal store shows
+    /// that one of its characters levelled up, instead of after its TTL.
+    pub summary_refresh_on_level_up: bool,
+    /// URLs that events are posted to as JSON.
+    pub webhooks: Vec<String>,
+    /// Account served by the single-account endpoints; the only account if
+    /// `None`.
+    pub default_account: Option<AccountId>,
+    /// Bearer token for the `/admin` endpoints; they are disabled if `None`.
+    pub admin_token: Option<Secret>,
+    /// Upstream base URLs that auths may point their requests at instead of
+    /// the default; only the scheme, host and port are compared.
+    pub allowed_base_urls: Vec<String>,
+    /// Megabytes of JSON the cached stores may take before the least recently
+    /// served are evicted; unlimited if `None`.
+    pub cache_budget_mb: Option<u64>,
+    /// Networks of reverse proxies whose forwarded client addresses are
+    /// trusted.
+    pub trusted_proxies: Vec<IpNet>,
+    /// Header the trusted proxies forward client addresses in.
+    pub forwarded_header: ForwardedHeader,
+    /// Where to write a record of every request; disabled if `None`.
+    pub access_log: Option<AccessLogTarget>,
+    /// Error budgets of upstream calls and handler responses.
+    pub slo: SloConfig,
+    /// Settings of the upstream client.
+    pub upstream: UpstreamConfig,
+    /// How long archived data is kept.
+    pub retention: RetentionConfig,
+    /// Where the metrics are pushed; only exposed at `/metrics` if `None`.
+    pub metrics_push: Option<MetricsPushConfig>,
+    /// Batching of watch matches and new rotations into digests; sent as
+    /// they happen if `None`.
+    pub digest: Option<DigestConfig>,
+    /// Telegram chat that events are sent to; disabled if `None`.
+    pub telegram: Option<TelegramConfig>,
+    /// Pushover user that watch matches are pushed to; disabled if `None`.
+    pub pushover: Option<PushoverConfig>,
+    /// ntfy topic that watch matches are pushed to; disabled if `None`.
+    pub ntfy: Option<NtfyConfig>,
+    /// MQTT broker that events are published to; disabled if `None`.
+    #[cfg(feature = "mqtt")]
+    pub mqtt: Option<crate::mqtt::MqttConfig>,
+}
+
+/// Target of the access log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum AccessLogTarget {
+    /// Append a JSON object per line to a file.
+    File(PathBuf),
+    /// Send each record to journald, with a field per value.
+    Journald,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen_addr: ([0, 0, 0, 0], 3000).into(),
+            summary_refresh_interval_mins: 60,
+            leaderboard_ttl_mins: 15,
+            drift_check_interval: None,
+            log_level: None,
+            cors_allowed_origins: None,
+            prefetch: false,
+            personal_stores: true,
+            summary_refresh_on_level_up: false,
+            webhooks: Vec::new(),
+            default_account: None,
+            admin_token: None,
+            allowed_base_urls: Vec::new(),
+            cache_budget_mb: None,
+            trusted_proxies: Vec::new(),
+            forwarded_header: ForwardedHeader::default(),
+            access_log: None,
+            slo: SloConfig::default(),
+            upstream: UpstreamConfig::default(),
+            retention: RetentionConfig::default(),
+            metrics_push: None,
+            digest: None,
+            telegram: None,
+            pushover: None,
+            ntfy: None,
+            #[cfg(feature = "mqtt")]
+            mqtt: None,
+        }
+    }
+}
+
+/// Scheme, host and port of an http or https URL, e.g. `https://example.com`.
+fn parse_base_url(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+    Some(url.origin().ascii_serialization())
+}
+
+/// A string that is redacted from debug output, so it doesn't end up in logs.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl Config {
+    /// Layer the config file, if any, over `base`.
+    pub fn load(base: &Config, path: Option<&PathBuf>) -> Result<Config> {
+        let mut figment = Figment::new().merge(Serialized::defaults(base));
+        if let Some(path) = path {
+            figment = figment.merge(Json::file(path));
+        }
+        let config: Config = figment.extract().context("Failed to load config")?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the values that would be out of range where they are used.
+    fn validate(&self) -> Result<()> {
+        ensure!(
+            (1..=MAX_SUMMARY_TTL_MINS).contains(&self.summary_refresh_interval_mins),
+            "summaryRefreshIntervalMins must be between 1 and {MAX_SUMMARY_TTL_MINS}"
+        );
+        ensure!(
+            (1..=MAX_SUMMARY_TTL_MINS).contains(&self.leaderboard_ttl_mins),
+            "leaderboardTtlMins must be between 1 and {MAX_SUMMARY_TTL_MINS}"
+        );
+        ensure!(
+            self.drift_check_interval != Some(0),
+            "driftCheckInterval must be at least 1"
+        );
+        ensure!(
+            self.upstream.rebuild_interval_secs != Some(0),
+            "upstream.rebuildIntervalSecs must be at least 1"
+        );
+        ensure!(
+            self.upstream.max_pages != Some(0),
+            "upstream.maxPages must be at least 1"
+        );
+        for base_url in &self.allowed_base_urls {
+            ensure!(
+                parse_base_url(base_url).is_some(),
+                "allowedBaseUrls must be http or https URLs, not {base_url}"
+            );
+        }
+        Ok(())
+    }
+
+    /// Whether auths may send their requests to `base_url`, i.e. it has the
+    /// scheme, host and port of one of `allowed_base_urls`.
+    pub fn allows_base_url(&self, base_url: &str) -> bool {
+        let Some(origin) = parse_base_url(base_url) else {
+            return false;
+        };
+        self.allowed_base_urls
+            .iter()
+            .filter_map(|allowed| parse_base_url(allowed))
+            .any(|allowed| allowed == origin)
+    }
+
+    /// The cache budget in bytes.
+    pub fn cache_budget_bytes(&self) -> Option<u64> {
+        self.cache_budget_mb
+            .map(|mb| mb.saturating_mul(1024 * 1024))
+    }
+
+    /// Build the log filter for `log_level`, falling back to `RUST_LOG`.
+    pub fn log_filter(&self) -> Result<EnvFilter> {
+        let builder = EnvFilter::builder()
+            .with_default_directive(tracing::metadata::LevelFilter::INFO.into());
+        match &self.log_level {
+            Some(log_level) => builder
+                .parse(log_level)
+                .context("Failed to parse log level"),
+            None => builder
+                .from_env()
+                .context("Failed to parse filter from env"),
+        }
+    }
+}
+
+/// Reloads the config file on `SIGHUP` or when it is modified, publishing the
+/// new config to the rest of the application.
+pub(crate) struct ConfigWatcher {
+    base: Config,
+    path: Option<PathBuf>,
+    tx: watch::Sender<Config>,
+    log_handle: LogHandle,
+}
+
+impl ConfigWatcher {
+    pub fn new(
+        base: Config,
+        path: Option<PathBuf>,
+        tx: watch::Sender<Config>,
+        log_handle: LogHandle,
+    ) -> Self {
+        Self {
+            base,
+            path,
+            tx,
+            log_handle,
+        }
+    }
+
+    #[instrument(skip_all)]
+    pub async fn start(self, token: CancellationToken) -> Result<()> {
+        let Some(path) = self.path.clone() else {
+            // Keep the sender alive so receivers don't see the channel close.
+            token.cancelled().await;
+            return Ok(());
+        };
+
+        #[cfg(target_family = "unix")]
+        let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+            .context("Failed to create hangup signal handler")?;
+
+        let mut modified = modified_time(&path);
+        let mut poll = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            let hangup = async {
+                #[cfg(target_family = "unix")]
+                hangup.recv().await;
+                #[cfg(not(target_family = "unix"))]
+                futures_util::future::pending::<()>().await;
+            };
+            tokio::select! {
+                _ = token.cancelled() => {
+                    info!("Shutting down config watcher");
+                    return Ok(());
+                }
+                _ = hangup => {
+                    info!("Received SIGHUP; reloading config");
+                    modified = modified_time(&path);
+                    self.reload(&path);
+                }
+                _ = poll.tick() => {
+                    let current = modified_time(&path);
+                    if current != modified {
+                        info!("Config file modified; reloading config");
+                        modified = current;
+                        self.reload(&path);
+                    }
+                }
+            }
+        }
+    }
+
+    #[instrument(skip(self))]
+    fn reload(&self, path: &PathBuf) {
+        let mut config = match Config::load(&self.base, Some(path)) {
+            Ok(config) => config,
+            Err(e) => {
+                error!(error = ?e, "Failed to reload config; keeping current config");
+                return;
+            }
+        };
+        let current = self.tx.borrow().clone();
+        if config.listen_addr != current.listen_addr {
+            warn!(
+                current = %current.listen_addr,
+                requested = %config.listen_addr,
+                "Changing listenAddr requires a restart; ignoring"
+            );
+            config.listen_addr = current.listen_addr;
+        }
+        if config.upstream != current.upstream {
+            warn!("Changing upstream requires a restart; ignoring");
+            config.upstream = current.upstream.clone();
+        }
+        if config == current {
+            info!("Config unchanged");
+            return;
+        }
+        if config.log_level != current.log_level {
+            match config.log_filter() {
+                Ok(filter) => {
+                    if let Err(e) = self.log_handle.reload(filter) {
+                        error!(error = %e, "Failed to apply log level");
+                    }
+                }
+                Err(e) => {
+                    error!(error = ?e, "Invalid log level; keeping current log level");
+                    config.log_level = current.log_level.clone();
+                }
+            }
+        }
+        info!(config = ?config, "Applying new config");
+        self.tx.send_replace(config);
+    }
+}
+
+fn modified_time(path: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_out_of_range_ttls() {
+        assert!(Config::default().validate().is_ok());
+        for mins in [0, -1, MAX_SUMMARY_TTL_MINS + 1, i64::MAX] {
+            let summary = Config {
+                summary_refresh_interval_mins: mins,
+                ..Config::default()
+            };
+            assert!(summary.validate().is_err(), "{mins}");
+            let leaderboard = Config {
+                leaderboard_ttl_mins: mins,
+                ..Config::default()
+            };
+            assert!(leaderboard.validate().is_err(), "{mins}");
+        }
+    }
+
+    #[test]
+    fn allows_only_listed_base_urls() {
+        let config = Config {
+            allowed_base_urls: vec!["https://backend.example.com/api".to_string()],
+            ..Config::default()
+        };
+        assert!(config.validate().is_ok());
+        assert!(config.allows_base_url("https://backend.example.com"));
+        assert!(config.allows_base_url("https://backend.example.com:443/other"));
+        assert!(!config.allows_base_url("http://backend.example.com"));
+        assert!(!config.allows_base_url("https://backend.example.com:8443"));
+        assert!(!config.allows_base_url("https://backend.example.com.evil.com"));
+        assert!(!config.allows_base_url("https://backend.example.com@evil.com"));
+        assert!(!config.allows_base_url("http://127.0.0.1"));
+        assert!(!config.allows_base_url("not a url"));
+        assert!(!Config::default().allows_base_url("https://backend.example.com"));
+
+        let invalid = Config {
+            allowed_base_urls: vec!["file:///etc".to_string()],
+            ..Config::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_zero_drift_check_interval() {
+        for (interval, valid) in [(0, false), (1, true)] {
+            let config = Config {
+                drift_check_interval: Some(interval),
+                ..Config::default()
+            };
+            assert_eq!(config.validate().is_ok(), valid, "{interval}");
+        }
+    }
+
+    #[test]
+    fn rejects_zero_rebuild_interval() {
+        let mut config = Config::default();
+        config.upstream.rebuild_interval_secs = Some(0);
+        assert!(config.validate().is_err());
+        config.upstream.rebuild_interval_secs = Some(1);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_zero_max_pages() {
+        let mut config = Config::default();
+        config.upstream.max_pages = Some(0);
+        assert!(config.validate().is_err());
+        config.upstream.max_pages = Some(1);
+        assert!(config.validate().is_ok());
+    }
+}
@@ -0,0 +1,551 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{ensure, Result};
+use dt_api::{models::AccountId, Auth};
+use tokio::time::Instant;
+use tracing::{debug, instrument};
+
+/// Rate limit for upstream requests.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RateLimit {
+    /// Sustained number of requests per second.
+    pub per_second: f64,
+    /// Number of requests that can be made at once after being idle.
+    pub burst: f64,
+}
+
+impl RateLimit {
+    /// Fails unless `per_second` is finite and positive, as no token would
+    /// ever be refilled otherwise.
+    pub fn per_second(per_second: f64) -> Result<Self> {
+        ensure!(
+            per_second.is_finite() && per_second > 0.0,
+            "Rate limit must be a positive number of requests per second, not {per_second}"
+        );
+        Ok(Self {
+            per_second,
+            burst: per_second.ceil().max(1.0),
+        })
+    }
+}
+
+/// Coordinates upstream work between fetcher instances serving the same accounts.
+///
+/// Only the instance holding the lease for an account refreshes its auth; the
+/// others adopt the auth it publishes. All instances share the upstream rate
+/// limit. In a cluster, only the instance owning an account on the hash ring
+/// of live instances takes its lease.
+#[derive(Debug, Clone)]
+pub(crate) enum Coordinator {
+    Local(LocalCoordinator),
+    #[cfg(feature = "redis")]
+    Redis(redis_coordinator::RedisCoordinator),
+}
+
+impl Coordinator {
+    pub fn local(rate_limit: Option<RateLimit>) -> Self {
+        Self::Local(LocalCoordinator {
+            bucket: rate_limit.map(|rate_limit| Arc::new(Mutex::new(TokenBucket::new(rate_limit)))),
+        })
+    }
+
+    #[cfg(feature = "redis")]
+    pub async fn redis(url: &str, rate_limit: Option<RateLimit>, cluster: bool) -> Result<Self> {
+        Ok(Self::Redis(
+            redis_coordinator::RedisCoordinator::new(url, rate_limit, cluster).await?,
+        ))
+    }
+
+    /// Whether accounts are split between the instances of a cluster.
+    pub fn is_clustered(&self) -> bool {
+        match self {
+            Coordinator::Local(_) => false,
+            #[cfg(feature = "redis")]
+            Coordinator::Redis(redis) => redis.is_clustered(),
+        }
+    }
+
+    /// Whether this instance does the upstream work for `id`: always, unless
+    /// another instance of the cluster owns the account.
+    #[cfg_attr(not(feature = "redis"), allow(unused_variables))]
+    pub fn owns(&self, id: AccountId) -> bool {
+        match self {
+            Coordinator::Local(_) => true,
+            #[cfg(feature = "redis")]
+            Coordinator::Redis(redis) => redis.owns(id),
+        }
+    }
+
+    /// Announce that this instance is alive for `ttl`, and update the hash
+    /// ring with the live instances. Returns their number.
+    #[instrument(skip(self))]
+    pub async fn renew_membership(&self, ttl: Duration) -> Result<usize> {
+        match self {
+            Coordinator::Local(_) => Ok(1),
+            #[cfg(feature = "redis")]
+            Coordinator::Redis(redis) => redis.renew_membership(ttl).await,
+        }
+    }
+
+    /// Leave the cluster, handing over the accounts this instance owns.
+    #[instrument(skip(self))]
+    pub async fn leave(&self) -> Result<()> {
+        match self {
+            Coordinator::Local(_) => Ok(()),
+            #[cfg(feature = "redis")]
+            Coordinator::Redis(redis) => redis.leave().await,
+        }
+    }
+
+    /// Accounts whose auths were published by any instance of the cluster.
+    #[instrument(skip(self))]
+    pub async fn accounts(&self) -> Result<Vec<AccountId>> {
+        match self {
+            Coordinator::Local(_) => Ok(Vec::new()),
+            #[cfg(feature = "redis")]
+            Coordinator::Redis(redis) => redis.accounts().await,
+        }
+    }
+
+    /// Try to acquire or renew the lease to perform upstream work for `id`.
+    #[instrument(skip(self))]
+    pub async fn acquire_lease(&self, id: AccountId, ttl: Duration) -> Result<bool> {
+        match self {
+            Coordinator::Local(_) => Ok(true),
+            #[cfg(feature = "redis")]
+            Coordinator::Redis(redis) => redis.acquire_lease(id, ttl).await,
+        }
+    }
+
+    /// Share a refreshed auth with the other instances.
+    #[instrument(skip(self))]
+    pub async fn publish_auth(&self, auth: &Auth) -> Result<()> {
+        match self {
+            Coordinator::Local(_) => Ok(()),
+            #[cfg(feature = "redis")]
+            Coordinator::Redis(redis) => redis.publish_auth(auth).await,
+        }
+    }
+
+    /// Get the latest auth published by any instance.
+    #[instrument(skip(self))]
+    pub async fn latest_auth(&self, id: AccountId) -> Result<Option<Auth>> {
+        match self {
+            Coordinator::Local(_) => Ok(None),
+            #[cfg(feature = "redis")]
+            Coordinator::Redis(redis) => redis.latest_auth(id).await,
+        }
+    }
+
+    /// Wait until an upstream request may be made under the rate limit.
+    pub async fn permit(&self) -> Result<()> {
+        loop {
+            let wait = match self {
+                Coordinator::Local(local) => local.take(),
+                #[cfg(feature = "redis")]
+                Coordinator::Redis(redis) => redis.take().await?,
+            };
+            match wait {
+                Some(wait) => {
+                    debug!(wait = ?wait, "Upstream rate limited");
+                    tokio::time::sleep(wait).await;
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct LocalCoordinator {
+    bucket: Option<Arc<Mutex<TokenBucket>>>,
+}
+
+impl LocalCoordinator {
+    fn take(&self) -> Option<Duration> {
+        self.bucket
+            .as_ref()
+            .and_then(|bucket| bucket.lock().expect("Token bucket poisoned").take())
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    rate_limit: RateLimit,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate_limit: RateLimit) -> Self {
+        Self {
+            rate_limit,
+            tokens: rate_limit.burst,
+            last: Instant::now(),
+        }
+    }
+
+    /// Take a token, or return how long to wait until one is available.
+    fn take(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+        self.tokens = (self.tokens
+            + now.duration_since(self.last).as_secs_f64() * self.rate_limit.per_second)
+            .min(self.rate_limit.burst);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64(
+                (1.0 - self.tokens) / self.rate_limit.per_second,
+            ))
+        }
+    }
+}
+
+/// Consistent hash ring of the instances of a cluster.
+///
+/// Each instance is placed at [`VIRTUAL_NODES`] points, and an account is owned
+/// by the instance at the first point after the hash of its id, so accounts
+/// spread evenly and only those of a joining or leaving instance move.
+#[cfg(feature = "redis")]
+mod ring {
+    use dt_api::models::AccountId;
+
+    /// Points per instance.
+    const VIRTUAL_NODES: u32 = 128;
+
+    #[derive(Default)]
+    pub(crate) struct Ring {
+        members: Vec<String>,
+        points: Vec<(u64, usize)>,
+    }
+
+    impl std::fmt::Debug for Ring {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("Ring")
+                .field("members", &self.members)
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl Ring {
+        pub fn new(mut members: Vec<String>) -> Self {
+            members.sort();
+            members.dedup();
+            let mut points: Vec<_> = members
+                .iter()
+                .enumerate()
+                .flat_map(|(index, member)| {
+                    (0..VIRTUAL_NODES).map(move |node| (hash(format!("{member}#{node}")), index))
+                })
+                .collect();
+            points.sort_unstable();
+            Self { members, points }
+        }
+
+        pub fn members(&self) -> &[String] {
+            &self.members
+        }
+
+        /// The instance owning the account, if there are any.
+        pub fn owner(&self, id: AccountId) -> Option<&str> {
+            let hash = hash(id.0.to_string());
+            let index = self.points.partition_point(|(point, _)| *point < hash);
+            let (_, member) = self.points.get(index).or_else(|| self.points.first())?;
+            Some(&self.members[*member])
+        }
+    }
+
+    /// FNV-1a, which is stable across builds and platforms, mixed with the
+    /// SplitMix64 finalizer to spread similar keys around the ring.
+    fn hash(key: impl AsRef<[u8]>) -> u64 {
+        let mut hash = key
+            .as_ref()
+            .iter()
+            .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+                (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+            });
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        hash ^ (hash >> 31)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::collections::HashMap;
+
+        use uuid::Uuid;
+
+        use super::*;
+
+        fn accounts() -> impl Iterator<Item = AccountId> {
+            (0..10_000u128)
+                .map(|i| AccountId(Uuid::from_u128(i.wrapping_mul(0x9e37_79b9_7f4a_7c15))))
+        }
+
+        #[test]
+        fn spreads_accounts_evenly() {
+            let ring = Ring::new((0..4).map(|i| format!("instance-{i}")).collect());
+            let mut owned = HashMap::new();
+            for id in accounts() {
+                *owned
+                    .entry(ring.owner(id).unwrap().to_string())
+                    .or_insert(0) += 1;
+            }
+            assert_eq!(owned.len(), 4);
+            for count in owned.values() {
+                assert!((1_500..3_500).contains(count), "{owned:?}");
+            }
+        }
+
+        #[test]
+        fn only_moves_accounts_of_leaving_instance() {
+            let members: Vec<_> = (0..4).map(|i| format!("instance-{i}")).collect();
+            let before = Ring::new(members.clone());
+            let after = Ring::new(members[1..].to_vec());
+            for id in accounts() {
+                let owner = before.owner(id).unwrap();
+                if owner != "instance-0" {
+                    assert_eq!(after.owner(id), Some(owner));
+                }
+            }
+            assert_eq!(Ring::default().owner(AccountId(Uuid::nil())), None);
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+mod redis_coordinator {
+    use std::{
+        sync::{Arc, RwLock},
+        time::Duration,
+    };
+
+    use anyhow::{Context, Result};
+    use dt_api::{models::AccountId, Auth};
+    use redis::{aio::ConnectionManager, AsyncCommands, Script};
+    use tracing::info;
+
+    use super::{ring::Ring, RateLimit};
+
+    const KEY_PREFIX: &str = "dt-fetcher";
+
+    // Drops expired members, then adds or renews this one until ARGV[2]
+    // milliseconds from now and returns the live members.
+    const RENEW_MEMBERSHIP: &str = r"
+        local time = redis.call('TIME')
+        local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+        redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now)
+        redis.call('ZADD', KEYS[1], now + tonumber(ARGV[2]), ARGV[1])
+        return redis.call('ZRANGE', KEYS[1], 0, -1)
+    ";
+
+    const ACQUIRE_LEASE: &str = r"
+        if redis.call('GET', KEYS[1]) == ARGV[1] then
+            redis.call('PEXPIRE', KEYS[1], ARGV[2])
+            return 1
+        end
+        if redis.call('SET', KEYS[1], ARGV[1], 'NX', 'PX', ARGV[2]) then
+            return 1
+        end
+        return 0
+    ";
+
+    // Returns the number of milliseconds to wait for a token, 0 if one was taken.
+    const TAKE_TOKEN: &str = r"
+        local per_second = tonumber(ARGV[1])
+        local burst = tonumber(ARGV[2])
+        local time = redis.call('TIME')
+        local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+        local state = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+        local tokens = tonumber(state[1]) or burst
+        local ts = tonumber(state[2]) or now
+        tokens = math.min(burst, tokens + (now - ts) * per_second / 1000)
+        local wait = 0
+        if tokens >= 1 then
+            tokens = tokens - 1
+        else
+            wait = math.ceil((1 - tokens) * 1000 / per_second)
+        end
+        redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'ts', now)
+        redis.call('PEXPIRE', KEYS[1], math.ceil(burst * 1000 / per_second) + 1000)
+        return wait
+    ";
+
+    #[derive(Clone)]
+    pub(crate) struct RedisCoordinator {
+        connection: ConnectionManager,
+        instance: String,
+        rate_limit: Option<RateLimit>,
+        /// Live instances of the cluster, if accounts are split between them.
+        ring: Option<Arc<RwLock<Ring>>>,
+    }
+
+    impl std::fmt::Debug for RedisCoordinator {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("RedisCoordinator")
+                .field("instance", &self.instance)
+                .field("rate_limit", &self.rate_limit)
+                .field("ring", &self.ring)
+                .finish()
+        }
+    }
+
+    impl RedisCoordinator {
+        pub async fn new(url: &str, rate_limit: Option<RateLimit>, cluster: bool) -> Result<Self> {
+            let client = redis::Client::open(url).context("Invalid redis url")?;
+            let connection = ConnectionManager::new(client)
+                .await
+                .context("Failed to connect to redis")?;
+            Ok(Self {
+                connection,
+                instance: uuid::Uuid::new_v4().to_string(),
+                rate_limit,
+                ring: cluster.then(Default::default),
+            })
+        }
+
+        pub fn is_clustered(&self) -> bool {
+            self.ring.is_some()
+        }
+
+        pub fn owns(&self, id: AccountId) -> bool {
+            let Some(ring) = &self.ring else {
+                return true;
+            };
+            // Before joining, act as the only instance.
+            ring.read()
+                .expect("ring poisoned")
+                .owner(id)
+                .map_or(true, |owner| owner == self.instance)
+        }
+
+        pub async fn renew_membership(&self, ttl: Duration) -> Result<usize> {
+            let Some(ring) = &self.ring else {
+                return Ok(1);
+            };
+            let members: Vec<String> = Script::new(RENEW_MEMBERSHIP)
+                .key(format!("{KEY_PREFIX}:members"))
+                .arg(&self.instance)
+                .arg(ttl.as_millis() as u64)
+                .invoke_async(&mut self.connection.clone())
+                .await
+                .context("Failed to renew membership")?;
+            let count = members.len();
+            let mut ring = ring.write().expect("ring poisoned");
+            let mut sorted = members.clone();
+            sorted.sort();
+            if ring.members() != sorted {
+                info!(instance = %self.instance, members = ?members, "Cluster membership changed");
+                *ring = Ring::new(members);
+            }
+            Ok(count)
+        }
+
+        pub async fn leave(&self) -> Result<()> {
+            if self.ring.is_none() {
+                return Ok(());
+            }
+            self.connection
+                .clone()
+                .zrem(format!("{KEY_PREFIX}:members"), &self.instance)
+                .await
+                .context("Failed to leave cluster")
+        }
+
+        pub async fn accounts(&self) -> Result<Vec<AccountId>> {
+            let ids: Vec<String> = self
+                .connection
+                .clone()
+                .smembers(format!("{KEY_PREFIX}:accounts"))
+                .await
+                .context("Failed to list cluster accounts")?;
+            Ok(ids
+                .iter()
+                .filter_map(|id| uuid::Uuid::parse_str(id).ok().map(AccountId))
+                .collect())
+        }
+
+        pub async fn acquire_lease(&self, id: AccountId, ttl: Duration) -> Result<bool> {
+            if !self.owns(id) {
+                return Ok(false);
+            }
+            let acquired: i32 = Script::new(ACQUIRE_LEASE)
+                .key(format!("{KEY_PREFIX}:lease:{id}"))
+                .arg(&self.instance)
+                .arg(ttl.as_millis() as u64)
+                .invoke_async(&mut self.connection.clone())
+                .await
+                .context("Failed to acquire lease")?;
+            Ok(acquired == 1)
+        }
+
+        pub async fn publish_auth(&self, auth: &Auth) -> Result<()> {
+            let auth_json = serde_json::to_string(auth).context("Failed to serialize auth")?;
+            if self.ring.is_some() {
+                self.connection
+                    .clone()
+                    .sadd::<_, _, ()>(format!("{KEY_PREFIX}:accounts"), auth.sub.to_string())
+                    .await
+                    .context("Failed to register account")?;
+            }
+            self.connection
+                .clone()
+                .set_ex(
+                    format!("{KEY_PREFIX}:auth:{}", auth.sub),
+                    auth_json,
+                    auth.expires_in.as_secs().max(1),
+                )
+                .await
+                .context("Failed to publish auth")
+        }
+
+        pub async fn latest_auth(&self, id: AccountId) -> Result<Option<Auth>> {
+            let auth_json: Option<String> = self
+                .connection
+                .clone()
+                .get(format!("{KEY_PREFIX}:auth:{id}"))
+                .await
+                .context("Failed to get published auth")?;
+            auth_json
+                .map(|auth| serde_json::from_str(&auth).context("Failed to deserialize auth"))
+                .transpose()
+        }
+
+        pub async fn take(&self) -> Result<Option<Duration>> {
+            let Some(rate_limit) = self.rate_limit else {
+                return Ok(None);
+            };
+            let wait_ms: u64 = Script::new(TAKE_TOKEN)
+                .key(format!("{KEY_PREFIX}:rate-limit"))
+                .arg(rate_limit.per_second)
+                .arg(rate_limit.burst)
+                .invoke_async(&mut self.connection.clone())
+                .await
+                .context("Failed to take rate limit token")?;
+            Ok((wait_ms > 0).then(|| Duration::from_millis(wait_ms)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit_waits_for_tokens() {
+        for invalid in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(RateLimit::per_second(invalid).is_err(), "{invalid}");
+        }
+
+        let mut bucket = TokenBucket::new(RateLimit::per_second(0.5).unwrap());
+        assert_eq!(bucket.take(), None);
+        assert_eq!(bucket.take(), Some(Duration::from_secs(2)));
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert_eq!(bucket.take(), None);
+    }
+}
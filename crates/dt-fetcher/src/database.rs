@@ -0,0 +1,103 @@
+use std::{collections::BTreeMap, path::Path, time::Duration};
+
+use anyhow::{Context, Result};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, instrument};
+
+use crate::auth::SledDbAuthStorage;
+
+/// How often database statistics are recorded.
+const REPORT_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Size and contents of the database.
+#[derive(Debug)]
+pub(crate) struct DbStats {
+    size_on_disk: u64,
+    /// Number of entries in each tree, by tree name.
+    trees: BTreeMap<String, usize>,
+}
+
+impl DbStats {
+    pub fn collect(db: &sled::Db) -> Result<Self> {
+        let mut trees = BTreeMap::new();
+        for name in db.tree_names() {
+            let tree = db.open_tree(&name).context("Failed to open tree")?;
+            let name = if name == db.name() {
+                "default".to_string()
+            } else {
+                String::from_utf8_lossy(&name).into_owned()
+            };
+            trees.insert(name, tree.len());
+        }
+        Ok(Self {
+            size_on_disk: db.size_on_disk().context("Failed to get db size")?,
+            trees,
+        })
+    }
+
+    /// Set the database gauges to these statistics.
+    pub fn record(&self) {
+        metrics::gauge!("dt_fetcher_db_size_bytes").set(self.size_on_disk as f64);
+        for (name, entries) in &self.trees {
+            metrics::gauge!("dt_fetcher_db_tree_entries", "tree" => name.clone())
+                .set(*entries as f64);
+        }
+    }
+
+    pub fn log(&self) {
+        info!(size_on_disk = self.size_on_disk, "Database size");
+        for (name, entries) in &self.trees {
+            info!(tree = %name, entries, "Tree entries");
+        }
+    }
+}
+
+/// Periodically records database statistics as metrics.
+pub(crate) struct DbMonitor {
+    db: Option<sled::Db>,
+}
+
+impl DbMonitor {
+    pub fn new(db: Option<sled::Db>) -> Self {
+        Self { db }
+    }
+
+    #[instrument(skip_all)]
+    pub async fn start(self, token: CancellationToken) -> Result<()> {
+        let Some(db) = self.db else {
+            token.cancelled().await;
+            return Ok(());
+        };
+        let mut interval = tokio::time::interval(REPORT_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => {
+                    info!("Shutting down database monitor");
+                    return Ok(());
+                }
+                _ = interval.tick() => {
+                    let db = db.clone();
+                    match tokio::task::spawn_blocking(move || DbStats::collect(&db)).await? {
+                        Ok(stats) => stats.record(),
+                        Err(e) => error!(error = ?e, "Failed to collect database statistics"),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Compact the database at `path`, logging its statistics before and after.
+#[instrument]
+pub(crate) fn compact(path: &Path) -> Result<()> {
+    {
+        let storage = SledDbAuthStorage::open(path)?;
+        info!("Before compaction");
+        DbStats::collect(storage.db())?.log();
+    }
+    SledDbAuthStorage::compact(path)?;
+    let storage = SledDbAuthStorage::open(path)?;
+    info!("After compaction");
+    DbStats::collect(storage.db())?.log();
+    Ok(())
+}
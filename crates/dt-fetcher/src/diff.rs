@@ -0,0 +1,190 @@
+//! Differences between two snapshots of a store.
+
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{Context, Result};
+use dt_api::models::{Offer, OfferId, Store};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::present;
+
+/// An offer, and whether it is one of the personal offers.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DiffOffer {
+    pub personal: bool,
+    pub offer: Offer,
+}
+
+/// A field of an offer that differs between the snapshots.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct FieldChange {
+    pub field: &'static str,
+    pub old: Value,
+    pub new: Value,
+}
+
+/// An offer in both snapshots, as it is in the newer one.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OfferChange {
+    pub personal: bool,
+    pub offer: Offer,
+    pub changes: Vec<FieldChange>,
+}
+
+/// Offers added, removed and changed from an older to a newer snapshot of a
+/// store. Offers are matched by their ID.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct StoreDiff {
+    pub added: Vec<DiffOffer>,
+    pub removed: Vec<DiffOffer>,
+    pub changed: Vec<OfferChange>,
+}
+
+impl StoreDiff {
+    pub fn new(old: &Store, new: &Store) -> Self {
+        let old_offers: HashMap<OfferId, &Offer> = offers(old)
+            .map(|(_, offer)| (offer.offer_id, offer))
+            .collect();
+        let new_offers: HashMap<OfferId, &Offer> = offers(new)
+            .map(|(_, offer)| (offer.offer_id, offer))
+            .collect();
+        let mut added = Vec::new();
+        let mut changed = Vec::new();
+        for (personal, offer) in offers(new) {
+            match old_offers.get(&offer.offer_id) {
+                None => added.push(DiffOffer {
+                    personal,
+                    offer: offer.clone(),
+                }),
+                Some(old) => {
+                    let changes = field_changes(old, offer);
+                    if !changes.is_empty() {
+                        changed.push(OfferChange {
+                            personal,
+                            offer: offer.clone(),
+                            changes,
+                        });
+                    }
+                }
+            }
+        }
+        let removed = offers(old)
+            .filter(|(_, offer)| !new_offers.contains_key(&offer.offer_id))
+            .map(|(personal, offer)| DiffOffer {
+                personal,
+                offer: offer.clone(),
+            })
+            .collect();
+        Self {
+            added,
+            removed,
+            changed,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// The personal offers of a store, then the public ones.
+fn offers(store: &Store) -> impl Iterator<Item = (bool, &Offer)> {
+    store
+        .personal
+        .iter()
+        .map(|offer| (true, offer))
+        .chain(store.public.iter().map(|offer| (false, offer)))
+}
+
+/// The compared fields of an offer.
+fn fields(offer: &Offer) -> [(&'static str, Value); 6] {
+    let item = offer.description.overrides.item();
+    let to_value = |value: Option<Value>| value.unwrap_or(Value::Null);
+    [
+        ("price", offer.price.amount.amount.into()),
+        ("state", offer.state.as_str().into()),
+        ("rarity", to_value(item.map(|item| item.rarity.into()))),
+        (
+            "itemLevel",
+            to_value(item.map(|item| item.item_level.into())),
+        ),
+        (
+            "traits",
+            to_value(item.and_then(|item| serde_json::to_value(&item.traits).ok())),
+        ),
+        (
+            "perks",
+            to_value(item.and_then(|item| serde_json::to_value(&item.perks).ok())),
+        ),
+    ]
+}
+
+fn field_changes(old: &Offer, new: &Offer) -> Vec<FieldChange> {
+    fields(old)
+        .into_iter()
+        .zip(fields(new))
+        .filter(|((_, old), (_, new))| old != new)
+        .map(|((field, old), (_, new))| FieldChange { field, old, new })
+        .collect()
+}
+
+fn read_store(path: &Path) -> Result<Store> {
+    let json = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_slice(&json)
+        .with_context(|| format!("Failed to parse {} as a store", path.display()))
+}
+
+/// Print the offers added, removed and changed from the store in `old` to the
+/// one in `new`.
+pub(crate) fn diff_files(old: &Path, new: &Path) -> Result<()> {
+    let diff = StoreDiff::new(&read_store(old)?, &read_store(new)?);
+    if diff.is_empty() {
+        println!("No differences");
+    } else {
+        println!(
+            "{}",
+            present::diff_table(&diff).with_color(present::use_color())
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(json: &str) -> Store {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn diffs_rotated_store() {
+        let old = fixture(include_str!("../tests/fixtures/store.json"));
+        let new = fixture(include_str!("../tests/fixtures/store_rotated.json"));
+        let diff = StoreDiff::new(&old, &new);
+        let names = |offers: &[DiffOffer]| {
+            offers
+                .iter()
+                .map(|o| o.offer.sku.name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&diff.added), ["Munitorum Mk III Power Sword"]);
+        assert_eq!(names(&diff.removed), ["Kantrael Mk IIb Lasgun"]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].offer.sku.name, "Lucius Mk V Helbore Lasgun");
+        assert_eq!(
+            diff.changed[0].changes,
+            [FieldChange {
+                field: "price",
+                old: 2150.into(),
+                new: 1900.into(),
+            }]
+        );
+        assert!(StoreDiff::new(&new, &new).is_empty());
+    }
+}
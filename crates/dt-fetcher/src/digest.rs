@@ -0,0 +1,304 @@
+//! Batches watch matches and new rotations into one digest per account, so
+//! notifiers send a single message instead of one per matching offer.
+//!
+//! Digests are sent after each prefetch of an account, or every `windowSecs`
+//! if set.
+
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use dt_api::models::AccountId;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, instrument};
+
+use crate::{
+    config::Config,
+    notify::{Event, Notifiers, RotatedStore},
+    present,
+    push::PushPriority,
+    watchlist::WatchMatch,
+};
+
+/// How often the config is checked while no window is set.
+const DISABLED_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How events are batched into digests.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DigestConfig {
+    /// Seconds over which events are batched; one digest per prefetch of an
+    /// account if `None`.
+    #[serde(default)]
+    pub window_secs: Option<u64>,
+    /// Body of the digests, with placeholders like `{matches}` replaced.
+    #[serde(default = "default_template")]
+    pub template: String,
+}
+
+impl Default for DigestConfig {
+    fn default() -> Self {
+        Self {
+            window_secs: None,
+            template: default_template(),
+        }
+    }
+}
+
+fn default_template() -> String {
+    "{matchCount} watch matches\n{matches}\n\n{rotationCount} new stores\n{rotations}".to_string()
+}
+
+/// Watch matches and new rotations of an account, batched into one event.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Digest {
+    pub account_id: AccountId,
+    /// When the first event of the digest happened.
+    pub since: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+    pub rotations: Vec<RotatedStore>,
+    pub matches: Vec<WatchMatch>,
+    /// The rendered template.
+    pub body: String,
+}
+
+impl Digest {
+    fn new(account_id: AccountId, pending: Pending, until: DateTime<Utc>, template: &str) -> Self {
+        let mut digest = Self {
+            account_id,
+            since: pending.since,
+            until,
+            rotations: pending.rotations,
+            matches: pending.matches,
+            body: String::new(),
+        };
+        digest.body = digest.render(template);
+        digest
+    }
+
+    /// Replace the placeholders of `template`: `{accountId}`, `{since}`,
+    /// `{until}`, `{matchCount}`, `{matches}`, `{rotationCount}` and
+    /// `{rotations}`.
+    fn render(&self, template: &str) -> String {
+        let mut matches = Vec::new();
+        for watch_match in &self.matches {
+            let offer = &watch_match.offer.offer;
+            let mut line = format!("• {}", offer.sku.name);
+            if let Some(rarity) = offer
+                .description
+                .overrides
+                .item()
+                .and_then(|item| present::rarity_name(item.rarity))
+            {
+                let _ = write!(line, " ({rarity})");
+            }
+            let _ = write!(
+                line,
+                ", {} {}",
+                offer.price.amount.formatted(),
+                watch_match.offer.currency_type
+            );
+            if let Some(character) = &watch_match.offer.character_name {
+                let _ = write!(line, " for {character}");
+            }
+            if let Some(name) = &watch_match.watch_name {
+                let _ = write!(line, " [{name}]");
+            }
+            matches.push(line);
+        }
+        let rotations: Vec<_> = self
+            .rotations
+            .iter()
+            .map(|rotation| {
+                format!(
+                    "• {}, {}: {} new offers until {} UTC",
+                    rotation.character_name,
+                    rotation.currency_type,
+                    rotation.new_offers.len(),
+                    rotation.rotation_end.format("%Y-%m-%d %H:%M")
+                )
+            })
+            .collect();
+        let format_time = |time: DateTime<Utc>| time.format("%Y-%m-%d %H:%M UTC").to_string();
+        template
+            .replace("{accountId}", &self.account_id.0.to_string())
+            .replace("{since}", &format_time(self.since))
+            .replace("{until}", &format_time(self.until))
+            .replace("{matchCount}", &self.matches.len().to_string())
+            .replace("{matches}", &matches.join("\n"))
+            .replace("{rotationCount}", &self.rotations.len().to_string())
+            .replace("{rotations}", &rotations.join("\n"))
+    }
+
+    /// The highest push priority of the matches, if any is pushed.
+    pub fn push_priority(&self) -> Option<PushPriority> {
+        self.matches.iter().filter_map(|m| m.push_priority).max()
+    }
+}
+
+/// Events of an account waiting for its next digest.
+#[derive(Debug)]
+struct Pending {
+    since: DateTime<Utc>,
+    rotations: Vec<RotatedStore>,
+    matches: Vec<WatchMatch>,
+}
+
+/// Events waiting for their digests, by account.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DigestBuffer(Arc<Mutex<HashMap<AccountId, Pending>>>);
+
+impl DigestBuffer {
+    /// Add an event to the next digest of its account. Returns whether it was
+    /// added; only watch matches and new rotations are batched.
+    pub fn add(&self, event: &Event, now: DateTime<Utc>) -> bool {
+        let account_id = match event {
+            Event::StoresRotated { account_id, .. } => *account_id,
+            Event::WatchMatched(watch_match) => watch_match.offer.account_id,
+            _ => return false,
+        };
+        let mut buffer = self.0.lock().expect("Digest buffer poisoned");
+        let pending = buffer.entry(account_id).or_insert_with(|| Pending {
+            since: now,
+            rotations: Vec::new(),
+            matches: Vec::new(),
+        });
+        match event {
+            Event::StoresRotated { rotations, .. } => {
+                pending.rotations.extend(rotations.iter().cloned())
+            }
+            Event::WatchMatched(watch_match) => pending.matches.push((**watch_match).clone()),
+            _ => unreachable!("only batched events are added"),
+        }
+        true
+    }
+
+    /// Take the digests of an account, or of every account if `None`.
+    pub fn take(
+        &self,
+        account_id: Option<AccountId>,
+        now: DateTime<Utc>,
+        template: &str,
+    ) -> Vec<Digest> {
+        let mut buffer = self.0.lock().expect("Digest buffer poisoned");
+        let pending: Vec<_> = match account_id {
+            Some(id) => buffer.remove(&id).map(|p| (id, p)).into_iter().collect(),
+            None => buffer.drain().collect(),
+        };
+        pending
+            .into_iter()
+            .map(|(id, pending)| Digest::new(id, pending, now, template))
+            .collect()
+    }
+}
+
+/// Sends the digests every `windowSecs`, and whatever is left on shutdown or
+/// once digests are turned off.
+pub(crate) struct DigestScheduler {
+    notifiers: Notifiers,
+    config: watch::Receiver<Config>,
+}
+
+impl DigestScheduler {
+    pub fn new(notifiers: Notifiers, config: watch::Receiver<Config>) -> Self {
+        Self { notifiers, config }
+    }
+
+    #[instrument(skip_all)]
+    pub async fn start(self, token: CancellationToken) -> Result<()> {
+        loop {
+            let digest = self.config.borrow().digest.clone();
+            let window = digest.as_ref().and_then(|digest| digest.window_secs);
+            let wait = window.map_or(DISABLED_INTERVAL, |secs| Duration::from_secs(secs.max(1)));
+            tokio::select! {
+                biased;
+                _ = token.cancelled() => {
+                    info!("Sending digests before shutting down");
+                    self.notifiers.send_digests(None).await;
+                    return Ok(());
+                }
+                _ = tokio::time::sleep(wait) => {}
+            }
+            if window.is_some() || digest.is_none() {
+                self.notifiers.send_digests(None).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use dt_api::models::{CharacterId, CurrencyType, Store};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{account::OfferMatch, watchlist::WatchId};
+
+    #[test]
+    fn batches_events_by_account() {
+        let account_id = AccountId(Uuid::nil());
+        let store: Store =
+            serde_json::from_str(include_str!("../tests/fixtures/store.json")).unwrap();
+        let offer = store.offers().next().unwrap().clone();
+        let since = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let buffer = DigestBuffer::default();
+
+        let rotated = Event::StoresRotated {
+            account_id,
+            rotations: vec![RotatedStore {
+                character_id: CharacterId(Uuid::nil()),
+                character_name: "Ash".to_string(),
+                currency_type: CurrencyType::Marks,
+                rotation_end: since + chrono::Duration::hours(1),
+                new_offers: vec![offer.sku.name.clone()],
+            }],
+        };
+        assert!(buffer.add(&rotated, since));
+        for push_priority in [Some(PushPriority::Low), Some(PushPriority::High), None] {
+            let matched = Event::WatchMatched(Box::new(WatchMatch {
+                watch_id: WatchId(Uuid::nil()),
+                watch_name: None,
+                push_priority,
+                offer: OfferMatch {
+                    account_id,
+                    character_id: CharacterId(Uuid::nil()),
+                    character_name: Some("Ash".to_string()),
+                    currency_type: CurrencyType::Marks,
+                    offer: offer.clone(),
+                },
+            }));
+            assert!(buffer.add(&matched, since + chrono::Duration::minutes(1)));
+        }
+        assert!(!buffer.add(&Event::NeedsReauth { account_id }, since));
+        assert!(buffer
+            .take(Some(AccountId(Uuid::from_u128(1))), since, "")
+            .is_empty());
+
+        let until = since + chrono::Duration::minutes(5);
+        let digests = buffer.take(
+            None,
+            until,
+            "{matchCount} matches, {rotationCount} stores from {since} to {until}\n{rotations}",
+        );
+        assert_eq!(digests.len(), 1);
+        let digest = &digests[0];
+        assert_eq!(digest.since, since);
+        assert_eq!(digest.matches.len(), 3);
+        assert_eq!(digest.push_priority(), Some(PushPriority::High));
+        assert_eq!(
+            digest.body,
+            "3 matches, 1 stores from 2024-01-01 12:00 UTC to 2024-01-01 12:05 UTC\n\
+             • Ash, marks: 1 new offers until 2024-01-01 13:00 UTC"
+        );
+        assert!(buffer.take(None, until, "").is_empty());
+    }
+}
@@ -0,0 +1,180 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use dt_api::{
+    drift::DriftKind,
+    models::{CurrencyType, Inventory, MasterData, Store, Summary, Wallets},
+    Auth, Endpoint,
+};
+use futures::future::Either;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::{sync::watch, time::Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, instrument, warn};
+
+use crate::{account::Accounts, auth::AuthData, config::Config, upstream::Upstream};
+
+/// Periodically compares raw upstream responses with the typed models to detect
+/// upstream schema changes.
+///
+/// Checks run every `drift_check_interval` seconds of the current config and
+/// are paused while it is unset.
+#[derive(Debug)]
+pub(crate) struct DriftDetector {
+    api: Upstream,
+    accounts: Accounts,
+    auth_data: AuthData,
+    config: watch::Receiver<Config>,
+}
+
+impl DriftDetector {
+    pub fn new(
+        api: Upstream,
+        accounts: Accounts,
+        auth_data: AuthData,
+        config: watch::Receiver<Config>,
+    ) -> Self {
+        Self {
+            api,
+            accounts,
+            auth_data,
+            config,
+        }
+    }
+
+    #[instrument(skip_all)]
+    pub async fn start(mut self, token: CancellationToken) -> Result<()> {
+        let mut last_check: Option<Instant> = None;
+        let mut interval = None;
+        loop {
+            let new_interval = self.config.borrow_and_update().drift_check_interval;
+            if new_interval != interval {
+                match new_interval {
+                    Some(interval) => info!(interval, "Enabling schema drift detection"),
+                    None if interval.is_some() => info!("Disabling schema drift detection"),
+                    None => {}
+                }
+                interval = new_interval;
+            }
+            let next_check = match interval {
+                Some(interval) => Either::Left(tokio::time::sleep_until(
+                    last_check
+                        .map(|last_check| last_check + Duration::from_secs(interval))
+                        .unwrap_or_else(Instant::now),
+                )),
+                None => Either::Right(futures::future::pending()),
+            };
+            tokio::select! {
+                // The config channel closes during shutdown, which isn't an error.
+                biased;
+                _ = token.cancelled() => {
+                    info!("Shutting down schema drift detector");
+                    return Ok(());
+                }
+                res = self.config.changed() => res?,
+                _ = next_check => {
+                    last_check = Some(Instant::now());
+                    self.check_accounts().await;
+                }
+            }
+        }
+    }
+
+    #[instrument(skip_all)]
+    async fn check_accounts(&self) {
+        for (id, _) in self.accounts.list().await {
+            match self.auth_data.get(id) {
+                Ok(Some(auth)) => self.check_account(&auth).await,
+                Ok(None) => warn!(sid = ?id, "Failed to find auth data"),
+                Err(e) => error!(sid = ?id, error = %e, "Failed to get auth data"),
+            }
+        }
+    }
+
+    #[instrument(skip_all, fields(sub = ?auth.sub))]
+    async fn check_account(&self, auth: &Auth) {
+        let summary = self.check::<Summary>(auth, Endpoint::Summary).await;
+        self.check::<MasterData>(auth, Endpoint::MasterData).await;
+        self.check::<Wallets>(auth, Endpoint::Wallets).await;
+        for character in summary.iter().flat_map(|s| s.characters.iter()) {
+            self.check::<Inventory>(auth, Endpoint::Inventory { character })
+                .await;
+            for currency_type in [CurrencyType::Marks, CurrencyType::Credits] {
+                self.check::<Store>(
+                    auth,
+                    Endpoint::Store {
+                        currency_type,
+                        character,
+                    },
+                )
+                .await;
+            }
+        }
+    }
+
+    async fn check<M: DeserializeOwned + Serialize>(
+        &self,
+        auth: &Auth,
+        endpoint: Endpoint<'_>,
+    ) -> Option<M> {
+        let label = endpoint_label(endpoint);
+        let raw = match self.api.get_raw(auth, endpoint).await {
+            Ok(raw) => raw,
+            Err(e) => {
+                warn!(endpoint = %endpoint, error = %e, "Failed to get raw response");
+                return None;
+            }
+        };
+        let (model, drifts) = match dt_api::drift::check::<M>(&raw) {
+            Ok(checked) => checked,
+            Err(e) => {
+                error!(endpoint = %endpoint, error = %e, "Response no longer matches model");
+                metrics::counter!(
+                    "dt_fetcher_schema_drift_total",
+                    "endpoint" => label,
+                    "kind" => "parse_error"
+                )
+                .increment(1);
+                return None;
+            }
+        };
+        if drifts.is_empty() {
+            info!(endpoint = %endpoint, "No schema drift detected");
+        }
+        for drift in drifts {
+            warn!(
+                endpoint = %endpoint,
+                path = %drift.path,
+                kind = %drift.kind,
+                "Schema drift detected"
+            );
+            metrics::counter!(
+                "dt_fetcher_schema_drift_total",
+                "endpoint" => label,
+                "kind" => kind_label(&drift.kind)
+            )
+            .increment(1);
+        }
+        Some(model)
+    }
+}
+
+fn endpoint_label(endpoint: Endpoint<'_>) -> &'static str {
+    match endpoint {
+        Endpoint::Summary => "summary",
+        Endpoint::Store { .. } | Endpoint::PublicStore { .. } => "store",
+        Endpoint::Inventory { .. } => "inventory",
+        Endpoint::Wallets => "wallets",
+        Endpoint::MasterData => "master_data",
+        Endpoint::Page { .. } => "page",
+    }
+}
+
+fn kind_label(kind: &DriftKind) -> &'static str {
+    match kind {
+        DriftKind::UnknownField => "unknown_field",
+        DriftKind::MissingField => "missing_field",
+        DriftKind::TypeMismatch { .. } => "type_mismatch",
+        DriftKind::ValueMismatch => "value_mismatch",
+    }
+}
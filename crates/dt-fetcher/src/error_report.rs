@@ -0,0 +1,94 @@
+//! Reports of panics and errors to Sentry.
+
+use std::collections::BTreeMap;
+
+use axum::{
+    body::Body,
+    http::{Method, Request},
+    middleware::Next,
+    response::Response,
+};
+use dt_api::Auth;
+use sentry::{protocol::Value, ClientInitGuard, Level};
+
+use crate::server::ClientIp;
+
+/// Start reporting to the Sentry project of `dsn`, including panics. Reports
+/// are sent until the guard is dropped.
+pub(crate) fn init(dsn: &str) -> ClientInitGuard {
+    sentry::init((
+        dsn,
+        sentry::ClientOptions {
+            release: sentry::release_name!(),
+            // Requests and auths hold tokens; only what is added explicitly is
+            // sent.
+            send_default_pii: false,
+            ..Default::default()
+        },
+    ))
+}
+
+/// Report `5xx` responses with the method, path and IDs of their request.
+///
+/// Headers, including `Authorization`, and bodies are not reported.
+pub(crate) async fn report_server_errors(request: Request<Body>, next: Next) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let client_ip = request.extensions().get::<ClientIp>().copied();
+    let response = next.run(request).await;
+    let status = response.status();
+    if status.is_server_error() {
+        report_server_error(&method, &path, status.as_u16(), request_id, client_ip);
+    }
+    response
+}
+
+fn report_server_error(
+    method: &Method,
+    path: &str,
+    status: u16,
+    request_id: Option<String>,
+    client_ip: Option<ClientIp>,
+) {
+    sentry::with_scope(
+        |scope| {
+            scope.set_tag("http.method", method);
+            scope.set_tag("http.status", status);
+            if let Some(request_id) = &request_id {
+                scope.set_tag("request_id", request_id);
+            }
+            let mut request = BTreeMap::new();
+            request.insert("method".to_string(), Value::from(method.as_str()));
+            request.insert("path".to_string(), Value::from(path));
+            if let Some(ClientIp(ip)) = client_ip {
+                request.insert("client_ip".to_string(), Value::from(ip.to_string()));
+            }
+            scope.set_context("request", sentry::protocol::Context::Other(request));
+        },
+        || sentry::capture_message(&format!("{method} {path} responded {status}"), Level::Error),
+    );
+}
+
+/// Report a failed auth refresh of `auth`'s account, with its tokens removed
+/// from the error.
+pub(crate) fn report_refresh_failure(auth: &Auth, error: &dyn std::fmt::Display) {
+    let message = strip_tokens(&error.to_string(), auth);
+    sentry::with_scope(
+        |scope| scope.set_tag("account_id", auth.sub),
+        || sentry::capture_message(&format!("Failed to refresh auth: {message}"), Level::Error),
+    );
+}
+
+fn strip_tokens(message: &str, auth: &Auth) -> String {
+    [&auth.access_token, &auth.refresh_token]
+        .into_iter()
+        .filter(|token| !token.is_empty())
+        .fold(message.to_string(), |message, token| {
+            message.replace(token.as_str(), "[redacted]")
+        })
+}
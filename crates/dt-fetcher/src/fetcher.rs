@@ -0,0 +1,430 @@
+//! The fetcher as a library: the endpoints as a router to serve from another
+//! axum app, and the background tasks keeping the cache fresh.
+
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{Context, Result};
+use axum::Router;
+use dt_api::{models::AccountId, Auth};
+use tokio::{sync::watch, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+#[cfg(feature = "s3")]
+use crate::history::{S3HistoryStorage, S3Uploader};
+
+use crate::{
+    account::{AccountData, Accounts, CacheMonitor},
+    auth::SledDbAuthStorage,
+    auth::{AuthData, AuthManager, ErasedAuthStorage, FilteredAuthStorage, InMemoryAuthStorage},
+    cluster::ClusterMember,
+    config::Config,
+    coordination::Coordinator,
+    database::DbMonitor,
+    digest::DigestScheduler,
+    drift::DriftDetector,
+    history::{History, InMemoryHistoryStorage, SledDbHistoryStorage},
+    metrics_push::MetricsPusher,
+    notify::Notifiers,
+    prefetch::Prefetcher,
+    request_queue::Priority,
+    retention::RetentionMonitor,
+    server::{normalize_path_prefix, Server},
+    settings::{InMemorySettingsStorage, Settings, SledDbSettingsStorage},
+    slo::SloMonitor,
+    supervisor::Supervisor,
+    upstream::Upstream,
+    watchlist::{InMemoryWatchlistStorage, SledDbWatchlistStorage, Watchlists},
+};
+
+/// Auth manager restarts allowed per 10 minutes, unless set.
+const DEFAULT_MAX_RESTARTS: usize = 3;
+
+/// Where auths, watchlists, settings and rotation history are kept.
+#[derive(Debug, Clone, Default)]
+pub enum Storage {
+    /// Lost on exit.
+    #[default]
+    InMemory,
+    /// A sled database at the path, created if missing.
+    Sled(PathBuf),
+}
+
+/// Builds a [`Fetcher`].
+#[derive(Debug)]
+pub struct FetcherBuilder {
+    api: Option<dt_api::Api>,
+    storage: Storage,
+    accounts: Vec<uuid::Uuid>,
+    config_file: Option<PathBuf>,
+    config: Option<watch::Receiver<Config>>,
+    coordinator: Option<Coordinator>,
+    single_endpoints: bool,
+    prefetch: Option<bool>,
+    static_dir: Option<PathBuf>,
+    path_prefix: String,
+    max_restarts: usize,
+    #[cfg(feature = "s3")]
+    s3_archive: Option<(S3HistoryStorage, S3Uploader)>,
+}
+
+impl Default for FetcherBuilder {
+    fn default() -> Self {
+        Self {
+            api: None,
+            storage: Storage::default(),
+            accounts: Vec::new(),
+            config_file: None,
+            config: None,
+            coordinator: None,
+            single_endpoints: true,
+            prefetch: None,
+            static_dir: None,
+            path_prefix: String::new(),
+            max_restarts: DEFAULT_MAX_RESTARTS,
+            #[cfg(feature = "s3")]
+            s3_archive: None,
+        }
+    }
+}
+
+impl FetcherBuilder {
+    /// Client for the upstream API; one with the default settings if unset.
+    pub fn api(mut self, api: dt_api::Api) -> Self {
+        self.api = Some(api);
+        self
+    }
+
+    pub fn storage(mut self, storage: Storage) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    /// Only serve these accounts from the storage.
+    pub fn accounts(mut self, accounts: impl IntoIterator<Item = uuid::Uuid>) -> Self {
+        self.accounts = accounts.into_iter().collect();
+        self
+    }
+
+    /// Load the config from a JSON file, as with `--config`. It isn't
+    /// reloaded when it changes.
+    pub fn config_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_file = Some(path.into());
+        self
+    }
+
+    /// Serve the `single` endpoint variants. Enabled by default.
+    pub fn single_endpoints(mut self, enabled: bool) -> Self {
+        self.single_endpoints = enabled;
+        self
+    }
+
+    /// Fetch stores as soon as they rotate, overriding the config.
+    pub fn prefetch(mut self, enabled: bool) -> Self {
+        self.prefetch = Some(enabled);
+        self
+    }
+
+    /// Serve a frontend from this directory for paths without an endpoint.
+    pub fn static_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.static_dir = Some(dir.into());
+        self
+    }
+
+    /// Serve the routes under this path, e.g. `/darktide` when a reverse
+    /// proxy forwards that path with the prefix kept.
+    pub fn path_prefix(mut self, prefix: &str) -> Self {
+        self.path_prefix = normalize_path_prefix(prefix);
+        self
+    }
+
+    /// Auth manager restarts allowed per 10 minutes before the other tasks
+    /// are shut down.
+    pub fn max_restarts(mut self, max_restarts: usize) -> Self {
+        self.max_restarts = max_restarts;
+        self
+    }
+
+    /// Use a reloadable config instead of loading it once.
+    pub(crate) fn config(mut self, config: watch::Receiver<Config>) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    pub(crate) fn coordinator(mut self, coordinator: Coordinator) -> Self {
+        self.coordinator = Some(coordinator);
+        self
+    }
+
+    /// Archive rotations in object storage instead of the storage.
+    #[cfg(feature = "s3")]
+    pub(crate) fn s3_archive(mut self, storage: S3HistoryStorage, uploader: S3Uploader) -> Self {
+        self.s3_archive = Some((storage, uploader));
+        self
+    }
+
+    /// Open the storage and create the fetcher, without starting anything.
+    pub fn build(self) -> Result<Fetcher> {
+        let config = match self.config {
+            Some(config) => config,
+            None => {
+                let mut config = Config::load(&Config::default(), self.config_file.as_ref())?;
+                if let Some(prefetch) = self.prefetch {
+                    config.prefetch = prefetch;
+                }
+                // Nothing sends updates, so the receiver keeps the config.
+                watch::channel(config).1
+            }
+        };
+        let api = match self.api {
+            Some(api) => api,
+            None => config.borrow().upstream.api()?,
+        };
+
+        let (auth_storage, watchlist_storage, settings_storage, history_storage, db) =
+            match self.storage {
+                Storage::Sled(db_path) => {
+                    info!("Using database at {} for storage", db_path.display());
+                    let auth_storage = SledDbAuthStorage::new(db_path)?;
+                    let watchlist_storage = SledDbWatchlistStorage::new(auth_storage.db())?;
+                    let settings_storage = SledDbSettingsStorage::new(auth_storage.db())?;
+                    let history_storage = SledDbHistoryStorage::new(auth_storage.db())?;
+                    let db = auth_storage.db().clone();
+                    (
+                        auth_storage.into(),
+                        watchlist_storage.into(),
+                        settings_storage.into(),
+                        history_storage.into(),
+                        Some(db),
+                    )
+                }
+                Storage::InMemory => {
+                    info!("Using in-memory storage");
+                    (
+                        InMemoryAuthStorage::default().into(),
+                        InMemoryWatchlistStorage::default().into(),
+                        InMemorySettingsStorage::default().into(),
+                        InMemoryHistoryStorage::default().into(),
+                        None,
+                    )
+                }
+            };
+        #[cfg(feature = "s3")]
+        let (history_storage, s3_uploader) = match self.s3_archive {
+            Some((storage, uploader)) => {
+                info!("Archiving rotations in object storage");
+                (storage.into(), Some(uploader))
+            }
+            None => (history_storage, None),
+        };
+        let auth_storage: ErasedAuthStorage = if self.accounts.is_empty() {
+            auth_storage
+        } else {
+            info!(accounts = ?self.accounts, "Only serving some accounts");
+            FilteredAuthStorage::new(auth_storage, self.accounts.iter().copied().map(AccountId))
+                .into()
+        };
+
+        let coordinator = self.coordinator.unwrap_or_else(|| Coordinator::local(None));
+        let recycle_after = config.borrow().upstream.recycle_after_connect_errors;
+        let api = Upstream::new(api, coordinator, History::new(history_storage))
+            .with_recycling(recycle_after);
+        let accounts = Accounts::default();
+        let settings = Settings::new(settings_storage);
+        let notifiers = Notifiers::new(config.clone());
+        let auth_manager = AuthManager::new_with_storage(
+            api.with_priority(Priority::Refresh),
+            accounts.clone(),
+            auth_storage,
+            notifiers.clone(),
+            settings.clone(),
+            config.clone(),
+        );
+        Ok(Fetcher {
+            api,
+            accounts,
+            auth_data: auth_manager.auth_data(),
+            auth_manager,
+            watchlists: Watchlists::new(watchlist_storage),
+            settings,
+            notifiers,
+            config,
+            db,
+            single_endpoints: self.single_endpoints,
+            static_dir: self.static_dir,
+            path_prefix: self.path_prefix,
+            max_restarts: self.max_restarts,
+            #[cfg(feature = "s3")]
+            s3_uploader,
+        })
+    }
+}
+
+/// The endpoints and background tasks of dt-fetcher, to embed in another
+/// application.
+///
+/// Serve [`Fetcher::router`] and run [`Fetcher::spawn`] for the cache to be
+/// kept fresh and the auths refreshed.
+pub struct Fetcher {
+    api: Upstream,
+    accounts: Accounts,
+    auth_manager: AuthManager,
+    auth_data: AuthData,
+    watchlists: Watchlists,
+    settings: Settings,
+    /// Shared by every task, so notifiers can limit their rate across them.
+    notifiers: Notifiers,
+    config: watch::Receiver<Config>,
+    db: Option<sled::Db>,
+    single_endpoints: bool,
+    static_dir: Option<PathBuf>,
+    path_prefix: String,
+    max_restarts: usize,
+    #[cfg(feature = "s3")]
+    s3_uploader: Option<S3Uploader>,
+}
+
+impl Fetcher {
+    pub fn builder() -> FetcherBuilder {
+        FetcherBuilder::default()
+    }
+
+    /// Add the auth of an account and start refreshing it.
+    pub async fn add_auth(&self, auth: Auth) -> Result<()> {
+        self.auth_data
+            .add_auth(auth)
+            .await
+            .context("Failed to add auth")
+    }
+
+    /// The endpoints, to merge or nest into another router.
+    ///
+    /// `POST /admin/shutdown` cancels `token`. The client IP of requests is
+    /// taken from their `ConnectInfo<SocketAddr>`, so serve the app with
+    /// `into_make_service_with_connect_info::<SocketAddr>()`.
+    pub fn router(&self, token: CancellationToken) -> Router {
+        self.server().into_router(token)
+    }
+
+    /// Spawn the background tasks, which run until `token` is cancelled or
+    /// one of them fails.
+    pub fn spawn(&self, token: CancellationToken) -> Tasks {
+        let mut tasks = Tasks::default();
+        self.spawn_with(&Supervisor::new(token, self.max_restarts), &mut tasks);
+        tasks
+    }
+
+    pub(crate) fn server(&self) -> Server {
+        Server::new(
+            self.api.clone(),
+            self.accounts.clone(),
+            self.auth_data.clone(),
+            self.watchlists.clone(),
+            self.settings.clone(),
+            self.config.clone(),
+            self.static_dir.clone(),
+            self.single_endpoints,
+            self.path_prefix.clone(),
+        )
+    }
+
+    pub(crate) fn spawn_with(&self, supervisor: &Supervisor, tasks: &mut Tasks) {
+        let token = supervisor.token();
+        let config = &self.config;
+        let rebuild_interval = config
+            .borrow()
+            .upstream
+            .rebuild_interval_secs
+            .map(Duration::from_secs);
+        let drift_detector = DriftDetector::new(
+            self.api.with_priority(Priority::Archive),
+            self.accounts.clone(),
+            self.auth_data.clone(),
+            config.clone(),
+        );
+        let prefetcher = Prefetcher::new(
+            self.api.with_priority(Priority::Prefetch),
+            self.accounts.clone(),
+            self.auth_data.clone(),
+            self.watchlists.clone(),
+            self.settings.clone(),
+            self.notifiers.clone(),
+            config.clone(),
+        );
+        let cache_monitor = CacheMonitor::new(self.accounts.clone(), config.clone());
+        let cluster_member =
+            ClusterMember::new(self.api.coordinator().clone(), self.auth_data.clone());
+        let slo_monitor = SloMonitor::new(
+            self.api.slo().clone(),
+            self.notifiers.clone(),
+            config.clone(),
+        );
+        let retention_monitor = RetentionMonitor::new(self.api.history().clone(), config.clone());
+        let digest_scheduler = DigestScheduler::new(self.notifiers.clone(), config.clone());
+
+        // Each run reloads the auths from storage.
+        tasks.push(supervisor.spawn_restarting("auth manager", {
+            let auth_manager = self.auth_manager.clone();
+            let token = token.clone();
+            move || auth_manager.clone().start(token.clone())
+        }));
+        tasks.push(supervisor.spawn("drift detector", drift_detector.start(token.clone())));
+        tasks.push(supervisor.spawn("prefetcher", prefetcher.start(token.clone())));
+        tasks.push(supervisor.spawn("cache monitor", cache_monitor.start(token.clone())));
+        tasks.push(supervisor.spawn("SLO monitor", slo_monitor.start(token.clone())));
+        tasks.push(supervisor.spawn("retention monitor", retention_monitor.start(token.clone())));
+        tasks.push(supervisor.spawn("digest scheduler", digest_scheduler.start(token.clone())));
+        tasks.push(supervisor.spawn(
+            "metrics pusher",
+            MetricsPusher::new(config.clone()).start(token.clone()),
+        ));
+        tasks.push(
+            supervisor.spawn(
+                "upstream rebuilder",
+                self.api
+                    .clone()
+                    .rebuild_periodically(rebuild_interval, token.clone()),
+            ),
+        );
+        tasks.push(supervisor.spawn("cluster member", cluster_member.start(token.clone())));
+        #[cfg(feature = "s3")]
+        if let Some(uploader) = &self.s3_uploader {
+            tasks.push(supervisor.spawn("archive uploader", uploader.clone().start(token.clone())));
+        }
+        tasks.push(supervisor.spawn(
+            "database monitor",
+            DbMonitor::new(self.db.clone()).start(token),
+        ));
+    }
+
+    pub(crate) fn auth_data(&self) -> &AuthData {
+        &self.auth_data
+    }
+
+    /// Seed the cache of an account, e.g. from an exported bundle.
+    pub(crate) async fn seed(&self, id: AccountId, data: AccountData) {
+        self.accounts.insert(id, data).await;
+    }
+}
+
+/// Handles of the background tasks of a [`Fetcher`].
+#[derive(Debug, Default)]
+pub struct Tasks {
+    handles: Vec<JoinHandle<Result<()>>>,
+}
+
+impl Tasks {
+    pub(crate) fn push(&mut self, handle: JoinHandle<Result<()>>) {
+        self.handles.push(handle);
+    }
+
+    /// Wait for every task to finish, failing with the first task that
+    /// failed. Failures are also logged as they happen.
+    pub async fn join(self) -> Result<()> {
+        let results = futures::future::try_join_all(self.handles).await?;
+        for result in results {
+            result?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use dt_api::models::{AccountId, CharacterId, CurrencyType, Store};
+use serde::{Deserialize, Serialize};
+use tracing::{error, instrument};
+
+#[cfg(feature = "s3")]
+mod s3;
+#[cfg(feature = "s3")]
+pub(crate) use s3::{S3HistoryStorage, S3Location, S3Uploader};
+mod storage;
+pub(crate) use storage::{
+    ErasedHistoryStorage, HistoryStorage, InMemoryHistoryStorage, SledDbHistoryStorage,
+};
+
+/// Store rotation as it was first fetched.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RotationSnapshot {
+    pub account_id: AccountId,
+    pub character_id: CharacterId,
+    pub currency_type: CurrencyType,
+    pub archived_at: DateTime<Utc>,
+    pub store: Store,
+}
+
+/// Archive of every store rotation seen.
+#[derive(Debug, Clone)]
+pub(crate) struct History {
+    storage: ErasedHistoryStorage,
+}
+
+impl History {
+    pub fn new(storage: ErasedHistoryStorage) -> Self {
+        Self { storage }
+    }
+
+    /// Archive a fetched store, unless its rotation is already archived.
+    #[instrument(skip(self, store))]
+    pub fn record(
+        &self,
+        account_id: AccountId,
+        character_id: CharacterId,
+        currency_type: CurrencyType,
+        store: &Store,
+    ) {
+        let snapshot = RotationSnapshot {
+            account_id,
+            character_id,
+            currency_type,
+            archived_at: Utc::now(),
+            store: store.clone(),
+        };
+        if let Err(e) = self.storage.insert(&snapshot) {
+            error!(error = %e, "Failed to archive store rotation");
+        }
+    }
+
+    /// Archived rotations of an account, oldest rotation end first.
+    pub fn list(&self, account_id: AccountId) -> Result<Vec<RotationSnapshot>> {
+        self.storage.list(account_id)
+    }
+
+    /// Remove the rotations that ended before `before`, then the oldest beyond
+    /// `max_entries`. Returns how many were removed.
+    pub fn prune(
+        &self,
+        before: Option<DateTime<Utc>>,
+        max_entries: Option<usize>,
+    ) -> Result<usize> {
+        self.storage.prune(before, max_entries)
+    }
+}
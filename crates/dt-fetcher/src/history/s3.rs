@@ -0,0 +1,287 @@
+//! Archive of store rotations in S3-compatible object storage, for instances
+//! without a persistent disk.
+//!
+//! New rotations are uploaded in batches as gzipped NDJSON objects, one
+//! snapshot per line, under `<prefix>/<date>/`. The latest rotations are also
+//! kept in memory, and restored from the bucket on startup, so the feeds and
+//! diffs don't need to read the bucket.
+
+use std::{
+    collections::VecDeque,
+    io::{BufRead, BufReader, Write},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use dt_api::models::AccountId;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use futures::TryStreamExt;
+use object_store::{aws::AmazonS3Builder, path::Path, ObjectStore};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, instrument, warn};
+
+use super::{
+    storage::MAX_IN_MEMORY_SNAPSHOTS, HistoryStorage, InMemoryHistoryStorage, RotationSnapshot,
+};
+
+/// How often pending snapshots are uploaded.
+const UPLOAD_INTERVAL: Duration = Duration::from_secs(60);
+/// Most snapshots waiting to be uploaded; the oldest are dropped beyond this,
+/// so an unreachable bucket doesn't grow memory without limit.
+const MAX_PENDING: usize = 10_000;
+
+/// Where rotations are archived.
+#[derive(Debug, Clone)]
+pub(crate) struct S3Location {
+    pub bucket: String,
+    /// Key prefix of the objects, without a trailing `/`.
+    pub prefix: String,
+}
+
+/// Rotations archived in object storage, with the latest kept in memory.
+#[derive(Debug, Clone)]
+pub(crate) struct S3HistoryStorage {
+    recent: InMemoryHistoryStorage,
+    pending: Arc<Mutex<VecDeque<RotationSnapshot>>>,
+}
+
+impl S3HistoryStorage {
+    /// Connect to the bucket, with credentials, region and endpoint from the
+    /// `AWS_*` environment variables, and restore the latest rotations.
+    pub async fn connect(location: S3Location) -> Result<(Self, S3Uploader)> {
+        let store = AmazonS3Builder::from_env()
+            .with_bucket_name(&location.bucket)
+            .build()
+            .context("Failed to configure S3 archive")?;
+        Self::with_store(Arc::new(store), Path::from(location.prefix)).await
+    }
+
+    async fn with_store(store: Arc<dyn ObjectStore>, prefix: Path) -> Result<(Self, S3Uploader)> {
+        let storage = Self {
+            recent: InMemoryHistoryStorage::default(),
+            pending: Arc::default(),
+        };
+        let restored = restore(&*store, &prefix, &storage.recent).await?;
+        info!(restored, "Restored archived rotations from object storage");
+        let uploader = S3Uploader {
+            store,
+            prefix,
+            pending: storage.pending.clone(),
+        };
+        Ok((storage, uploader))
+    }
+}
+
+impl HistoryStorage for S3HistoryStorage {
+    #[instrument(skip_all)]
+    fn insert(&self, snapshot: &RotationSnapshot) -> Result<()> {
+        if self.recent.insert_new(snapshot) {
+            let mut pending = self.pending.lock().expect("Pending snapshots poisoned");
+            if pending.len() == MAX_PENDING {
+                warn!("Too many rotations waiting to be archived; dropping the oldest");
+                pending.pop_front();
+            }
+            pending.push_back(snapshot.clone());
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    fn list(&self, account: AccountId) -> Result<Vec<RotationSnapshot>> {
+        self.recent.list(account)
+    }
+
+    /// Only prunes the rotations kept in memory; use lifecycle rules of the
+    /// bucket to expire the archived objects.
+    #[instrument(skip(self))]
+    fn prune(&self, before: Option<DateTime<Utc>>, max_entries: Option<usize>) -> Result<usize> {
+        self.recent.prune(before, max_entries)
+    }
+}
+
+/// Uploads the rotations archived by an [`S3HistoryStorage`].
+#[derive(Debug, Clone)]
+pub(crate) struct S3Uploader {
+    store: Arc<dyn ObjectStore>,
+    prefix: Path,
+    pending: Arc<Mutex<VecDeque<RotationSnapshot>>>,
+}
+
+impl S3Uploader {
+    /// Upload pending rotations periodically, and once more on shutdown.
+    #[instrument(skip_all)]
+    pub async fn start(self, token: CancellationToken) -> Result<()> {
+        let mut interval = tokio::time::interval(UPLOAD_INTERVAL);
+        loop {
+            tokio::select! {
+                biased;
+                _ = token.cancelled() => {
+                    info!("Uploading archived rotations before shutting down");
+                    self.upload().await;
+                    return Ok(());
+                }
+                _ = interval.tick() => self.upload().await,
+            }
+        }
+    }
+
+    /// Upload the pending rotations as one object, keeping them pending if
+    /// the upload fails.
+    async fn upload(&self) {
+        let snapshots: Vec<_> = self
+            .pending
+            .lock()
+            .expect("Pending snapshots poisoned")
+            .drain(..)
+            .collect();
+        if snapshots.is_empty() {
+            return;
+        }
+        let now = Utc::now();
+        let location = self
+            .prefix
+            .child(now.format("%Y-%m-%d").to_string())
+            .child(format!(
+                "{}-{}.ndjson.gz",
+                now.timestamp_millis(),
+                uuid::Uuid::new_v4()
+            ));
+        let result = match encode(&snapshots) {
+            Ok(bytes) => self
+                .store
+                .put(&location, bytes.into())
+                .await
+                .map(|_| ())
+                .map_err(anyhow::Error::from),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => {
+                info!(%location, snapshots = snapshots.len(), "Archived rotations");
+                metrics::counter!("dt_fetcher_archive_uploads_total", "outcome" => "success")
+                    .increment(1);
+            }
+            Err(e) => {
+                error!(error = ?e, "Failed to archive rotations");
+                metrics::counter!("dt_fetcher_archive_uploads_total", "outcome" => "failure")
+                    .increment(1);
+                let mut pending = self.pending.lock().expect("Pending snapshots poisoned");
+                for snapshot in snapshots.into_iter().rev() {
+                    if pending.len() < MAX_PENDING {
+                        pending.push_front(snapshot);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Gzipped NDJSON of `snapshots`.
+fn encode(snapshots: &[RotationSnapshot]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    for snapshot in snapshots {
+        serde_json::to_writer(&mut encoder, snapshot).context("Failed to serialize snapshot")?;
+        encoder.write_all(b"\n")?;
+    }
+    Ok(encoder.finish()?)
+}
+
+/// Snapshots of gzipped NDJSON, skipping lines that aren't snapshots.
+fn decode(bytes: &[u8]) -> Result<Vec<RotationSnapshot>> {
+    let mut snapshots = Vec::new();
+    for line in BufReader::new(GzDecoder::new(bytes)).lines() {
+        let line = line.context("Failed to decompress archive")?;
+        if line.is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(snapshot) => snapshots.push(snapshot),
+            Err(e) => warn!(error = %e, "Skipping invalid snapshot"),
+        }
+    }
+    Ok(snapshots)
+}
+
+/// Load the latest archived objects into `recent`, newest first, until it
+/// holds as many rotations as are kept in memory. Returns how many were
+/// restored.
+async fn restore(
+    store: &dyn ObjectStore,
+    prefix: &Path,
+    recent: &InMemoryHistoryStorage,
+) -> Result<usize> {
+    let mut objects: Vec<_> = store
+        .list(Some(prefix))
+        .try_collect()
+        .await
+        .context("Failed to list archived rotations")?;
+    // Keys start with the upload date and time, so they sort chronologically.
+    objects.sort_by(|a, b| b.location.cmp(&a.location));
+    let mut restored = 0;
+    for object in objects {
+        if restored >= MAX_IN_MEMORY_SNAPSHOTS {
+            break;
+        }
+        let bytes = match store.get(&object.location).await {
+            Ok(result) => result.bytes().await,
+            Err(e) => Err(e),
+        };
+        let snapshots = match bytes.map_err(anyhow::Error::from).and_then(|b| decode(&b)) {
+            Ok(snapshots) => snapshots,
+            Err(e) => {
+                warn!(location = %object.location, error = ?e, "Skipping unreadable archive");
+                continue;
+            }
+        };
+        for snapshot in snapshots {
+            if recent.insert_new(&snapshot) {
+                restored += 1;
+            }
+        }
+    }
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use object_store::memory::InMemory;
+
+    use super::*;
+
+    fn snapshot(character: u128) -> RotationSnapshot {
+        RotationSnapshot {
+            account_id: AccountId(uuid::Uuid::nil()),
+            character_id: dt_api::models::CharacterId(uuid::Uuid::from_u128(character)),
+            currency_type: dt_api::models::CurrencyType::Marks,
+            archived_at: Utc::now(),
+            store: serde_json::from_str(include_str!("../../tests/fixtures/store.json")).unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn restores_uploaded_rotations() {
+        let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let prefix = Path::from("history");
+        let (storage, uploader) = S3HistoryStorage::with_store(store.clone(), prefix.clone())
+            .await
+            .unwrap();
+        storage.insert(&snapshot(1)).unwrap();
+        storage.insert(&snapshot(1)).unwrap();
+        storage.insert(&snapshot(2)).unwrap();
+        uploader.upload().await;
+        uploader.upload().await;
+
+        let objects: Vec<_> = store.list(Some(&prefix)).try_collect().await.unwrap();
+        assert_eq!(objects.len(), 1);
+        let (restored, _) = S3HistoryStorage::with_store(store, prefix).await.unwrap();
+        let characters: Vec<_> = restored
+            .list(AccountId(uuid::Uuid::nil()))
+            .unwrap()
+            .into_iter()
+            .map(|snapshot| snapshot.character_id.0.as_u128())
+            .collect();
+        assert_eq!(characters, [1, 2]);
+    }
+}
@@ -0,0 +1,265 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use dt_api::models::{AccountId, CurrencyType};
+use dyn_clone::DynClone;
+use tracing::{instrument, warn};
+
+use super::RotationSnapshot;
+
+pub(crate) trait HistoryStorage: Send + Sync + DynClone + std::fmt::Debug + 'static {
+    /// Archive a snapshot if its rotation isn't archived yet.
+    fn insert(&self, snapshot: &RotationSnapshot) -> Result<()>;
+
+    /// Archived snapshots of an account, oldest rotation end first.
+    fn list(&self, account: AccountId) -> Result<Vec<RotationSnapshot>>;
+
+    /// Remove the snapshots of rotations that ended before `before`, then the
+    /// oldest rotations beyond `max_entries`. Returns how many were removed.
+    fn prune(&self, before: Option<DateTime<Utc>>, max_entries: Option<usize>) -> Result<usize>;
+}
+
+dyn_clone::clone_trait_object!(HistoryStorage);
+
+/// Key that sorts snapshots by account, then rotation end.
+fn snapshot_key(snapshot: &RotationSnapshot) -> Vec<u8> {
+    let mut key = Vec::with_capacity(41);
+    key.extend_from_slice(snapshot.account_id.0.as_bytes());
+    key.extend_from_slice(
+        &snapshot
+            .store
+            .current_rotation_end
+            .timestamp_millis()
+            .to_be_bytes(),
+    );
+    key.extend_from_slice(snapshot.character_id.0.as_bytes());
+    key.push(match snapshot.currency_type {
+        CurrencyType::Marks => 0,
+        CurrencyType::Credits => 1,
+    });
+    key
+}
+
+/// Rotation end in milliseconds of the snapshot at `key`.
+fn key_rotation_end(key: &[u8]) -> Option<i64> {
+    let millis = key.get(16..24)?.try_into().ok()?;
+    Some(i64::from_be_bytes(millis))
+}
+
+/// Keys of the snapshots to prune from `entries` of keys and rotation ends:
+/// those that ended before `before`, then the oldest beyond `max_entries`.
+fn keys_to_prune<K>(
+    mut entries: Vec<(K, i64)>,
+    before: Option<DateTime<Utc>>,
+    max_entries: Option<usize>,
+) -> Vec<K> {
+    entries.sort_by_key(|(_, rotation_end)| *rotation_end);
+    let expired = match before {
+        Some(before) => entries.partition_point(|(_, end)| *end < before.timestamp_millis()),
+        None => 0,
+    };
+    let excess = max_entries.map_or(0, |max| entries.len().saturating_sub(max));
+    entries
+        .into_iter()
+        .take(expired.max(excess))
+        .map(|(key, _)| key)
+        .collect()
+}
+
+/// Maximum number of snapshots kept in memory; the oldest rotations are dropped first.
+pub(crate) const MAX_IN_MEMORY_SNAPSHOTS: usize = 1000;
+
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryHistoryStorage {
+    snapshots: Arc<RwLock<BTreeMap<Vec<u8>, RotationSnapshot>>>,
+}
+
+impl InMemoryHistoryStorage {
+    /// Archive a snapshot if its rotation isn't archived yet, returning
+    /// whether it was.
+    pub(crate) fn insert_new(&self, snapshot: &RotationSnapshot) -> bool {
+        let mut snapshots = self.snapshots.write().expect("History poisoned");
+        let key = snapshot_key(snapshot);
+        if snapshots.contains_key(&key) {
+            return false;
+        }
+        snapshots.insert(key, snapshot.clone());
+        if snapshots.len() > MAX_IN_MEMORY_SNAPSHOTS {
+            let oldest = snapshots
+                .iter()
+                .min_by_key(|(_, snapshot)| snapshot.store.current_rotation_end)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                snapshots.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+impl HistoryStorage for InMemoryHistoryStorage {
+    #[instrument(skip_all)]
+    fn insert(&self, snapshot: &RotationSnapshot) -> Result<()> {
+        self.insert_new(snapshot);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    fn list(&self, account: AccountId) -> Result<Vec<RotationSnapshot>> {
+        let snapshots = self.snapshots.read().expect("History poisoned");
+        Ok(snapshots
+            .range(account.0.as_bytes().to_vec()..)
+            .take_while(|(key, _)| key.starts_with(account.0.as_bytes()))
+            .map(|(_, snapshot)| snapshot.clone())
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    fn prune(&self, before: Option<DateTime<Utc>>, max_entries: Option<usize>) -> Result<usize> {
+        let mut snapshots = self.snapshots.write().expect("History poisoned");
+        let entries = snapshots
+            .iter()
+            .map(|(key, snapshot)| {
+                let end = snapshot.store.current_rotation_end.timestamp_millis();
+                (key.clone(), end)
+            })
+            .collect();
+        let pruned = keys_to_prune(entries, before, max_entries);
+        for key in &pruned {
+            snapshots.remove(key);
+        }
+        Ok(pruned.len())
+    }
+}
+
+const HISTORY_TREE: &str = "history";
+
+/// Snapshots stored as JSON in a tree of the auth database.
+#[derive(Debug, Clone)]
+pub struct SledDbHistoryStorage {
+    tree: sled::Tree,
+}
+
+impl SledDbHistoryStorage {
+    pub fn new(db: &sled::Db) -> Result<Self> {
+        Ok(Self {
+            tree: db
+                .open_tree(HISTORY_TREE)
+                .context("Failed to open history")?,
+        })
+    }
+}
+
+impl HistoryStorage for SledDbHistoryStorage {
+    #[instrument(skip_all)]
+    fn insert(&self, snapshot: &RotationSnapshot) -> Result<()> {
+        let key = snapshot_key(snapshot);
+        if self
+            .tree
+            .contains_key(&key)
+            .context("Failed to read history")?
+        {
+            return Ok(());
+        }
+        let value = serde_json::to_vec(snapshot).context("Failed to serialize snapshot")?;
+        self.tree
+            .insert(key, value)
+            .context("Failed to archive snapshot")?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    fn list(&self, account: AccountId) -> Result<Vec<RotationSnapshot>> {
+        let mut snapshots = Vec::new();
+        for result in self.tree.scan_prefix(account.0.as_bytes()) {
+            let (key, value) = result.context("Failed to read history")?;
+            match serde_json::from_slice(&value) {
+                Ok(snapshot) => snapshots.push(snapshot),
+                Err(e) => warn!(key = ?key, error = %e, "Skipping invalid snapshot"),
+            }
+        }
+        Ok(snapshots)
+    }
+
+    /// Reads only the keys, which hold the rotation ends, so snapshots aren't
+    /// decoded.
+    #[instrument(skip(self))]
+    fn prune(&self, before: Option<DateTime<Utc>>, max_entries: Option<usize>) -> Result<usize> {
+        let mut entries = Vec::new();
+        for key in self.tree.iter().keys() {
+            let key = key.context("Failed to read history")?;
+            match key_rotation_end(&key) {
+                Some(end) => entries.push((key, end)),
+                None => warn!(key = ?key, "Skipping invalid snapshot key"),
+            }
+        }
+        let pruned = keys_to_prune(entries, before, max_entries);
+        for key in &pruned {
+            self.tree.remove(key).context("Failed to prune snapshot")?;
+        }
+        Ok(pruned.len())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ErasedHistoryStorage(Box<dyn HistoryStorage>);
+
+impl HistoryStorage for ErasedHistoryStorage {
+    #[instrument(skip_all)]
+    fn insert(&self, snapshot: &RotationSnapshot) -> Result<()> {
+        self.0.insert(snapshot)
+    }
+
+    #[instrument(skip(self))]
+    fn list(&self, account: AccountId) -> Result<Vec<RotationSnapshot>> {
+        self.0.list(account)
+    }
+
+    #[instrument(skip(self))]
+    fn prune(&self, before: Option<DateTime<Utc>>, max_entries: Option<usize>) -> Result<usize> {
+        self.0.prune(before, max_entries)
+    }
+}
+
+impl From<InMemoryHistoryStorage> for ErasedHistoryStorage {
+    fn from(value: InMemoryHistoryStorage) -> Self {
+        Self(Box::new(value))
+    }
+}
+
+impl From<SledDbHistoryStorage> for ErasedHistoryStorage {
+    fn from(value: SledDbHistoryStorage) -> Self {
+        Self(Box::new(value))
+    }
+}
+
+#[cfg(feature = "s3")]
+impl From<super::S3HistoryStorage> for ErasedHistoryStorage {
+    fn from(value: super::S3HistoryStorage) -> Self {
+        Self(Box::new(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prunes_expired_then_excess() {
+        let at = |millis| DateTime::<Utc>::from_timestamp_millis(millis).unwrap();
+        let entries = || vec![("c", 300), ("a", 100), ("d", 400), ("b", 200)];
+        assert!(keys_to_prune(entries(), None, None).is_empty());
+        assert_eq!(keys_to_prune(entries(), Some(at(250)), None), ["a", "b"]);
+        assert_eq!(keys_to_prune(entries(), None, Some(1)), ["a", "b", "c"]);
+        // Whichever limit removes more applies.
+        assert_eq!(keys_to_prune(entries(), Some(at(150)), Some(2)), ["a", "b"]);
+        assert_eq!(
+            keys_to_prune(entries(), Some(at(350)), Some(3)),
+            ["a", "b", "c"]
+        );
+    }
+}
@@ -0,0 +1,81 @@
+//! Caching proxy for the Darktide API, keeping the stores, summaries and
+//! auths of accounts fresh.
+//!
+//! The `dt-fetcher` binary serves the endpoints on its own. To serve them from
+//! another axum app instead, build a [`Fetcher`]:
+//!
+//! ```no_run
+//! use dt_fetcher::{Fetcher, Storage};
+//! use tokio_util::sync::CancellationToken;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let fetcher = Fetcher::builder()
+//!     .storage(Storage::Sled("dt-fetcher.db".into()))
+//!     .single_endpoints(false)
+//!     .build()?;
+//! let token = CancellationToken::new();
+//! let app = axum::Router::new().nest("/darktide", fetcher.router(token.clone()));
+//! let tasks = fetcher.spawn(token.clone());
+//!
+//! let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
+//! axum::serve(
+//!     listener,
+//!     app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+//! )
+//! .with_graceful_shutdown(token.cancelled_owned())
+//! .await?;
+//! tasks.join().await
+//! # }
+//! ```
+
+mod account;
+mod auth;
+mod cached;
+#[cfg(feature = "chaos")]
+mod chaos;
+mod check;
+pub mod cli;
+mod client;
+mod clock;
+mod cluster;
+mod compare;
+mod config;
+mod coordination;
+mod database;
+mod diff;
+mod digest;
+mod drift;
+#[cfg(feature = "sentry")]
+mod error_report;
+mod fetcher;
+mod history;
+mod metrics_push;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod notify;
+mod prefetch;
+mod present;
+mod projection;
+mod push;
+#[cfg(feature = "query")]
+mod query;
+mod request_queue;
+mod retention;
+mod scrub;
+mod server;
+mod settings;
+mod slo;
+mod store_metrics;
+mod stores;
+mod supervisor;
+mod systemd;
+mod tabular;
+mod telegram;
+mod telemetry;
+mod timeline;
+mod upstream;
+mod watchlist;
+#[cfg(windows)]
+mod windows;
+
+pub use fetcher::{Fetcher, FetcherBuilder, Storage, Tasks};
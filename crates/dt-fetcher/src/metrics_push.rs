@@ -0,0 +1,278 @@
+//! Pushes the metrics to a Prometheus Pushgateway or remote-write endpoint,
+//! for setups where nothing can scrape `/metrics`.
+
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    config::{Config, Secret},
+    telemetry,
+};
+
+/// How often the config is checked while pushing is disabled.
+const DISABLED_INTERVAL: Duration = Duration::from_secs(60);
+/// Longest a push may take.
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where and how often metrics are pushed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct MetricsPushConfig {
+    /// Pushgateway URL with the grouping key, e.g.
+    /// `http://pushgateway:9091/metrics/job/dt-fetcher`, or remote-write URL.
+    pub url: String,
+    pub protocol: PushProtocol,
+    /// Seconds between pushes.
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// Sent as a bearer token, if set.
+    #[serde(default)]
+    pub bearer_token: Option<Secret>,
+}
+
+fn default_interval_secs() -> u64 {
+    60
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum PushProtocol {
+    /// The text exposition format, replacing the metrics of the grouping key.
+    Pushgateway,
+    /// Prometheus remote write 1.0: snappy-compressed protobuf.
+    RemoteWrite,
+}
+
+/// Periodically pushes the metrics as configured by `metricsPush`.
+pub(crate) struct MetricsPusher {
+    client: reqwest::Client,
+    config: watch::Receiver<Config>,
+}
+
+impl MetricsPusher {
+    pub fn new(config: watch::Receiver<Config>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            config,
+        }
+    }
+
+    #[instrument(skip_all)]
+    pub async fn start(self, token: CancellationToken) -> Result<()> {
+        loop {
+            let push = self.config.borrow().metrics_push.clone();
+            let wait = match &push {
+                Some(push) => Duration::from_secs(push.interval_secs.max(1)),
+                None => DISABLED_INTERVAL,
+            };
+            tokio::select! {
+                biased;
+                _ = token.cancelled() => {
+                    info!("Shutting down metrics pusher");
+                    return Ok(());
+                }
+                _ = tokio::time::sleep(wait) => {}
+            }
+            let Some(push) = push else {
+                continue;
+            };
+            match self.push(&push).await {
+                Ok(()) => debug!(url = %push.url, "Pushed metrics"),
+                Err(e) => warn!(url = %push.url, error = ?e, "Failed to push metrics"),
+            }
+        }
+    }
+
+    async fn push(&self, push: &MetricsPushConfig) -> Result<()> {
+        let Some(exposition) = telemetry::render() else {
+            return Ok(());
+        };
+        let request = match push.protocol {
+            PushProtocol::Pushgateway => self
+                .client
+                .put(&push.url)
+                .header("Content-Type", "text/plain; version=0.0.4")
+                .body(exposition),
+            PushProtocol::RemoteWrite => {
+                let samples = parse_exposition(&exposition);
+                let request = encode_write_request(&samples, Utc::now().timestamp_millis());
+                let body = snap::raw::Encoder::new()
+                    .compress_vec(&request)
+                    .context("Failed to compress metrics")?;
+                self.client
+                    .post(&push.url)
+                    .header("Content-Type", "application/x-protobuf")
+                    .header("Content-Encoding", "snappy")
+                    .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+                    .body(body)
+            }
+        };
+        let request = match &push.bearer_token {
+            Some(token) => request.bearer_auth(token.expose()),
+            None => request,
+        };
+        let response = request.timeout(PUSH_TIMEOUT).send().await?;
+        if !response.status().is_success() {
+            bail!("Push rejected with {}", response.status());
+        }
+        Ok(())
+    }
+}
+
+/// A sample of the text exposition, with `__name__` as its first label.
+#[derive(Debug, Clone, PartialEq)]
+struct Sample {
+    labels: Vec<(String, String)>,
+    value: f64,
+}
+
+/// Samples of the text exposition format, skipping comments and lines that
+/// don't parse.
+fn parse_exposition(exposition: &str) -> Vec<Sample> {
+    exposition
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(parse_sample)
+        .collect()
+}
+
+fn parse_sample(line: &str) -> Option<Sample> {
+    let name_end = line.find(|c: char| c == '{' || c.is_whitespace())?;
+    let mut labels = vec![("__name__".to_string(), line[..name_end].to_string())];
+    let mut rest = &line[name_end..];
+    if let Some(inner) = rest.strip_prefix('{') {
+        rest = inner;
+        loop {
+            rest = rest.trim_start_matches([',', ' ']);
+            if let Some(after) = rest.strip_prefix('}') {
+                rest = after;
+                break;
+            }
+            let (name, after) = rest.split_once("=\"")?;
+            let (value, after) = unescape_label_value(after)?;
+            labels.push((name.trim().to_string(), value));
+            rest = after;
+        }
+    }
+    let value = rest.split_whitespace().next()?.parse().ok()?;
+    Some(Sample { labels, value })
+}
+
+/// The label value at the start of `text`, up to its closing quote, and the
+/// text after the quote.
+fn unescape_label_value(text: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &text[i + 1..])),
+            '\\' => match chars.next()?.1 {
+                'n' => value.push('\n'),
+                escaped => value.push(escaped),
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// Protobuf of a remote-write `WriteRequest` with a time series per sample,
+/// each with the one sample at `timestamp` in milliseconds.
+fn encode_write_request(samples: &[Sample], timestamp: i64) -> Vec<u8> {
+    let mut request = Vec::new();
+    for sample in samples {
+        let mut series = Vec::new();
+        // Remote write requires labels sorted by name.
+        let mut labels: Vec<_> = sample.labels.iter().collect();
+        labels.sort();
+        for (name, value) in labels {
+            let mut label = Vec::new();
+            encode_bytes(&mut label, 1, name.as_bytes());
+            encode_bytes(&mut label, 2, value.as_bytes());
+            encode_bytes(&mut series, 1, &label);
+        }
+        let mut point = Vec::new();
+        // Field 1, fixed 64-bit.
+        point.push(1 << 3 | 1);
+        point.extend_from_slice(&sample.value.to_le_bytes());
+        // Field 2, varint.
+        point.push(2 << 3);
+        encode_varint(&mut point, timestamp as u64);
+        encode_bytes(&mut series, 2, &point);
+        encode_bytes(&mut request, 1, &series);
+    }
+    request
+}
+
+/// Append a length-delimited field.
+fn encode_bytes(buf: &mut Vec<u8>, field: u8, bytes: &[u8]) {
+    buf.push(field << 3 | 2);
+    encode_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn encode_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_exposition_for_remote_write() {
+        let exposition = "# TYPE dt_fetcher_cache_stores gauge\n\
+            dt_fetcher_cache_stores 3\n\
+            dt_fetcher_store_offers{rarity=\"5\",archetype=\"zea\\\"lot\"} 1.5\n\
+            dt_fetcher_request_seconds_bucket{le=\"+Inf\"} 7\n";
+        let samples = parse_exposition(exposition);
+        assert_eq!(
+            samples,
+            [
+                Sample {
+                    labels: vec![("__name__".into(), "dt_fetcher_cache_stores".into())],
+                    value: 3.0,
+                },
+                Sample {
+                    labels: vec![
+                        ("__name__".into(), "dt_fetcher_store_offers".into()),
+                        ("rarity".into(), "5".into()),
+                        ("archetype".into(), "zea\"lot".into()),
+                    ],
+                    value: 1.5,
+                },
+                Sample {
+                    labels: vec![
+                        (
+                            "__name__".into(),
+                            "dt_fetcher_request_seconds_bucket".into()
+                        ),
+                        ("le".into(), "+Inf".into()),
+                    ],
+                    value: 7.0,
+                },
+            ]
+        );
+
+        let request = encode_write_request(&samples[..1], 300);
+        let mut expected = vec![0x0a, 51, 0x0a, 35, 0x0a, 8];
+        expected.extend_from_slice(b"__name__");
+        expected.extend_from_slice(&[0x12, 23]);
+        expected.extend_from_slice(b"dt_fetcher_cache_stores");
+        expected.extend_from_slice(&[0x12, 12, 0x09]);
+        expected.extend_from_slice(&3.0f64.to_le_bytes());
+        expected.extend_from_slice(&[0x10, 0xac, 0x02]);
+        assert_eq!(request, expected);
+    }
+}
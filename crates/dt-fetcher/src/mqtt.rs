@@ -0,0 +1,195 @@
+//! Publishes events to an MQTT broker, for home automation and dashboards
+//! that already subscribe to one.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use futures::future::BoxFuture;
+use rumqttc::{AsyncClient, Event as MqttEvent, MqttOptions, Outgoing, Packet, QoS, Transport};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Secret,
+    notify::{Event, Notifier},
+};
+
+/// Longest publishing an event may take, including connecting.
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Stands in for `{accountId}` in the topics of events about no account.
+const NO_ACCOUNT: &str = "global";
+
+/// The broker and topics events are published to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct MqttConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// Prefix of the client id; a random suffix is added to each connection.
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<Secret>,
+    /// Connect with TLS, verifying the broker with the system roots.
+    #[serde(default)]
+    pub tls: bool,
+    /// Topic of events, with `{type}` and `{accountId}` replaced.
+    #[serde(default = "default_topic")]
+    pub topic: String,
+    /// Topics of specific event types, e.g. `watchMatched`, instead of
+    /// `topic`.
+    #[serde(default)]
+    pub topics: BTreeMap<String, String>,
+    /// 0, 1 or 2.
+    #[serde(default = "default_qos")]
+    pub qos: u8,
+    #[serde(default)]
+    pub retain: bool,
+}
+
+fn default_port() -> u16 {
+    1883
+}
+
+fn default_client_id() -> String {
+    "dt-fetcher".to_string()
+}
+
+fn default_topic() -> String {
+    "dt-fetcher/{type}".to_string()
+}
+
+fn default_qos() -> u8 {
+    1
+}
+
+impl MqttConfig {
+    /// The topic `event` is published to.
+    fn topic(&self, event: &Event) -> String {
+        let kind = event.kind();
+        let account = event
+            .account_id()
+            .map(|id| id.0.to_string())
+            .unwrap_or_else(|| NO_ACCOUNT.to_string());
+        self.topics
+            .get(kind)
+            .unwrap_or(&self.topic)
+            .replace("{type}", kind)
+            .replace("{accountId}", &account)
+    }
+
+    fn qos(&self) -> Result<QoS> {
+        match self.qos {
+            0 => Ok(QoS::AtMostOnce),
+            1 => Ok(QoS::AtLeastOnce),
+            2 => Ok(QoS::ExactlyOnce),
+            qos => bail!("Invalid MQTT QoS {qos}"),
+        }
+    }
+
+    fn options(&self) -> MqttOptions {
+        let client_id = format!("{}-{}", self.client_id, uuid::Uuid::new_v4().simple());
+        let mut options = MqttOptions::new(client_id, &self.host, self.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if let Some(username) = &self.username {
+            let password = self.password.as_ref().map(Secret::expose).unwrap_or("");
+            options.set_credentials(username, password);
+        }
+        if self.tls {
+            options.set_transport(Transport::tls_with_default_config());
+        }
+        options
+    }
+}
+
+/// Publishes events as JSON to an MQTT broker, connecting for each event.
+#[derive(Debug)]
+pub(crate) struct MqttNotifier {
+    config: MqttConfig,
+}
+
+impl MqttNotifier {
+    pub fn new(config: MqttConfig) -> Self {
+        Self { config }
+    }
+
+    async fn publish(&self, topic: String, qos: QoS, payload: Vec<u8>) -> Result<()> {
+        let (client, mut eventloop) = AsyncClient::new(self.config.options(), 10);
+        client
+            .publish(topic, qos, self.config.retain, payload)
+            .await
+            .context("Failed to queue MQTT message")?;
+        // The event loop does the actual work: poll it until the broker has
+        // the message, then until the disconnect is sent.
+        let mut disconnecting = false;
+        loop {
+            let delivered = match eventloop.poll().await.context("MQTT connection failed")? {
+                MqttEvent::Outgoing(Outgoing::Publish(_)) => qos == QoS::AtMostOnce,
+                MqttEvent::Incoming(Packet::PubAck(_) | Packet::PubComp(_)) => true,
+                MqttEvent::Outgoing(Outgoing::Disconnect) => return Ok(()),
+                _ => false,
+            };
+            if delivered && !disconnecting {
+                disconnecting = true;
+                client
+                    .disconnect()
+                    .await
+                    .context("Failed to disconnect from MQTT broker")?;
+            }
+        }
+    }
+}
+
+impl Notifier for MqttNotifier {
+    fn notify<'a>(&'a self, event: &'a Event) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let qos = self.config.qos()?;
+            let payload = serde_json::to_vec(event).context("Failed to serialize event")?;
+            tokio::time::timeout(
+                PUBLISH_TIMEOUT,
+                self.publish(self.config.topic(event), qos, payload),
+            )
+            .await
+            .context("Timed out publishing to MQTT broker")?
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dt_api::models::AccountId;
+
+    use super::*;
+    use crate::slo::Source;
+
+    #[test]
+    fn renders_topics_of_events() {
+        let mut config: MqttConfig = serde_json::from_value(serde_json::json!({
+            "host": "localhost",
+            "topics": { "needsReauth": "alerts/{accountId}/reauth" }
+        }))
+        .unwrap();
+        let account_id = AccountId(uuid::Uuid::nil());
+
+        let rotated = Event::StoresRotated {
+            account_id,
+            rotations: Vec::new(),
+        };
+        assert_eq!(config.topic(&rotated), "dt-fetcher/storesRotated");
+        assert_eq!(
+            config.topic(&Event::NeedsReauth { account_id }),
+            format!("alerts/{}/reauth", account_id.0)
+        );
+
+        config.topic = "dt/{accountId}/{type}".to_string();
+        let exceeded = Event::ErrorBudgetExceeded {
+            source: Source::Upstream,
+            error_rate: 0.5,
+            threshold: 0.1,
+        };
+        assert_eq!(config.topic(&exceeded), "dt/global/errorBudgetExceeded");
+        assert_eq!(config.qos().unwrap(), QoS::AtLeastOnce);
+    }
+}
@@ -0,0 +1,228 @@
+use std::fmt::Debug;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use dt_api::models::{AccountId, CharacterId, CurrencyType};
+use futures::future::BoxFuture;
+use serde::Serialize;
+use tokio::sync::watch;
+use tracing::{instrument, warn};
+
+use crate::{
+    account::CharacterChanges,
+    config::Config,
+    digest::{Digest, DigestBuffer},
+    push::{NtfyNotifier, PushoverNotifier},
+    slo::Source,
+    telegram::{TelegramLimiter, TelegramNotifier},
+    watchlist::WatchMatch,
+};
+
+/// Something worth telling users about.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub(crate) enum Event {
+    /// New store rotations were prefetched for an account.
+    #[serde(rename_all = "camelCase")]
+    StoresRotated {
+        account_id: AccountId,
+        rotations: Vec<RotatedStore>,
+    },
+    /// A new store rotation has an offer matching a watch.
+    WatchMatched(Box<WatchMatch>),
+    /// The refresh token of an account was rejected; a fresh auth must be
+    /// provided with `PUT /auth/:id`.
+    #[serde(rename_all = "camelCase")]
+    NeedsReauth { account_id: AccountId },
+    /// Characters were created or deleted in game.
+    CharactersChanged(CharacterChanges),
+    /// Watch matches and new rotations of an account, batched when digests
+    /// are enabled.
+    Digest(Box<Digest>),
+    /// The error rate of upstream calls or handler responses reached its
+    /// threshold.
+    #[serde(rename_all = "camelCase")]
+    ErrorBudgetExceeded {
+        source: Source,
+        error_rate: f64,
+        threshold: f64,
+    },
+    /// The error rate fell below its threshold again.
+    #[serde(rename_all = "camelCase")]
+    ErrorBudgetRecovered { source: Source, error_rate: f64 },
+}
+
+/// A store with a new rotation.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RotatedStore {
+    pub character_id: CharacterId,
+    pub character_name: String,
+    pub currency_type: CurrencyType,
+    pub rotation_end: DateTime<Utc>,
+    /// Names of the offers that weren't in the previous rotation.
+    pub new_offers: Vec<String>,
+}
+
+impl Event {
+    /// The `type` of the event, as serialized.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::StoresRotated { .. } => "storesRotated",
+            Event::WatchMatched(_) => "watchMatched",
+            Event::NeedsReauth { .. } => "needsReauth",
+            Event::CharactersChanged(_) => "charactersChanged",
+            Event::Digest(_) => "digest",
+            Event::ErrorBudgetExceeded { .. } => "errorBudgetExceeded",
+            Event::ErrorBudgetRecovered { .. } => "errorBudgetRecovered",
+        }
+    }
+
+    /// The account the event is about, if any.
+    pub fn account_id(&self) -> Option<AccountId> {
+        match self {
+            Event::StoresRotated { account_id, .. } | Event::NeedsReauth { account_id } => {
+                Some(*account_id)
+            }
+            Event::WatchMatched(watch_match) => Some(watch_match.offer.account_id),
+            Event::CharactersChanged(changes) => Some(changes.account_id),
+            Event::Digest(digest) => Some(digest.account_id),
+            Event::ErrorBudgetExceeded { .. } | Event::ErrorBudgetRecovered { .. } => None,
+        }
+    }
+}
+
+/// Delivers events to users.
+pub(crate) trait Notifier: Send + Sync + Debug {
+    fn notify<'a>(&'a self, event: &'a Event) -> BoxFuture<'a, Result<()>>;
+}
+
+/// Posts events as JSON to a URL.
+#[derive(Debug)]
+pub(crate) struct WebhookNotifier {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify<'a>(&'a self, event: &'a Event) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let body = serde_json::to_vec(event).context("Failed to serialize event")?;
+            self.client
+                .post(&self.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body)
+                .send()
+                .await
+                .context("Failed to send webhook")?
+                .error_for_status()
+                .context("Webhook failed")?;
+            Ok(())
+        })
+    }
+}
+
+/// Sends events to every notifier in the current config.
+#[derive(Debug, Clone)]
+pub(crate) struct Notifiers {
+    client: reqwest::Client,
+    config: watch::Receiver<Config>,
+    telegram: TelegramLimiter,
+    digest: DigestBuffer,
+}
+
+impl Notifiers {
+    pub fn new(config: watch::Receiver<Config>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            config,
+            telegram: TelegramLimiter::default(),
+            digest: DigestBuffer::default(),
+        }
+    }
+
+    fn notifiers(&self) -> Vec<Box<dyn Notifier>> {
+        let config = self.config.borrow();
+        let mut notifiers: Vec<_> = config
+            .webhooks
+            .iter()
+            .map(|url| {
+                Box::new(WebhookNotifier {
+                    client: self.client.clone(),
+                    url: url.clone(),
+                }) as Box<dyn Notifier>
+            })
+            .collect();
+        if let Some(telegram) = &config.telegram {
+            notifiers.push(Box::new(TelegramNotifier::new(
+                self.client.clone(),
+                telegram.clone(),
+                self.telegram.clone(),
+            )));
+        }
+        if let Some(pushover) = &config.pushover {
+            notifiers.push(Box::new(PushoverNotifier::new(
+                self.client.clone(),
+                pushover.clone(),
+            )));
+        }
+        if let Some(ntfy) = &config.ntfy {
+            notifiers.push(Box::new(NtfyNotifier::new(
+                self.client.clone(),
+                ntfy.clone(),
+            )));
+        }
+        #[cfg(feature = "mqtt")]
+        if let Some(mqtt) = &config.mqtt {
+            notifiers.push(Box::new(crate::mqtt::MqttNotifier::new(mqtt.clone())));
+        }
+        notifiers
+    }
+
+    /// Send an event, or add it to the next digest of its account if digests
+    /// are enabled.
+    #[instrument(skip(self))]
+    pub async fn notify(&self, event: &Event) {
+        let digests = self.config.borrow().digest.is_some();
+        if digests && self.digest.add(event, Utc::now()) {
+            return;
+        }
+        self.send(event).await;
+    }
+
+    /// Send the digests of an account, or of every account if `None`.
+    pub async fn send_digests(&self, account_id: Option<AccountId>) {
+        let template = self
+            .config
+            .borrow()
+            .digest
+            .clone()
+            .unwrap_or_default()
+            .template;
+        for digest in self.digest.take(account_id, Utc::now(), &template) {
+            self.send(&Event::Digest(Box::new(digest))).await;
+        }
+    }
+
+    /// Send the digest of an account once its new rotations were fetched,
+    /// unless digests are sent every window.
+    pub async fn prefetched(&self, account_id: AccountId) {
+        let per_prefetch = self
+            .config
+            .borrow()
+            .digest
+            .as_ref()
+            .is_some_and(|digest| digest.window_secs.is_none());
+        if per_prefetch {
+            self.send_digests(Some(account_id)).await;
+        }
+    }
+
+    async fn send(&self, event: &Event) {
+        for notifier in self.notifiers() {
+            if let Err(e) = notifier.notify(event).await {
+                warn!(notifier = ?notifier, event = event.kind(), sid = ?event.account_id(), error = ?e, "Failed to notify");
+            }
+        }
+    }
+}
@@ -0,0 +1,389 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use dt_api::{
+    models::{CurrencyType, OfferId, Store},
+    Auth,
+};
+use futures::future::Either;
+use tokio::sync::{broadcast, watch};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::{
+    account::{AccountData, Accounts, CharacterChanges},
+    auth::AuthData,
+    clock::Clock,
+    config::Config,
+    notify::{Event, Notifiers, RotatedStore},
+    settings::Settings,
+    stores::{StoreRequest, StoreView},
+    timeline::{Resource, TimelineEvent},
+    upstream::Upstream,
+    watchlist::{WatchMatch, Watchlists},
+};
+
+/// Longest wait between checks, so that changed summary TTLs take effect.
+const RETRY_INTERVAL: Duration = Duration::from_secs(300);
+/// Delay after a rotation ends before fetching the new stores.
+const ROTATION_DELAY: Duration = Duration::from_secs(10);
+/// Shortest wait for a summary to expire, so that a zero TTL doesn't refresh
+/// summaries in a busy loop.
+const MIN_SUMMARY_WAIT: Duration = Duration::from_secs(60);
+
+/// Fetches stores as soon as they rotate and summaries as soon as their TTL
+/// runs out, and notifies about offers matching watchlists.
+#[derive(Debug)]
+pub(crate) struct Prefetcher {
+    api: Upstream,
+    accounts: Accounts,
+    auth_data: AuthData,
+    watchlists: Watchlists,
+    settings: Settings,
+    notifiers: Notifiers,
+    config: watch::Receiver<Config>,
+    changes: broadcast::Receiver<CharacterChanges>,
+}
+
+impl Prefetcher {
+    pub fn new(
+        api: Upstream,
+        accounts: Accounts,
+        auth_data: AuthData,
+        watchlists: Watchlists,
+        settings: Settings,
+        notifiers: Notifiers,
+        config: watch::Receiver<Config>,
+    ) -> Self {
+        Self {
+            api,
+            changes: accounts.subscribe(),
+            accounts,
+            auth_data,
+            watchlists,
+            settings,
+            notifiers,
+            config,
+        }
+    }
+
+    #[instrument(skip_all)]
+    pub async fn start(mut self, token: CancellationToken) -> Result<()> {
+        let mut enabled = false;
+        loop {
+            let prefetch = self.config.borrow_and_update().prefetch;
+            if prefetch != enabled {
+                enabled = prefetch;
+                if enabled {
+                    info!("Enabling store prefetching");
+                } else {
+                    info!("Disabling store prefetching");
+                }
+            }
+            let next = if enabled {
+                Either::Left(tokio::time::sleep(self.next_deadline().await))
+            } else {
+                Either::Right(futures::future::pending())
+            };
+            tokio::select! {
+                // The config channel closes during shutdown, which isn't an error.
+                biased;
+                _ = token.cancelled() => {
+                    info!("Shutting down prefetcher");
+                    return Ok(());
+                }
+                res = self.config.changed() => res?,
+                changes = self.changes.recv() => match changes {
+                    Ok(changes) => self.characters_changed(changes, enabled).await,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!(skipped, "Missed character changes");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        unreachable!("Accounts holds the sender")
+                    }
+                },
+                _ = next => self.prefetch().await,
+            }
+        }
+    }
+
+    /// Time until the earliest cached store rotates or summary expires, or
+    /// until the upstream stops rate limiting requests if that is later.
+    async fn next_deadline(&self) -> Duration {
+        let now = self.accounts.clock().now();
+        let default_ttl = self.config.borrow().summary_refresh_interval_mins;
+        let mut next = RETRY_INTERVAL;
+        for (id, account_data) in self.accounts.list().await {
+            let expires = account_data.last_updated + self.settings.summary_ttl(id, default_ttl);
+            let expiry = (expires - now).to_std().unwrap_or_default();
+            next = next.min(expiry.max(MIN_SUMMARY_WAIT));
+            for currency_type in [CurrencyType::Marks, CurrencyType::Credits] {
+                for (_, store) in account_data.stores(currency_type).read().await.iter() {
+                    let rotates_at = store.store().rotates_at();
+                    if rotates_at > now {
+                        let rotation = (rotates_at - now).to_std().unwrap_or_default();
+                        next = next.min(rotation + ROTATION_DELAY);
+                    }
+                }
+            }
+        }
+        match self.api.rate_limited_for() {
+            Some(backoff) => next.max(backoff),
+            None => next,
+        }
+    }
+
+    #[instrument(skip_all)]
+    async fn prefetch(&self) {
+        for (id, account_data) in self.accounts.list().await {
+            if !self.api.coordinator().owns(id) {
+                debug!(sid = ?id, "Skipping account owned by another instance");
+                continue;
+            }
+            if let Some(backoff) = self.api.rate_limited_for() {
+                warn!(backoff = ?backoff, "Upstream rate limited; pausing prefetching");
+                return;
+            }
+            match self.auth_data.get(id) {
+                Ok(Some(auth)) => self.prefetch_account(&auth, &account_data).await,
+                Ok(None) => warn!(sid = ?id, "Failed to find auth data"),
+                Err(e) => error!(sid = ?id, error = %e, "Failed to get auth data"),
+            }
+        }
+    }
+
+    /// Notify about characters created or deleted in game, and fetch the
+    /// stores of new characters if prefetching.
+    #[instrument(skip_all, fields(sub = ?changes.account_id))]
+    async fn characters_changed(&self, changes: CharacterChanges, prefetch: bool) {
+        let id = changes.account_id;
+        let added = !changes.added.is_empty();
+        self.notifiers
+            .notify(&Event::CharactersChanged(changes))
+            .await;
+        if !prefetch || !added {
+            return;
+        }
+        let Some(account_data) = self.accounts.get(&id).await else {
+            return;
+        };
+        match self.auth_data.get(id) {
+            Ok(Some(auth)) => self.prefetch_account(&auth, &account_data).await,
+            Ok(None) => warn!("Failed to find auth data"),
+            Err(e) => error!(error = %e, "Failed to get auth data"),
+        }
+    }
+
+    #[instrument(skip_all, fields(sub = ?auth.sub))]
+    async fn prefetch_account(&self, auth: &Auth, account_data: &AccountData) {
+        let default_ttl = self.config.borrow().summary_refresh_interval_mins;
+        let ttl = self.settings.summary_ttl(auth.sub, default_ttl);
+        if account_data.last_updated + ttl <= self.accounts.clock().now() {
+            match self.accounts.refresh_summary(&self.api, auth).await {
+                Ok(_) => info!("Refreshed summary"),
+                Err(e) => error!(error = ?e, "Failed to refresh summary"),
+            }
+        } else if stores_due(account_data, self.accounts.clock().now()).await {
+            // Fetch the new rotation for the characters there are now.
+            match self.accounts.confirm_characters(&self.api, auth).await {
+                Ok(changed) => debug!(changed, "Confirmed characters"),
+                Err(e) => error!(error = ?e, "Failed to confirm characters"),
+            }
+        }
+        let characters = match account_data.summary.peek() {
+            Some(summary) => summary.characters.clone(),
+            None => return,
+        };
+        let default_personal = self.config.borrow().personal_stores;
+        let personal = self.settings.personal_stores(auth.sub, default_personal);
+        let level_ups = self.config.borrow().summary_refresh_on_level_up;
+        let mut levelled_up = false;
+        let mut rotated = AccountData::new(None, HashMap::new(), HashMap::new(), None);
+        let mut rotations = Vec::new();
+        for request in StoreRequest::plan(&characters, personal) {
+            for currency_type in [CurrencyType::Marks, CurrencyType::Credits] {
+                let stores = account_data.stores(currency_type);
+                // The earliest rotation of the characters, if all are cached.
+                let current = {
+                    let stores = stores.read().await;
+                    request
+                        .characters()
+                        .iter()
+                        .map(|c| stores.view_of(&c.id).map(|s| s.store().rotates_at()))
+                        .collect::<Option<Vec<_>>>()
+                        .and_then(|ends| ends.into_iter().min())
+                };
+                if current.is_some_and(|end| end > self.accounts.clock().now()) {
+                    continue;
+                }
+                let store = match self
+                    .api
+                    .get_requested_store(auth, currency_type, &request)
+                    .await
+                {
+                    Ok(store) => store,
+                    Err(e) => {
+                        error!(archetype = request.archetype(), error = %e, "Failed to prefetch store");
+                        self.api
+                            .timeline()
+                            .record_failure(auth.sub, Resource::Store, &e);
+                        continue;
+                    }
+                };
+                if current.is_some_and(|end| end >= store.rotates_at()) {
+                    continue;
+                }
+                info!(archetype = request.archetype(), characters = request.characters().len(), currency_type = %currency_type, "Prefetched new rotation");
+                let new_offers = {
+                    let stores = stores.read().await;
+                    request
+                        .characters()
+                        .iter()
+                        .map(|c| new_offers(stores.view_of(&c.id), &store))
+                        .collect::<Vec<_>>()
+                };
+                rotated
+                    .stores(currency_type)
+                    .write()
+                    .await
+                    .insert_all(&request, &store);
+                stores.write().await.insert_all(&request, &store);
+                if level_ups && !levelled_up {
+                    levelled_up = self.refresh_on_level_up(auth, &request, &store).await;
+                }
+                for (character, new_offers) in request.characters().iter().zip(new_offers) {
+                    rotations.push(RotatedStore {
+                        character_id: character.id,
+                        character_name: character.name.clone(),
+                        currency_type,
+                        rotation_end: store.current_rotation_end,
+                        new_offers,
+                    });
+                    self.api.timeline().record(
+                        auth.sub,
+                        TimelineEvent::StoreRotated {
+                            character_id: character.id,
+                            currency_type,
+                            rotation_end: store.current_rotation_end,
+                        },
+                    );
+                }
+            }
+        }
+        rotated.summary = account_data.summary.clone();
+        let budget = self.config.borrow().cache_budget_bytes();
+        self.accounts.evict_stores(budget).await;
+        if !rotations.is_empty() {
+            self.notifiers
+                .notify(&Event::StoresRotated {
+                    account_id: auth.sub,
+                    rotations,
+                })
+                .await;
+        }
+        self.notify_matches(auth, &rotated).await;
+        self.notifiers.prefetched(auth.sub).await;
+    }
+
+    /// Refresh the summary if a character of `request` levelled up, going by
+    /// its store. Returns whether it was refreshed.
+    async fn refresh_on_level_up(
+        &self,
+        auth: &Auth,
+        request: &StoreRequest<'_>,
+        store: &Store,
+    ) -> bool {
+        for character in request.characters() {
+            match self
+                .accounts
+                .refresh_on_level_up(&self.api, auth, character, store)
+                .await
+            {
+                Ok(true) => return true,
+                Ok(false) => {}
+                Err(e) => error!(error = ?e, "Failed to refresh summary after level up"),
+            }
+        }
+        false
+    }
+
+    async fn notify_matches(&self, auth: &Auth, rotated: &AccountData) {
+        let matches: Vec<WatchMatch> = match self.watchlists.matches(auth.sub, rotated).await {
+            Ok(matches) => matches,
+            Err(e) => {
+                error!(error = %e, "Failed to match watches");
+                return;
+            }
+        };
+        for watch_match in matches {
+            info!(watch_id = ?watch_match.watch_id, offer = %watch_match.offer.offer.sku.name, "Watch matched");
+            self.api.timeline().record(
+                auth.sub,
+                TimelineEvent::WatchMatched {
+                    watch_id: watch_match.watch_id,
+                    character_id: watch_match.offer.character_id,
+                    offer: watch_match.offer.offer.sku.name.clone(),
+                },
+            );
+            self.notifiers
+                .notify(&Event::WatchMatched(Box::new(watch_match)))
+                .await;
+        }
+    }
+}
+
+/// When the prefetcher fetches the rotation after one ending at
+/// `rotates_at`: shortly after it ends, but not before the upstream stops
+/// rate limiting requests.
+pub(crate) fn next_prefetch(
+    rotates_at: DateTime<Utc>,
+    now: DateTime<Utc>,
+    backoff: Option<Duration>,
+) -> DateTime<Utc> {
+    let delay =
+        chrono::Duration::from_std(ROTATION_DELAY).unwrap_or_else(|_| chrono::Duration::zero());
+    let after_rotation = rotates_at.max(now) + delay;
+    match backoff.and_then(|backoff| chrono::Duration::from_std(backoff).ok()) {
+        Some(backoff) => after_rotation.max(now + backoff),
+        None => after_rotation,
+    }
+}
+
+/// Names of the offers of `store` that aren't in the cached store it
+/// replaces.
+fn new_offers(cached: Option<StoreView<'_>>, store: &Store) -> Vec<String> {
+    let cached: HashSet<OfferId> = cached
+        .into_iter()
+        .flat_map(|view| view.offers())
+        .map(|offer| offer.offer_id)
+        .collect();
+    store
+        .offers()
+        .filter(|offer| !cached.contains(&offer.offer_id))
+        .map(|offer| offer.sku.name.clone())
+        .collect()
+}
+
+/// Whether a character of the account has no cached store, or one that has
+/// rotated by `now`.
+async fn stores_due(account_data: &AccountData, now: DateTime<Utc>) -> bool {
+    let Some(summary) = account_data.summary.peek() else {
+        return false;
+    };
+    for currency_type in [CurrencyType::Marks, CurrencyType::Credits] {
+        let stores = account_data.stores(currency_type).read().await;
+        let due = summary.characters.iter().any(|character| {
+            !stores
+                .view_of(&character.id)
+                .is_some_and(|store| store.store().rotates_at() > now)
+        });
+        if due {
+            return true;
+        }
+    }
+    false
+}
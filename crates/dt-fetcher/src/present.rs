@@ -0,0 +1,335 @@
+//! Terminal tables for the subcommands.
+
+use std::{fmt, io::IsTerminal};
+
+use dt_api::models::{Amount, CurrencyType, Offer, Store};
+use nu_ansi_term::{Color, Style};
+use serde_json::Value;
+
+use crate::diff::{FieldChange, StoreDiff};
+
+/// Whether to colorize output to stdout: only on a terminal, and unless
+/// `NO_COLOR` is set.
+pub(crate) fn use_color() -> bool {
+    std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal()
+}
+
+/// A table cell, with the style it is printed in when colorized.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Cell {
+    text: String,
+    style: Style,
+}
+
+impl Cell {
+    pub fn styled(text: impl Into<String>, style: Style) -> Self {
+        Self {
+            text: text.into(),
+            style,
+        }
+    }
+}
+
+impl From<String> for Cell {
+    fn from(text: String) -> Self {
+        Self {
+            text,
+            style: Style::default(),
+        }
+    }
+}
+
+impl From<&str> for Cell {
+    fn from(text: &str) -> Self {
+        text.to_string().into()
+    }
+}
+
+/// Rows printed in columns aligned to their widest cell.
+#[derive(Debug, Clone)]
+pub(crate) struct Table {
+    header: Vec<Cell>,
+    rows: Vec<Vec<Cell>>,
+    color: bool,
+}
+
+impl Table {
+    pub fn new(header: &[&str], rows: Vec<Vec<Cell>>) -> Self {
+        Self {
+            header: header
+                .iter()
+                .map(|name| Cell::styled(*name, Style::new().bold()))
+                .collect(),
+            rows,
+            color: false,
+        }
+    }
+
+    /// Print the cells in their styles.
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines: Vec<&Vec<Cell>> = std::iter::once(&self.header).chain(&self.rows).collect();
+        let widths: Vec<usize> = (0..self.header.len())
+            .map(|column| {
+                lines
+                    .iter()
+                    .filter_map(|line| line.get(column))
+                    .map(|cell| cell.text.chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        for (index, line) in lines.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            let cells = line.len() - line.iter().rev().take_while(|c| c.text.is_empty()).count();
+            let mut padding = 0;
+            for (column, (cell, width)) in line[..cells].iter().zip(&widths).enumerate() {
+                // Padding is written before the next cell, so lines don't end
+                // in spaces.
+                if column > 0 {
+                    write!(f, "{:padding$}", "")?;
+                }
+                if self.color {
+                    write!(f, "{}", cell.style.paint(&cell.text))?;
+                } else {
+                    f.write_str(&cell.text)?;
+                }
+                padding = width - cell.text.chars().count() + 2;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Name of an item rarity, as in game.
+pub(crate) fn rarity_name(rarity: i32) -> Option<&'static str> {
+    Some(match rarity {
+        1 => "Profane",
+        2 => "Redeemed",
+        3 => "Anointed",
+        4 => "Relic",
+        5 => "Transcendant",
+        _ => return None,
+    })
+}
+
+/// Name and color of an item rarity.
+fn rarity(rarity: i32) -> Cell {
+    let color = match rarity {
+        1 => Color::White,
+        2 => Color::Green,
+        3 => Color::Blue,
+        4 => Color::Purple,
+        5 => Color::Fixed(208),
+        _ => return rarity.to_string().into(),
+    };
+    Cell::styled(rarity_name(rarity).unwrap_or_default(), color.normal())
+}
+
+/// Blessing tiers are written as roman numerals in game.
+fn tier(rarity: i32) -> String {
+    match rarity {
+        1 => "I".to_string(),
+        2 => "II".to_string(),
+        3 => "III".to_string(),
+        4 => "IV".to_string(),
+        _ => rarity.to_string(),
+    }
+}
+
+/// Readable names of traits or perks, e.g. `crit chance scaled on weakspot IV`
+/// for `content/items/traits/bespoke_lasgun_p1/crit_chance_scaled_on_weakspot`
+/// of rarity 4.
+pub(crate) fn summarize<'a>(ids: impl Iterator<Item = (&'a str, i32)>) -> String {
+    ids.map(|(id, rarity)| {
+        let name = id.rsplit('/').next().unwrap_or(id).replace('_', " ");
+        format!("{name} {}", tier(rarity))
+    })
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+fn offer_row(offer: &Offer, kind: &str) -> Vec<Cell> {
+    let item = offer.description.overrides.item();
+    vec![
+        offer.sku.name.as_str().into(),
+        offer.sku.category.as_str().into(),
+        item.map_or_else(Cell::default, |item| rarity(item.rarity)),
+        item.map_or_else(Cell::default, |item| item.item_level.to_string().into()),
+        offer.price.amount.formatted().into(),
+        kind.into(),
+        item.map_or_else(Cell::default, |item| {
+            summarize(item.traits.iter().map(|t| (t.id.as_str(), t.rarity))).into()
+        }),
+        item.map_or_else(Cell::default, |item| {
+            summarize(item.perks.iter().map(|p| (p.id.as_str(), p.rarity))).into()
+        }),
+    ]
+}
+
+/// The personal and public offers of a store.
+pub(crate) fn store_table(store: &Store) -> Table {
+    let rows = store
+        .personal
+        .iter()
+        .map(|offer| offer_row(offer, "personal"))
+        .chain(store.public.iter().map(|offer| offer_row(offer, "public")))
+        .collect();
+    Table::new(
+        &[
+            "NAME", "CATEGORY", "RARITY", "LEVEL", "PRICE", "OFFER", "TRAITS", "PERKS",
+        ],
+        rows,
+    )
+}
+
+/// A changed field, e.g. `price 2,150 → 1,900`.
+fn describe_change(change: &FieldChange, currency_type: CurrencyType) -> String {
+    let text = |value: &Value| match value {
+        Value::Null => "none".to_string(),
+        Value::String(text) => text.clone(),
+        // Traits and perks
+        Value::Array(values) => summarize(values.iter().filter_map(|value| {
+            let id = value.get("id")?.as_str()?;
+            let rarity = value.get("rarity")?.as_i64()?;
+            Some((id, rarity as i32))
+        })),
+        Value::Number(amount) if change.field == "price" => match amount.as_i64() {
+            Some(amount) => Amount {
+                amount: amount as i32,
+                amount_type: currency_type,
+            }
+            .formatted(),
+            None => amount.to_string(),
+        },
+        value => value.to_string(),
+    };
+    format!(
+        "{} {} → {}",
+        change.field,
+        text(&change.old),
+        text(&change.new)
+    )
+}
+
+/// The offers added, removed and changed between two snapshots of a store.
+pub(crate) fn diff_table(diff: &StoreDiff) -> Table {
+    let kind = |personal| if personal { "personal" } else { "public" };
+    let row = |change: Cell, offer: &Offer, personal, details: String| {
+        let mut row = vec![change];
+        row.extend(offer_row(offer, kind(personal)));
+        row.push(details.into());
+        row
+    };
+    let rows = diff
+        .added
+        .iter()
+        .map(|added| {
+            let change = Cell::styled("added", Color::Green.normal());
+            row(change, &added.offer, added.personal, String::new())
+        })
+        .chain(diff.removed.iter().map(|removed| {
+            let change = Cell::styled("removed", Color::Red.normal());
+            row(change, &removed.offer, removed.personal, String::new())
+        }))
+        .chain(diff.changed.iter().map(|changed| {
+            let change = Cell::styled("changed", Color::Yellow.normal());
+            let details = changed
+                .changes
+                .iter()
+                .map(|change| describe_change(change, changed.offer.price.amount.amount_type))
+                .collect::<Vec<_>>()
+                .join("; ");
+            row(change, &changed.offer, changed.personal, details)
+        }))
+        .collect();
+    Table::new(
+        &[
+            "CHANGE", "NAME", "CATEGORY", "RARITY", "LEVEL", "PRICE", "OFFER", "TRAITS", "PERKS",
+            "DETAILS",
+        ],
+        rows,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> Store {
+        serde_json::from_str(include_str!("../tests/fixtures/store.json")).unwrap()
+    }
+
+    fn rotated_fixture() -> Store {
+        serde_json::from_str(include_str!("../tests/fixtures/store_rotated.json")).unwrap()
+    }
+
+    #[test]
+    fn renders_store() {
+        let table = store_table(&fixture()).to_string();
+        let expected = "\
+NAME                        CATEGORY  RARITY        LEVEL  PRICE  OFFER     TRAITS                                                               PERKS
+Lucius Mk V Helbore Lasgun  WEAPON    Transcendant  410    2,150  personal  crit chance scaled on weakspot IV, stacking rending on weakspot III  weapon perk increase crit chance IV
+Kantrael Mk IIb Lasgun      WEAPON    Redeemed      120    420    personal
+Mysterious Weapon           WEAPON                         1,000  personal
+Blessed Curio               GADGET    Anointed      300    900    public    gadget inate health increase III                                     gadget toughness regen delay II";
+        assert_eq!(table, expected);
+    }
+
+    #[test]
+    fn renders_diff() {
+        let table = diff_table(&StoreDiff::new(&fixture(), &rotated_fixture())).to_string();
+        let expected = "\
+CHANGE   NAME                          CATEGORY  RARITY        LEVEL  PRICE  OFFER     TRAITS                                                               PERKS                                DETAILS
+added    Munitorum Mk III Power Sword  WEAPON    Relic         350    1,700  personal
+removed  Kantrael Mk IIb Lasgun        WEAPON    Redeemed      120    420    personal
+changed  Lucius Mk V Helbore Lasgun    WEAPON    Transcendant  410    1,900  personal  crit chance scaled on weakspot IV, stacking rending on weakspot III  weapon perk increase crit chance IV  price 2,150 → 1,900";
+        assert_eq!(table, expected);
+    }
+
+    #[test]
+    fn describes_changed_blessings() {
+        let blessing =
+            |rarity| serde_json::json!([{"id": "traits/brutal_momentum", "rarity": rarity}]);
+        let change = FieldChange {
+            field: "traits",
+            old: blessing(2),
+            new: blessing(3),
+        };
+        assert_eq!(
+            describe_change(&change, CurrencyType::Marks),
+            "traits brutal momentum II → brutal momentum III"
+        );
+    }
+
+    #[test]
+    fn aligns_colorized_cells_by_their_text() {
+        let plain = store_table(&fixture()).to_string();
+        let colored = store_table(&fixture()).with_color(true).to_string();
+        assert!(colored.contains(&Color::Fixed(208).paint("Transcendant").to_string()));
+        assert!(colored.contains(&Color::Green.paint("Redeemed").to_string()));
+        let stripped = strip_escapes(&colored);
+        assert_eq!(stripped, plain);
+    }
+
+    fn strip_escapes(text: &str) -> String {
+        let mut stripped = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|c| *c == 'm');
+            } else {
+                stripped.push(c);
+            }
+        }
+        stripped
+    }
+}
@@ -0,0 +1,109 @@
+//! Sparse fieldsets: responses reduced to the fields a client asks for with
+//! `?fields=`, such as `characters.name,characters.level`.
+//!
+//! Fields are dotted paths into the JSON of a response. Arrays are projected
+//! element by element, so `characters.name` keeps the name of every character.
+//! Projection applies after serialization, so annotations and expansions can
+//! be selected like any other field.
+
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+/// Fields to keep, as a tree of their path segments.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Fields {
+    /// Whether the whole value is kept, as its path was listed.
+    whole: bool,
+    children: BTreeMap<String, Fields>,
+}
+
+impl Fields {
+    /// Parse comma-separated dotted paths, or `None` if there are none.
+    pub fn parse(fields: &str) -> Option<Self> {
+        let mut root = Fields::default();
+        for path in fields.split(',') {
+            let segments: Vec<_> = path
+                .split('.')
+                .map(str::trim)
+                .filter(|segment| !segment.is_empty())
+                .collect();
+            if segments.is_empty() {
+                continue;
+            }
+            let node = segments.into_iter().fold(&mut root, |node, segment| {
+                node.children.entry(segment.to_string()).or_default()
+            });
+            node.whole = true;
+        }
+        (!root.children.is_empty()).then_some(root)
+    }
+
+    /// Keep only the selected fields of `value`. Values that aren't objects
+    /// or arrays are kept whole, as they have no fields to select.
+    pub fn project(&self, value: Value) -> Value {
+        if self.whole {
+            return value;
+        }
+        match value {
+            Value::Object(mut object) => Value::Object(
+                self.children
+                    .iter()
+                    .filter_map(|(name, fields)| {
+                        let value = object.remove(name)?;
+                        Some((name.clone(), fields.project(value)))
+                    })
+                    .collect(),
+            ),
+            Value::Array(values) => Value::Array(
+                values
+                    .into_iter()
+                    .map(|value| self.project(value))
+                    .collect(),
+            ),
+            value => value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn keeps_selected_fields_of_every_element() {
+        let summary = json!({
+            "characters": [
+                { "name": "Lucius", "level": 30, "archetype": "veteran" },
+                { "name": "Kantrael", "level": 12, "archetype": "zealot" }
+            ],
+            "_links": { "self": { "href": "/summary" } },
+            "profile": { "accountName": "someone", "platform": "steam" }
+        });
+        let fields =
+            Fields::parse("characters.name, characters.level,profile,missing.field").unwrap();
+
+        assert_eq!(
+            fields.project(summary),
+            json!({
+                "characters": [
+                    { "name": "Lucius", "level": 30 },
+                    { "name": "Kantrael", "level": 12 }
+                ],
+                "profile": { "accountName": "someone", "platform": "steam" }
+            })
+        );
+    }
+
+    #[test]
+    fn listing_a_field_keeps_it_whole() {
+        let fields = Fields::parse("profile.platform,profile").unwrap();
+        let value = json!({ "profile": { "accountName": "someone", "platform": "steam" } });
+        assert_eq!(fields.project(value.clone()), value);
+
+        assert_eq!(Fields::parse(""), None);
+        assert_eq!(Fields::parse(" , ."), None);
+    }
+}
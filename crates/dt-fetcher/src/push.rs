@@ -0,0 +1,293 @@
+//! Push notifications of watch matches through Pushover and ntfy, for phones
+//! without a chat app.
+//!
+//! Only matches of watches with a `pushPriority` are pushed, alone or in a
+//! digest; the priority is mapped to the levels of each service.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Secret,
+    notify::{Event, Notifier},
+    present,
+    watchlist::WatchMatch,
+};
+
+const PUSHOVER_URL: &str = "https://api.pushover.net/1/messages.json";
+/// Seconds between repeats of an urgent Pushover notification until it is
+/// acknowledged, and for how long it is repeated.
+const PUSHOVER_RETRY_SECS: u32 = 60;
+const PUSHOVER_EXPIRE_SECS: u32 = 3600;
+/// Longest sending a notification may take.
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How urgently a watch match is pushed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum PushPriority {
+    /// Without sound or vibration.
+    Min,
+    Low,
+    Default,
+    High,
+    /// Repeated by Pushover until acknowledged.
+    Urgent,
+}
+
+impl PushPriority {
+    /// From -2 to 2.
+    fn pushover(self) -> i8 {
+        match self {
+            PushPriority::Min => -2,
+            PushPriority::Low => -1,
+            PushPriority::Default => 0,
+            PushPriority::High => 1,
+            PushPriority::Urgent => 2,
+        }
+    }
+
+    /// From 1 to 5.
+    fn ntfy(self) -> u8 {
+        match self {
+            PushPriority::Min => 1,
+            PushPriority::Low => 2,
+            PushPriority::Default => 3,
+            PushPriority::High => 4,
+            PushPriority::Urgent => 5,
+        }
+    }
+}
+
+/// The Pushover application and user notifications are sent with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PushoverConfig {
+    /// API token of the application.
+    pub app_token: Secret,
+    /// Key of the user or group to notify.
+    pub user_key: Secret,
+}
+
+/// The ntfy topic notifications are published to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct NtfyConfig {
+    #[serde(default = "default_ntfy_server")]
+    pub server: String,
+    pub topic: String,
+    /// Access token for protected topics.
+    #[serde(default)]
+    pub token: Option<Secret>,
+}
+
+fn default_ntfy_server() -> String {
+    "https://ntfy.sh".to_string()
+}
+
+/// Title and body of the notification of a watch match.
+fn notification(watch_match: &WatchMatch) -> (String, String) {
+    let offer = &watch_match.offer.offer;
+    let title = match &watch_match.watch_name {
+        Some(name) => format!("{}: {name}", offer.sku.name),
+        None => offer.sku.name.clone(),
+    };
+    let mut lines = Vec::new();
+    if let Some(item) = offer.description.overrides.item() {
+        lines.push(format!(
+            "{} {}, item level {}",
+            present::rarity_name(item.rarity).unwrap_or("Unknown"),
+            offer.sku.category,
+            item.item_level
+        ));
+        if !item.traits.is_empty() {
+            let traits = item.traits.iter().map(|t| (t.id.as_str(), t.rarity));
+            lines.push(format!("Blessings: {}", present::summarize(traits)));
+        }
+    }
+    let character = watch_match
+        .offer
+        .character_name
+        .clone()
+        .unwrap_or_else(|| watch_match.offer.character_id.0.to_string());
+    lines.push(format!(
+        "{} {} in the store of {character}",
+        offer.price.amount.formatted(),
+        watch_match.offer.currency_type
+    ));
+    (title, lines.join("\n"))
+}
+
+/// Title, body and priority of the notification of `event`, if it is pushed.
+/// Digests are pushed with the highest priority of their matches.
+fn pushed(event: &Event) -> Option<(String, String, PushPriority)> {
+    match event {
+        Event::WatchMatched(watch_match) => {
+            let priority = watch_match.push_priority?;
+            let (title, message) = notification(watch_match);
+            Some((title, message, priority))
+        }
+        Event::Digest(digest) => {
+            let priority = digest.push_priority()?;
+            let title = format!("{} watch matches", digest.matches.len());
+            Some((title, digest.body.clone(), priority))
+        }
+        _ => None,
+    }
+}
+
+/// Pushes watch matches through Pushover.
+#[derive(Debug)]
+pub(crate) struct PushoverNotifier {
+    client: reqwest::Client,
+    config: PushoverConfig,
+}
+
+impl PushoverNotifier {
+    pub fn new(client: reqwest::Client, config: PushoverConfig) -> Self {
+        Self { client, config }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct PushoverMessage<'a> {
+    token: &'a str,
+    user: &'a str,
+    title: &'a str,
+    message: &'a str,
+    priority: i8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expire: Option<u32>,
+}
+
+impl Notifier for PushoverNotifier {
+    fn notify<'a>(&'a self, event: &'a Event) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let Some((title, message, priority)) = pushed(event) else {
+                return Ok(());
+            };
+            let urgent = priority == PushPriority::Urgent;
+            self.client
+                .post(PUSHOVER_URL)
+                .form(&PushoverMessage {
+                    token: self.config.app_token.expose(),
+                    user: self.config.user_key.expose(),
+                    title: &title,
+                    message: &message,
+                    priority: priority.pushover(),
+                    retry: urgent.then_some(PUSHOVER_RETRY_SECS),
+                    expire: urgent.then_some(PUSHOVER_EXPIRE_SECS),
+                })
+                .timeout(SEND_TIMEOUT)
+                .send()
+                .await
+                .context("Failed to send Pushover notification")?
+                .error_for_status()
+                .context("Pushover rejected notification")?;
+            Ok(())
+        })
+    }
+}
+
+/// Pushes watch matches through ntfy.
+#[derive(Debug)]
+pub(crate) struct NtfyNotifier {
+    client: reqwest::Client,
+    config: NtfyConfig,
+}
+
+impl NtfyNotifier {
+    pub fn new(client: reqwest::Client, config: NtfyConfig) -> Self {
+        Self { client, config }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct NtfyMessage<'a> {
+    topic: &'a str,
+    title: &'a str,
+    message: &'a str,
+    priority: u8,
+    tags: [&'static str; 1],
+}
+
+impl Notifier for NtfyNotifier {
+    fn notify<'a>(&'a self, event: &'a Event) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let Some((title, message, priority)) = pushed(event) else {
+                return Ok(());
+            };
+            let body = serde_json::to_vec(&NtfyMessage {
+                topic: &self.config.topic,
+                title: &title,
+                message: &message,
+                priority: priority.ntfy(),
+                tags: ["shopping_cart"],
+            })
+            .context("Failed to serialize notification")?;
+            // Publishing JSON to the root URL allows any characters in the
+            // title, unlike the `Title` header.
+            let request = self
+                .client
+                .post(self.config.server.trim_end_matches('/'))
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body);
+            let request = match &self.config.token {
+                Some(token) => request.bearer_auth(token.expose()),
+                None => request,
+            };
+            request
+                .timeout(SEND_TIMEOUT)
+                .send()
+                .await
+                .context("Failed to send ntfy notification")?
+                .error_for_status()
+                .context("ntfy rejected notification")?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dt_api::models::{AccountId, CharacterId, CurrencyType, Store};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{account::OfferMatch, watchlist::WatchId};
+
+    #[test]
+    fn pushes_matches_of_watches_with_a_priority() {
+        let store: Store =
+            serde_json::from_str(include_str!("../tests/fixtures/store.json")).unwrap();
+        let offer = store.offers().next().unwrap().clone();
+        let mut watch_match = WatchMatch {
+            watch_id: WatchId(Uuid::nil()),
+            watch_name: Some("Good rolls".to_string()),
+            push_priority: None,
+            offer: OfferMatch {
+                account_id: AccountId(Uuid::nil()),
+                character_id: CharacterId(Uuid::nil()),
+                character_name: Some("Ash".to_string()),
+                currency_type: CurrencyType::Credits,
+                offer: offer.clone(),
+            },
+        };
+        assert!(pushed(&Event::WatchMatched(Box::new(watch_match.clone()))).is_none());
+
+        watch_match.push_priority = Some(PushPriority::High);
+        let event = Event::WatchMatched(Box::new(watch_match));
+        let (title, message, priority) = pushed(&event).unwrap();
+        assert_eq!((priority.pushover(), priority.ntfy()), (1, 4));
+        assert_eq!(title, format!("{}: Good rolls", offer.sku.name));
+        assert!(message.ends_with(&format!(
+            "{} credits in the store of Ash",
+            offer.price.amount.formatted()
+        )));
+    }
+}
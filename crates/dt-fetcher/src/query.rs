@@ -0,0 +1,397 @@
+//! jq expressions evaluated against cached resources, so custom extractions
+//! don't need a new endpoint.
+//!
+//! Expressions are run by jaq with the jq standard library, except that the
+//! [`HOST_FILTERS`] reaching outside of the input, such as `env` and `halt`,
+//! fail.
+//! Evaluation stops after [`MAX_RESULTS`] results, or after [`MAX_STEPS`]
+//! calls of filters and values of `range`, so that no expression runs
+//! forever.
+
+use std::{cell::Cell, fmt};
+
+use jaq_core::{
+    box_iter::box_once,
+    compile::Lut,
+    load::{
+        self,
+        lex::{StrPart, Token},
+        parse::{Def, Pattern, Term},
+        Arena, File, Lexer, Loader, Parser,
+    },
+    path::Part,
+    Compiler, Ctx, Cv, Exn, Native, RcIter, ValXs,
+};
+use jaq_json::Val;
+use serde_json::Value;
+
+/// Most results an expression may have.
+pub(crate) const MAX_RESULTS: usize = 1000;
+
+/// Longest expression accepted, in bytes.
+pub(crate) const MAX_EXPRESSION_LEN: usize = 4096;
+
+/// Most filter calls and values of `range` an expression may take.
+pub(crate) const MAX_STEPS: usize = 1_000_000;
+
+/// Native filter that takes a step; called at the start of every defined
+/// filter. Its name can't be written in expressions.
+const STEP: &str = "!step";
+
+/// Name the expression is defined as, to be run as the main filter.
+const MAIN: &str = "query";
+
+/// Native filters that fail instead, as they read the environment or clock
+/// of the host, exit the process or write to its output.
+const HOST_FILTERS: &[&str] = &["env", "now", "halt", "halt_error", "stderr", "debug"];
+
+/// Why an expression couldn't be evaluated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum QueryError {
+    /// The expression doesn't parse or uses undefined names.
+    Invalid(String),
+    /// Evaluating the expression failed, e.g. indexing a string with a key.
+    Failed(String),
+    TooManyResults,
+    TooManySteps,
+}
+
+thread_local! {
+    /// Steps left to the expression evaluated on this thread. Native filters
+    /// are plain function pointers, so this can't be passed to them.
+    static STEPS_LEFT: Cell<usize> = const { Cell::new(0) };
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::Invalid(error) => write!(f, "Invalid expression: {error}"),
+            QueryError::Failed(error) => write!(f, "Failed to evaluate expression: {error}"),
+            QueryError::TooManyResults => {
+                write!(f, "Expression has more than {MAX_RESULTS} results")
+            }
+            QueryError::TooManySteps => {
+                write!(f, "Expression takes more than {MAX_STEPS} steps")
+            }
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// Evaluate `expression` with `input` as `.`, returning every result.
+pub(crate) fn evaluate(expression: &str, input: Value) -> Result<Vec<Value>, QueryError> {
+    if expression.len() > MAX_EXPRESSION_LEN {
+        return Err(QueryError::Invalid(format!(
+            "longer than {MAX_EXPRESSION_LEN} bytes"
+        )));
+    }
+    let tokens = Lexer::new(expression)
+        .lex()
+        .map_err(|errors| QueryError::Invalid(load_errors(expression, load::Error::Lex(errors))))?;
+    let main = Parser::new(&tokens).parse(Parser::term).map_err(|errors| {
+        let errors = errors
+            .into_iter()
+            .map(|(expect, found)| (expect, Token::opt_as_str(found, expression)))
+            .collect();
+        QueryError::Invalid(load_errors(expression, load::Error::Parse(errors)))
+    })?;
+    let main = Def {
+        name: MAIN,
+        args: Vec::new(),
+        body: main,
+    };
+    let mut defs: Vec<_> = jaq_std::defs().chain(jaq_json::defs()).collect();
+    defs.push(main);
+    defs.iter_mut().for_each(count_steps);
+    let loader = Loader::new(defs);
+    let arena = Arena::default();
+    let program = File {
+        code: MAIN,
+        path: (),
+    };
+    let modules = loader.load(&arena, program).map_err(|errors| {
+        let errors = errors
+            .into_iter()
+            .map(|(_, error)| load_errors(MAIN, error));
+        QueryError::Invalid(errors.collect::<Vec<_>>().join(", "))
+    })?;
+    let filter = Compiler::default()
+        .with_funs(
+            jaq_std::funs()
+                .chain(jaq_json::funs())
+                .map(|(name, arity, native)| {
+                    if HOST_FILTERS.contains(&name) {
+                        (name, arity, Native::new(unavailable))
+                    } else if name == "range" {
+                        (name, arity, Native::new(range))
+                    } else {
+                        (name, arity, native)
+                    }
+                })
+                .chain([(STEP, Box::default(), Native::new(step))]),
+        )
+        .compile(modules)
+        .map_err(|errors| {
+            let undefined = errors
+                .into_iter()
+                .flat_map(|(_, errors)| errors)
+                .map(|(name, undefined)| format!("undefined {} `{name}`", undefined.as_str()));
+            QueryError::Invalid(undefined.collect::<Vec<_>>().join(", "))
+        })?;
+
+    STEPS_LEFT.with(|left| left.set(MAX_STEPS));
+    let exhausted = || STEPS_LEFT.with(Cell::get) == 0;
+    let inputs = RcIter::new(core::iter::empty());
+    let mut results = Vec::new();
+    for result in filter.run((Ctx::new([], &inputs), Val::from(input))) {
+        if results.len() == MAX_RESULTS {
+            return Err(QueryError::TooManyResults);
+        }
+        // The error of the last step may be caught by the expression.
+        if exhausted() {
+            return Err(QueryError::TooManySteps);
+        }
+        let value = result.map_err(|e| QueryError::Failed(e.to_string()))?;
+        results.push(value.into());
+    }
+    if exhausted() {
+        return Err(QueryError::TooManySteps);
+    }
+    Ok(results)
+}
+
+/// Take a step of the evaluation, returning whether any was left.
+fn take_step() -> bool {
+    STEPS_LEFT.with(|left| match left.get() {
+        0 => false,
+        n => {
+            left.set(n - 1);
+            true
+        }
+    })
+}
+
+fn no_steps_left<T>() -> Result<T, Exn<'static, Val>> {
+    Err(Exn::from(jaq_core::Error::str("out of steps")))
+}
+
+/// Make `def` and every filter defined in it take a [`STEP`] when called.
+/// Once no steps are left, calls fail, so recursion ends.
+fn count_steps(def: &mut Def<&str>) {
+    count_term_steps(&mut def.body);
+    let body = std::mem::take(&mut def.body);
+    def.body = Term::Pipe(Box::new(Term::Call(STEP, Vec::new())), None, Box::new(body));
+}
+
+fn count_term_steps(term: &mut Term<&str>) {
+    fn pattern(p: &mut Pattern<&str>) {
+        match p {
+            Pattern::Var(_) => {}
+            Pattern::Arr(ps) => ps.iter_mut().for_each(pattern),
+            Pattern::Obj(entries) => {
+                for (key, p) in entries {
+                    count_term_steps(key);
+                    pattern(p);
+                }
+            }
+        }
+    }
+    match term {
+        Term::Id | Term::Recurse | Term::Num(_) | Term::Break(_) | Term::Var(_) => {}
+        Term::Str(_, parts) => {
+            for part in parts {
+                if let StrPart::Term(t) = part {
+                    count_term_steps(t);
+                }
+            }
+        }
+        Term::Arr(t) => t.iter_mut().for_each(|t| count_term_steps(t)),
+        Term::Obj(entries) => {
+            for (key, value) in entries {
+                count_term_steps(key);
+                value.iter_mut().for_each(count_term_steps);
+            }
+        }
+        Term::Neg(t) | Term::Label(_, t) => count_term_steps(t),
+        Term::Pipe(l, p, r) => {
+            count_term_steps(l);
+            p.iter_mut().for_each(pattern);
+            count_term_steps(r);
+        }
+        Term::BinOp(l, _, r) => {
+            count_term_steps(l);
+            count_term_steps(r);
+        }
+        Term::Fold(_, xs, p, args) => {
+            count_term_steps(xs);
+            pattern(p);
+            args.iter_mut().for_each(count_term_steps);
+        }
+        Term::TryCatch(t, catch) => {
+            count_term_steps(t);
+            catch.iter_mut().for_each(|t| count_term_steps(t));
+        }
+        Term::IfThenElse(branches, otherwise) => {
+            for (cond, then) in branches {
+                count_term_steps(cond);
+                count_term_steps(then);
+            }
+            otherwise.iter_mut().for_each(|t| count_term_steps(t));
+        }
+        Term::Def(defs, t) => {
+            defs.iter_mut().for_each(count_steps);
+            count_term_steps(t);
+        }
+        Term::Call(_, args) => args.iter_mut().for_each(count_term_steps),
+        Term::Path(t, path) => {
+            count_term_steps(t);
+            for (part, _) in &mut path.0 {
+                match part {
+                    Part::Index(i) => count_term_steps(i),
+                    Part::Range(from, to) => {
+                        from.iter_mut().for_each(count_term_steps);
+                        to.iter_mut().for_each(count_term_steps);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Calls a filter in one [`STEP`].
+fn step<'a>(_: &'a Lut<Native<Val>>, cv: Cv<'a, Val>) -> ValXs<'a, Val> {
+    if take_step() {
+        box_once(Ok(cv.1))
+    } else {
+        box_once(no_steps_left())
+    }
+}
+
+/// `range($from; $to; $by)` of the standard library, taking a step for each
+/// value.
+fn range<'a>(_: &'a Lut<Native<Val>>, mut cv: Cv<'a, Val>) -> ValXs<'a, Val> {
+    use std::cmp::Ordering::{Equal, Greater, Less};
+    let by = cv.0.pop_var();
+    let to = cv.0.pop_var();
+    let mut next = Some(cv.0.pop_var());
+    let direction = by.partial_cmp(&Val::from(0isize)).unwrap_or(Equal);
+    Box::new(std::iter::from_fn(move || {
+        let x = next.take()?;
+        let more = match direction {
+            Greater => x < to,
+            Less => x > to,
+            Equal => x != to,
+        };
+        if !more {
+            return None;
+        }
+        if !take_step() {
+            return Some(no_steps_left());
+        }
+        match x.clone() + by.clone() {
+            Ok(y) => next = Some(y),
+            Err(e) => return Some(Err(Exn::from(e))),
+        }
+        Some(Ok(x))
+    }))
+}
+
+/// Stands in for the [`HOST_FILTERS`], which the standard library refers to.
+fn unavailable<'a>(_: &'a Lut<Native<Val>>, _: Cv<'a, Val>) -> ValXs<'a, Val> {
+    box_once(Err(Exn::from(jaq_core::Error::str(
+        "not available in queries",
+    ))))
+}
+
+/// Describe why an expression didn't parse, with the byte offset of each
+/// error.
+fn load_errors(expression: &str, error: load::Error<&str>) -> String {
+    let offset = |at: &str| at.as_ptr() as usize - expression.as_ptr() as usize;
+    let messages: Vec<_> = match error {
+        load::Error::Io(errors) => errors
+            .into_iter()
+            .map(|(path, error)| format!("cannot load `{path}`: {error}"))
+            .collect(),
+        load::Error::Lex(errors) => errors
+            .into_iter()
+            .map(|(expect, at)| format!("expected {} at {}", expect.as_str(), offset(at)))
+            .collect(),
+        load::Error::Parse(errors) => errors
+            .into_iter()
+            .map(|(expect, at)| format!("expected {} at {}", expect.as_str(), offset(at)))
+            .collect(),
+    };
+    messages.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn evaluates_expressions() {
+        let summary = json!({
+            "characters": [
+                { "name": "Lucius", "level": 30 },
+                { "name": "Kantrael", "level": 12 }
+            ]
+        });
+
+        assert_eq!(
+            evaluate(
+                ".characters[] | select(.level > 20) | .name",
+                summary.clone()
+            ),
+            Ok(vec![json!("Lucius")])
+        );
+        assert_eq!(
+            evaluate("[.characters[].level] | add", summary.clone()),
+            Ok(vec![json!(42)])
+        );
+        assert!(matches!(
+            evaluate(".characters[", summary.clone()),
+            Err(QueryError::Invalid(_))
+        ));
+        for host in ["env", "halt", "now"] {
+            assert!(matches!(
+                evaluate(host, summary.clone()),
+                Err(QueryError::Failed(_))
+            ));
+        }
+        assert!(matches!(
+            evaluate("$ENV", summary.clone()),
+            Err(QueryError::Invalid(_))
+        ));
+        assert!(matches!(
+            evaluate(".characters[0].name.first", summary.clone()),
+            Err(QueryError::Failed(_))
+        ));
+        assert_eq!(
+            evaluate("range(2000)", summary.clone()),
+            Err(QueryError::TooManyResults)
+        );
+        assert_eq!(
+            evaluate(
+                "[range(3)], [range(5; 0; -2)], [limit(2; repeat(1))]",
+                summary.clone()
+            ),
+            Ok(vec![json!([0, 1, 2]), json!([5, 3, 1]), json!([1, 1])])
+        );
+        for endless in [
+            "until(false; .)",
+            "last(range(1e18))",
+            "def f: f; f",
+            "try until(false; .) catch 1",
+            "[.[]?, repeat(1)?] | length",
+        ] {
+            assert_eq!(
+                evaluate(endless, summary.clone()),
+                Err(QueryError::TooManySteps),
+                "{endless}"
+            );
+        }
+    }
+}
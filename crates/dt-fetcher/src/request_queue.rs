@@ -0,0 +1,209 @@
+//! Priority order of upstream calls waiting for the rate limit, so that
+//! requests of users aren't held up behind background work.
+//!
+//! Calls take turns at the rate limiter: only the call holding the turn waits
+//! for a permit, and then hands the turn to the waiting call with the highest
+//! priority, or the longest waiting one of those.
+
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::oneshot;
+
+/// Priority class of an upstream call, lowest first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) enum Priority {
+    /// Calls whose responses are only checked or archived, such as schema
+    /// drift checks.
+    Archive,
+    /// Stores and summaries fetched before they are requested.
+    Prefetch,
+    /// Auth refreshes, and the account data fetched for new auths.
+    Refresh,
+    /// Calls made to serve a request.
+    #[default]
+    Interactive,
+}
+
+/// Calls waiting for their turn at the rate limiter. Clones share the queue.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RequestQueue {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    taken: bool,
+    waiting: BinaryHeap<Waiter>,
+    enqueued: u64,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    priority: Priority,
+    /// Order of arrival, to serve calls of the same priority first come,
+    /// first served.
+    seq: u64,
+    turn: oneshot::Sender<()>,
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Waiter {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl RequestQueue {
+    /// Wait for the turn of a call of `priority`, which lasts until the
+    /// returned [`Turn`] is dropped.
+    pub async fn turn(&self, priority: Priority) -> Turn {
+        let turn = {
+            let mut state = self.lock();
+            if !state.taken {
+                state.taken = true;
+                return Turn {
+                    queue: self.clone(),
+                };
+            }
+            let (tx, rx) = oneshot::channel();
+            state.enqueued += 1;
+            let seq = state.enqueued;
+            state.waiting.push(Waiter {
+                priority,
+                seq,
+                turn: tx,
+            });
+            rx
+        };
+        let mut waiting = Waiting {
+            turn,
+            queue: self.clone(),
+        };
+        // Waiters are only dropped from the queue once given the turn.
+        let _ = (&mut waiting.turn).await;
+        Turn {
+            queue: self.clone(),
+        }
+    }
+
+    /// Hand the turn to the next waiting call.
+    fn pass(&self) {
+        let mut state = self.lock();
+        while let Some(waiter) = state.waiting.pop() {
+            if waiter.turn.send(()).is_ok() {
+                return;
+            }
+        }
+        state.taken = false;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .expect("request queue lock is not poisoned")
+    }
+}
+
+/// The turn of a call at the rate limiter, passed on when dropped.
+#[derive(Debug)]
+pub(crate) struct Turn {
+    queue: RequestQueue,
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        self.queue.pass();
+    }
+}
+
+/// A call waiting for its turn, which passes the turn on if the call is
+/// cancelled right after being given it.
+struct Waiting {
+    turn: oneshot::Receiver<()>,
+    queue: RequestQueue,
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        self.turn.close();
+        if self.turn.try_recv().is_ok() {
+            self.queue.pass();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn serves_higher_priorities_first() {
+        let queue = RequestQueue::default();
+        let turn = queue.turn(Priority::Archive).await;
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        for priority in [
+            Priority::Prefetch,
+            Priority::Interactive,
+            Priority::Prefetch,
+        ] {
+            let (queue, tx) = (queue.clone(), tx.clone());
+            tokio::spawn(async move {
+                let _turn = queue.turn(priority).await;
+                tx.send(priority).unwrap();
+            });
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(queue.lock().waiting.len(), 3);
+
+        drop(turn);
+        let mut served = Vec::new();
+        for _ in 0..3 {
+            served.push(rx.recv().await.unwrap());
+        }
+        assert_eq!(
+            served,
+            [
+                Priority::Interactive,
+                Priority::Prefetch,
+                Priority::Prefetch
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn cancelled_calls_pass_their_turn() {
+        let queue = RequestQueue::default();
+        let turn = queue.turn(Priority::Interactive).await;
+        let cancelled = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.turn(Priority::Interactive).await }
+        });
+        tokio::task::yield_now().await;
+        cancelled.abort();
+        drop(turn);
+
+        let next = tokio::time::timeout(Duration::from_secs(1), queue.turn(Priority::Archive));
+        assert!(next.await.is_ok());
+    }
+}
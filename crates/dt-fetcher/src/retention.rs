@@ -0,0 +1,84 @@
+//! Retention of archived data, so the database stays bounded on long-running
+//! installs.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, instrument};
+
+use crate::{config::Config, history::History};
+
+/// How often archived data is pruned.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// How long archived data of each kind is kept.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct RetentionConfig {
+    /// Archived store rotations.
+    pub history: RetentionPolicy,
+}
+
+/// Limits of archived data; either is unlimited if `None`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct RetentionPolicy {
+    /// Days after which entries are removed.
+    pub max_age_days: Option<u64>,
+    /// Number of entries beyond which the oldest are removed.
+    pub max_entries: Option<usize>,
+}
+
+/// Periodically prunes archived data beyond the retention of the config.
+pub(crate) struct RetentionMonitor {
+    history: History,
+    config: watch::Receiver<Config>,
+}
+
+impl RetentionMonitor {
+    pub fn new(history: History, config: watch::Receiver<Config>) -> Self {
+        Self { history, config }
+    }
+
+    #[instrument(skip_all)]
+    pub async fn start(self, token: CancellationToken) -> Result<()> {
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+        loop {
+            tokio::select! {
+                biased;
+                _ = token.cancelled() => {
+                    info!("Shutting down retention monitor");
+                    return Ok(());
+                }
+                _ = interval.tick() => self.prune().await?,
+            }
+        }
+    }
+
+    async fn prune(&self) -> Result<()> {
+        let policy = self.config.borrow().retention.history.clone();
+        if policy.max_age_days.is_none() && policy.max_entries.is_none() {
+            return Ok(());
+        }
+        let before = policy
+            .max_age_days
+            .and_then(|days| Utc::now().checked_sub_days(chrono::Days::new(days)));
+        let history = self.history.clone();
+        match tokio::task::spawn_blocking(move || history.prune(before, policy.max_entries)).await?
+        {
+            Ok(pruned) => {
+                if pruned > 0 {
+                    info!(pruned, "Pruned archived store rotations");
+                }
+                metrics::counter!("dt_fetcher_retention_pruned_total", "kind" => "history")
+                    .increment(pruned as u64);
+            }
+            Err(e) => error!(error = ?e, "Failed to prune archived store rotations"),
+        }
+        Ok(())
+    }
+}
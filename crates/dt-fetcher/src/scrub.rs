@@ -0,0 +1,270 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use tracing::{info, instrument, warn};
+use uuid::Uuid;
+
+/// Length of a hyphenated UUID.
+const UUID_LEN: usize = 36;
+
+/// Strips personal data from JSON, remapping each value to the same
+/// replacement everywhere it occurs, so scrubbed files still line up with
+/// each other.
+#[derive(Debug, Default)]
+pub(crate) struct Scrubber {
+    uuids: HashMap<Uuid, Uuid>,
+    accounts: HashMap<String, String>,
+    characters: HashMap<String, String>,
+    emails: HashMap<String, String>,
+}
+
+impl Scrubber {
+    /// Scrub account and character names, emails, linked accounts, tokens and
+    /// UUIDs, keeping the shape of the models.
+    pub fn scrub_value(&mut self, value: &mut Value) {
+        dt_api::capture::scrub(value);
+        self.scrub_in_place(value);
+    }
+
+    fn scrub_in_place(&mut self, value: &mut Value) {
+        match value {
+            Value::Object(fields) => {
+                let is_account = fields.contains_key("discriminator");
+                let scrubbed = std::mem::take(fields)
+                    .into_iter()
+                    .map(|(key, mut value)| {
+                        match (key.as_str(), &mut value) {
+                            ("username" | "name", Value::String(name)) if is_account => {
+                                *name = remap(&mut self.accounts, "account", name);
+                            }
+                            ("accountName", Value::String(name)) => {
+                                *name = remap(&mut self.accounts, "account", name);
+                            }
+                            ("characterName", Value::String(name)) => {
+                                *name = remap(&mut self.characters, "character", name);
+                            }
+                            ("discriminator", Value::String(discriminator)) => {
+                                *discriminator = "0000".to_string();
+                            }
+                            ("linkedAccounts", Value::Object(linked)) => {
+                                for id in linked.values_mut() {
+                                    *id = String::new().into();
+                                }
+                            }
+                            ("characters", Value::Array(characters)) => {
+                                for character in characters.iter_mut() {
+                                    if let Some(Value::String(name)) = character.get_mut("name") {
+                                        *name = remap(&mut self.characters, "character", name);
+                                    }
+                                }
+                                self.scrub_in_place(&mut value);
+                            }
+                            _ => self.scrub_in_place(&mut value),
+                        }
+                        (self.scrub_str(&key), value)
+                    })
+                    .collect();
+                *fields = scrubbed;
+            }
+            Value::Array(values) => values.iter_mut().for_each(|v| self.scrub_in_place(v)),
+            Value::String(s) => *s = self.scrub_str(s),
+            _ => {}
+        }
+    }
+
+    /// Replace an email address, or the UUIDs within a string.
+    fn scrub_str(&mut self, s: &str) -> String {
+        if is_email(s) {
+            let next = self.emails.len() + 1;
+            return self
+                .emails
+                .entry(s.to_string())
+                .or_insert_with(|| format!("user-{next}@example.com"))
+                .clone();
+        }
+        let mut scrubbed = String::with_capacity(s.len());
+        let mut rest = s;
+        while let Some((start, uuid)) = find_uuid(rest) {
+            let replacement = *self.uuids.entry(uuid).or_insert_with(Uuid::new_v4);
+            scrubbed.push_str(&rest[..start]);
+            scrubbed.push_str(&replacement.to_string());
+            rest = &rest[start + UUID_LEN..];
+        }
+        scrubbed.push_str(rest);
+        scrubbed
+    }
+
+    /// Remap the UUIDs in a relative path, such as the character id naming a
+    /// captured store.
+    fn scrub_path(&mut self, path: &Path) -> PathBuf {
+        path.iter()
+            .map(|component| self.scrub_str(&component.to_string_lossy()))
+            .collect()
+    }
+}
+
+fn remap(names: &mut HashMap<String, String>, kind: &str, name: &str) -> String {
+    let next = names.len() + 1;
+    names
+        .entry(name.to_string())
+        .or_insert_with(|| format!("{kind}-{next}"))
+        .clone()
+}
+
+fn is_email(s: &str) -> bool {
+    match s.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && !s.contains(char::is_whitespace)
+                && !domain.contains('@')
+        }
+        None => false,
+    }
+}
+
+/// Find the first hyphenated UUID in `s` and its byte offset.
+fn find_uuid(s: &str) -> Option<(usize, Uuid)> {
+    let bytes = s.as_bytes();
+    (0..=bytes.len().checked_sub(UUID_LEN)?).find_map(|start| {
+        let candidate = &bytes[start..start + UUID_LEN];
+        let hyphenated = candidate.iter().enumerate().all(|(i, b)| match i {
+            8 | 13 | 18 | 23 => *b == b'-',
+            _ => b.is_ascii_hexdigit(),
+        });
+        if !hyphenated {
+            return None;
+        }
+        // Only ASCII was matched, so the range is on character boundaries.
+        Uuid::try_parse(&s[start..start + UUID_LEN])
+            .ok()
+            .map(|uuid| (start, uuid))
+    })
+}
+
+/// Scrub an exported bundle or other JSON file, or every JSON and JSON lines
+/// file in a capture directory, writing the results to `output`.
+#[instrument]
+pub(crate) fn scrub(input: &Path, output: &Path) -> Result<()> {
+    if output.exists() && output.canonicalize()? == input.canonicalize()? {
+        bail!("Refusing to overwrite the input; choose another output");
+    }
+    let mut scrubber = Scrubber::default();
+    if input.is_dir() {
+        let mut files = Vec::new();
+        collect_files(input, &mut files)?;
+        files.sort();
+        for file in files {
+            let relative = file.strip_prefix(input)?;
+            let target = output.join(scrubber.scrub_path(relative));
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent).context("Failed to create output directory")?;
+            }
+            scrub_file(&mut scrubber, &file, &target)?;
+        }
+    } else {
+        scrub_file(&mut scrubber, input, output)?;
+    }
+    info!(
+        uuids = scrubber.uuids.len(),
+        accounts = scrubber.accounts.len(),
+        characters = scrubber.characters.len(),
+        emails = scrubber.emails.len(),
+        "Scrubbed personal data"
+    );
+    Ok(())
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir).context("Failed to read directory")? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn scrub_file(scrubber: &mut Scrubber, input: &Path, output: &Path) -> Result<()> {
+    let contents = std::fs::read_to_string(input)
+        .with_context(|| format!("Failed to read {}", input.display()))?;
+    let scrubbed = match input.extension().and_then(|e| e.to_str()) {
+        Some("jsonl") => {
+            let mut lines = String::with_capacity(contents.len());
+            for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+                let mut value = serde_json::from_str(line)
+                    .with_context(|| format!("Failed to parse {}", input.display()))?;
+                scrubber.scrub_value(&mut value);
+                lines.push_str(&serde_json::to_string(&value)?);
+                lines.push('\n');
+            }
+            lines
+        }
+        Some("json") => {
+            let mut value = serde_json::from_str(&contents)
+                .with_context(|| format!("Failed to parse {}", input.display()))?;
+            scrubber.scrub_value(&mut value);
+            serde_json::to_string_pretty(&value)?
+        }
+        _ => {
+            warn!(path = %input.display(), "Skipping file that isn't JSON");
+            return Ok(());
+        }
+    };
+    std::fs::write(output, scrubbed)
+        .with_context(|| format!("Failed to write {}", output.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn remaps_consistently() {
+        let id = "11111111-1111-1111-1111-111111111111";
+        let mut summary = json!({
+            "_links": {"self": {"href": format!("/web/{id}/summary")}},
+            "username": "someone",
+            "name": "someone",
+            "discriminator": "1234",
+            "email": "someone@example.org",
+            "linkedAccounts": {"steam": "7656", "twitch": ""},
+            "characters": [{"id": id, "name": "Zola", "archetype": "veteran"}],
+        });
+        let mut offers = json!({id: {"characterName": "Zola", "accountName": "someone"}});
+        let mut scrubber = Scrubber::default();
+        scrubber.scrub_value(&mut summary);
+        scrubber.scrub_value(&mut offers);
+
+        let text = format!("{summary}{offers}");
+        for secret in [id, "someone", "Zola", "1234", "7656"] {
+            assert!(!text.contains(secret), "{secret} in {text}");
+        }
+        let new_id = summary["characters"][0]["id"].as_str().unwrap();
+        assert_eq!(
+            summary["_links"]["self"]["href"],
+            format!("/web/{new_id}/summary")
+        );
+        assert_eq!(offers[new_id]["characterName"], "character-1");
+        assert_eq!(offers[new_id]["accountName"], summary["username"]);
+        assert_eq!(summary["email"], "user-1@example.com");
+    }
+
+    #[test]
+    fn remaps_uuids_in_paths() {
+        let mut scrubber = Scrubber::default();
+        let path = Path::new("abc/stores/marks/11111111-1111-1111-1111-111111111111.json");
+        let scrubbed = scrubber.scrub_path(path);
+        assert!(scrubbed.starts_with("abc/stores/marks"));
+        assert_ne!(scrubbed, path);
+        assert_eq!(scrubber.scrub_path(path), scrubbed);
+    }
+}
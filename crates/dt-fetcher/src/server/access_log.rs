@@ -0,0 +1,228 @@
+use std::{
+    cell::Cell,
+    fs::{File, OpenOptions},
+    io::Write,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use anyhow::{Context, Result};
+use axum::{
+    body::Body,
+    extract::{FromRequestParts, RawPathParams, State},
+    http::Request,
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use dt_api::models::AccountId;
+use serde::Serialize;
+use tokio::sync::watch;
+use tracing::warn;
+
+use crate::{
+    config::{AccessLogTarget, Config},
+    server::ClientIp,
+};
+
+/// Whether a request was served from the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum CacheResult {
+    Hit,
+    /// Something the request needed was fetched from upstream.
+    Miss,
+}
+
+/// What handlers noted about the request being served.
+#[derive(Debug, Clone, Copy, Default)]
+struct Notes {
+    cache: Option<CacheResult>,
+    account: Option<AccountId>,
+}
+
+tokio::task_local! {
+    static NOTES: Cell<Notes>;
+}
+
+fn note(f: impl FnOnce(&mut Notes)) {
+    // Outside of a request, or with the access log disabled, there is nothing
+    // to note.
+    let _ = NOTES.try_with(|notes| {
+        let mut value = notes.get();
+        f(&mut value);
+        notes.set(value);
+    });
+}
+
+/// Note that the request was served from the cache, unless it already missed.
+pub(crate) fn cache_hit() {
+    note(|notes| {
+        notes.cache.get_or_insert(CacheResult::Hit);
+    });
+}
+
+/// Note that the request needed something that wasn't cached.
+pub(crate) fn cache_miss() {
+    note(|notes| notes.cache = Some(CacheResult::Miss));
+}
+
+/// Note the account of a request without one in its path.
+pub(crate) fn account(id: AccountId) {
+    note(|notes| {
+        notes.account.get_or_insert(id);
+    });
+}
+
+/// One record of the access log.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Record {
+    time: DateTime<Utc>,
+    request_id: Option<String>,
+    method: String,
+    path: String,
+    status: u16,
+    latency_ms: f64,
+    cache: Option<CacheResult>,
+    account_id: Option<AccountId>,
+    client_ip: Option<IpAddr>,
+}
+
+/// Writes a record for every request to the target of the `accessLog`
+/// setting.
+#[derive(Debug, Clone)]
+pub(crate) struct AccessLog {
+    config: watch::Receiver<Config>,
+    /// The open log file and its path, reopened when the path changes.
+    file: Arc<Mutex<Option<(PathBuf, File)>>>,
+}
+
+impl AccessLog {
+    pub fn new(config: watch::Receiver<Config>) -> Self {
+        Self {
+            config,
+            file: Arc::default(),
+        }
+    }
+
+    fn write(&self, target: &AccessLogTarget, record: &Record) -> Result<()> {
+        match target {
+            AccessLogTarget::File(path) => self.write_file(path, record),
+            AccessLogTarget::Journald => write_journald(record),
+        }
+    }
+
+    fn write_file(&self, path: &Path, record: &Record) -> Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().expect("access log lock is not poisoned");
+        if file.as_ref().map_or(true, |(open, _)| open != path) {
+            let opened = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            *file = Some((path.to_path_buf(), opened));
+        }
+        let (_, file) = file.as_mut().expect("access log file was just opened");
+        file.write_all(&line).context("Failed to write access log")
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn write_journald(record: &Record) -> Result<()> {
+    use libsystemd::logging::{journal_send, Priority};
+
+    let mut fields = vec![
+        ("SYSLOG_IDENTIFIER", "dt-fetcher-access".to_string()),
+        ("HTTP_METHOD", record.method.clone()),
+        ("HTTP_PATH", record.path.clone()),
+        ("HTTP_STATUS", record.status.to_string()),
+        ("LATENCY_MS", format!("{:.3}", record.latency_ms)),
+    ];
+    let optional = [
+        ("REQUEST_ID", record.request_id.clone()),
+        (
+            "CACHE",
+            record
+                .cache
+                .map(|cache| format!("{cache:?}").to_lowercase()),
+        ),
+        ("ACCOUNT_ID", record.account_id.map(|id| id.to_string())),
+        ("CLIENT_IP", record.client_ip.map(|ip| ip.to_string())),
+    ];
+    fields.extend(
+        optional
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, value?))),
+    );
+    let message = format!(
+        "{} {} {} {:.1}ms",
+        record.method, record.path, record.status, record.latency_ms
+    );
+    journal_send(Priority::Info, &message, fields.into_iter())
+        .context("Failed to send access log to journald")
+}
+
+#[cfg(not(target_os = "linux"))]
+fn write_journald(_record: &Record) -> Result<()> {
+    anyhow::bail!("Journald is not supported on this platform")
+}
+
+/// Write an access log record for the request, if the access log is enabled.
+pub(crate) async fn access_log(
+    State(log): State<AccessLog>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let Some(target) = log.config.borrow().access_log.clone() else {
+        return next.run(request).await;
+    };
+    let start = Instant::now();
+    let (mut parts, body) = request.into_parts();
+    let path_account = RawPathParams::from_request_parts(&mut parts, &())
+        .await
+        .ok()
+        .and_then(|params| {
+            params
+                .iter()
+                .find(|(key, _)| *key == "id")
+                .and_then(|(_, value)| value.parse().ok())
+                .map(AccountId)
+        });
+    let mut record = Record {
+        time: Utc::now(),
+        request_id: parts
+            .headers
+            .get("x-request-id")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+        method: parts.method.to_string(),
+        path: parts.uri.path().to_string(),
+        status: 0,
+        latency_ms: 0.0,
+        cache: None,
+        account_id: path_account,
+        client_ip: parts.extensions.get::<ClientIp>().map(|ip| ip.0),
+    };
+    let request = Request::from_parts(parts, body);
+
+    let (notes, response) = NOTES
+        .scope(Cell::default(), async {
+            let response = next.run(request).await;
+            (NOTES.with(Cell::get), response)
+        })
+        .await;
+
+    record.status = response.status().as_u16();
+    record.latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+    record.cache = notes.cache;
+    record.account_id = record.account_id.or(notes.account);
+    if let Err(e) = log.write(&target, &record) {
+        warn!(error = %e, "Failed to write access log");
+    }
+    response
+}
@@ -0,0 +1,47 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use dt_api::models::AccountId;
+use serde::Serialize;
+use tracing::instrument;
+
+use crate::{account::AccountStatus, server::AppData, timeline::TimelineEntry};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AccountInfo {
+    id: AccountId,
+    last_updated: DateTime<Utc>,
+    /// The refresh token was rejected; `PUT /auth/:id` with a fresh auth.
+    needs_reauth: bool,
+    sections: AccountStatus,
+}
+
+#[instrument(skip(state))]
+pub(crate) async fn list_accounts(State(state): State<AppData>) -> Json<Vec<AccountInfo>> {
+    let mut accounts = Vec::new();
+    for (id, account_data) in state.accounts.list().await {
+        accounts.push(AccountInfo {
+            id,
+            last_updated: account_data.last_updated,
+            needs_reauth: state.auth_data.needs_reauth(&id).await,
+            sections: account_data.status().await,
+        });
+    }
+    Json(accounts)
+}
+
+/// Recent events of an account, newest first.
+#[instrument(skip(state))]
+pub(crate) async fn account_events(
+    Path(id): Path<AccountId>,
+    State(state): State<AppData>,
+) -> Result<Json<Vec<TimelineEntry>>, StatusCode> {
+    if state.accounts.get(&id).await.is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(Json(state.api.timeline().recent(id)))
+}
@@ -0,0 +1,127 @@
+#[cfg(feature = "chaos")]
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+#[cfg(feature = "chaos")]
+use axum::Json;
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use dt_api::models::AccountId;
+use tokio_util::sync::CancellationToken;
+use tracing::{instrument, warn};
+
+use crate::{
+    auth,
+    server::{AppData, ClientIp},
+};
+#[cfg(feature = "chaos")]
+use crate::{chaos::Faults, upstream::UpstreamCall};
+
+/// Shut down gracefully, as on `SIGINT`.
+///
+/// Requires `Authorization: Bearer <adminToken>`, and is not found if no admin
+/// token is configured.
+#[instrument(skip_all)]
+pub(crate) async fn shutdown(
+    headers: HeaderMap,
+    client_ip: Option<Extension<ClientIp>>,
+    Extension(token): Extension<CancellationToken>,
+    State(state): State<AppData>,
+) -> StatusCode {
+    let client_ip = client_ip.map(|Extension(ip)| ip.0);
+    if let Err(status) = authorize(&headers, client_ip, &state) {
+        return status;
+    }
+    warn!(client_ip = ?client_ip, "Shutting down by admin request");
+    token.cancel();
+    StatusCode::ACCEPTED
+}
+
+/// Refresh the auth of an account now, as [`auth::refresh_auth`] does. Each
+/// refresh is an upstream call, so it requires the admin token.
+#[instrument(skip_all, fields(sid = ?id))]
+pub(crate) async fn refresh_auth(
+    Path(id): Path<AccountId>,
+    headers: HeaderMap,
+    client_ip: Option<Extension<ClientIp>>,
+    State(state): State<AppData>,
+) -> Response {
+    let client_ip = client_ip.map(|Extension(ip)| ip.0);
+    if let Err(status) = authorize(&headers, client_ip, &state) {
+        return status.into_response();
+    }
+    warn!(client_ip = ?client_ip, "Refreshing auth by admin request");
+    auth::refresh_auth(Path(id), State(state.auth_data.clone())).await
+}
+
+/// Get the faults injected into upstream calls, by endpoint.
+#[cfg(feature = "chaos")]
+#[instrument(skip_all)]
+pub(crate) async fn get_chaos(
+    headers: HeaderMap,
+    client_ip: Option<Extension<ClientIp>>,
+    State(state): State<AppData>,
+) -> Result<Json<HashMap<UpstreamCall, Faults>>, StatusCode> {
+    authorize(&headers, client_ip.map(|Extension(ip)| ip.0), &state)?;
+    Ok(Json(state.api.chaos().get()))
+}
+
+/// Replace the faults injected into upstream calls; an empty object stops
+/// injecting any.
+#[cfg(feature = "chaos")]
+#[instrument(skip_all)]
+pub(crate) async fn put_chaos(
+    headers: HeaderMap,
+    client_ip: Option<Extension<ClientIp>>,
+    State(state): State<AppData>,
+    Json(faults): Json<HashMap<UpstreamCall, Faults>>,
+) -> StatusCode {
+    let client_ip = client_ip.map(|Extension(ip)| ip.0);
+    if let Err(status) = authorize(&headers, client_ip, &state) {
+        return status;
+    }
+    if !faults.values().all(Faults::is_valid) {
+        return StatusCode::UNPROCESSABLE_ENTITY;
+    }
+    warn!(client_ip = ?client_ip, faults = ?faults, "Injecting upstream faults by admin request");
+    state.api.chaos().set(faults);
+    StatusCode::NO_CONTENT
+}
+
+/// Check the admin token of a request.
+///
+/// Fails with `404` if no admin token is configured, and with `401` if the
+/// request doesn't have `Authorization: Bearer <adminToken>`.
+pub(super) fn authorize(
+    headers: &HeaderMap,
+    client_ip: Option<IpAddr>,
+    state: &AppData,
+) -> Result<(), StatusCode> {
+    let Some(admin_token) = state.config.borrow().admin_token.clone() else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !bearer
+        .is_some_and(|bearer| constant_time_eq(bearer.as_bytes(), admin_token.expose().as_bytes()))
+    {
+        warn!(
+            client_ip = ?client_ip,
+            "Rejected admin request with missing or wrong admin token"
+        );
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(())
+}
+
+/// Compare without short-circuiting, so the time taken doesn't reveal how much
+/// of the token matched.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
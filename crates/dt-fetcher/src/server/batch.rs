@@ -0,0 +1,222 @@
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    Json,
+};
+use dt_api::models::AccountId;
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use tracing::{error, instrument};
+
+use crate::server::{
+    format::{Projection, ResponseFormat},
+    inventory::{inventory, InventoryQuery},
+    master_data,
+    materials::materials,
+    raw::Bypass,
+    store::{store, store_by_archetype, ArchetypeQuery, StoreQuery},
+    summary, AppData,
+};
+
+/// Most resources a batch may request.
+const MAX_BATCH_SIZE: usize = 50;
+
+/// Largest response body of a resource in a batch.
+const MAX_ITEM_BODY: usize = 16 * 1024 * 1024;
+
+/// A resource requested in a batch, with the parameters of its endpoint.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "resource", rename_all = "camelCase")]
+pub(crate) enum BatchItem {
+    #[serde(rename_all = "camelCase")]
+    Summary { account_id: AccountId },
+    #[serde(rename_all = "camelCase")]
+    Store {
+        account_id: AccountId,
+        #[serde(flatten)]
+        target: StoreTarget,
+    },
+    #[serde(rename_all = "camelCase")]
+    Inventory {
+        account_id: AccountId,
+        #[serde(flatten)]
+        query: InventoryQuery,
+    },
+    #[serde(rename_all = "camelCase", alias = "wallets")]
+    Materials { account_id: AccountId },
+    #[serde(rename_all = "camelCase")]
+    MasterData { account_id: AccountId },
+}
+
+/// The store of a character, by id or by archetype.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum StoreTarget {
+    Character(StoreQuery),
+    Archetype {
+        archetype: String,
+        #[serde(flatten)]
+        query: ArchetypeQuery,
+    },
+}
+
+/// The response for a resource of a batch.
+#[derive(Debug, Serialize)]
+pub(crate) struct BatchResult {
+    status: u16,
+    /// The JSON body, or `null` if there is none.
+    body: serde_json::Value,
+}
+
+/// Get several resources in one request, each as from its own endpoint.
+#[instrument(skip_all, fields(items = items.len()))]
+pub(crate) async fn batch(
+    State(state): State<AppData>,
+    Json(items): Json<Vec<BatchItem>>,
+) -> Result<Json<Vec<BatchResult>>, StatusCode> {
+    if items.len() > MAX_BATCH_SIZE {
+        error!(max = MAX_BATCH_SIZE, "Batch too large");
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    let results = join_all(items.into_iter().map(|item| {
+        let state = state.clone();
+        async move { result(get(item, state).await).await }
+    }))
+    .await;
+    Ok(Json(results))
+}
+
+/// Serve a resource of a batch with the handler of its endpoint.
+async fn get(item: BatchItem, state: AppData) -> Response {
+    let (format, bypass, projection, headers) = (
+        ResponseFormat::Json,
+        Bypass(false),
+        Projection(None),
+        HeaderMap::new(),
+    );
+    let response = match item {
+        BatchItem::Summary { account_id } => {
+            summary(
+                Path(account_id),
+                format,
+                bypass,
+                projection,
+                headers,
+                State(state),
+            )
+            .await
+        }
+        BatchItem::Store {
+            account_id,
+            target: StoreTarget::Character(query),
+        } => {
+            store(
+                Path(account_id),
+                Query(query),
+                format,
+                bypass,
+                projection,
+                headers,
+                State(state),
+            )
+            .await
+        }
+        BatchItem::Store {
+            account_id,
+            target: StoreTarget::Archetype { archetype, query },
+        } => {
+            return store_by_archetype(
+                Path((account_id, archetype)),
+                Query(query),
+                format,
+                bypass,
+                projection,
+                headers,
+                State(state),
+            )
+            .await
+        }
+        BatchItem::Inventory { account_id, query } => {
+            inventory(Path(account_id), Query(query), format, bypass, State(state)).await
+        }
+        BatchItem::Materials { account_id } => {
+            materials(Path(account_id), format, bypass, State(state)).await
+        }
+        BatchItem::MasterData { account_id } => {
+            master_data(Path(account_id), format, bypass, State(state)).await
+        }
+    };
+    response.unwrap_or_else(|status| {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = status;
+        response
+    })
+}
+
+async fn result(response: Response) -> BatchResult {
+    let status = response.status().as_u16();
+    let body = match to_bytes(response.into_body(), MAX_ITEM_BODY).await {
+        Ok(bytes) if bytes.is_empty() => serde_json::Value::Null,
+        Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+            error!(error = %e, "Failed to parse response in batch");
+            serde_json::Value::Null
+        }),
+        Err(e) => {
+            error!(error = %e, "Failed to read response in batch");
+            serde_json::Value::Null
+        }
+    };
+    BatchResult { status, body }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parses_resource_descriptors() {
+        let account = "11111111-1111-1111-1111-111111111111";
+        let character = "22222222-2222-2222-2222-222222222222";
+        let items: Vec<BatchItem> = serde_json::from_value(json!([
+            { "resource": "summary", "accountId": account },
+            {
+                "resource": "store",
+                "accountId": account,
+                "archetype": "zealot",
+                "currencyType": "marks"
+            },
+            {
+                "resource": "store",
+                "accountId": account,
+                "characterId": character,
+                "currencyType": "credits",
+                "annotate": "stats"
+            },
+            { "resource": "wallets", "accountId": account },
+            { "resource": "masterData", "accountId": account }
+        ]))
+        .unwrap();
+
+        assert!(matches!(items[0], BatchItem::Summary { .. }));
+        assert!(matches!(
+            &items[1],
+            BatchItem::Store {
+                target: StoreTarget::Archetype { archetype, .. },
+                ..
+            } if archetype == "zealot"
+        ));
+        assert!(matches!(
+            items[2],
+            BatchItem::Store {
+                target: StoreTarget::Character(_),
+                ..
+            }
+        ));
+        assert!(matches!(items[3], BatchItem::Materials { .. }));
+        assert!(matches!(items[4], BatchItem::MasterData { .. }));
+    }
+}
@@ -0,0 +1,49 @@
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Extension, Json,
+};
+use dt_api::models::AccountId;
+use tracing::{error, instrument, warn};
+
+use crate::{
+    account::{AccountBundle, AccountData},
+    server::{admin, AppData, ClientIp},
+};
+
+#[instrument(skip(state))]
+pub(crate) async fn export(
+    Path(id): Path<AccountId>,
+    State(state): State<AppData>,
+) -> Result<Json<AccountBundle>, StatusCode> {
+    match state.accounts.get(&id).await {
+        Some(account_data) => Ok(Json(account_data.bundle(id).await)),
+        None => {
+            error!(sid = ?id, "Failed to find account data");
+            Err(StatusCode::NOT_FOUND)
+        }
+    }
+}
+
+/// Seed the cache of an account with a bundle, replacing its cached data.
+///
+/// Requires `Authorization: Bearer <adminToken>`, and is not found if no admin
+/// token is configured.
+#[instrument(skip_all, fields(sid = ?bundle.id))]
+pub(crate) async fn import(
+    headers: HeaderMap,
+    client_ip: Option<Extension<ClientIp>>,
+    State(state): State<AppData>,
+    Json(bundle): Json<AccountBundle>,
+) -> StatusCode {
+    let client_ip = client_ip.map(|Extension(ip)| ip.0);
+    if let Err(status) = admin::authorize(&headers, client_ip, &state) {
+        return status;
+    }
+    warn!(client_ip = ?client_ip, "Seeding account data from bundle by admin request");
+    state
+        .accounts
+        .insert(bundle.id, AccountData::from_bundle(bundle))
+        .await;
+    StatusCode::CREATED
+}
@@ -0,0 +1,247 @@
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+};
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, State},
+    http::{header, HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use crate::config::Config;
+
+/// Header the trusted proxies forward client addresses in. Only that header is
+/// read, as proxies pass the other one on from the client untouched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum ForwardedHeader {
+    /// `X-Forwarded-For`, as written by nginx and most load balancers.
+    #[default]
+    XForwardedFor,
+    /// `Forwarded` from RFC 7239.
+    Forwarded,
+}
+
+/// Address of the client that made a request.
+///
+/// The peer address, unless the peer is one of the `trustedProxies`: then the
+/// address the proxies forwarded in the `forwardedHeader`, skipping those of
+/// trusted proxies from the right, as proxies append the address of their
+/// peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ClientIp(pub IpAddr);
+
+impl fmt::Display for ClientIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Add the [`ClientIp`] extension to requests.
+///
+/// Requests without a peer address, which only happens when the router isn't
+/// served with connect info, get no extension.
+pub(crate) async fn client_ip(
+    State(config): State<watch::Receiver<Config>>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    if let Some(peer) = peer {
+        let client = {
+            let config = config.borrow();
+            resolve(
+                peer,
+                request.headers(),
+                config.forwarded_header,
+                &config.trusted_proxies,
+            )
+        };
+        request.extensions_mut().insert(ClientIp(client));
+    }
+    next.run(request).await
+}
+
+fn resolve(
+    peer: IpAddr,
+    headers: &HeaderMap,
+    header: ForwardedHeader,
+    trusted: &[IpNet],
+) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+    if !is_trusted(&peer) {
+        return peer;
+    }
+    let mut client = peer;
+    // An address that can't be parsed, such as an obfuscated identifier,
+    // leaves the last proxy as the client.
+    for forwarded in forwarded_for(headers, header).into_iter().rev() {
+        let Some(ip) = forwarded else {
+            break;
+        };
+        client = ip;
+        if !is_trusted(&ip) {
+            break;
+        }
+    }
+    client
+}
+
+/// The addresses forwarded in `header`, from the client to the last proxy.
+fn forwarded_for(headers: &HeaderMap, header: ForwardedHeader) -> Vec<Option<IpAddr>> {
+    let values = |name| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect::<Vec<_>>()
+    };
+    match header {
+        ForwardedHeader::Forwarded => values(header::FORWARDED)
+            .into_iter()
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_node(node.trim().trim_matches('"')))
+            })
+            .collect(),
+        ForwardedHeader::XForwardedFor => {
+            values(header::HeaderName::from_static("x-forwarded-for"))
+                .into_iter()
+                .map(parse_node)
+                .collect()
+        }
+    }
+}
+
+/// Parse an address with an optional port, and IPv6 addresses in brackets.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')
+        .and_then(|node| node.strip_suffix(']'))
+        .and_then(|ip| ip.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    header::HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    fn trusted() -> Vec<IpNet> {
+        vec!["10.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()]
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn ignores_headers_from_untrusted_peers() {
+        let headers = headers(&[("x-forwarded-for", "192.0.2.1")]);
+        assert_eq!(
+            resolve(
+                ip("198.51.100.7"),
+                &headers,
+                ForwardedHeader::default(),
+                &trusted()
+            ),
+            ip("198.51.100.7")
+        );
+    }
+
+    #[test]
+    fn skips_trusted_proxies_from_the_right() {
+        let headers = headers(&[("x-forwarded-for", "192.0.2.1, 203.0.113.9, 10.1.2.3")]);
+        assert_eq!(
+            resolve(
+                ip("10.0.0.1"),
+                &headers,
+                ForwardedHeader::default(),
+                &trusted()
+            ),
+            ip("203.0.113.9")
+        );
+    }
+
+    #[test]
+    fn reads_only_the_configured_header() {
+        let headers = headers(&[
+            (
+                "forwarded",
+                r#"for=192.0.2.1, for="[2001:db8::17]:4711";proto=https"#,
+            ),
+            ("x-forwarded-for", "203.0.113.9"),
+        ]);
+        assert_eq!(
+            resolve(ip("::1"), &headers, ForwardedHeader::Forwarded, &trusted()),
+            ip("2001:db8::17")
+        );
+        assert_eq!(
+            resolve(
+                ip("::1"),
+                &headers,
+                ForwardedHeader::XForwardedFor,
+                &trusted()
+            ),
+            ip("203.0.113.9")
+        );
+    }
+
+    #[test]
+    fn stops_at_unknown_addresses() {
+        let headers = headers(&[("forwarded", "for=192.0.2.1, for=_hidden, for=10.0.0.2:80")]);
+        assert_eq!(
+            resolve(
+                ip("10.0.0.1"),
+                &headers,
+                ForwardedHeader::Forwarded,
+                &trusted()
+            ),
+            ip("10.0.0.2")
+        );
+    }
+
+    #[test]
+    fn falls_back_to_peer_without_headers() {
+        assert_eq!(
+            resolve(
+                ip("10.0.0.1"),
+                &HeaderMap::new(),
+                ForwardedHeader::default(),
+                &trusted()
+            ),
+            ip("10.0.0.1")
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use rust_embed::RustEmbed;
+
+use crate::server::AppData;
+
+/// Dashboard assets, embedded in the binary.
+#[derive(RustEmbed)]
+#[folder = "dashboard/"]
+struct Assets;
+
+/// The dashboard page, with a base URL under the path prefix so its relative
+/// links resolve whether or not the prefix is requested with a trailing slash.
+pub(crate) async fn index(State(state): State<AppData>) -> Response {
+    if state.path_prefix.is_empty() {
+        return asset("index.html");
+    }
+    let Some(index) = Assets::get("index.html") else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let html = String::from_utf8_lossy(&index.data).replacen(
+        "<head>",
+        &format!("<head>\n  <base href=\"{}/\">", state.path_prefix),
+        1,
+    );
+    ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], html).into_response()
+}
+
+pub(crate) async fn dashboard(Path(file): Path<String>) -> Response {
+    asset(&file)
+}
+
+fn asset(file: &str) -> Response {
+    let Some(asset) = Assets::get(file) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let content_type = match file.rsplit_once('.').map(|(_, ext)| ext) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        _ => "application/octet-stream",
+    };
+    ([(header::CONTENT_TYPE, content_type)], asset.data).into_response()
+}
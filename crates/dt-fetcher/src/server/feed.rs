@@ -0,0 +1,189 @@
+use std::{collections::BTreeSet, fmt::Write};
+
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use dt_api::models::{AccountId, CharacterId, Offer, Summary};
+use tracing::{error, instrument};
+
+use crate::{history::RotationSnapshot, server::AppData};
+
+/// Maximum number of rotations in the RSS feed.
+const RSS_ITEMS: usize = 50;
+
+/// Serve the rotation feed of an account: `:id.rss` for an RSS feed of new
+/// rotations, or `:id.ics` for a calendar of rotation end times.
+#[instrument(skip(state))]
+pub(crate) async fn feed(
+    Path(file): Path<String>,
+    State(state): State<AppData>,
+) -> Result<Response, StatusCode> {
+    let (id, extension) = file.rsplit_once('.').ok_or(StatusCode::NOT_FOUND)?;
+    let id = AccountId(uuid::Uuid::parse_str(id).map_err(|_| StatusCode::NOT_FOUND)?);
+    let Some(account_data) = state.accounts.get(&id).await else {
+        error!(sid = ?id, "Failed to find account data");
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let snapshots = state.api.history().list(id).map_err(|e| {
+        error!(sid = ?id, error = %e, "Failed to read history");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let summary = account_data.summary.peek();
+    match extension {
+        "rss" => Ok((
+            [(header::CONTENT_TYPE, "application/rss+xml")],
+            rss(id, summary.as_deref(), &snapshots, &state.path_prefix),
+        )
+            .into_response()),
+        "ics" => Ok((
+            [(header::CONTENT_TYPE, "text/calendar")],
+            ics(summary.as_deref(), &snapshots),
+        )
+            .into_response()),
+        _ => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+fn character_name(summary: Option<&Summary>, id: CharacterId) -> String {
+    summary
+        .and_then(|summary| summary.characters.iter().find(|c| c.id == id))
+        .map_or_else(|| id.to_string(), |c| c.name.clone())
+}
+
+fn offer_summary(offer: &Offer) -> String {
+    let mut summary = format!("{} ({}", offer.sku.name, offer.sku.category);
+    if let Some(item) = offer.description.overrides.item() {
+        let _ = write!(
+            summary,
+            ", rarity {}, item level {}",
+            item.rarity, item.item_level
+        );
+    }
+    let _ = write!(summary, ") - {}", offer.price.amount);
+    summary
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn rss(
+    id: AccountId,
+    summary: Option<&Summary>,
+    snapshots: &[RotationSnapshot],
+    path_prefix: &str,
+) -> String {
+    let account_name = summary.map_or_else(|| id.to_string(), |summary| summary.name.clone());
+    let mut feed = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    feed.push_str(r#"<rss version="2.0"><channel>"#);
+    let _ = write!(
+        feed,
+        "<title>{}</title><link>{path_prefix}/feed/{id}.rss</link><description>{}</description>",
+        escape_xml(&format!("Store rotations for {account_name}")),
+        escape_xml(&format!("New Darktide store rotations for {account_name}")),
+    );
+    let mut snapshots = snapshots.iter().collect::<Vec<_>>();
+    snapshots.sort_by_key(|snapshot| std::cmp::Reverse(snapshot.archived_at));
+    for snapshot in snapshots.into_iter().take(RSS_ITEMS) {
+        let store = &snapshot.store;
+        let items = store
+            .public
+            .iter()
+            .chain(store.personal.iter())
+            .map(|offer| format!("<li>{}</li>", escape_xml(&offer_summary(offer))))
+            .collect::<String>();
+        let _ = write!(
+            feed,
+            "<item><title>{}</title><guid isPermaLink=\"false\">{}</guid><pubDate>{}</pubDate><description>{}</description></item>",
+            escape_xml(&format!(
+                "New {} store for {}, until {}",
+                snapshot.currency_type,
+                character_name(summary, snapshot.character_id),
+                store.current_rotation_end.format("%Y-%m-%d %H:%M UTC"),
+            )),
+            rotation_uid(snapshot),
+            snapshot.archived_at.to_rfc2822(),
+            escape_xml(&format!("<ul>{items}</ul>")),
+        );
+    }
+    feed.push_str("</channel></rss>");
+    feed
+}
+
+fn rotation_uid(snapshot: &RotationSnapshot) -> String {
+    format!(
+        "{}-{}-{}-{}",
+        snapshot.account_id,
+        snapshot.character_id,
+        snapshot.currency_type,
+        snapshot.store.current_rotation_end.timestamp()
+    )
+}
+
+fn escape_ics(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+fn ics_time(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Append a content line, folded to 75 octets as required by RFC 5545.
+fn push_ics_line(calendar: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            calendar.push_str("\r\n ");
+            width = 1;
+        }
+        calendar.push(c);
+        width += c.len_utf8();
+    }
+    calendar.push_str("\r\n");
+}
+
+fn ics(summary: Option<&Summary>, snapshots: &[RotationSnapshot]) -> String {
+    let mut calendar = String::new();
+    push_ics_line(&mut calendar, "BEGIN:VCALENDAR");
+    push_ics_line(&mut calendar, "VERSION:2.0");
+    push_ics_line(&mut calendar, "PRODID:-//dt-fetcher//Store rotations//EN");
+    let now = ics_time(Utc::now());
+    let rotations = snapshots
+        .iter()
+        .map(|snapshot| (snapshot.character_id.0, snapshot.store.current_rotation_end))
+        .collect::<BTreeSet<_>>();
+    for (character_id, rotation_end) in rotations {
+        let end = ics_time(rotation_end);
+        push_ics_line(&mut calendar, "BEGIN:VEVENT");
+        push_ics_line(
+            &mut calendar,
+            &format!("UID:{character_id}-{}@dt-fetcher", rotation_end.timestamp()),
+        );
+        push_ics_line(&mut calendar, &format!("DTSTAMP:{now}"));
+        push_ics_line(&mut calendar, &format!("DTSTART:{end}"));
+        push_ics_line(&mut calendar, &format!("DTEND:{end}"));
+        push_ics_line(
+            &mut calendar,
+            &format!(
+                "SUMMARY:{}",
+                escape_ics(&format!(
+                    "Store rotates for {}",
+                    character_name(summary, CharacterId(character_id))
+                ))
+            ),
+        );
+        push_ics_line(&mut calendar, "END:VEVENT");
+    }
+    push_ics_line(&mut calendar, "END:VCALENDAR");
+    calendar
+}
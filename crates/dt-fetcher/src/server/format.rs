@@ -0,0 +1,354 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Query},
+    http::{header, request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::{projection::Fields, tabular::Delimited};
+
+#[derive(Debug, Deserialize)]
+struct FormatQuery {
+    format: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FieldsQuery {
+    fields: Option<String>,
+}
+
+/// Response encoding requested with `?format=` or, failing that, the `Accept`
+/// header. The binary formats encode the same models as JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ResponseFormat {
+    Json,
+    MessagePack,
+    Cbor,
+    Delimited(Delimited),
+}
+
+impl ResponseFormat {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(ResponseFormat::Json),
+            "msgpack" => Some(ResponseFormat::MessagePack),
+            "cbor" => Some(ResponseFormat::Cbor),
+            "csv" => Some(ResponseFormat::Delimited(Delimited::Csv)),
+            "tsv" => Some(ResponseFormat::Delimited(Delimited::Tsv)),
+            _ => None,
+        }
+    }
+
+    /// The known media type in `accept` with the highest quality, the first
+    /// of them on ties. Media types with `q=0` are not acceptable.
+    fn from_accept(accept: &str) -> Self {
+        let mut best = None;
+        for media_type in accept.split(',') {
+            let mut params = media_type.split(';');
+            let format = match params.next().unwrap_or_default().trim() {
+                "application/json" => ResponseFormat::Json,
+                "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                    ResponseFormat::MessagePack
+                }
+                "application/cbor" => ResponseFormat::Cbor,
+                "text/csv" => ResponseFormat::Delimited(Delimited::Csv),
+                "text/tab-separated-values" => ResponseFormat::Delimited(Delimited::Tsv),
+                _ => continue,
+            };
+            let quality = params
+                .filter_map(|param| param.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                .map_or(Some(1.0), |(_, value)| value.trim().parse::<f32>().ok());
+            let Some(quality) = quality.filter(|quality| *quality > 0.0) else {
+                continue;
+            };
+            if best.map_or(true, |(_, best)| quality > best) {
+                best = Some((format, quality));
+            }
+        }
+        best.map_or(ResponseFormat::Json, |(format, _)| format)
+    }
+
+    /// Encode `value`, or `rows` flattened from it for delimited formats.
+    pub fn render<T: Serialize, R: Serialize>(
+        self,
+        value: &T,
+        rows: impl IntoIterator<Item = R>,
+    ) -> Result<Response, StatusCode> {
+        match self {
+            ResponseFormat::Delimited(delimited) => {
+                let body = delimited.to_vec(rows).map_err(|e| {
+                    error!(error = ?e, "Failed to encode rows");
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?;
+                Ok(([(header::CONTENT_TYPE, delimited.content_type())], body).into_response())
+            }
+            _ => self.encode(value),
+        }
+    }
+
+    /// Encode `value` for endpoints that have no delimited form.
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Response, StatusCode> {
+        let (content_type, body) = match self {
+            ResponseFormat::Json => return Ok(Json(value).into_response()),
+            ResponseFormat::MessagePack => (
+                "application/msgpack",
+                rmp_serde::to_vec_named(value).map_err(anyhow::Error::from),
+            ),
+            ResponseFormat::Cbor => {
+                let mut body = Vec::new();
+                let result = ciborium::into_writer(value, &mut body)
+                    .map(|_| body)
+                    .map_err(anyhow::Error::from);
+                ("application/cbor", result)
+            }
+            ResponseFormat::Delimited(_) => return Err(StatusCode::NOT_ACCEPTABLE),
+        };
+        let body = body.map_err(|e| {
+            error!(error = ?e, format = ?self, "Failed to encode response");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        Ok(([(header::CONTENT_TYPE, content_type)], body).into_response())
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ResponseFormat {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Query(FormatQuery { format }) =
+            Query::try_from_uri(&parts.uri).map_err(|_| StatusCode::BAD_REQUEST)?;
+        if let Some(format) = format {
+            return Self::from_name(&format).ok_or(StatusCode::BAD_REQUEST);
+        }
+        Ok(parts
+            .headers
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .map_or(ResponseFormat::Json, Self::from_accept))
+    }
+}
+
+/// Fields requested with `?fields=`, or `None` to keep every field.
+///
+/// The delimited formats have fixed columns, so requests combining them with
+/// `?fields=` are rejected.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Projection(pub Option<Fields>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Projection {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(FieldsQuery { fields }) =
+            Query::try_from_uri(&parts.uri).map_err(|_| StatusCode::BAD_REQUEST)?;
+        let fields = fields.as_deref().and_then(Fields::parse);
+        if fields.is_some() {
+            if let ResponseFormat::Delimited(_) =
+                ResponseFormat::from_request_parts(parts, state).await?
+            {
+                return Err(StatusCode::BAD_REQUEST);
+            }
+        }
+        Ok(Projection(fields))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::to_bytes;
+    use dt_api::models::{Store, Summary};
+    use serde::de::DeserializeOwned;
+
+    use super::*;
+
+    const STORE: &str = r#"{
+        "_links": {},
+        "catalog": {
+            "id": "88888888-8888-8888-8888-888888888888",
+            "name": "catalog",
+            "generation": 1,
+            "layoutRef": null,
+            "validFrom": "1700000000000",
+            "validTo": "1700600000000"
+        },
+        "name": "marks",
+        "public": [],
+        "personal": [{
+            "offerId": "33333333-3333-3333-3333-333333333333",
+            "sku": {
+                "id": "44444444-4444-4444-4444-444444444444",
+                "displayPriority": 1,
+                "internalName": "lasgun_p1_m2",
+                "name": "Lasgun",
+                "description": "",
+                "category": "WEAPON",
+                "assetId": "lasgun",
+                "tags": [],
+                "dlcReq": []
+            },
+            "entitlement": {
+                "id": "55555555-5555-5555-5555-555555555555",
+                "limit": 1,
+                "type": "GearInstance"
+            },
+            "price": {
+                "amount": { "amount": 1200, "type": "marks" },
+                "id": "66666666-6666-6666-6666-666666666666",
+                "priority": 1,
+                "priceFormula": null
+            },
+            "state": "active",
+            "description": {
+                "id": "lasgun_p1_m2",
+                "gearId": "77777777-7777-7777-7777-777777777777",
+                "rotation": "daily",
+                "type": "weapon",
+                "properties": { "level": 30, "tags": ["ranged"] },
+                "overrides": {
+                    "ver": 1,
+                    "rarity": 4,
+                    "characterLevel": 30,
+                    "itemLevel": 380,
+                    "baseItemLevel": 300,
+                    "traits": [{ "id": "t1", "rarity": 3, "value": 0.5 }],
+                    "perks": [{ "id": "p1", "rarity": 2 }],
+                    "base_stats": [{ "name": "damage", "value": 0.75 }]
+                }
+            },
+            "media": []
+        }],
+        "rerollsThisRotation": 0,
+        "currentRotationEnd": "1700000000000"
+    }"#;
+
+    const SUMMARY: &str = r#"{
+        "_links": {},
+        "username": "user",
+        "name": "name",
+        "discriminator": "1234",
+        "allowRename": false,
+        "characters": [{
+            "id": "11111111-1111-1111-1111-111111111111",
+            "name": "Zola",
+            "gender": "female",
+            "archetype": "veteran",
+            "specialization": "veteran_2",
+            "level": 30
+        }],
+        "email": { "verified": true },
+        "linkedAccounts": { "steam": "", "twitch": "" },
+        "marketingPreferences": {
+            "newsletterSubscribe": false,
+            "optIn": false,
+            "termsAgreed": true
+        }
+    }"#;
+
+    /// Encode `json` as `T` in `format` and decode it again, returning it as
+    /// JSON for comparison.
+    async fn round_trip<T: Serialize + DeserializeOwned>(
+        format: ResponseFormat,
+        json: &str,
+    ) -> serde_json::Value {
+        let value: T = serde_json::from_str(json).unwrap();
+        let body = to_bytes(format.encode(&value).unwrap().into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let decoded: T = match format {
+            ResponseFormat::MessagePack => rmp_serde::from_slice(&body).unwrap(),
+            ResponseFormat::Cbor => ciborium::from_reader(&body[..]).unwrap(),
+            _ => serde_json::from_slice(&body).unwrap(),
+        };
+        serde_json::to_value(decoded).unwrap()
+    }
+
+    #[tokio::test]
+    async fn store_round_trips() {
+        let expected = serde_json::to_value(serde_json::from_str::<Store>(STORE).unwrap()).unwrap();
+        for format in [ResponseFormat::MessagePack, ResponseFormat::Cbor] {
+            assert_eq!(
+                round_trip::<Store>(format, STORE).await,
+                expected,
+                "{format:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn summary_round_trips() {
+        let expected =
+            serde_json::to_value(serde_json::from_str::<Summary>(SUMMARY).unwrap()).unwrap();
+        for format in [ResponseFormat::MessagePack, ResponseFormat::Cbor] {
+            assert_eq!(
+                round_trip::<Summary>(format, SUMMARY).await,
+                expected,
+                "{format:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn negotiates_from_accept() {
+        assert_eq!(
+            ResponseFormat::from_accept("application/msgpack"),
+            ResponseFormat::MessagePack
+        );
+        assert_eq!(
+            ResponseFormat::from_accept("application/cbor;q=0.9, application/json"),
+            ResponseFormat::Json
+        );
+        assert_eq!(
+            ResponseFormat::from_accept("application/cbor;q=0"),
+            ResponseFormat::Json
+        );
+        assert_eq!(
+            ResponseFormat::from_accept("text/csv; q=0.5, application/cbor; q=0.8, */*"),
+            ResponseFormat::Cbor
+        );
+        assert_eq!(
+            ResponseFormat::from_accept("application/msgpack, application/cbor"),
+            ResponseFormat::MessagePack
+        );
+        assert_eq!(ResponseFormat::from_accept("*/*"), ResponseFormat::Json);
+    }
+
+    async fn projection(uri: &str, accept: Option<&str>) -> Result<Projection, StatusCode> {
+        let mut request = axum::http::Request::builder().uri(uri);
+        if let Some(accept) = accept {
+            request = request.header(header::ACCEPT, accept);
+        }
+        let (mut parts, ()) = request.body(()).unwrap().into_parts();
+        Projection::from_request_parts(&mut parts, &()).await
+    }
+
+    #[tokio::test]
+    async fn rejects_fields_with_delimited_formats() {
+        let fields = Fields::parse("name");
+        assert_eq!(
+            projection("/summary?fields=name", None).await,
+            Ok(Projection(fields.clone()))
+        );
+        assert_eq!(
+            projection("/summary?fields=name&format=cbor", None).await,
+            Ok(Projection(fields))
+        );
+        assert_eq!(
+            projection("/summary?fields=name&format=csv", None).await,
+            Err(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(
+            projection("/summary?fields=name", Some("text/tab-separated-values")).await,
+            Err(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(
+            projection("/summary?format=csv", None).await,
+            Ok(Projection(None))
+        );
+    }
+}
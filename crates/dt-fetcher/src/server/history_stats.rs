@@ -0,0 +1,178 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use dt_api::models::{AccountId, CurrencyType, Store};
+use serde::{Deserialize, Serialize};
+use tracing::{error, instrument};
+
+use crate::server::AppData;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct StatsQuery {
+    /// Only the rotations of this account, instead of every tracked account.
+    account_id: Option<AccountId>,
+    currency_type: Option<CurrencyType>,
+}
+
+/// How often something was offered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Frequency {
+    /// Rotations offering it at least once.
+    pub rotations: usize,
+    /// Offers of it, across every rotation.
+    pub offers: usize,
+    /// Share of the rotations offering it, from 0 to 1.
+    pub frequency: f64,
+}
+
+/// How often items, categories and traits appear in archived rotations.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct HistoryStats {
+    /// Archived rotations counted, one per character and currency.
+    rotations: usize,
+    /// By item id, e.g. `chainsword_p1_m1`.
+    items: BTreeMap<String, Frequency>,
+    /// By SKU category, e.g. `weapon`.
+    categories: BTreeMap<String, Frequency>,
+    /// By trait id.
+    traits: BTreeMap<String, Frequency>,
+}
+
+impl HistoryStats {
+    fn observe(&mut self, store: &Store) {
+        self.rotations += 1;
+        let mut items = BTreeSet::new();
+        let mut categories = BTreeSet::new();
+        let mut traits = BTreeSet::new();
+        for offer in store.offers() {
+            items.insert(offer.description.id.as_str());
+            categories.insert(offer.sku.category.as_str());
+            for t in offer
+                .description
+                .overrides
+                .item()
+                .into_iter()
+                .flat_map(|item| &item.traits)
+            {
+                traits.insert(t.id.as_str());
+                count_offer(&mut self.traits, &t.id);
+            }
+            count_offer(&mut self.items, &offer.description.id);
+            count_offer(&mut self.categories, &offer.sku.category);
+        }
+        count_rotation(&mut self.items, items);
+        count_rotation(&mut self.categories, categories);
+        count_rotation(&mut self.traits, traits);
+    }
+
+    fn finish(mut self) -> Self {
+        let rotations = self.rotations as f64;
+        for frequency in self
+            .items
+            .values_mut()
+            .chain(self.categories.values_mut())
+            .chain(self.traits.values_mut())
+        {
+            frequency.frequency = frequency.rotations as f64 / rotations;
+        }
+        self
+    }
+}
+
+fn count_offer(frequencies: &mut BTreeMap<String, Frequency>, key: &str) {
+    frequencies.entry(key.to_string()).or_default().offers += 1;
+}
+
+fn count_rotation<'a>(
+    frequencies: &mut BTreeMap<String, Frequency>,
+    keys: impl IntoIterator<Item = &'a str>,
+) {
+    for key in keys {
+        if let Some(frequency) = frequencies.get_mut(key) {
+            frequency.rotations += 1;
+        }
+    }
+}
+
+/// Count how often items, categories and traits were offered in the archived
+/// rotations of an account, or of every tracked account.
+#[instrument(skip(state))]
+pub(crate) async fn history_stats(
+    Query(StatsQuery {
+        account_id,
+        currency_type,
+    }): Query<StatsQuery>,
+    State(state): State<AppData>,
+) -> Result<Json<HistoryStats>, StatusCode> {
+    let accounts = match account_id {
+        Some(id) => {
+            if state.accounts.get(&id).await.is_none() {
+                error!(sid = ?id, "Failed to find account data");
+                return Err(StatusCode::NOT_FOUND);
+            }
+            vec![id]
+        }
+        None => state
+            .accounts
+            .list()
+            .await
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect(),
+    };
+    let mut stats = HistoryStats::default();
+    for id in accounts {
+        let snapshots = state.api.history().list(id).map_err(|e| {
+            error!(sid = ?id, error = %e, "Failed to read history");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        for snapshot in snapshots {
+            if !currency_type.is_some_and(|currency_type| currency_type != snapshot.currency_type) {
+                stats.observe(&snapshot.store);
+            }
+        }
+    }
+    Ok(Json(stats.finish()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_rotations_and_offers() {
+        let store: Store =
+            serde_json::from_str(include_str!("../../tests/fixtures/store.json")).unwrap();
+        let mut empty = store.clone();
+        empty.personal.clear();
+        empty.public.clear();
+        let mut stats = HistoryStats::default();
+        stats.observe(&store);
+        stats.observe(&empty);
+        let stats = stats.finish();
+
+        assert_eq!(stats.rotations, 2);
+        let offers = store.offers().count();
+        assert_eq!(
+            stats.categories.values().map(|f| f.offers).sum::<usize>(),
+            offers
+        );
+        for frequency in stats
+            .items
+            .values()
+            .chain(stats.categories.values())
+            .chain(stats.traits.values())
+        {
+            assert_eq!(frequency.rotations, 1);
+            assert!(frequency.offers >= 1);
+            assert_eq!(frequency.frequency, 0.5);
+        }
+    }
+}
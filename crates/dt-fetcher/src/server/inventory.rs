@@ -0,0 +1,109 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use dt_api::models::{AccountId, CharacterId, Inventory};
+use tracing::{error, info, instrument};
+
+use crate::{
+    account::CachedInventory,
+    clock::Clock,
+    server::{
+        access_log, current_summary,
+        format::ResponseFormat,
+        raw::{upstream_response, Bypass, RawResource},
+        single_account, AppData,
+    },
+};
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct InventoryQuery {
+    character_id: CharacterId,
+}
+
+#[instrument(skip(state))]
+pub(crate) async fn inventory(
+    Path(id): Path<AccountId>,
+    Query(InventoryQuery { character_id }): Query<InventoryQuery>,
+    format: ResponseFormat,
+    Bypass(raw): Bypass,
+    State(state): State<AppData>,
+) -> Result<Response, StatusCode> {
+    if raw {
+        return upstream_response(id, RawResource::Inventory { character_id }, state).await;
+    }
+    let Json(inventory) = current_inventory(id, character_id, state).await?;
+    format.encode(&inventory)
+}
+
+/// Get the cached inventory of a character, refreshing it if it is older than
+/// the summary TTL of the account.
+#[instrument(skip(state))]
+pub(super) async fn current_inventory(
+    id: AccountId,
+    character_id: CharacterId,
+    state: AppData,
+) -> Result<Json<Inventory>, StatusCode> {
+    let Some(account_data) = state.accounts.get(&id).await else {
+        error!("Failed to find account data");
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let default_ttl = state.config.borrow().summary_refresh_interval_mins;
+    let ttl = state.settings.summary_ttl(id, default_ttl);
+    if let Some(cached) = account_data.inventories.read().await.get(&character_id) {
+        if cached.fetched_at + ttl > state.accounts.clock().now() {
+            info!("Returning cached inventory");
+            access_log::cache_hit();
+            return Ok(Json(cached.inventory.clone()));
+        }
+        info!("Inventory out of date; refreshing");
+    }
+    access_log::cache_miss();
+    let summary = current_summary(id, state.clone()).await?;
+    let Some(character) = summary.characters.iter().find(|c| c.id == character_id) else {
+        error!(character.id = %character_id, "Failed to find character");
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let auth_data = match state.auth_data.get(id) {
+        Ok(Some(auth_data)) => auth_data,
+        Ok(None) => {
+            error!(sid = ?id, "Failed to find auth data");
+            return Err(StatusCode::NOT_FOUND);
+        }
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    match state.api.get_inventory(&auth_data, character).await {
+        Ok(inventory) => {
+            account_data.inventories.write().await.insert(
+                character_id,
+                CachedInventory {
+                    fetched_at: state.accounts.clock().now(),
+                    inventory: inventory.clone(),
+                },
+            );
+            info!("Successfully fetched inventory");
+            Ok(Json(inventory))
+        }
+        Err(e) => {
+            error!(character.id = %character_id, error = %e, "Failed to get inventory");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[instrument(skip(state))]
+pub(crate) async fn inventory_single(
+    query: Query<InventoryQuery>,
+    format: ResponseFormat,
+    bypass: Bypass,
+    State(state): State<AppData>,
+) -> Result<Response, Response> {
+    let account = single_account(&state).map_err(IntoResponse::into_response)?;
+    inventory(Path(account), query, format, bypass, State(state))
+        .await
+        .map_err(IntoResponse::into_response)
+}
@@ -0,0 +1,195 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use dt_api::{
+    models::{AccountId, LeaderboardEntry},
+    Auth,
+};
+use serde::Serialize;
+use tokio::sync::Mutex;
+use tracing::{error, info, instrument, warn};
+
+use crate::{
+    auth::SingleAccount,
+    clock::Clock,
+    server::{access_log, format::ResponseFormat, AppData},
+};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+/// Leaderboard with when it was fetched.
+#[derive(Debug, Clone)]
+struct CachedLeaderboard {
+    fetched_at: DateTime<Utc>,
+    entries: Arc<Vec<LeaderboardEntry>>,
+}
+
+/// A leaderboard, locked while it is fetched.
+type Board = Arc<Mutex<Option<CachedLeaderboard>>>;
+
+/// Leaderboards shared by every account, keyed by board name.
+///
+/// Each board has its own lock, held while fetching, so concurrent requests
+/// for an expired board wait for a single upstream fetch without holding up
+/// the other boards.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Leaderboards(Arc<std::sync::Mutex<HashMap<String, Board>>>);
+
+impl Leaderboards {
+    fn board(&self, name: &str) -> Board {
+        let mut boards = self.0.lock().expect("Leaderboards poisoned");
+        boards.entry(name.to_string()).or_default().clone()
+    }
+
+    /// Forget a board that failed to fetch, so that requests for boards that
+    /// don't exist leave nothing behind.
+    fn remove(&self, name: &str, board: &Board) {
+        let mut boards = self.0.lock().expect("Leaderboards poisoned");
+        if boards.get(name).is_some_and(|b| Arc::ptr_eq(b, board)) {
+            boards.remove(name);
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub(crate) struct LeaderboardQuery {
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LeaderboardPage<'a> {
+    board: &'a str,
+    fetched_at: DateTime<Utc>,
+    total: usize,
+    entries: &'a [LeaderboardEntry],
+}
+
+#[instrument(skip(state))]
+pub(crate) async fn leaderboard(
+    Path(board): Path<String>,
+    Query(LeaderboardQuery { offset, limit }): Query<LeaderboardQuery>,
+    format: ResponseFormat,
+    State(state): State<AppData>,
+) -> Result<Response, StatusCode> {
+    if !is_valid_board(&board) {
+        error!("Invalid leaderboard name");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let cached = current_leaderboard(&board, &state).await?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let entries = cached
+        .entries
+        .get(offset..)
+        .map_or(&[][..], |entries| &entries[..limit.min(entries.len())]);
+    let page = LeaderboardPage {
+        board: &board,
+        fetched_at: cached.fetched_at,
+        total: cached.entries.len(),
+        entries,
+    };
+    format.render(&page, entries)
+}
+
+/// Get the cached leaderboard, refreshing it if it is older than
+/// `leaderboardTtlMins`. If the refresh fails, an expired leaderboard is
+/// served rather than none.
+#[instrument(skip(state))]
+async fn current_leaderboard(
+    board: &str,
+    state: &AppData,
+) -> Result<CachedLeaderboard, StatusCode> {
+    let ttl = chrono::Duration::minutes(state.config.borrow().leaderboard_ttl_mins);
+    let slot = state.leaderboards.board(board);
+    let mut cached = slot.lock().await;
+    if let Some(cached) = &*cached {
+        if cached.fetched_at + ttl > state.accounts.clock().now() {
+            info!("Returning cached leaderboard");
+            access_log::cache_hit();
+            return Ok(cached.clone());
+        }
+        info!("Leaderboard out of date; refreshing");
+    }
+    access_log::cache_miss();
+    let auth = match leaderboard_auth(state) {
+        Ok(auth) => auth,
+        Err(status) => {
+            if cached.is_none() {
+                state.leaderboards.remove(board, &slot);
+            }
+            return Err(status);
+        }
+    };
+    match state.api.get_leaderboard(&auth, board).await {
+        Ok(entries) => {
+            info!(entries = entries.len(), "Successfully fetched leaderboard");
+            let fetched = CachedLeaderboard {
+                fetched_at: state.accounts.clock().now(),
+                entries: Arc::new(entries),
+            };
+            *cached = Some(fetched.clone());
+            Ok(fetched)
+        }
+        Err(e) => match &*cached {
+            Some(cached) => {
+                warn!(error = %e, "Failed to refresh leaderboard; returning cached leaderboard");
+                Ok(cached.clone())
+            }
+            None => {
+                error!(error = %e, "Failed to get leaderboard");
+                state.leaderboards.remove(board, &slot);
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        },
+    }
+}
+
+/// The auth of the account leaderboards are fetched with.
+fn leaderboard_auth(state: &AppData) -> Result<Auth, StatusCode> {
+    let account = leaderboard_account(state)?;
+    match state.auth_data.get(account) {
+        Ok(Some(auth)) => Ok(auth),
+        Ok(None) => {
+            error!(sid = ?account, "Failed to find auth data");
+            Err(StatusCode::NOT_FOUND)
+        }
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// Leaderboards are the same for every account, so they are fetched with the
+/// default account, or any tracked account if there is none.
+fn leaderboard_account(state: &AppData) -> Result<AccountId, StatusCode> {
+    let default = state.config.borrow().default_account;
+    match state.auth_data.get_single(default) {
+        Ok(SingleAccount::Found(account)) => Ok(account),
+        Ok(SingleAccount::Ambiguous(accounts)) => {
+            accounts.first().copied().ok_or(StatusCode::NOT_FOUND)
+        }
+        Ok(SingleAccount::Missing) => {
+            error!(?default, "No account to fetch leaderboards with");
+            Err(StatusCode::NOT_FOUND)
+        }
+        Err(e) => {
+            error!(error = ?e, "Failed to get account");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Board names are put in the upstream path, so only allow plain names.
+fn is_valid_board(board: &str) -> bool {
+    !board.is_empty()
+        && board.len() <= 64
+        && board
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
@@ -0,0 +1,78 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use dt_api::models::AccountId;
+use tracing::{error, info, instrument};
+
+use crate::server::{
+    access_log, current_summary,
+    format::ResponseFormat,
+    raw::{upstream_response, Bypass, RawResource},
+    single_account, AppData,
+};
+
+/// Get the crafting materials of an account.
+///
+/// The materials are refreshed alongside the summary, so this refreshes both
+/// once the summary TTL of the account has run out.
+#[instrument(skip(state))]
+pub(crate) async fn materials(
+    Path(id): Path<AccountId>,
+    format: ResponseFormat,
+    Bypass(raw): Bypass,
+    State(state): State<AppData>,
+) -> Result<Response, StatusCode> {
+    if raw {
+        return upstream_response(id, RawResource::Wallets, state).await;
+    }
+    let _ = current_summary(id, state.clone()).await?;
+    let Some(account_data) = state.accounts.get(&id).await else {
+        error!("Failed to find account data");
+        return Err(StatusCode::NOT_FOUND);
+    };
+    if let Some(materials) = account_data.materials.peek() {
+        info!("Returning cached materials");
+        access_log::cache_hit();
+        return format.encode(&materials);
+    }
+    access_log::cache_miss();
+    let materials = account_data
+        .materials
+        .get(None, || async {
+            info!("Materials missing; fetching");
+            let auth_data = match state.auth_data.get(id) {
+                Ok(Some(auth_data)) => auth_data,
+                Ok(None) => {
+                    error!(sid = ?id, "Failed to find auth data");
+                    return Err(StatusCode::NOT_FOUND);
+                }
+                Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+            };
+            match state.api.get_wallets(&auth_data).await {
+                Ok(wallets) => {
+                    info!("Successfully fetched materials");
+                    Ok(wallets.materials())
+                }
+                Err(e) => {
+                    error!(error = %e, "Failed to get wallets");
+                    Err(StatusCode::INTERNAL_SERVER_ERROR)
+                }
+            }
+        })
+        .await?;
+    format.encode(&materials)
+}
+
+#[instrument(skip(state))]
+pub(crate) async fn materials_single(
+    format: ResponseFormat,
+    bypass: Bypass,
+    State(state): State<AppData>,
+) -> Result<Response, Response> {
+    let account = single_account(&state).map_err(IntoResponse::into_response)?;
+    materials(Path(account), format, bypass, State(state))
+        .await
+        .map_err(IntoResponse::into_response)
+}
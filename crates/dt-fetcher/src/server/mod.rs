@@ -1,45 +1,145 @@
-use std::{net::SocketAddr, time::Duration};
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
 
 use anyhow::Result;
 use axum::{
     body::Body,
     extract::{FromRef, Path, State},
-    http::{Request, Response, StatusCode},
-    routing::{get, put},
-    Json, Router,
+    http::{HeaderMap, Request, Response, StatusCode},
+    middleware,
+    response::IntoResponse,
+    routing::{get, post, put},
+    Extension, Json, Router,
 };
-use dt_api::models::{AccountId, MasterData, Summary};
+use dt_api::models::{AccountId, ItemCatalog, MasterData, Summary};
+use tokio::sync::watch;
 use tokio_util::sync::CancellationToken;
-use tower_http::{cors::CorsLayer, trace::TraceLayer};
+use tower_http::{
+    cors::{AllowOrigin, Any, CorsLayer},
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    services::{ServeDir, ServeFile},
+    trace::TraceLayer,
+};
 use tracing::{error, Span};
 use tracing::{info, instrument};
 
-use crate::auth::{get_auth, put_auth, AuthData, AuthStorage};
+use crate::{
+    auth::{get_auth, put_auth, AuthData, SingleAccount},
+    cached::{self, Cached},
+    config::Config,
+    settings::{get_settings, put_settings, Settings},
+    tabular::summary_rows,
+    upstream::Upstream,
+    watchlist::{
+        create_watch, delete_watch, get_watch, list_watches, matches, matches_all, put_watch,
+        Watchlists,
+    },
+};
+
+mod accounts;
+use accounts::{account_events, list_accounts};
+
+mod access_log;
+use access_log::AccessLog;
+
+mod admin;
+
+mod batch;
+use batch::batch;
+
+mod bundle;
+use bundle::{export, import};
+
+mod client_ip;
+pub(crate) use client_ip::{ClientIp, ForwardedHeader};
+
+#[cfg(feature = "dashboard")]
+mod dashboard;
+
+mod feed;
+use feed::feed;
+
+mod format;
+use format::{Projection, ResponseFormat};
+
+mod history_stats;
+use history_stats::history_stats;
+
+mod inventory;
+use inventory::{inventory, inventory_single};
+
+mod leaderboard;
+use leaderboard::{leaderboard, Leaderboards};
+
+mod materials;
+use materials::{materials, materials_single};
+
+#[cfg(feature = "query")]
+mod query;
+
+mod raw;
+use raw::{upstream_response, Bypass, RawResource};
+
+mod rotation;
+use rotation::rotation;
+
+mod search;
+use search::{compare, query_store, search};
 
 mod store;
-use store::{store, store_single};
+use store::{store, store_at, store_by_archetype, store_diff, store_single, store_summary};
+
+mod traits;
+use traits::traits;
+
+mod version;
+use version::version;
 
 #[derive(Debug, Clone)]
-struct AppData<T: AuthStorage> {
-    api: dt_api::Api,
+struct AppData {
+    api: Upstream,
     accounts: crate::account::Accounts,
-    auth_data: AuthData<T>,
+    auth_data: AuthData,
+    watchlists: Watchlists,
+    settings: Settings,
+    leaderboards: Leaderboards,
+    config: watch::Receiver<Config>,
+    started_at: chrono::DateTime<chrono::Utc>,
+    /// Prefix of every route, for generated links.
+    path_prefix: String,
 }
 
-impl<T: AuthStorage + Clone> FromRef<AppData<T>> for AuthData<T> {
-    fn from_ref(state: &AppData<T>) -> Self {
+impl FromRef<AppData> for Watchlists {
+    fn from_ref(state: &AppData) -> Self {
+        state.watchlists.clone()
+    }
+}
+
+impl FromRef<AppData> for Settings {
+    fn from_ref(state: &AppData) -> Self {
+        state.settings.clone()
+    }
+}
+
+impl FromRef<AppData> for AuthData {
+    fn from_ref(state: &AppData) -> Self {
         state.auth_data.clone()
     }
 }
 
-impl<T: AuthStorage> FromRef<AppData<T>> for dt_api::Api {
-    fn from_ref(state: &AppData<T>) -> Self {
+impl FromRef<AppData> for Upstream {
+    fn from_ref(state: &AppData) -> Self {
         state.api.clone()
     }
 }
 
-impl<T: AuthStorage> FromRef<AppData<T>> for crate::account::Accounts {
-    fn from_ref(state: &AppData<T>) -> Self {
+impl FromRef<AppData> for watch::Receiver<Config> {
+    fn from_ref(state: &AppData) -> Self {
+        state.config.clone()
+    }
+}
+
+impl FromRef<AppData> for crate::account::Accounts {
+    fn from_ref(state: &AppData) -> Self {
         state.accounts.clone()
     }
 }
@@ -50,143 +150,368 @@ pub(crate) struct Server {
 }
 
 impl Server {
-    pub fn new<T: AuthStorage + Clone>(
-        api: dt_api::Api,
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        api: Upstream,
         accounts: crate::account::Accounts,
-        auth_data: crate::AuthData<T>,
-        listen_addr: SocketAddr,
-    ) -> Self {
-        Self::new_impl(api, accounts, auth_data, listen_addr, false)
-    }
-
-    pub fn new_with_single<T: AuthStorage + Clone>(
-        api: dt_api::Api,
-        accounts: crate::account::Accounts,
-        auth_data: crate::AuthData<T>,
-        listen_addr: SocketAddr,
-    ) -> Self {
-        Self::new_impl(api, accounts, auth_data, listen_addr, true)
-    }
-
-    fn new_impl<T: AuthStorage + Clone>(
-        api: dt_api::Api,
-        accounts: crate::account::Accounts,
-        auth_data: AuthData<T>,
-        listen_addr: SocketAddr,
+        auth_data: AuthData,
+        watchlists: Watchlists,
+        settings: Settings,
+        config: watch::Receiver<Config>,
+        static_dir: Option<PathBuf>,
         enable_single: bool,
+        path_prefix: String,
     ) -> Self {
+        if enable_single {
+            info!("Creating server with single endpoint variants enabled");
+        } else {
+            info!("Creating server with single endpoint variants disabled");
+        }
+        let listen_addr = config.borrow().listen_addr;
+        let cors = cors_layer(config.clone());
+        let client_ip_config = config.clone();
+        let access_log = AccessLog::new(config.clone());
+        let slo = api.slo().clone();
         let app_data = AppData {
             api,
             accounts,
             auth_data,
+            watchlists,
+            settings,
+            leaderboards: Leaderboards::default(),
+            config,
+            started_at: chrono::Utc::now(),
+            path_prefix: path_prefix.clone(),
         };
 
         let mut router = Router::new()
+            .route("/accounts", get(list_accounts))
+            .route("/accounts/:id/events", get(account_events))
+            .route(
+                "/accounts/:id/settings",
+                get(get_settings).put(put_settings),
+            )
+            .route("/metrics", get(crate::telemetry::metrics))
+            .route("/version", get(version))
+            .route("/readyz", get(readyz))
+            .route("/export/:id", get(export))
+            .route("/import", post(import))
+            .route("/search", get(search))
+            .route("/batch", post(batch))
+            .route("/compare", get(compare))
+            .route("/traits", get(traits))
+            .route("/feed/:file", get(feed))
+            .route("/history/stats", get(history_stats))
             .route("/store/:id", get(store))
+            .route("/store/:id/query", get(query_store))
+            .route("/store/:id/summary", get(store_summary))
+            .route("/store/:id/diff", get(store_diff))
+            .route("/store/:id/at", get(store_at))
+            .route(
+                "/store/:id/by-archetype/:archetype",
+                get(store_by_archetype),
+            )
             .route("/summary/:id", get(summary))
+            .route("/inventory/:id", get(inventory))
+            .route("/materials/:id", get(materials))
+            .route("/leaderboard/:board", get(leaderboard))
+            .route("/rotation/:id", get(rotation))
             .route("/master_data/:id", get(master_data))
+            .route("/watchlist/matches", get(matches_all))
+            .route("/watchlist/:id", get(list_watches).post(create_watch))
+            .route("/watchlist/:id/matches", get(matches))
+            .route(
+                "/watchlist/:id/:watch_id",
+                get(get_watch).put(put_watch).delete(delete_watch),
+            )
             .route("/auth/:id", put(put_auth))
-            .route("/auth/:id", get(get_auth));
+            .route("/auth/:id", get(get_auth))
+            .route("/auth/:id/refresh", post(admin::refresh_auth))
+            .route("/admin/shutdown", post(admin::shutdown));
+
+        #[cfg(feature = "chaos")]
+        {
+            router = router.route("/admin/chaos", get(admin::get_chaos).put(admin::put_chaos));
+        }
+
+        #[cfg(feature = "query")]
+        {
+            router = router.route("/query", post(query::query));
+        }
+
+        #[cfg(feature = "dashboard")]
+        if static_dir.is_none() {
+            router = router
+                .route("/", get(dashboard::index))
+                .route("/dashboard/:file", get(dashboard::dashboard));
+        }
 
         if enable_single {
             router = router
                 .route("/store", get(store_single))
                 .route("/summary", get(summary_single))
+                .route("/inventory", get(inventory_single))
+                .route("/materials", get(materials_single))
                 .route("/master_data", get(master_data_single));
         }
 
+        if let Some(static_dir) = static_dir {
+            // Unknown paths get the index so client-side routes still load.
+            let index = ServeFile::new(static_dir.join("index.html"));
+            router = router.fallback_service(ServeDir::new(static_dir).fallback(index));
+        }
+
+        if !path_prefix.is_empty() {
+            info!(path_prefix, "Serving routes under a path prefix");
+            router = Router::new().nest(&path_prefix, router);
+        }
+
         let app = router.with_state(app_data)
         .layer(
             TraceLayer::new_for_http()
-                .make_span_with(|_request: &Request<Body>| tracing::info_span!("http-request"))
+                .make_span_with(|request: &Request<Body>| {
+                    let client_ip = request.extensions().get::<ClientIp>().map(tracing::field::display);
+                    let request_id = request
+                        .headers()
+                        .get("x-request-id")
+                        .and_then(|value| value.to_str().ok());
+                    tracing::info_span!("http-request", client_ip, request_id)
+                })
                 .on_request(|request: &Request<Body>, _span: &Span| {
                     tracing::info!(method = %request.method(), path = %request.uri().path(), "got request")
                 })
                 .on_response(|_response: &Response<Body>, latency: Duration, _span: &Span| {
                 tracing::info!("response generated in {:?}", latency)
             })
-        ).layer(CorsLayer::permissive());
+        )
+        .layer(middleware::from_fn_with_state(slo, crate::slo::record_responses));
+        #[cfg(feature = "sentry")]
+        let app = app.layer(middleware::from_fn(
+            crate::error_report::report_server_errors,
+        ));
+        let app = app
+            .layer(middleware::from_fn_with_state(
+                access_log,
+                access_log::access_log,
+            ))
+            .layer(PropagateRequestIdLayer::x_request_id())
+            .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+            .layer(middleware::from_fn_with_state(
+                client_ip_config,
+                client_ip::client_ip,
+            ))
+            .layer(cors);
 
         Self { app, listen_addr }
     }
 
+    /// The app, with `POST /admin/shutdown` cancelling `token`.
+    pub fn into_router(self, token: CancellationToken) -> Router {
+        self.app.layer(Extension(token))
+    }
+
     #[instrument(skip_all)]
     pub async fn start(self, token: CancellationToken) -> Result<()> {
         let listener = tokio::net::TcpListener::bind(self.listen_addr).await?;
 
-        axum::serve(listener, self.app)
-            .with_graceful_shutdown(token.cancelled_owned())
-            .await?;
+        let app = self.into_router(token.clone());
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(token.cancelled_owned())
+        .await?;
 
         Ok(())
     }
 }
 
-const SUMMARY_REFRESH_INTERVAL_MINS: i64 = 60;
+/// `prefix` with a leading and without a trailing slash, or empty to serve
+/// the routes at the root.
+pub(crate) fn normalize_path_prefix(prefix: &str) -> String {
+    let prefix = prefix.trim_matches('/');
+    if prefix.is_empty() {
+        String::new()
+    } else {
+        format!("/{prefix}")
+    }
+}
+
+/// Allow the origins from the current config, or any origin if unset.
+fn cors_layer(config: watch::Receiver<Config>) -> CorsLayer {
+    CorsLayer::new()
+        .allow_methods(Any)
+        .allow_headers(Any)
+        .expose_headers(Any)
+        .allow_origin(AllowOrigin::predicate(move |origin, _| {
+            match &config.borrow().cors_allowed_origins {
+                Some(allowed) => allowed
+                    .iter()
+                    .any(|allowed| allowed.as_bytes() == origin.as_bytes()),
+                None => true,
+            }
+        }))
+}
 
 #[instrument(skip(state))]
-async fn summary<T: AuthStorage>(
+async fn summary(
     Path(id): Path<AccountId>,
-    State(state): State<AppData<T>>,
-) -> Result<Json<Summary>, StatusCode> {
-    if let Some(account_data) = state.accounts.get(&id).await {
-        if account_data.last_updated
-            < chrono::Utc::now() - chrono::Duration::minutes(SUMMARY_REFRESH_INTERVAL_MINS)
-        {
-            info!("Summary out of date; refreshing");
-            refresh_summary(&id, state).await
-        } else {
-            info!("Returning cached summary");
-            Ok(Json(account_data.summary.read().await.clone()))
-        }
-    } else {
-        info!("Account data not found, attempting to refresh");
-        refresh_summary(&id, state).await
+    format: ResponseFormat,
+    Bypass(raw): Bypass,
+    Projection(fields): Projection,
+    headers: HeaderMap,
+    State(state): State<AppData>,
+) -> Result<Response<Body>, StatusCode> {
+    if raw {
+        return upstream_response(id, RawResource::Summary, state).await;
+    }
+    let summary = current_summary(id, state).await?;
+    if let Some(fields) = fields {
+        let value = serde_json::to_value(&*summary).map_err(|e| {
+            error!(error = %e, "Failed to serialize summary");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        let value = fields.project(value);
+        return match format {
+            ResponseFormat::Json => Ok(cached::respond(&value, &headers)),
+            _ => format.encode(&value),
+        };
+    }
+    match format {
+        ResponseFormat::Json => Ok(summary.respond(&headers)),
+        _ => format.render(&summary, summary_rows(&summary)),
     }
 }
 
+/// Ready, unless an error budget is exceeded.
 #[instrument(skip(state))]
-async fn summary_single<T: AuthStorage>(
-    State(state): State<AppData<T>>,
-) -> Result<Json<Summary>, StatusCode> {
-    let account = state
-        .auth_data
-        .get_single()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    if let Some(account) = account {
-        summary(Path(account), State(state)).await
-    } else {
-        error!("Failed to find account data");
-        Err(StatusCode::NOT_FOUND)
+async fn readyz(State(state): State<AppData>) -> Response<Body> {
+    crate::slo::readiness(state.api.slo(), state.api.connection_state())
+}
+
+/// Get the cached summary, refreshing it if it is older than the summary TTL
+/// of the account.
+#[instrument(skip(state))]
+async fn current_summary(id: AccountId, state: AppData) -> Result<Cached<Summary>, StatusCode> {
+    let default_ttl = state.config.borrow().summary_refresh_interval_mins;
+    let ttl = state.settings.summary_ttl(id, default_ttl);
+    let cached = match state.accounts.get(&id).await {
+        Some(account_data) => account_data.summary.fresh(ttl.to_std().unwrap_or_default()),
+        None => None,
+    };
+    if let Some(summary) = cached {
+        info!("Returning cached summary");
+        access_log::cache_hit();
+        return Ok(summary);
+    }
+    info!("Summary missing or out of date; refreshing");
+    access_log::cache_miss();
+    refresh_summary(&id, state).await
+}
+
+/// Error returned when the single-account endpoints can't pick an account.
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SingleAccountError {
+    #[serde(skip)]
+    status: StatusCode,
+    error: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    accounts: Vec<AccountId>,
+}
+
+impl IntoResponse for SingleAccountError {
+    fn into_response(self) -> Response<Body> {
+        (self.status, Json(self)).into_response()
     }
 }
 
+/// The account served by the single-account endpoints.
+fn single_account(state: &AppData) -> Result<AccountId, SingleAccountError> {
+    let default = state.config.borrow().default_account;
+    let (status, error, accounts) = match state.auth_data.get_single(default) {
+        Ok(SingleAccount::Found(account)) => {
+            access_log::account(account);
+            return Ok(account);
+        }
+        Ok(SingleAccount::Missing) => match default {
+            Some(default) => (
+                StatusCode::NOT_FOUND,
+                format!("The default account {default} is not tracked"),
+                Vec::new(),
+            ),
+            None => (
+                StatusCode::NOT_FOUND,
+                "No accounts are tracked".to_string(),
+                Vec::new(),
+            ),
+        },
+        Ok(SingleAccount::Ambiguous(accounts)) => (
+            StatusCode::CONFLICT,
+            format!(
+                "{} accounts are tracked; set a default account or use the per-account endpoints",
+                accounts.len()
+            ),
+            accounts,
+        ),
+        Err(e) => {
+            error!(error = ?e, "Failed to get single account");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to read the tracked accounts".to_string(),
+                Vec::new(),
+            )
+        }
+    };
+    error!(?default, "{}", error);
+    Err(SingleAccountError {
+        status,
+        error,
+        accounts,
+    })
+}
+
 #[instrument(skip(state))]
-async fn refresh_summary<T: AuthStorage>(
+async fn summary_single(
+    format: ResponseFormat,
+    bypass: Bypass,
+    projection: Projection,
+    headers: HeaderMap,
+    State(state): State<AppData>,
+) -> Result<Response<Body>, Response<Body>> {
+    let account = single_account(&state).map_err(IntoResponse::into_response)?;
+    summary(
+        Path(account),
+        format,
+        bypass,
+        projection,
+        headers,
+        State(state),
+    )
+    .await
+    .map_err(IntoResponse::into_response)
+}
+
+#[instrument(skip(state))]
+async fn refresh_summary(
     account_id: &AccountId,
-    state: AppData<T>,
-) -> Result<Json<Summary>, StatusCode> {
-    let api = &state.api;
-    let account_data = if let Some(account_data) = state.accounts.get(account_id).await {
-        account_data
-    } else {
+    state: AppData,
+) -> Result<Cached<Summary>, StatusCode> {
+    if state.accounts.get(account_id).await.is_none() {
         error!(sid = ?account_id, "Failed to find account data");
         return Err(StatusCode::NOT_FOUND);
-    };
+    }
     if let Some(auth_data) = state
         .auth_data
         .get(*account_id)
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
     {
-        let new_summary = api.get_summary(&auth_data).await;
-        if let Ok(new_summary) = new_summary {
-            let mut summary = account_data.summary.write().await;
-            *summary = new_summary.clone();
-            state.accounts.update_timestamp(account_id).await;
-            Ok(Json(new_summary))
-        } else {
-            error!(error = %new_summary.unwrap_err(), "Failed to get summary");
-            Err(StatusCode::NOT_FOUND)
+        match state.accounts.refresh_summary(&state.api, &auth_data).await {
+            Ok(summary) => Ok(summary),
+            Err(e) => {
+                error!(error = ?e, "Failed to refresh summary");
+                Err(StatusCode::NOT_FOUND)
+            }
         }
     } else {
         error!(sid = ?account_id, "Failed to find auth data");
@@ -195,31 +520,105 @@ async fn refresh_summary<T: AuthStorage>(
 }
 
 #[instrument(skip(state))]
-async fn master_data<T: AuthStorage>(
+async fn master_data(
     Path(id): Path<AccountId>,
-    State(state): State<AppData<T>>,
-) -> Result<Json<MasterData>, StatusCode> {
-    if let Some(account_data) = state.accounts.get(&id).await {
-        info!("Returning cached master data");
-        Ok(Json(account_data.master_data.read().await.clone()))
-    } else {
-        error!("Failed to find account data");
-        Err(StatusCode::NOT_FOUND)
+    format: ResponseFormat,
+    Bypass(raw): Bypass,
+    State(state): State<AppData>,
+) -> Result<Response<Body>, StatusCode> {
+    if raw {
+        return upstream_response(id, RawResource::MasterData, state).await;
     }
+    let Json(master_data) = current_master_data(id, state).await?;
+    format.encode(&master_data)
 }
 
+/// Get the cached master data, fetching it if missing.
 #[instrument(skip(state))]
-async fn master_data_single<T: AuthStorage>(
-    State(state): State<AppData<T>>,
+async fn current_master_data(
+    id: AccountId,
+    state: AppData,
 ) -> Result<Json<MasterData>, StatusCode> {
-    let account = state
-        .auth_data
-        .get_single()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    if let Some(account) = account {
-        master_data(Path(account), State(state)).await
-    } else {
+    let Some(account_data) = state.accounts.get(&id).await else {
         error!("Failed to find account data");
-        Err(StatusCode::NOT_FOUND)
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let master_data = account_data
+        .master_data
+        .get(None, || async {
+            info!("Master data missing; refreshing");
+            let auth_data = state
+                .auth_data
+                .get(id)
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+                .ok_or_else(|| {
+                    error!(sid = ?id, "Failed to find auth data");
+                    StatusCode::NOT_FOUND
+                })?;
+            state.api.get_master_data(&auth_data).await.map_err(|e| {
+                error!(error = %e, "Failed to get master data");
+                StatusCode::NOT_FOUND
+            })
+        })
+        .await?;
+    Ok(Json(master_data))
+}
+
+/// Get the cached item catalog, fetching it and the master data it is linked
+/// from if missing.
+#[instrument(skip(state))]
+async fn current_item_catalog(id: AccountId, state: AppData) -> Result<ItemCatalog, StatusCode> {
+    let Some(account_data) = state.accounts.get(&id).await else {
+        error!("Failed to find account data");
+        return Err(StatusCode::NOT_FOUND);
+    };
+    account_data
+        .item_catalog
+        .get(None, || async {
+            info!("Item catalog missing; fetching");
+            let Json(master_data) = current_master_data(id, state.clone()).await?;
+            let auth_data = state
+                .auth_data
+                .get(id)
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+                .ok_or_else(|| {
+                    error!(sid = ?id, "Failed to find auth data");
+                    StatusCode::NOT_FOUND
+                })?;
+            state
+                .api
+                .get_item_catalog(&auth_data, &master_data)
+                .await
+                .map_err(|e| {
+                    error!(error = %e, "Failed to get item catalog");
+                    StatusCode::BAD_GATEWAY
+                })
+        })
+        .await
+}
+
+#[instrument(skip(state))]
+async fn master_data_single(
+    format: ResponseFormat,
+    bypass: Bypass,
+    State(state): State<AppData>,
+) -> Result<Response<Body>, Response<Body>> {
+    let account = single_account(&state).map_err(IntoResponse::into_response)?;
+    master_data(Path(account), format, bypass, State(state))
+        .await
+        .map_err(IntoResponse::into_response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_path_prefix() {
+        assert_eq!(normalize_path_prefix(""), "");
+        assert_eq!(normalize_path_prefix("/"), "");
+        assert_eq!(normalize_path_prefix("darktide"), "/darktide");
+        assert_eq!(normalize_path_prefix("/darktide/"), "/darktide");
+        assert_eq!(normalize_path_prefix("/proxy/darktide"), "/proxy/darktide");
     }
 }
@@ -0,0 +1,184 @@
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use dt_api::models::{AccountId, CharacterId, CurrencyType};
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument, warn};
+
+use crate::{
+    query::{evaluate, QueryError},
+    server::{admin, AppData, ClientIp},
+};
+
+/// Longest an expression may run before the request fails.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A cached resource to evaluate an expression against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum QueryResource {
+    Summary,
+    /// Needs `characterId` and `currencyType`.
+    Store,
+    /// Needs `characterId`.
+    Inventory,
+    Materials,
+    MasterData,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct QueryRequest {
+    account_id: AccountId,
+    resource: QueryResource,
+    character_id: Option<CharacterId>,
+    currency_type: Option<CurrencyType>,
+    /// jq expression, with the resource as `.`.
+    expression: String,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct QueryFailure {
+    #[serde(skip)]
+    status: StatusCode,
+    error: String,
+}
+
+impl QueryFailure {
+    fn new(status: StatusCode, error: impl ToString) -> Self {
+        Self {
+            status,
+            error: error.to_string(),
+        }
+    }
+}
+
+impl IntoResponse for QueryFailure {
+    fn into_response(self) -> Response<Body> {
+        (self.status, Json(self)).into_response()
+    }
+}
+
+impl From<QueryError> for QueryFailure {
+    fn from(error: QueryError) -> Self {
+        let status = match error {
+            QueryError::Invalid(_) => StatusCode::BAD_REQUEST,
+            QueryError::Failed(_) | QueryError::TooManyResults | QueryError::TooManySteps => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+        };
+        Self::new(status, error)
+    }
+}
+
+/// Evaluate a jq expression against a cached resource, returning its results.
+/// Requires `Authorization: Bearer <adminToken>`.
+#[instrument(skip(headers, state))]
+pub(crate) async fn query(
+    headers: HeaderMap,
+    client_ip: Option<Extension<ClientIp>>,
+    State(state): State<AppData>,
+    Json(request): Json<QueryRequest>,
+) -> Result<Json<Vec<serde_json::Value>>, QueryFailure> {
+    let client_ip = client_ip.map(|Extension(ip)| ip.0);
+    admin::authorize(&headers, client_ip, &state).map_err(|status| {
+        QueryFailure::new(status, status.canonical_reason().unwrap_or_default())
+    })?;
+    let input = cached_resource(&request, &state).await?;
+    let expression = request.expression;
+    let evaluation = tokio::task::spawn_blocking(move || evaluate(&expression, input));
+    match tokio::time::timeout(QUERY_TIMEOUT, evaluation).await {
+        Ok(Ok(results)) => {
+            let results = results?;
+            info!(results = results.len(), "Evaluated query");
+            Ok(Json(results))
+        }
+        Ok(Err(e)) => {
+            warn!(error = %e, "Query evaluation panicked");
+            Err(QueryFailure::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Query evaluation failed",
+            ))
+        }
+        Err(_) => {
+            warn!("Query timed out");
+            Err(QueryFailure::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Query took longer than {QUERY_TIMEOUT:?}"),
+            ))
+        }
+    }
+}
+
+/// Get the resource of a query from the cache, without fetching it.
+async fn cached_resource(
+    request: &QueryRequest,
+    state: &AppData,
+) -> Result<serde_json::Value, QueryFailure> {
+    let Some(account_data) = state.accounts.get(&request.account_id).await else {
+        return Err(QueryFailure::new(
+            StatusCode::NOT_FOUND,
+            format!("Account {} is not tracked", request.account_id.0),
+        ));
+    };
+    let character_id = || {
+        request.character_id.ok_or_else(|| {
+            QueryFailure::new(
+                StatusCode::BAD_REQUEST,
+                "characterId is required for this resource",
+            )
+        })
+    };
+    let value = match request.resource {
+        QueryResource::Summary => account_data
+            .summary
+            .peek()
+            .map(|summary| serde_json::to_value(&*summary)),
+        QueryResource::Store => {
+            let currency_type = request.currency_type.ok_or_else(|| {
+                QueryFailure::new(
+                    StatusCode::BAD_REQUEST,
+                    "currencyType is required for stores",
+                )
+            })?;
+            let character_id = character_id()?;
+            account_data
+                .stores(currency_type)
+                .read()
+                .await
+                .get(&character_id)
+                .map(|store| serde_json::to_value(&*store))
+        }
+        QueryResource::Inventory => {
+            let character_id = character_id()?;
+            account_data
+                .inventories
+                .read()
+                .await
+                .get(&character_id)
+                .map(|cached| serde_json::to_value(&cached.inventory))
+        }
+        QueryResource::Materials => account_data.materials.peek().map(serde_json::to_value),
+        QueryResource::MasterData => account_data.master_data.peek().map(serde_json::to_value),
+    };
+    match value {
+        Some(Ok(value)) => Ok(value),
+        Some(Err(e)) => Err(QueryFailure::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to serialize resource: {e}"),
+        )),
+        None => Err(QueryFailure::new(
+            StatusCode::NOT_FOUND,
+            format!(
+                "{:?} isn't cached; get it from its endpoint first",
+                request.resource
+            ),
+        )),
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Query},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use dt_api::{
+    models::{AccountId, CharacterId, CurrencyType},
+    Endpoint, RawResponse,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, instrument, warn};
+
+use crate::server::{admin, AppData, ClientIp};
+
+#[derive(Debug, Deserialize)]
+struct RawQuery {
+    #[serde(default)]
+    raw: bool,
+}
+
+/// Whether an admin asked with `?raw=true` to bypass the cache and get the
+/// upstream response as received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Bypass(pub bool);
+
+#[async_trait]
+impl FromRequestParts<AppData> for Bypass {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppData,
+    ) -> Result<Self, Self::Rejection> {
+        let Query(RawQuery { raw }) =
+            Query::try_from_uri(&parts.uri).map_err(|_| StatusCode::BAD_REQUEST)?;
+        if raw {
+            let client_ip = parts.extensions.get::<ClientIp>().map(|ip| ip.0);
+            admin::authorize(&parts.headers, client_ip, state)?;
+        }
+        Ok(Bypass(raw))
+    }
+}
+
+/// Upstream resource of a data route.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum RawResource {
+    Summary,
+    Store {
+        currency_type: CurrencyType,
+        character_id: CharacterId,
+    },
+    Inventory {
+        character_id: CharacterId,
+    },
+    Wallets,
+    MasterData,
+}
+
+/// An upstream response, as returned to admins.
+#[derive(Debug, Serialize)]
+struct UpstreamResponse {
+    status: u16,
+    /// Header values, joined with `, ` if repeated.
+    headers: BTreeMap<String, String>,
+    body: serde_json::Value,
+}
+
+impl From<RawResponse> for UpstreamResponse {
+    fn from(response: RawResponse) -> Self {
+        let mut headers = BTreeMap::<String, String>::new();
+        for (name, value) in &response.headers {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            headers
+                .entry(name.to_string())
+                .and_modify(|values| {
+                    values.push_str(", ");
+                    values.push_str(&value);
+                })
+                .or_insert_with(|| value.into_owned());
+        }
+        Self {
+            status: response.status.as_u16(),
+            headers,
+            body: response.body,
+        }
+    }
+}
+
+/// Fetch `resource` of the account straight from upstream, without reading
+/// or updating the cache.
+#[instrument(skip(state))]
+pub(crate) async fn upstream_response(
+    id: AccountId,
+    resource: RawResource,
+    state: AppData,
+) -> Result<Response, StatusCode> {
+    let auth_data = match state.auth_data.get(id) {
+        Ok(Some(auth_data)) => auth_data,
+        Ok(None) => {
+            error!(sid = ?id, "Failed to find auth data");
+            return Err(StatusCode::NOT_FOUND);
+        }
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    let needs_character = matches!(
+        resource,
+        RawResource::Store { .. } | RawResource::Inventory { .. }
+    );
+    let characters = if needs_character {
+        state
+            .api
+            .get_characters(&auth_data, None)
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Failed to get characters");
+                StatusCode::BAD_GATEWAY
+            })?
+            .characters
+    } else {
+        Vec::new()
+    };
+    let find_character = |character_id: CharacterId| {
+        characters
+            .iter()
+            .find(|c| c.id == character_id)
+            .ok_or_else(|| {
+                error!(character.id = %character_id, "Failed to find character");
+                StatusCode::NOT_FOUND
+            })
+    };
+    let endpoint = match resource {
+        RawResource::Summary => Endpoint::Summary,
+        RawResource::Store {
+            currency_type,
+            character_id,
+        } => Endpoint::Store {
+            currency_type,
+            character: find_character(character_id)?,
+        },
+        RawResource::Inventory { character_id } => Endpoint::Inventory {
+            character: find_character(character_id)?,
+        },
+        RawResource::Wallets => Endpoint::Wallets,
+        RawResource::MasterData => Endpoint::MasterData,
+    };
+    warn!(endpoint = %endpoint, "Bypassing the cache by admin request");
+    let response = state
+        .api
+        .get_response(&auth_data, endpoint)
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Failed to get upstream response");
+            StatusCode::BAD_GATEWAY
+        })?;
+    Ok(Json(UpstreamResponse::from(response)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE, SET_COOKIE};
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn joins_repeated_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.append(SET_COOKIE, HeaderValue::from_static("a=1"));
+        headers.append(SET_COOKIE, HeaderValue::from_static("b=2"));
+        let response = UpstreamResponse::from(RawResponse {
+            status: reqwest::StatusCode::NOT_FOUND,
+            headers,
+            body: json!({ "error": "missing" }),
+        });
+
+        assert_eq!(
+            serde_json::to_value(response).unwrap(),
+            json!({
+                "status": 404,
+                "headers": {
+                    "content-type": "application/json",
+                    "set-cookie": "a=1, b=2"
+                },
+                "body": { "error": "missing" }
+            })
+        );
+    }
+}
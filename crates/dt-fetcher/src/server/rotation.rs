@@ -0,0 +1,134 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use dt_api::models::{AccountId, CharacterId, CurrencyType};
+use serde::Serialize;
+use tracing::{error, instrument};
+
+use crate::{clock::Clock, prefetch::next_prefetch, server::AppData};
+
+/// Time left in the cached rotations of an account.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Rotations {
+    /// When the countdowns were computed, to correct for clock skew.
+    now: DateTime<Utc>,
+    rotations: Vec<Countdown>,
+}
+
+/// Time left in the cached store of a character.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Countdown {
+    character_id: CharacterId,
+    currency_type: CurrencyType,
+    rotation_end: DateTime<Utc>,
+    /// Zero once the rotation has ended.
+    seconds_remaining: i64,
+    /// When the next rotation is prefetched, or `None` if prefetching is off.
+    next_prefetch: Option<DateTime<Utc>>,
+}
+
+impl Countdown {
+    fn new(
+        character_id: CharacterId,
+        currency_type: CurrencyType,
+        rotation_end: DateTime<Utc>,
+        now: DateTime<Utc>,
+        next_prefetch: Option<DateTime<Utc>>,
+    ) -> Self {
+        Self {
+            character_id,
+            currency_type,
+            rotation_end,
+            seconds_remaining: (rotation_end - now).num_seconds().max(0),
+            next_prefetch,
+        }
+    }
+}
+
+/// List the time left until each cached store of the account rotates, by
+/// character in summary order and currency.
+#[instrument(skip(state))]
+pub(crate) async fn rotation(
+    Path(id): Path<AccountId>,
+    State(state): State<AppData>,
+) -> Result<Json<Rotations>, StatusCode> {
+    let Some(account_data) = state.accounts.get(&id).await else {
+        error!(sid = ?id, "Failed to find account data");
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let now = state.accounts.clock().now();
+    let prefetch = state.config.borrow().prefetch;
+    let backoff = state.api.rate_limited_for();
+    let characters: Vec<CharacterId> = account_data
+        .summary
+        .peek()
+        .iter()
+        .flat_map(|summary| &summary.characters)
+        .map(|character| character.id)
+        .collect();
+    let mut rotations = Vec::new();
+    for character_id in characters {
+        for currency_type in [CurrencyType::Marks, CurrencyType::Credits] {
+            let stores = account_data.stores(currency_type).read().await;
+            let Some(store) = stores.view_of(&character_id) else {
+                continue;
+            };
+            let rotates_at = store.store().rotates_at();
+            rotations.push(Countdown::new(
+                character_id,
+                currency_type,
+                rotates_at,
+                now,
+                prefetch.then(|| next_prefetch(rotates_at, now, backoff)),
+            ));
+        }
+    }
+    Ok(Json(Rotations { now, rotations }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::TimeZone;
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn counts_down_to_the_rotation_and_prefetch() {
+        let character_id = CharacterId(Uuid::nil());
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let end = now + chrono::Duration::minutes(90);
+
+        let countdown = Countdown::new(
+            character_id,
+            CurrencyType::Marks,
+            end,
+            now,
+            Some(next_prefetch(end, now, None)),
+        );
+        assert_eq!(countdown.seconds_remaining, 5400);
+        assert_eq!(
+            countdown.next_prefetch,
+            Some(end + chrono::Duration::seconds(10))
+        );
+
+        let ended = Countdown::new(
+            character_id,
+            CurrencyType::Credits,
+            now - chrono::Duration::minutes(1),
+            now,
+            None,
+        );
+        assert_eq!(ended.seconds_remaining, 0);
+
+        let rate_limited = next_prefetch(end, now, Some(Duration::from_secs(3 * 3600)));
+        assert_eq!(rate_limited, now + chrono::Duration::hours(3));
+    }
+}
@@ -0,0 +1,149 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use dt_api::models::{AccountId, Offer, OfferId};
+use serde::Deserialize;
+use tracing::{error, instrument};
+
+use crate::{
+    account::{AccountData, OfferMatch},
+    compare::Comparison,
+    server::AppData,
+};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SearchQuery {
+    /// Case-insensitive text to find in the offer name or description.
+    q: Option<String>,
+    /// Exact item rarity.
+    rarity: Option<i32>,
+    /// Case-insensitive offer category, e.g. `weapon` or `gadget`.
+    category: Option<String>,
+}
+
+impl SearchQuery {
+    fn matches(&self, q: Option<&str>, offer: &Offer) -> bool {
+        let text = |text: &str| q.map_or(true, |q| text.to_lowercase().contains(q));
+        let sku = &offer.sku;
+        (text(&sku.name) || text(&sku.description))
+            && self.rarity.map_or(true, |rarity| {
+                offer.description.overrides.item().map(|item| item.rarity) == Some(rarity)
+            })
+            && self
+                .category
+                .as_ref()
+                .map_or(true, |category| sku.category.eq_ignore_ascii_case(category))
+    }
+}
+
+/// Find matching offers in every cached store of every account.
+#[instrument(skip(state))]
+pub(crate) async fn search(
+    Query(query): Query<SearchQuery>,
+    State(state): State<AppData>,
+) -> Json<Vec<OfferMatch>> {
+    let q = query.q.as_ref().map(|q| q.to_lowercase());
+    let mut results = Vec::new();
+    for (account_id, account_data) in state.accounts.list().await {
+        results.extend(
+            account_data
+                .find_offers(account_id, |offer| query.matches(q.as_deref(), offer))
+                .await,
+        );
+    }
+    Json(results)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TraitQuery {
+    /// Comma-separated trait ids that must all be present.
+    traits: Option<String>,
+    /// Minimum rarity of the listed traits, or of every trait if none are listed.
+    min_trait_rarity: Option<i32>,
+}
+
+impl TraitQuery {
+    fn matches(&self, traits: &[&str], offer: &Offer) -> bool {
+        let Some(item) = offer.description.overrides.item() else {
+            return false;
+        };
+        let rarity_ok = |rarity: i32| self.min_trait_rarity.map_or(true, |min| rarity >= min);
+        if traits.is_empty() {
+            item.traits.iter().all(|t| rarity_ok(t.rarity))
+        } else {
+            traits.iter().all(|id| {
+                item.traits
+                    .iter()
+                    .any(|t| t.id == *id && rarity_ok(t.rarity))
+            })
+        }
+    }
+}
+
+/// Find weapons and gadgets with the requested traits in the cached stores of an account.
+#[instrument(skip(state))]
+pub(crate) async fn query_store(
+    Path(id): Path<AccountId>,
+    Query(query): Query<TraitQuery>,
+    State(state): State<AppData>,
+) -> Result<Json<Vec<OfferMatch>>, StatusCode> {
+    let Some(account_data) = state.accounts.get(&id).await else {
+        error!(sid = ?id, "Failed to find account data");
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let traits = query
+        .traits
+        .as_deref()
+        .map(|traits| {
+            traits
+                .split(',')
+                .map(str::trim)
+                .filter(|t| !t.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    Ok(Json(
+        account_data
+            .find_offers(id, |offer| query.matches(&traits, offer))
+            .await,
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CompareQuery {
+    offer_a: OfferId,
+    offer_b: OfferId,
+}
+
+/// Compare two offers of any cached stores.
+#[instrument(skip(state))]
+pub(crate) async fn compare(
+    Query(query): Query<CompareQuery>,
+    State(state): State<AppData>,
+) -> Result<Json<Comparison>, StatusCode> {
+    let accounts = state.accounts.list().await;
+    let a = find_offer(&accounts, query.offer_a).await;
+    let b = find_offer(&accounts, query.offer_b).await;
+    match (a, b) {
+        (Some(a), Some(b)) => Ok(Json(Comparison::new(a, b))),
+        _ => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+async fn find_offer(accounts: &[(AccountId, AccountData)], id: OfferId) -> Option<OfferMatch> {
+    for (account_id, account_data) in accounts {
+        let found = account_data
+            .find_offers(*account_id, |offer| offer.offer_id == id)
+            .await;
+        if let Some(offer) = found.into_iter().next() {
+            return Some(offer);
+        }
+    }
+    error!(offer_id = ?id, "Failed to find offer");
+    None
+}
@@ -1,18 +1,31 @@
-use std::time::SystemTime;
-
 use anyhow::Result;
 use axum::{
     extract::{Path, Query, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
 use chrono::{DateTime, Utc};
-use dt_api::models::{AccountId, CharacterId, Store};
+use dt_api::models::{
+    AccountId, CharacterId, CurrencyType, Inventory, ItemCatalog, Offer, Overrides, Store, Summary,
+};
 use tracing::{debug, error, info, instrument};
 
 use crate::{
-    auth::AuthStorage,
-    server::{refresh_summary, AppData},
+    cached::{self, Cached},
+    clock::Clock,
+    diff::StoreDiff,
+    history::RotationSnapshot,
+    server::{
+        access_log, current_item_catalog, current_summary,
+        format::{Projection, ResponseFormat},
+        inventory::current_inventory,
+        raw::{upstream_response, Bypass, RawResource},
+        refresh_summary, single_account, AppData,
+    },
+    stores::StoreRequest,
+    tabular::store_rows,
+    timeline::{Resource, TimelineEvent},
 };
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -20,15 +33,169 @@ use crate::{
 pub(crate) struct StoreQuery {
     character_id: CharacterId,
     currency_type: dt_api::models::CurrencyType,
+    annotate: Option<Annotation>,
+    expand: Option<Expansion>,
+}
+
+/// Extra information to add to each offer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum Annotation {
+    /// Whether, and how many times, the character already owns the item.
+    Owned,
+    /// The base stats of weapons as the game shows them.
+    Stats,
+}
+
+/// Get the inventory of the character if offers are to be annotated with
+/// ownership.
+async fn annotation_inventory(
+    id: AccountId,
+    character_id: CharacterId,
+    annotate: Option<Annotation>,
+    state: AppData,
+) -> Result<Option<Inventory>, StatusCode> {
+    match annotate {
+        Some(Annotation::Owned) => {
+            let Json(inventory) = current_inventory(id, character_id, state).await?;
+            Ok(Some(inventory))
+        }
+        Some(Annotation::Stats) | None => Ok(None),
+    }
+}
+
+/// Related data to embed in offers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum Expansion {
+    /// The items a random item offer may turn out to be.
+    Random,
+}
+
+/// Get the item catalog, and the archetype of the character, if random item
+/// offers are to be expanded.
+async fn expansion_catalog(
+    id: AccountId,
+    character_id: CharacterId,
+    expand: Option<Expansion>,
+    state: AppData,
+) -> Result<Option<(ItemCatalog, Option<String>)>, StatusCode> {
+    match expand {
+        Some(Expansion::Random) => {
+            let archetype = match state.accounts.get(&id).await {
+                Some(account_data) => account_data.summary.peek().and_then(|summary| {
+                    summary
+                        .characters
+                        .iter()
+                        .find(|c| c.id == character_id)
+                        .map(|c| c.archetype.clone())
+                }),
+                None => None,
+            };
+            let catalog = current_item_catalog(id, state).await?;
+            Ok(Some((catalog, archetype)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Call `f` with the JSON object of every offer of `store`.
+fn for_each_offer(
+    value: &mut serde_json::Value,
+    store: &Store,
+    mut f: impl FnMut(&mut serde_json::Map<String, serde_json::Value>, &Offer),
+) {
+    for (key, offers) in [("personal", &store.personal), ("public", &store.public)] {
+        let Some(values) = value.get_mut(key).and_then(|v| v.as_array_mut()) else {
+            continue;
+        };
+        for (value, offer) in values.iter_mut().zip(offers) {
+            if let Some(value) = value.as_object_mut() {
+                f(value, offer);
+            }
+        }
+    }
+}
+
+/// Add `owned` and `ownedCount` to every offer of `store`.
+fn annotate_owned(value: &mut serde_json::Value, store: &Store, inventory: &Inventory) {
+    for_each_offer(value, store, |value, offer| {
+        let owned_count = inventory.count(&offer.description.id);
+        value.insert("owned".to_string(), (owned_count > 0).into());
+        value.insert("ownedCount".to_string(), owned_count.into());
+    });
+}
+
+/// A base stat of a weapon as the game shows it.
+#[derive(Debug, serde::Serialize)]
+struct DisplayStat<'a> {
+    name: &'a str,
+    percent: u32,
+}
+
+/// Add `displayStats` and `displayStatsTotal` to every weapon offer of
+/// `store`.
+fn annotate_stats(value: &mut serde_json::Value, store: &Store) {
+    for_each_offer(value, store, |value, offer| {
+        let Overrides::Weapon(weapon) = &offer.description.overrides else {
+            return;
+        };
+        let stats: Vec<_> = weapon
+            .base_stats
+            .iter()
+            .map(|stat| DisplayStat {
+                name: &stat.name,
+                percent: stat.percent(),
+            })
+            .collect();
+        value.insert("displayStats".to_string(), serde_json::json!(stats));
+        value.insert("displayStatsTotal".to_string(), weapon.stats_total().into());
+    });
+}
+
+/// An item a random item offer may turn out to be.
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Candidate<'a> {
+    id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    display_name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    item_type: Option<&'a str>,
+}
+
+/// Add the `candidates` of every random item offer of `store`, the items of
+/// the catalog that fit its slots and are usable by `archetype`.
+fn expand_random(
+    value: &mut serde_json::Value,
+    store: &Store,
+    catalog: &ItemCatalog,
+    archetype: Option<&str>,
+) {
+    for_each_offer(value, store, |value, offer| {
+        let Overrides::RandomItem { slots } = &offer.description.overrides else {
+            return;
+        };
+        let candidates: Vec<_> = catalog
+            .candidates(slots, archetype)
+            .into_iter()
+            .map(|(id, item)| Candidate {
+                id,
+                display_name: item.display_name.as_deref(),
+                item_type: item.item_type.as_deref(),
+            })
+            .collect();
+        value.insert("candidates".to_string(), serde_json::json!(candidates));
+    });
 }
 
 #[instrument(skip(state))]
-async fn refresh_store<T: AuthStorage + Clone>(
+async fn refresh_store(
     account_id: &AccountId,
     character_id: CharacterId,
-    state: AppData<T>,
+    state: AppData,
     currency_type: dt_api::models::CurrencyType,
-) -> Result<Json<Store>, StatusCode> {
+) -> Result<Cached<Store>, StatusCode> {
     let api = &state.api;
     let account_data = if let Some(account_data) = state.accounts.get(account_id).await {
         account_data
@@ -36,26 +203,25 @@ async fn refresh_store<T: AuthStorage + Clone>(
         error!(sid = ?account_id, "Failed to find account data");
         return Err(StatusCode::NOT_FOUND);
     };
-    let mut summary = account_data.summary.read().await;
-    let character =
-        if let Some(character) = summary.characters.iter().find(|c| c.id == character_id) {
+    let find_character = |summary: &Option<Cached<Summary>>| {
+        summary
+            .as_deref()
+            .and_then(|s| s.characters.iter().find(|c| c.id == character_id).cloned())
+    };
+    let character = if let Some(character) = find_character(&account_data.summary.peek()) {
+        character
+    } else {
+        info!("Failed to find character in summary, fetching new summary");
+        if refresh_summary(account_id, state.clone()).await.is_err() {
+            error!("Failed to refresh summary");
+            return Err(StatusCode::NOT_FOUND);
+        } else if let Some(character) = find_character(&account_data.summary.peek()) {
             character
         } else {
-            info!("Failed to find character in summary, fetching new summary");
-            drop(summary);
-            if refresh_summary(account_id, state.clone()).await.is_err() {
-                error!("Failed to refresh summary");
-                return Err(StatusCode::NOT_FOUND);
-            } else {
-                summary = account_data.summary.read().await;
-                if let Some(character) = summary.characters.iter().find(|c| c.id == character_id) {
-                    character
-                } else {
-                    error!(character.id = %character_id, "Failed to find character");
-                    return Err(StatusCode::NOT_FOUND);
-                }
-            }
-        };
+            error!(character.id = %character_id, "Failed to find character");
+            return Err(StatusCode::NOT_FOUND);
+        }
+    };
     let auth_data = if let Some(auth_data) = state
         .auth_data
         .get(*account_id)
@@ -66,7 +232,14 @@ async fn refresh_store<T: AuthStorage + Clone>(
         error!(sid = ?account_id, "Failed to find auth data");
         return Err(StatusCode::NOT_FOUND);
     };
-    let store = api.get_store(&auth_data, currency_type, character).await;
+    let default_personal = state.config.borrow().personal_stores;
+    let personal = state
+        .settings
+        .personal_stores(*account_id, default_personal);
+    let request = StoreRequest::single(&character, personal);
+    let store = api
+        .get_requested_store(&auth_data, currency_type, &request)
+        .await;
     match store {
         Err(e) => {
             error!(
@@ -74,59 +247,382 @@ async fn refresh_store<T: AuthStorage + Clone>(
                 error = %e,
                 "Failed to get store"
             );
+            api.timeline()
+                .record_failure(*account_id, Resource::Store, &e);
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
         Ok(store) => {
-            match currency_type {
-                dt_api::models::CurrencyType::Marks => {
-                    account_data
-                        .marks_store
-                        .write()
-                        .await
-                        .insert(character_id, store.clone());
-                }
-                dt_api::models::CurrencyType::Credits => {
-                    account_data
-                        .credits_store
-                        .write()
-                        .await
-                        .insert(character_id, store.clone());
-                }
+            let mut stores = account_data.stores(currency_type).write().await;
+            let previous_end = stores
+                .view_of(&character_id)
+                .map(|cached| cached.store().current_rotation_end);
+            if !previous_end.is_some_and(|end| end >= store.current_rotation_end) {
+                api.timeline().record(
+                    *account_id,
+                    TimelineEvent::StoreRotated {
+                        character_id,
+                        currency_type,
+                        rotation_end: store.current_rotation_end,
+                    },
+                );
             }
+            let store = stores.insert(character_id, store);
+            drop(stores);
             info!("Successfully fetched store");
-            Ok(Json(store))
+            if state.config.borrow().summary_refresh_on_level_up {
+                if let Err(e) = state
+                    .accounts
+                    .refresh_on_level_up(api, &auth_data, &character, &store)
+                    .await
+                {
+                    error!(error = ?e, "Failed to refresh summary after level up");
+                }
+            }
+            let budget = state.config.borrow().cache_budget_bytes();
+            state.accounts.evict_stores(budget).await;
+            Ok(store)
         }
     }
 }
 
 #[instrument(skip(state))]
-pub(crate) async fn store<T: AuthStorage + Clone>(
+pub(crate) async fn store(
     Path(id): Path<AccountId>,
     Query(StoreQuery {
         character_id,
         currency_type,
+        annotate,
+        expand,
     }): Query<StoreQuery>,
-    State(state): State<AppData<T>>,
-) -> Result<Json<Store>, StatusCode> {
-    if let Some(account_data) = state.accounts.get(&id).await {
-        let currency_store = match currency_type {
-            dt_api::models::CurrencyType::Marks => account_data.marks_store.read().await,
-            dt_api::models::CurrencyType::Credits => account_data.credits_store.read().await,
+    format: ResponseFormat,
+    Bypass(raw): Bypass,
+    Projection(fields): Projection,
+    headers: HeaderMap,
+    State(state): State<AppData>,
+) -> Result<Response, StatusCode> {
+    if raw {
+        let resource = RawResource::Store {
+            currency_type,
+            character_id,
+        };
+        return upstream_response(id, resource, state).await;
+    }
+    let store = current_store(id, character_id, currency_type, state.clone()).await?;
+    let inventory = annotation_inventory(id, character_id, annotate, state.clone()).await?;
+    let catalog = expansion_catalog(id, character_id, expand, state).await?;
+    if annotate.is_none() && catalog.is_none() && fields.is_none() {
+        return match format {
+            ResponseFormat::Json => Ok(store.respond(&headers)),
+            _ => format.render(&store, store_rows(character_id, &store)),
         };
-        let char_store = currency_store.get(&character_id);
+    }
+    let mut value = serde_json::to_value(&*store).map_err(|e| {
+        error!(error = %e, "Failed to serialize store");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if let Some(inventory) = &inventory {
+        annotate_owned(&mut value, &store, inventory);
+    }
+    if annotate == Some(Annotation::Stats) {
+        annotate_stats(&mut value, &store);
+    }
+    if let Some((catalog, archetype)) = &catalog {
+        expand_random(&mut value, &store, catalog, archetype.as_deref());
+    }
+    if let Some(fields) = &fields {
+        value = fields.project(value);
+    }
+    match format {
+        ResponseFormat::Json => Ok(cached::respond(&value, &headers)),
+        _ => format.render(&value, store_rows(character_id, &store)),
+    }
+}
+
+/// An offer without its description, media or overrides, for clients on slow
+/// connections.
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OfferSummary<'a> {
+    name: &'a str,
+    rarity: Option<i32>,
+    item_level: Option<i32>,
+    price: i32,
+    personal: bool,
+    expires: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owned: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owned_count: Option<usize>,
+}
+
+impl<'a> OfferSummary<'a> {
+    fn new(store: &Store, offer: &'a Offer, personal: bool, inventory: Option<&Inventory>) -> Self {
+        let item = offer.description.overrides.item();
+        let owned_count = inventory.map(|inventory| inventory.count(&offer.description.id));
+        Self {
+            name: &offer.sku.name,
+            rarity: item.map(|item| item.rarity),
+            item_level: item.map(|item| item.item_level),
+            price: offer.price.amount.amount,
+            personal,
+            expires: store.current_rotation_end,
+            owned: owned_count.map(|count| count > 0),
+            owned_count,
+        }
+    }
+}
+
+#[instrument(skip(state))]
+pub(crate) async fn store_summary(
+    Path(id): Path<AccountId>,
+    Query(StoreQuery {
+        character_id,
+        currency_type,
+        annotate,
+        ..
+    }): Query<StoreQuery>,
+    format: ResponseFormat,
+    State(state): State<AppData>,
+) -> Result<Response, StatusCode> {
+    let store = current_store(id, character_id, currency_type, state.clone()).await?;
+    let inventory = annotation_inventory(id, character_id, annotate, state).await?;
+    let offers: Vec<_> = store
+        .personal
+        .iter()
+        .map(|offer| OfferSummary::new(&store, offer, true, inventory.as_ref()))
+        .chain(
+            store
+                .public
+                .iter()
+                .map(|offer| OfferSummary::new(&store, offer, false, inventory.as_ref())),
+        )
+        .collect();
+    format.render(&offers, &offers)
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DiffQuery {
+    character_id: CharacterId,
+    currency_type: CurrencyType,
+}
+
+/// Differences of the current rotation from the previous one.
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RotationDiff {
+    previous_rotation_end: DateTime<Utc>,
+    current_rotation_end: DateTime<Utc>,
+    #[serde(flatten)]
+    diff: StoreDiff,
+}
+
+/// Compare the current store with the last archived rotation before it.
+#[instrument(skip(state))]
+pub(crate) async fn store_diff(
+    Path(id): Path<AccountId>,
+    Query(DiffQuery {
+        character_id,
+        currency_type,
+    }): Query<DiffQuery>,
+    format: ResponseFormat,
+    State(state): State<AppData>,
+) -> Result<Response, StatusCode> {
+    let store = current_store(id, character_id, currency_type, state.clone()).await?;
+    let snapshots = state.api.history().list(id).map_err(|e| {
+        error!(sid = ?id, error = %e, "Failed to read history");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let Some(previous) = snapshots.into_iter().rev().find(|snapshot| {
+        snapshot.character_id == character_id
+            && snapshot.currency_type == currency_type
+            && snapshot.store.current_rotation_end < store.current_rotation_end
+    }) else {
+        info!("No previous rotation archived");
+        return Err(StatusCode::NOT_FOUND);
+    };
+    format.encode(&RotationDiff {
+        previous_rotation_end: previous.store.current_rotation_end,
+        current_rotation_end: store.current_rotation_end,
+        diff: StoreDiff::new(&previous.store, &store),
+    })
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AtQuery {
+    character_id: CharacterId,
+    currency_type: CurrencyType,
+    timestamp: DateTime<Utc>,
+}
+
+/// The archived rotation of the store that was active at `at`, going by the
+/// validity window of its catalog.
+fn active_at(
+    snapshots: Vec<RotationSnapshot>,
+    character_id: CharacterId,
+    currency_type: CurrencyType,
+    at: DateTime<Utc>,
+) -> Option<RotationSnapshot> {
+    snapshots.into_iter().find(|snapshot| {
+        snapshot.character_id == character_id
+            && snapshot.currency_type == currency_type
+            && snapshot.store.catalog.valid_from <= at
+            && at < snapshot.store.rotates_at()
+    })
+}
+
+/// Get the archived rotation of the store that was active at a time.
+#[instrument(skip(state))]
+pub(crate) async fn store_at(
+    Path(id): Path<AccountId>,
+    Query(AtQuery {
+        character_id,
+        currency_type,
+        timestamp,
+    }): Query<AtQuery>,
+    format: ResponseFormat,
+    State(state): State<AppData>,
+) -> Result<Response, StatusCode> {
+    let snapshots = state.api.history().list(id).map_err(|e| {
+        error!(sid = ?id, error = %e, "Failed to read history");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let Some(snapshot) = active_at(snapshots, character_id, currency_type, timestamp) else {
+        info!("No rotation archived at the time");
+        return Err(StatusCode::NOT_FOUND);
+    };
+    format.render(&snapshot.store, store_rows(character_id, &snapshot.store))
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ArchetypeQuery {
+    currency_type: CurrencyType,
+    annotate: Option<Annotation>,
+    expand: Option<Expansion>,
+    /// Which of several characters of the archetype to use, in summary order.
+    index: Option<usize>,
+}
+
+/// Body of the error returned when the archetype doesn't resolve to exactly
+/// one character.
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ArchetypeError<'a> {
+    error: String,
+    characters: Vec<ArchetypeCandidate<'a>>,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ArchetypeCandidate<'a> {
+    index: usize,
+    id: CharacterId,
+    name: &'a str,
+    level: u32,
+}
+
+#[instrument(skip(state))]
+pub(crate) async fn store_by_archetype(
+    Path((id, archetype)): Path<(AccountId, String)>,
+    Query(ArchetypeQuery {
+        currency_type,
+        annotate,
+        expand,
+        index,
+    }): Query<ArchetypeQuery>,
+    format: ResponseFormat,
+    bypass: Bypass,
+    projection: Projection,
+    headers: HeaderMap,
+    State(state): State<AppData>,
+) -> Response {
+    let summary = match current_summary(id, state.clone()).await {
+        Ok(summary) => summary,
+        Err(status) => return status.into_response(),
+    };
+    let characters: Vec<_> = summary
+        .characters
+        .iter()
+        .filter(|c| c.archetype.eq_ignore_ascii_case(&archetype))
+        .enumerate()
+        .map(|(index, c)| ArchetypeCandidate {
+            index,
+            id: c.id,
+            name: &c.name,
+            level: c.level,
+        })
+        .collect();
+    let selected = match (index, characters.len()) {
+        (_, 0) => Err((
+            StatusCode::NOT_FOUND,
+            format!("No character with archetype {archetype}"),
+        )),
+        (None, 1) => Ok(0),
+        (None, n) => Err((
+            StatusCode::CONFLICT,
+            format!("{n} characters with archetype {archetype}, pass index to pick one"),
+        )),
+        (Some(index), n) if index < n => Ok(index),
+        (Some(index), n) => Err((
+            StatusCode::NOT_FOUND,
+            format!("Index {index} out of range for {n} characters with archetype {archetype}"),
+        )),
+    };
+    let character_id = match selected {
+        Ok(selected) => characters[selected].id,
+        Err((status, error)) => {
+            error!(%archetype, ?index, "{}", error);
+            return (status, Json(ArchetypeError { error, characters })).into_response();
+        }
+    };
+    store(
+        Path(id),
+        Query(StoreQuery {
+            character_id,
+            currency_type,
+            annotate,
+            expand,
+        }),
+        format,
+        bypass,
+        projection,
+        headers,
+        State(state),
+    )
+    .await
+    .into_response()
+}
+
+/// Get the cached store, refreshing it if it has rotated.
+#[instrument(skip(state))]
+async fn current_store(
+    id: AccountId,
+    character_id: CharacterId,
+    currency_type: dt_api::models::CurrencyType,
+    state: AppData,
+) -> Result<Cached<Store>, StatusCode> {
+    if let Some(account_data) = state.accounts.get(&id).await {
+        let char_store = account_data
+            .stores(currency_type)
+            .read()
+            .await
+            .get(&character_id);
         if let Some(store) = char_store {
-            if store.current_rotation_end <= DateTime::<Utc>::from(SystemTime::now()) {
-                drop(currency_store);
+            if store.current_rotation_end <= state.accounts.clock().now() {
                 info!("Store is out of date, refreshing");
+                access_log::cache_miss();
                 refresh_store(&id, character_id, state.clone(), currency_type).await
             } else {
                 debug!("Store valid until {:?}", store.current_rotation_end);
                 info!("Returning cached store");
-                Ok(Json(store.clone()))
+                access_log::cache_hit();
+                Ok(store)
             }
         } else {
-            drop(currency_store);
             info!("Trying to fetch store");
+            access_log::cache_miss();
             refresh_store(&id, character_id, state.clone(), currency_type).await
         }
     } else {
@@ -136,18 +632,169 @@ pub(crate) async fn store<T: AuthStorage + Clone>(
 }
 
 #[instrument(skip(state))]
-pub(crate) async fn store_single<T: AuthStorage + Clone>(
+pub(crate) async fn store_single(
     query: Query<StoreQuery>,
-    State(state): State<AppData<T>>,
-) -> Result<Json<Store>, StatusCode> {
-    let account = state
-        .auth_data
-        .get_single()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    if let Some(account) = account {
-        store(Path(account), query, State(state)).await
-    } else {
-        error!("Failed to find account data");
-        Err(StatusCode::NOT_FOUND)
+    format: ResponseFormat,
+    bypass: Bypass,
+    projection: Projection,
+    headers: HeaderMap,
+    State(state): State<AppData>,
+) -> Result<Response, Response> {
+    let account = single_account(&state).map_err(IntoResponse::into_response)?;
+    store(
+        Path(account),
+        query,
+        format,
+        bypass,
+        projection,
+        headers,
+        State(state),
+    )
+    .await
+    .map_err(IntoResponse::into_response)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn random_item_store() -> Store {
+        serde_json::from_value(json!({
+            "_links": {},
+            "catalog": {
+                "id": "11111111-1111-1111-1111-111111111111",
+                "name": "catalog",
+                "generation": 1,
+                "layoutRef": null,
+                "validFrom": "0",
+                "validTo": "0"
+            },
+            "name": "marks_store_veteran",
+            "public": [],
+            "personal": [{
+                "offerId": "22222222-2222-2222-2222-222222222222",
+                "sku": {
+                    "id": "33333333-3333-3333-3333-333333333333",
+                    "displayPriority": 0,
+                    "internalName": "random",
+                    "name": "Random Weapon",
+                    "description": "",
+                    "category": "weapon",
+                    "assetId": "",
+                    "tags": [],
+                    "dlcReq": []
+                },
+                "entitlement": {
+                    "id": "44444444-4444-4444-4444-444444444444",
+                    "limit": 1,
+                    "type": "GearInstance"
+                },
+                "price": {
+                    "amount": { "amount": 100, "type": "marks" },
+                    "id": "55555555-5555-5555-5555-555555555555",
+                    "priority": 1,
+                    "priceFormula": null
+                },
+                "state": "active",
+                "description": {
+                    "id": "random",
+                    "gearId": "66666666-6666-6666-6666-666666666666",
+                    "rotation": "daily",
+                    "type": "weapon",
+                    "properties": {},
+                    "overrides": { "slots": ["slot_primary"] }
+                },
+                "media": []
+            }],
+            "rerollsThisRotation": 0,
+            "currentRotationEnd": "1700000000000"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn expands_random_items_to_usable_candidates() {
+        let store = random_item_store();
+        let catalog: ItemCatalog = serde_json::from_value(json!({
+            "chainsword_p1_m1": {
+                "display_name": "Chainsword",
+                "item_type": "WEAPON_MELEE",
+                "slots": ["slot_primary"],
+                "archetypes": ["veteran", "zealot"]
+            },
+            "force_sword_p1_m1": {
+                "slots": ["slot_primary"],
+                "archetypes": ["psyker"]
+            },
+            "lasgun_p1_m1": { "slots": ["slot_secondary"] },
+            "combat_blade_p1_m1": { "slots": ["slot_primary"] }
+        }))
+        .unwrap();
+        let mut value = serde_json::to_value(&store).unwrap();
+
+        expand_random(&mut value, &store, &catalog, Some("veteran"));
+
+        assert_eq!(
+            value["personal"][0]["candidates"],
+            json!([
+                {
+                    "id": "chainsword_p1_m1",
+                    "displayName": "Chainsword",
+                    "itemType": "WEAPON_MELEE"
+                },
+                { "id": "combat_blade_p1_m1" }
+            ])
+        );
+    }
+
+    #[test]
+    fn finds_the_rotation_active_at_a_time() {
+        let store: Store =
+            serde_json::from_str(include_str!("../../tests/fixtures/store.json")).unwrap();
+        let character_id = CharacterId(uuid::Uuid::from_u128(1));
+        let snapshot = |currency_type| RotationSnapshot {
+            account_id: AccountId(uuid::Uuid::nil()),
+            character_id,
+            currency_type,
+            archived_at: store.catalog.valid_from,
+            store: store.clone(),
+        };
+        let snapshots = vec![
+            snapshot(CurrencyType::Credits),
+            snapshot(CurrencyType::Marks),
+        ];
+        let during = store.catalog.valid_from + chrono::Duration::minutes(30);
+
+        let active = active_at(snapshots.clone(), character_id, CurrencyType::Marks, during);
+        assert_eq!(active.map(|s| s.currency_type), Some(CurrencyType::Marks));
+        for at in [
+            store.catalog.valid_from - chrono::Duration::seconds(1),
+            store.current_rotation_end,
+        ] {
+            assert!(active_at(snapshots.clone(), character_id, CurrencyType::Marks, at).is_none());
+        }
+    }
+
+    #[test]
+    fn annotates_weapons_with_display_stats() {
+        let store: Store =
+            serde_json::from_str(include_str!("../../tests/fixtures/store.json")).unwrap();
+        let mut value = serde_json::to_value(&store).unwrap();
+
+        annotate_stats(&mut value, &store);
+
+        let weapon = &value["personal"][0];
+        assert_eq!(
+            weapon["displayStats"],
+            json!([{ "name": "damage", "percent": 64 }])
+        );
+        assert_eq!(weapon["displayStatsTotal"], json!(64));
+        for_each_offer(&mut value, &store, |value, offer| {
+            if !matches!(offer.description.overrides, Overrides::Weapon(_)) {
+                assert!(!value.contains_key("displayStats"));
+            }
+        });
     }
 }
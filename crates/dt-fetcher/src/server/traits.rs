@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+
+use axum::{extract::State, Json};
+use dt_api::models::{Item, ItemCatalog, Offer};
+use serde::Serialize;
+use tracing::{instrument, warn};
+
+use crate::server::{current_item_catalog, AppData};
+
+/// Item type of traits in the item catalog.
+const TRAIT_ITEM_TYPE: &str = "TRAIT";
+
+/// Values seen for a trait of one rarity.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub(crate) struct ValueRange {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl ValueRange {
+    fn observe(&mut self, value: Option<f64>) {
+        let Some(value) = value else {
+            return;
+        };
+        self.min = Some(self.min.map_or(value, |min| min.min(value)));
+        self.max = Some(self.max.map_or(value, |max| max.max(value)));
+    }
+}
+
+/// A trait, or blessing, with what is known about it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TraitEntry {
+    pub id: String,
+    /// Name from the item catalog, as a localization key if the catalog isn't
+    /// localized.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Values seen in cached stores, by rarity.
+    pub rarities: BTreeMap<i32, ValueRange>,
+}
+
+/// Traits seen in cached stores or listed in the item catalog, by id.
+#[derive(Debug, Default)]
+pub(crate) struct TraitDictionary {
+    traits: BTreeMap<String, TraitEntry>,
+}
+
+impl TraitDictionary {
+    fn entry(&mut self, id: &str) -> &mut TraitEntry {
+        self.traits
+            .entry(id.to_string())
+            .or_insert_with(|| TraitEntry {
+                id: id.to_string(),
+                ..TraitEntry::default()
+            })
+    }
+
+    /// Add the traits of the item of `offer`, if any.
+    pub fn observe(&mut self, offer: &Offer) {
+        let Some(item) = offer.description.overrides.item() else {
+            return;
+        };
+        for t in &item.traits {
+            self.entry(&t.id)
+                .rarities
+                .entry(t.rarity)
+                .or_default()
+                .observe(t.value);
+        }
+    }
+
+    /// Add the traits of `catalog`, and name the ones already seen.
+    pub fn add_catalog(&mut self, catalog: &ItemCatalog) {
+        for (id, item) in &catalog.items {
+            let is_trait = item
+                .item_type
+                .as_deref()
+                .is_some_and(|t| t.eq_ignore_ascii_case(TRAIT_ITEM_TYPE));
+            if is_trait || self.traits.contains_key(id) {
+                let entry = self.entry(id);
+                entry.display_name.clone_from(&item.display_name);
+                entry.description = description(item);
+            }
+        }
+    }
+
+    pub fn into_entries(self) -> Vec<TraitEntry> {
+        self.traits.into_values().collect()
+    }
+}
+
+fn description(item: &Item) -> Option<String> {
+    item.other
+        .get("description")
+        .and_then(|d| d.as_str())
+        .map(str::to_string)
+}
+
+/// List every trait seen in the cached stores of any account or listed in the
+/// item catalog, ordered by id.
+#[instrument(skip(state))]
+pub(crate) async fn traits(State(state): State<AppData>) -> Json<Vec<TraitEntry>> {
+    let accounts = state.accounts.list().await;
+    let mut dictionary = TraitDictionary::default();
+    for (account_id, account_data) in &accounts {
+        for offer_match in account_data.find_offers(*account_id, |_| true).await {
+            dictionary.observe(&offer_match.offer);
+        }
+    }
+    // Every account gets the same catalog, so one is enough.
+    let cached = accounts
+        .iter()
+        .find_map(|(_, account_data)| account_data.item_catalog.peek());
+    let catalog = match (cached, accounts.first()) {
+        (Some(catalog), _) => Some(catalog),
+        (None, Some((account_id, _))) => current_item_catalog(*account_id, state.clone())
+            .await
+            .map_err(|status| warn!(%status, "Failed to get item catalog for traits"))
+            .ok(),
+        (None, None) => None,
+    };
+    if let Some(catalog) = catalog {
+        dictionary.add_catalog(&catalog);
+    }
+    Json(dictionary.into_entries())
+}
+
+#[cfg(test)]
+mod tests {
+    use dt_api::models::Store;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn collects_traits_from_stores_and_catalog() {
+        let store: Store =
+            serde_json::from_str(include_str!("../../tests/fixtures/store.json")).unwrap();
+        let catalog: ItemCatalog = serde_json::from_value(json!({
+            "content/items/traits/gadget_inate_health_increase": {
+                "display_name": "loc_gadget_inate_health_increase",
+                "item_type": "TRAIT",
+                "description": "loc_gadget_inate_health_increase_desc"
+            },
+            "content/items/traits/bespoke_autogun_p1/crit_chance_scaled_on_weakspot": {
+                "item_type": "TRAIT"
+            },
+            "lasgun_p1_m1": { "item_type": "WEAPON_RANGED" }
+        }))
+        .unwrap();
+
+        let mut dictionary = TraitDictionary::default();
+        for offer in store.personal.iter().chain(&store.public) {
+            dictionary.observe(offer);
+        }
+        dictionary.add_catalog(&catalog);
+        let entries = dictionary.into_entries();
+
+        let ids: Vec<_> = entries.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            [
+                "content/items/traits/bespoke_autogun_p1/crit_chance_scaled_on_weakspot",
+                "content/items/traits/bespoke_lasgun_p1/crit_chance_scaled_on_weakspot",
+                "content/items/traits/bespoke_lasgun_p1/stacking_rending_on_weakspot",
+                "content/items/traits/gadget_inate_health_increase",
+            ]
+        );
+        assert!(entries[0].rarities.is_empty());
+        assert_eq!(
+            entries[1].rarities,
+            BTreeMap::from([(4, ValueRange::default())])
+        );
+        assert_eq!(
+            entries[3].display_name.as_deref(),
+            Some("loc_gadget_inate_health_increase")
+        );
+        assert_eq!(
+            entries[3].description.as_deref(),
+            Some("loc_gadget_inate_health_increase_desc")
+        );
+    }
+
+    #[test]
+    fn widens_value_ranges() {
+        let mut range = ValueRange::default();
+        for value in [Some(0.1), None, Some(0.25), Some(0.05)] {
+            range.observe(value);
+        }
+        assert_eq!(
+            range,
+            ValueRange {
+                min: Some(0.05),
+                max: Some(0.25),
+            }
+        );
+    }
+}
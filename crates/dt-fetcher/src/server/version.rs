@@ -0,0 +1,53 @@
+use axum::{extract::State, Json};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::instrument;
+
+use crate::server::AppData;
+
+const GIT_COMMIT: &str = env!("DT_FETCHER_GIT_COMMIT");
+const BUILD_TIME: &str = env!("DT_FETCHER_BUILD_TIME");
+
+/// Cargo features the binary was built with.
+const FEATURES: &[(&str, bool)] = &[
+    ("chaos", cfg!(feature = "chaos")),
+    ("dashboard", cfg!(feature = "dashboard")),
+    ("mqtt", cfg!(feature = "mqtt")),
+    ("query", cfg!(feature = "query")),
+    ("redis", cfg!(feature = "redis")),
+    ("s3", cfg!(feature = "s3")),
+    ("sentry", cfg!(feature = "sentry")),
+];
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct VersionInfo {
+    version: &'static str,
+    git_commit: &'static str,
+    built_at: Option<DateTime<Utc>>,
+    features: Vec<&'static str>,
+    storage: &'static str,
+    started_at: DateTime<Utc>,
+    uptime_secs: i64,
+}
+
+#[instrument(skip(state))]
+pub(crate) async fn version(State(state): State<AppData>) -> Json<VersionInfo> {
+    let built_at = BUILD_TIME
+        .parse()
+        .ok()
+        .and_then(|secs| DateTime::from_timestamp(secs, 0));
+    Json(VersionInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: GIT_COMMIT,
+        built_at,
+        features: FEATURES
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| *name)
+            .collect(),
+        storage: state.auth_data.backend(),
+        started_at: state.started_at,
+        uptime_secs: (Utc::now() - state.started_at).num_seconds(),
+    })
+}
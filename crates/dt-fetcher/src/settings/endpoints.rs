@@ -0,0 +1,40 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use dt_api::models::AccountId;
+use tracing::{error, instrument};
+
+use super::{AccountSettings, Settings, MAX_SUMMARY_TTL_MINS};
+
+#[instrument(skip(state))]
+pub(crate) async fn get_settings(
+    Path(id): Path<AccountId>,
+    State(state): State<Settings>,
+) -> Result<Json<AccountSettings>, StatusCode> {
+    state.get(id).map(Json).map_err(|e| {
+        error!(sid = ?id, error = %e, "Failed to get settings");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+#[instrument(skip(state))]
+pub(crate) async fn put_settings(
+    Path(id): Path<AccountId>,
+    State(state): State<Settings>,
+    Json(settings): Json<AccountSettings>,
+) -> Result<Json<AccountSettings>, StatusCode> {
+    if settings
+        .summary_ttl_mins
+        .is_some_and(|mins| !(1..=MAX_SUMMARY_TTL_MINS).contains(&mins))
+    {
+        error!(sid = ?id, "Summary TTL must be between a minute and a year");
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    if let Err(e) = state.insert(id, settings.clone()) {
+        error!(sid = ?id, error = %e, "Failed to update settings");
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    Ok(Json(settings))
+}
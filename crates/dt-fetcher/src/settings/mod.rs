@@ -0,0 +1,72 @@
+use anyhow::Result;
+use dt_api::models::AccountId;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+mod endpoints;
+pub(crate) use endpoints::{get_settings, put_settings};
+
+mod storage;
+pub(crate) use storage::{
+    ErasedSettingsStorage, InMemorySettingsStorage, SettingsStorage, SledDbSettingsStorage,
+};
+
+/// Longest allowed summary TTL, a year.
+pub(crate) const MAX_SUMMARY_TTL_MINS: i64 = 60 * 24 * 365;
+
+/// Per-account overrides of the global config.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct AccountSettings {
+    /// Minutes after which a cached summary is refreshed; the global
+    /// `summaryRefreshIntervalMins` if `None`.
+    pub summary_ttl_mins: Option<i64>,
+    /// Whether the personal offers of each character are fetched; the global
+    /// `personalStores` if `None`.
+    pub personal_stores: Option<bool>,
+}
+
+/// Settings of all accounts.
+#[derive(Debug, Clone)]
+pub(crate) struct Settings {
+    storage: ErasedSettingsStorage,
+}
+
+impl Settings {
+    pub fn new(storage: ErasedSettingsStorage) -> Self {
+        Self { storage }
+    }
+
+    pub fn get(&self, account: AccountId) -> Result<AccountSettings> {
+        Ok(self.storage.get(account)?.unwrap_or_default())
+    }
+
+    pub fn insert(&self, account: AccountId, settings: AccountSettings) -> Result<()> {
+        self.storage.insert(account, settings)
+    }
+
+    /// How long the cached summary of an account stays fresh, falling back to
+    /// `default_mins` if the account has no override.
+    pub fn summary_ttl(&self, account: AccountId, default_mins: i64) -> chrono::Duration {
+        let mins = match self.get(account) {
+            Ok(settings) => settings.summary_ttl_mins.unwrap_or(default_mins),
+            Err(e) => {
+                error!(sid = ?account, error = %e, "Failed to get settings; using default TTL");
+                default_mins
+            }
+        };
+        chrono::Duration::minutes(mins)
+    }
+
+    /// Whether the personal stores of an account are fetched, falling back to
+    /// `default` if the account has no override.
+    pub fn personal_stores(&self, account: AccountId, default: bool) -> bool {
+        match self.get(account) {
+            Ok(settings) => settings.personal_stores.unwrap_or(default),
+            Err(e) => {
+                error!(sid = ?account, error = %e, "Failed to get settings; using default store scope");
+                default
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{Context, Result};
+use dt_api::models::AccountId;
+use dyn_clone::DynClone;
+use tracing::instrument;
+
+use super::AccountSettings;
+
+pub(crate) trait SettingsStorage:
+    Send + Sync + DynClone + std::fmt::Debug + 'static
+{
+    fn get(&self, account: AccountId) -> Result<Option<AccountSettings>>;
+
+    fn insert(&self, account: AccountId, settings: AccountSettings) -> Result<()>;
+}
+
+dyn_clone::clone_trait_object!(SettingsStorage);
+
+#[derive(Debug, Clone, Default)]
+pub struct InMemorySettingsStorage {
+    settings: Arc<RwLock<HashMap<AccountId, AccountSettings>>>,
+}
+
+impl SettingsStorage for InMemorySettingsStorage {
+    #[instrument(skip(self))]
+    fn get(&self, account: AccountId) -> Result<Option<AccountSettings>> {
+        let settings = self.settings.read().expect("Settings poisoned");
+        Ok(settings.get(&account).cloned())
+    }
+
+    #[instrument(skip(self))]
+    fn insert(&self, account: AccountId, settings: AccountSettings) -> Result<()> {
+        let mut all = self.settings.write().expect("Settings poisoned");
+        all.insert(account, settings);
+        Ok(())
+    }
+}
+
+const SETTINGS_TREE: &str = "settings";
+
+/// Settings stored in a tree of the auth database, keyed by account id.
+#[derive(Debug, Clone)]
+pub struct SledDbSettingsStorage {
+    tree: sled::Tree,
+}
+
+impl SledDbSettingsStorage {
+    pub fn new(db: &sled::Db) -> Result<Self> {
+        Ok(Self {
+            tree: db
+                .open_tree(SETTINGS_TREE)
+                .context("Failed to open settings")?,
+        })
+    }
+}
+
+impl SettingsStorage for SledDbSettingsStorage {
+    #[instrument(skip(self))]
+    fn get(&self, account: AccountId) -> Result<Option<AccountSettings>> {
+        self.tree
+            .get(account.0.as_bytes())
+            .context("Failed to get settings")?
+            .map(|value| serde_json::from_slice(&value).context("Failed to deserialize settings"))
+            .transpose()
+    }
+
+    #[instrument(skip(self))]
+    fn insert(&self, account: AccountId, settings: AccountSettings) -> Result<()> {
+        let value = serde_json::to_vec(&settings).context("Failed to serialize settings")?;
+        self.tree
+            .insert(account.0.as_bytes(), value)
+            .context("Failed to insert settings")?;
+        self.tree.flush().context("Failed to flush")?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ErasedSettingsStorage(Box<dyn SettingsStorage>);
+
+impl SettingsStorage for ErasedSettingsStorage {
+    #[instrument(skip(self))]
+    fn get(&self, account: AccountId) -> Result<Option<AccountSettings>> {
+        self.0.get(account)
+    }
+
+    #[instrument(skip(self))]
+    fn insert(&self, account: AccountId, settings: AccountSettings) -> Result<()> {
+        self.0.insert(account, settings)
+    }
+}
+
+impl From<InMemorySettingsStorage> for ErasedSettingsStorage {
+    fn from(value: InMemorySettingsStorage) -> Self {
+        Self(Box::new(value))
+    }
+}
+
+impl From<SledDbSettingsStorage> for ErasedSettingsStorage {
+    fn from(value: SledDbSettingsStorage) -> Self {
+        Self(Box::new(value))
+    }
+}
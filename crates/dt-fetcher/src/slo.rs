@@ -0,0 +1,346 @@
+//! Error budgets of upstream calls and handler responses.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+use axum::{
+    body::Body,
+    extract::State,
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    config::Config,
+    notify::{Event, Notifiers},
+    upstream::ConnectionState,
+};
+
+/// How often the error rates are checked against their thresholds.
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Error budget settings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct SloConfig {
+    /// Seconds over which error rates are measured.
+    pub window_secs: u64,
+    /// Outcomes needed in the window before its error rate is checked.
+    pub min_requests: u64,
+    /// Rate of failed upstream calls at which the budget is exceeded; not
+    /// checked if `None`.
+    pub upstream_error_rate: Option<f64>,
+    /// Rate of `5xx` responses at which the budget is exceeded; not checked if
+    /// `None`.
+    pub handler_error_rate: Option<f64>,
+}
+
+impl Default for SloConfig {
+    fn default() -> Self {
+        Self {
+            window_secs: 300,
+            min_requests: 20,
+            upstream_error_rate: None,
+            handler_error_rate: None,
+        }
+    }
+}
+
+/// What an error budget is kept for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum Source {
+    /// Calls to the upstream API.
+    Upstream,
+    /// Responses of the server.
+    Handler,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Upstream => f.write_str("upstream"),
+            Source::Handler => f.write_str("handler"),
+        }
+    }
+}
+
+/// Outcomes of one second.
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    second: i64,
+    ok: u64,
+    failed: u64,
+}
+
+/// Outcomes of the last seconds, in a bucket per second.
+#[derive(Debug, Default)]
+struct Window {
+    buckets: VecDeque<Bucket>,
+    exceeded: bool,
+}
+
+impl Window {
+    fn record(&mut self, second: i64, ok: bool) {
+        match self.buckets.back_mut() {
+            Some(bucket) if bucket.second == second => {}
+            _ => self.buckets.push_back(Bucket {
+                second,
+                ok: 0,
+                failed: 0,
+            }),
+        }
+        let bucket = self.buckets.back_mut().expect("a bucket was just pushed");
+        if ok {
+            bucket.ok += 1;
+        } else {
+            bucket.failed += 1;
+        }
+    }
+
+    /// Drop the outcomes older than `window_secs` and count the rest.
+    fn counts(&mut self, now: i64, window_secs: u64) -> (u64, u64) {
+        let oldest = now - window_secs as i64;
+        while self.buckets.front().is_some_and(|b| b.second <= oldest) {
+            self.buckets.pop_front();
+        }
+        self.buckets
+            .iter()
+            .fold((0, 0), |(ok, failed), b| (ok + b.ok, failed + b.failed))
+    }
+}
+
+#[derive(Debug, Default)]
+struct Windows {
+    upstream: Window,
+    handler: Window,
+}
+
+impl Windows {
+    fn get(&mut self, source: Source) -> &mut Window {
+        match source {
+            Source::Upstream => &mut self.upstream,
+            Source::Handler => &mut self.handler,
+        }
+    }
+}
+
+/// Rolling success rates of upstream calls and handler responses.
+///
+/// Clones share the outcomes.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Slo {
+    windows: Arc<Mutex<Windows>>,
+}
+
+impl Slo {
+    pub fn record(&self, source: Source, ok: bool) {
+        let second = Utc::now().timestamp();
+        self.lock().get(source).record(second, ok);
+    }
+
+    /// Sources whose error budget is exceeded, as of the last check.
+    pub fn degraded(&self) -> Vec<Source> {
+        let mut windows = self.lock();
+        [Source::Upstream, Source::Handler]
+            .into_iter()
+            .filter(|source| windows.get(*source).exceeded)
+            .collect()
+    }
+
+    /// Check the error rate of `source` against `threshold`. Returns the error
+    /// rate, and whether the budget became exceeded or recovered.
+    fn check(&self, source: Source, config: &SloConfig, threshold: Option<f64>) -> Check {
+        let mut windows = self.lock();
+        let window = windows.get(source);
+        let (ok, failed) = window.counts(Utc::now().timestamp(), config.window_secs);
+        let total = ok + failed;
+        let error_rate = if total == 0 {
+            0.0
+        } else {
+            failed as f64 / total as f64
+        };
+        let exceeded = match threshold {
+            Some(threshold) => total >= config.min_requests && error_rate >= threshold,
+            None => false,
+        };
+        let changed = exceeded != window.exceeded;
+        window.exceeded = exceeded;
+        Check {
+            error_rate,
+            changed: changed.then_some(exceeded),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Windows> {
+        self.windows.lock().expect("SLO lock is not poisoned")
+    }
+}
+
+struct Check {
+    error_rate: f64,
+    /// `Some(true)` if the budget became exceeded, `Some(false)` if it
+    /// recovered.
+    changed: Option<bool>,
+}
+
+/// Record the outcome of every response; `5xx` responses are failures.
+pub(crate) async fn record_responses(
+    State(slo): State<Slo>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    // Readiness probes fail while degraded, which would keep it degraded.
+    let is_probe = request.uri().path() == "/readyz";
+    let response = next.run(request).await;
+    if !is_probe {
+        slo.record(Source::Handler, !response.status().is_server_error());
+    }
+    response
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Readiness {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    degraded: Vec<Source>,
+    upstream: ConnectionState,
+}
+
+/// Ready, or `503 Service Unavailable` while an error budget is exceeded.
+/// Either way, the health of the upstream connections is included.
+pub(crate) fn readiness(slo: &Slo, upstream: ConnectionState) -> Response {
+    let degraded = slo.degraded();
+    if degraded.is_empty() {
+        Json(Readiness {
+            status: "ready",
+            degraded,
+            upstream,
+        })
+        .into_response()
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(Readiness {
+                status: "degraded",
+                degraded,
+                upstream,
+            }),
+        )
+            .into_response()
+    }
+}
+
+/// Checks the error rates against the thresholds of the config, and notifies
+/// when a budget is exceeded or recovers.
+pub(crate) struct SloMonitor {
+    slo: Slo,
+    notifiers: Notifiers,
+    config: watch::Receiver<Config>,
+}
+
+impl SloMonitor {
+    pub fn new(slo: Slo, notifiers: Notifiers, config: watch::Receiver<Config>) -> Self {
+        Self {
+            slo,
+            notifiers,
+            config,
+        }
+    }
+
+    #[instrument(skip_all)]
+    pub async fn start(self, token: CancellationToken) -> Result<()> {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            tokio::select! {
+                biased;
+                _ = token.cancelled() => return Ok(()),
+                _ = interval.tick() => self.check().await,
+            }
+        }
+    }
+
+    async fn check(&self) {
+        let config = self.config.borrow().slo.clone();
+        for (source, threshold) in [
+            (Source::Upstream, config.upstream_error_rate),
+            (Source::Handler, config.handler_error_rate),
+        ] {
+            let check = self.slo.check(source, &config, threshold);
+            metrics::gauge!("dt_fetcher_slo_error_rate", "source" => source.to_string())
+                .set(check.error_rate);
+            let (Some(exceeded), Some(threshold)) = (check.changed, threshold) else {
+                continue;
+            };
+            let event = if exceeded {
+                warn!(%source, error_rate = check.error_rate, threshold, "Error budget exceeded");
+                Event::ErrorBudgetExceeded {
+                    source,
+                    error_rate: check.error_rate,
+                    threshold,
+                }
+            } else {
+                info!(%source, error_rate = check.error_rate, "Error budget recovered");
+                Event::ErrorBudgetRecovered {
+                    source,
+                    error_rate: check.error_rate,
+                }
+            };
+            self.notifiers.notify(&event).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_outcomes_outside_the_window() {
+        let mut window = Window::default();
+        window.record(100, true);
+        window.record(100, false);
+        window.record(150, false);
+        assert_eq!(window.counts(159, 60), (1, 2));
+        assert_eq!(window.counts(201, 60), (0, 1));
+        assert_eq!(window.counts(300, 60), (0, 0));
+    }
+
+    #[test]
+    fn needs_min_requests_before_exceeding() {
+        let slo = Slo::default();
+        let config = SloConfig {
+            min_requests: 4,
+            ..SloConfig::default()
+        };
+        for _ in 0..3 {
+            slo.record(Source::Upstream, false);
+        }
+        assert_eq!(
+            slo.check(Source::Upstream, &config, Some(0.5)).changed,
+            None
+        );
+        slo.record(Source::Upstream, true);
+        let check = slo.check(Source::Upstream, &config, Some(0.5));
+        assert_eq!(check.changed, Some(true));
+        assert_eq!(check.error_rate, 0.75);
+        assert_eq!(slo.degraded(), vec![Source::Upstream]);
+        // Recovers once the threshold is raised above the error rate.
+        let check = slo.check(Source::Upstream, &config, Some(0.8));
+        assert_eq!(check.changed, Some(false));
+        assert!(slo.degraded().is_empty());
+    }
+}
@@ -0,0 +1,168 @@
+//! Gauges describing the contents of the cached stores, so alerts can be set
+//! on offers, e.g. when an item of the highest rarity appears.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Utc};
+use dt_api::models::{CharacterId, CurrencyType};
+
+use crate::{account::Accounts, stores::StoreView};
+
+/// Values of the store gauges, by their labels.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct StoreGauges {
+    /// Offers by rarity, archetype and currency.
+    offers: BTreeMap<(String, String, String), u64>,
+    /// Seconds until the store rotates, by character and currency.
+    until_rotation: BTreeMap<(String, String), f64>,
+}
+
+impl StoreGauges {
+    /// Gauges of every cached store.
+    pub async fn collect(accounts: &Accounts) -> Self {
+        let now = Utc::now();
+        let mut gauges = Self::default();
+        for (_, account_data) in accounts.list().await {
+            let archetypes: HashMap<CharacterId, String> = account_data
+                .summary
+                .peek()
+                .iter()
+                .flat_map(|summary| &summary.characters)
+                .map(|character| (character.id, character.archetype.clone()))
+                .collect();
+            for currency_type in [CurrencyType::Marks, CurrencyType::Credits] {
+                let stores = account_data.stores(currency_type).read().await;
+                for (character_id, store) in stores.iter() {
+                    let archetype = archetypes
+                        .get(&character_id)
+                        .map_or("unknown", String::as_str);
+                    gauges.add(character_id, archetype, currency_type, store, now);
+                }
+            }
+        }
+        gauges
+    }
+
+    fn add(
+        &mut self,
+        character_id: CharacterId,
+        archetype: &str,
+        currency_type: CurrencyType,
+        store: StoreView<'_>,
+        now: DateTime<Utc>,
+    ) {
+        for offer in store.offers() {
+            let rarity = match offer.description.overrides.item() {
+                Some(item) => item.rarity.to_string(),
+                None => "none".to_string(),
+            };
+            *self
+                .offers
+                .entry((rarity, archetype.to_string(), currency_type.to_string()))
+                .or_default() += 1;
+        }
+        let until_rotation = (store.store().current_rotation_end - now)
+            .num_seconds()
+            .max(0);
+        self.until_rotation.insert(
+            (character_id.0.to_string(), currency_type.to_string()),
+            until_rotation as f64,
+        );
+    }
+}
+
+/// Records the store gauges, keeping the last values to zero the labels that
+/// disappeared, so alerts on them resolve.
+#[derive(Debug, Default)]
+pub(crate) struct StoreMetrics {
+    last: StoreGauges,
+}
+
+impl StoreMetrics {
+    pub fn record(&mut self, gauges: StoreGauges) {
+        for key in self.last.offers.keys() {
+            if !gauges.offers.contains_key(key) {
+                set_offers(key, 0);
+            }
+        }
+        for key in self.last.until_rotation.keys() {
+            if !gauges.until_rotation.contains_key(key) {
+                set_until_rotation(key, 0.0);
+            }
+        }
+        for (key, count) in &gauges.offers {
+            set_offers(key, *count);
+        }
+        for (key, seconds) in &gauges.until_rotation {
+            set_until_rotation(key, *seconds);
+        }
+        self.last = gauges;
+    }
+}
+
+fn set_offers((rarity, archetype, currency): &(String, String, String), count: u64) {
+    metrics::gauge!(
+        "dt_fetcher_store_offers",
+        "rarity" => rarity.clone(),
+        "archetype" => archetype.clone(),
+        "currency" => currency.clone()
+    )
+    .set(count as f64);
+}
+
+fn set_until_rotation((character, currency): &(String, String), seconds: f64) {
+    metrics::gauge!(
+        "dt_fetcher_store_seconds_until_rotation",
+        "character" => character.clone(),
+        "currency" => currency.clone()
+    )
+    .set(seconds);
+}
+
+#[cfg(test)]
+mod tests {
+    use dt_api::models::Store;
+
+    use super::*;
+    use crate::stores::Stores;
+
+    fn labels(labels: &[&str]) -> Vec<String> {
+        labels.iter().map(|label| label.to_string()).collect()
+    }
+
+    #[test]
+    fn counts_offers_by_rarity() {
+        let store: Store =
+            serde_json::from_str(include_str!("../tests/fixtures/store.json")).unwrap();
+        let character_id = CharacterId(uuid::Uuid::from_u128(1));
+        let now = store.current_rotation_end - chrono::Duration::seconds(90);
+        let stores: Stores = [(character_id, store.clone())].into_iter().collect();
+        let view = stores.view_of(&character_id).unwrap();
+        let mut gauges = StoreGauges::default();
+        gauges.add(character_id, "zealot", CurrencyType::Marks, view, now);
+
+        let offers: Vec<(Vec<String>, u64)> = gauges
+            .offers
+            .iter()
+            .map(|((rarity, archetype, currency), count)| {
+                (labels(&[rarity, archetype, currency]), *count)
+            })
+            .collect();
+        assert_eq!(
+            offers,
+            [
+                (labels(&["2", "zealot", "marks"]), 1),
+                (labels(&["3", "zealot", "marks"]), 1),
+                (labels(&["5", "zealot", "marks"]), 1),
+                (labels(&["none", "zealot", "marks"]), 1),
+            ]
+        );
+        let key = (character_id.0.to_string(), "marks".to_string());
+        assert_eq!(gauges.until_rotation[&key], 90.0);
+
+        // A rotation that has passed counts down no further than zero.
+        let now = store.current_rotation_end + chrono::Duration::seconds(5);
+        gauges.add(character_id, "zealot", CurrencyType::Marks, view, now);
+        assert_eq!(gauges.until_rotation[&key], 0.0);
+    }
+}
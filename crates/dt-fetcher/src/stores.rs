@@ -0,0 +1,474 @@
+//! Cached stores of the characters of an account, for one currency.
+//!
+//! The public offers of a rotation are the same for every character, so they
+//! are cached once and merged with the personal offers of a character when
+//! its store is first read, until either changes.
+//!
+//! Accounts that only fetch public stores request them once per archetype
+//! with a [`StoreRequest`], and cache the result for each of its characters.
+
+use std::{collections::HashMap, sync::OnceLock};
+
+use chrono::{DateTime, Utc};
+use dt_api::models::{Character, CharacterId, Offer, Store};
+
+use crate::cached::Cached;
+
+/// Characters whose stores are fetched with a single upstream request.
+#[derive(Debug, Clone)]
+pub(crate) struct StoreRequest<'a> {
+    characters: Vec<&'a Character>,
+    personal: bool,
+}
+
+impl<'a> StoreRequest<'a> {
+    /// The store of `character`, with its personal offers if `personal`.
+    pub fn single(character: &'a Character, personal: bool) -> Self {
+        Self {
+            characters: vec![character],
+            personal,
+        }
+    }
+
+    /// Requests for the stores of `characters`: one per character if
+    /// `personal`, otherwise one per archetype for the public store that its
+    /// characters share.
+    pub fn plan(characters: &'a [Character], personal: bool) -> Vec<Self> {
+        if personal {
+            return characters
+                .iter()
+                .map(|character| Self::single(character, true))
+                .collect();
+        }
+        let mut requests: Vec<Self> = Vec::new();
+        for character in characters {
+            match requests
+                .iter_mut()
+                .find(|request| request.archetype() == character.archetype)
+            {
+                Some(request) => request.characters.push(character),
+                None => requests.push(Self::single(character, false)),
+            }
+        }
+        requests
+    }
+
+    /// The characters the fetched store is cached for, all of the same
+    /// archetype.
+    pub fn characters(&self) -> &[&'a Character] {
+        &self.characters
+    }
+
+    pub fn archetype(&self) -> &'a str {
+        &self.characters[0].archetype
+    }
+
+    /// Whether the personal offers of the character are requested.
+    pub fn is_personal(&self) -> bool {
+        self.personal
+    }
+}
+
+/// Public offers shared by the stores of a rotation.
+#[derive(Debug, Clone)]
+struct SharedOffers {
+    rotation_end: DateTime<Utc>,
+    offers: Cached<Vec<Offer>>,
+}
+
+/// Cached store of a character.
+#[derive(Debug, Clone)]
+struct CharacterStore {
+    /// The store, without its public offers if it shares them.
+    store: Cached<Store>,
+    shared: bool,
+    /// The store with the public offers it shares, built when first served.
+    /// Replacing the entry or the shared offers starts a new one.
+    merged: OnceLock<Cached<Store>>,
+}
+
+impl CharacterStore {
+    fn new(store: Cached<Store>, shared: bool) -> Self {
+        Self {
+            store,
+            shared,
+            merged: OnceLock::new(),
+        }
+    }
+}
+
+/// A cached store, with the public offers it may share with other stores.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct StoreView<'a> {
+    store: &'a Store,
+    public: &'a [Offer],
+}
+
+impl<'a> StoreView<'a> {
+    /// The store, without its public offers. Use [`StoreView::public`] for
+    /// those.
+    pub fn store(&self) -> &'a Store {
+        self.store
+    }
+
+    pub fn public(&self) -> &'a [Offer] {
+        self.public
+    }
+
+    pub fn personal(&self) -> &'a [Offer] {
+        &self.store.personal
+    }
+
+    /// The personal offers, then the public offers.
+    pub fn offers(&self) -> impl Iterator<Item = &'a Offer> {
+        self.store.personal.iter().chain(self.public)
+    }
+}
+
+/// Cached stores for one currency, keyed by character.
+#[derive(Debug, Default)]
+pub(crate) struct Stores {
+    shared: Option<SharedOffers>,
+    characters: HashMap<CharacterId, CharacterStore>,
+}
+
+impl Stores {
+    /// Cache the store of a character, sharing its public offers with the
+    /// other stores of the rotation if they are the same.
+    ///
+    /// Returns the cached store.
+    pub fn insert(&mut self, character_id: CharacterId, mut store: Store) -> Cached<Store> {
+        let shares = match &self.shared {
+            Some(shared) if shared.rotation_end == store.current_rotation_end => {
+                Cached::new(&store.public).json() == shared.offers.json()
+            }
+            Some(shared) if shared.rotation_end > store.current_rotation_end => false,
+            _ => {
+                self.share(
+                    Cached::new(store.public.clone()),
+                    store.current_rotation_end,
+                );
+                true
+            }
+        };
+        if !shares {
+            let cached = Cached::new(store);
+            self.characters
+                .insert(character_id, CharacterStore::new(cached.clone(), false));
+            return cached;
+        }
+        let public = std::mem::take(&mut store.public);
+        let entry = CharacterStore::new(Cached::new(store), true);
+        let mut merged = Store::clone(&entry.store);
+        merged.public = public;
+        let merged = entry.merged.get_or_init(|| Cached::new(merged)).clone();
+        self.characters.insert(character_id, entry);
+        merged
+    }
+
+    /// Cache `store` for every character of `request`.
+    pub fn insert_all(&mut self, request: &StoreRequest<'_>, store: &Store) {
+        for character in request.characters() {
+            self.insert(character.id, store.clone());
+        }
+    }
+
+    /// Share `offers` from now on, giving the stores that shared the previous
+    /// offers their own copy.
+    fn share(&mut self, offers: Cached<Vec<Offer>>, rotation_end: DateTime<Utc>) {
+        if let Some(previous) = self.shared.take() {
+            for entry in self.characters.values_mut().filter(|entry| entry.shared) {
+                let mut store = Store::clone(&entry.store);
+                store.public = previous.offers.to_vec();
+                *entry = CharacterStore::new(Cached::new(store), false);
+            }
+        }
+        self.shared = Some(SharedOffers {
+            rotation_end,
+            offers,
+        });
+    }
+
+    /// Get the store of a character, recording that it was served.
+    pub fn get(&self, character_id: &CharacterId) -> Option<Cached<Store>> {
+        let entry = self.characters.get(character_id)?;
+        entry.store.touch();
+        Some(self.merged(entry))
+    }
+
+    fn merged(&self, entry: &CharacterStore) -> Cached<Store> {
+        match &self.shared {
+            Some(shared) if entry.shared => entry
+                .merged
+                .get_or_init(|| {
+                    let mut store = Store::clone(&entry.store);
+                    store.public = shared.offers.to_vec();
+                    Cached::new(store)
+                })
+                .clone(),
+            _ => entry.store.clone(),
+        }
+    }
+
+    fn view<'a>(&'a self, entry: &'a CharacterStore) -> StoreView<'a> {
+        let public = match &self.shared {
+            Some(shared) if entry.shared => shared.offers.as_slice(),
+            _ => entry.store.public.as_slice(),
+        };
+        StoreView {
+            store: &entry.store,
+            public,
+        }
+    }
+
+    /// Get the store of a character without merging its offers.
+    pub fn view_of(&self, character_id: &CharacterId) -> Option<StoreView<'_>> {
+        let entry = self.characters.get(character_id)?;
+        Some(self.view(entry))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (CharacterId, StoreView<'_>)> {
+        self.characters
+            .iter()
+            .map(|(id, entry)| (*id, self.view(entry)))
+    }
+
+    /// The stores as cached, without the public offers they share, for
+    /// measuring and evicting them.
+    pub fn entries(&self) -> impl Iterator<Item = (CharacterId, &Cached<Store>)> {
+        self.characters
+            .iter()
+            .map(|(id, entry)| (*id, &entry.store))
+    }
+
+    pub fn contains_key(&self, character_id: &CharacterId) -> bool {
+        self.characters.contains_key(character_id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.characters.is_empty()
+    }
+
+    pub fn remove(&mut self, character_id: &CharacterId) {
+        self.characters.remove(character_id);
+        if !self.characters.values().any(|entry| entry.shared) {
+            self.shared = None;
+        }
+    }
+
+    /// Remove the store of a character if it is still `store`, returning
+    /// whether it was.
+    pub fn remove_if_cached(&mut self, character_id: &CharacterId, store: &Cached<Store>) -> bool {
+        let cached = self
+            .characters
+            .get(character_id)
+            .is_some_and(|entry| entry.store.ptr_eq(store));
+        if cached {
+            self.remove(character_id);
+        }
+        cached
+    }
+
+    /// Size of the JSON of the cached stores and the offers they share.
+    pub fn size(&self) -> usize {
+        let shared = self
+            .shared
+            .as_ref()
+            .map_or(0, |shared| shared.offers.size());
+        self.characters
+            .values()
+            .map(|entry| entry.store.size())
+            .sum::<usize>()
+            + shared
+    }
+
+    /// Cache the stores of `other` for the characters that have none.
+    pub fn fill_missing(&mut self, other: &Stores) {
+        for (id, entry) in &other.characters {
+            if self.contains_key(id) {
+                continue;
+            }
+            if entry.shared {
+                self.insert(*id, Store::clone(&other.merged(entry)));
+            } else {
+                // Keep the same cached store, and when it was last served.
+                self.characters.insert(*id, entry.clone());
+            }
+        }
+    }
+
+    /// The stores with their public offers.
+    pub fn to_map(&self) -> HashMap<CharacterId, Store> {
+        self.characters
+            .iter()
+            .map(|(id, entry)| (*id, Store::clone(&self.merged(entry))))
+            .collect()
+    }
+}
+
+impl FromIterator<(CharacterId, Store)> for Stores {
+    fn from_iter<I: IntoIterator<Item = (CharacterId, Store)>>(iter: I) -> Self {
+        let mut stores = Stores::default();
+        for (id, store) in iter {
+            stores.insert(id, store);
+        }
+        stores
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn fixture() -> Store {
+        serde_json::from_str(include_str!("../tests/fixtures/store.json")).unwrap()
+    }
+
+    fn character(n: u128) -> CharacterId {
+        CharacterId(Uuid::from_u128(n))
+    }
+
+    fn of_archetype(n: u128, archetype: &str) -> Character {
+        Character {
+            id: character(n),
+            name: format!("character {n}"),
+            gender: dt_api::models::Gender::Female,
+            archetype: archetype.to_string(),
+            specialization: format!("{archetype}_1"),
+            level: 30,
+        }
+    }
+
+    #[test]
+    fn plans_one_public_request_per_archetype() {
+        let characters = [
+            of_archetype(1, "veteran"),
+            of_archetype(2, "zealot"),
+            of_archetype(3, "veteran"),
+        ];
+        let ids = |request: &StoreRequest<'_>| {
+            request
+                .characters()
+                .iter()
+                .map(|character| character.id)
+                .collect::<Vec<_>>()
+        };
+
+        let personal = StoreRequest::plan(&characters, true);
+        assert_eq!(personal.len(), 3);
+        assert!(personal.iter().all(StoreRequest::is_personal));
+
+        let public = StoreRequest::plan(&characters, false);
+        assert_eq!(
+            public
+                .iter()
+                .map(|request| (request.archetype(), ids(request)))
+                .collect::<Vec<_>>(),
+            [
+                ("veteran", vec![character(1), character(3)]),
+                ("zealot", vec![character(2)]),
+            ]
+        );
+
+        let mut store = fixture();
+        store.personal.clear();
+        let mut stores = Stores::default();
+        stores.insert_all(&public[0], &store);
+        assert!(stores.contains_key(&character(1)) && stores.contains_key(&character(3)));
+        assert!(!stores.contains_key(&character(2)));
+        let without_public = Cached::new(Store {
+            public: Vec::new(),
+            ..store.clone()
+        });
+        assert_eq!(
+            stores.size(),
+            2 * without_public.size() + Cached::new(&store.public).size()
+        );
+    }
+
+    #[test]
+    fn shares_identical_public_offers() {
+        let store = fixture();
+        let mut personal = fixture();
+        personal.personal.pop();
+        let mut stores = Stores::default();
+        stores.insert(character(1), store.clone());
+        let single = stores.size();
+        stores.insert(character(2), personal.clone());
+
+        let public_size = Cached::new(&store.public).size();
+        assert!(stores.size() < 2 * single);
+        assert_eq!(
+            stores.size(),
+            Cached::new(Store {
+                public: Vec::new(),
+                ..store.clone()
+            })
+            .size()
+                + Cached::new(Store {
+                    public: Vec::new(),
+                    ..personal.clone()
+                })
+                .size()
+                + public_size
+        );
+        for (id, expected) in [(character(1), &store), (character(2), &personal)] {
+            let merged = stores.get(&id).unwrap();
+            assert_eq!(merged.json(), Cached::new(expected).json());
+            assert!(stores.get(&id).unwrap().ptr_eq(&merged));
+            let view = stores.view_of(&id).unwrap();
+            assert_eq!(view.public().len(), expected.public.len());
+            assert!(view.store().public.is_empty());
+        }
+
+        let served = stores.get(&character(2)).unwrap();
+        let mut updated = personal.clone();
+        updated.personal.pop();
+        stores.insert(character(2), updated.clone());
+        let merged = stores.get(&character(2)).unwrap();
+        assert!(!merged.ptr_eq(&served));
+        assert_eq!(merged.json(), Cached::new(&updated).json());
+
+        let served = stores.get(&character(1)).unwrap();
+        let mut rotated = store.clone();
+        rotated.current_rotation_end += chrono::Duration::hours(1);
+        stores.insert(character(3), rotated);
+        let unshared = stores.get(&character(1)).unwrap();
+        assert!(!unshared.ptr_eq(&served));
+        assert_eq!(unshared.json(), Cached::new(&store).json());
+    }
+
+    #[test]
+    fn keeps_differing_and_previous_rotations_whole() {
+        let store = fixture();
+        let mut differing = fixture();
+        differing.public.clear();
+        let mut rotated = fixture();
+        rotated.current_rotation_end += chrono::Duration::hours(1);
+        rotated.public.pop();
+
+        let mut stores = Stores::default();
+        stores.insert(character(1), store.clone());
+        stores.insert(character(2), differing.clone());
+        stores.insert(character(3), rotated.clone());
+
+        for (id, expected) in [
+            (character(1), &store),
+            (character(2), &differing),
+            (character(3), &rotated),
+        ] {
+            let merged = stores.get(&id).unwrap();
+            assert_eq!(merged.json(), Cached::new(expected).json());
+        }
+        assert_eq!(stores.to_map().len(), 3);
+
+        stores.remove(&character(3));
+        assert!(stores.shared.is_none());
+        assert_eq!(
+            stores.get(&character(1)).unwrap().json(),
+            Cached::new(&store).json()
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! Supervision of the long-running tasks.
+
+use std::{
+    backtrace::Backtrace,
+    collections::VecDeque,
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context, Result};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, warn};
+
+/// Failures of a restarting task within this window count towards its limit.
+const RESTART_WINDOW: Duration = Duration::from_secs(600);
+/// How long to wait before restarting a failed task.
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Log panics with a backtrace through tracing, so they end up wherever the
+/// rest of the logs go.
+pub(crate) fn install_panic_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        let backtrace = Backtrace::force_capture();
+        error!(panic = %info, %backtrace, "Panicked");
+    }));
+}
+
+/// Spawns tasks that shut down the others by cancelling the token when they
+/// fail or panic, instead of leaving the process running without them.
+#[derive(Debug, Clone)]
+pub(crate) struct Supervisor {
+    token: CancellationToken,
+    max_restarts: usize,
+}
+
+impl Supervisor {
+    /// Restarting tasks may fail `max_restarts` times within
+    /// [`RESTART_WINDOW`] before shutting down the others.
+    pub fn new(token: CancellationToken, max_restarts: usize) -> Self {
+        Self {
+            token,
+            max_restarts,
+        }
+    }
+
+    /// The token cancelled to shut down the tasks.
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    /// Run `task`, shutting down the other tasks if it fails or panics.
+    pub fn spawn<F>(&self, name: &'static str, task: F) -> JoinHandle<Result<()>>
+    where
+        F: Future<Output = Result<()>> + Send + 'static,
+    {
+        let token = self.token.clone();
+        tokio::spawn(async move {
+            let result = run(name, task).await;
+            if result.is_err() {
+                token.cancel();
+            }
+            result
+        })
+    }
+
+    /// Run the task made by `make`, making and running a new one whenever it
+    /// fails or panics, until it has failed too often.
+    pub fn spawn_restarting<M, F>(&self, name: &'static str, mut make: M) -> JoinHandle<Result<()>>
+    where
+        M: FnMut() -> F + Send + 'static,
+        F: Future<Output = Result<()>> + Send + 'static,
+    {
+        let token = self.token.clone();
+        let max_restarts = self.max_restarts;
+        tokio::spawn(async move {
+            let mut failures = VecDeque::new();
+            loop {
+                let result = run(name, make()).await;
+                if result.is_ok() || token.is_cancelled() {
+                    return result;
+                }
+                let now = Instant::now();
+                while failures
+                    .front()
+                    .is_some_and(|failed| now.duration_since(*failed) > RESTART_WINDOW)
+                {
+                    failures.pop_front();
+                }
+                if failures.len() >= max_restarts {
+                    error!(task = name, "Task failed too often; shutting down");
+                    token.cancel();
+                    return result;
+                }
+                failures.push_back(now);
+                warn!(
+                    task = name,
+                    restart = failures.len(),
+                    max_restarts,
+                    "Restarting task"
+                );
+                tokio::select! {
+                    _ = token.cancelled() => return result,
+                    _ = tokio::time::sleep(RESTART_DELAY) => {}
+                }
+            }
+        })
+    }
+}
+
+/// Run `task` in its own task, so that a panic is returned as an error.
+async fn run<F>(name: &'static str, task: F) -> Result<()>
+where
+    F: Future<Output = Result<()>> + Send + 'static,
+{
+    match tokio::spawn(task).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => {
+            error!(task = name, error = ?e, "Task failed");
+            Err(e)
+        }
+        // The panic hook has already logged the panic and its backtrace.
+        Err(e) if e.is_panic() => Err(anyhow!("{name} task panicked")),
+        Err(e) => Err(e).context(format!("{name} task was cancelled")),
+    }
+}
@@ -0,0 +1,131 @@
+//! Readiness and watchdog notifications for systemd, see `sd_notify(3)`.
+
+use std::net::SocketAddr;
+#[cfg(target_os = "linux")]
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+
+#[cfg(target_os = "linux")]
+use anyhow::Context;
+use anyhow::Result;
+use tokio_util::sync::CancellationToken;
+use tracing::instrument;
+#[cfg(target_os = "linux")]
+use tracing::{info, warn};
+
+use crate::auth::AuthData;
+
+/// How long the server and the auth manager may take to respond to a health check.
+#[cfg(target_os = "linux")]
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often to check whether the service is ready.
+#[cfg(target_os = "linux")]
+const READY_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tells systemd when the service is ready, pings its watchdog while the server
+/// and the auth manager respond, and tells it when the service is stopping.
+///
+/// Does nothing when not started by systemd with `Type=notify`.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub(crate) struct SystemdNotifier {
+    auth_data: AuthData,
+    listen_addr: SocketAddr,
+}
+
+impl SystemdNotifier {
+    pub fn new(auth_data: AuthData, listen_addr: SocketAddr) -> Self {
+        Self {
+            auth_data,
+            listen_addr,
+        }
+    }
+
+    #[instrument(skip_all)]
+    pub async fn start(self, token: CancellationToken) -> Result<()> {
+        #[cfg(target_os = "linux")]
+        if std::env::var_os("NOTIFY_SOCKET").is_some() {
+            return self.notify(token).await;
+        }
+        token.cancelled().await;
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn notify(self, token: CancellationToken) -> Result<()> {
+        use libsystemd::daemon::{self, NotifyState};
+
+        let watchdog = daemon::watchdog_enabled(false);
+
+        let mut poll = tokio::time::interval(READY_POLL_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => {
+                    daemon::notify(false, &[NotifyState::Stopping]).context("Failed to notify systemd")?;
+                    return Ok(());
+                }
+                _ = poll.tick() => {
+                    if self.check_health().await.is_ok() {
+                        break;
+                    }
+                }
+            }
+        }
+        daemon::notify(
+            false,
+            &[
+                NotifyState::Ready,
+                NotifyState::Status(format!("Listening on {}", self.listen_addr)),
+            ],
+        )
+        .context("Failed to notify systemd")?;
+        info!("Notified systemd that the service is ready");
+
+        let Some(watchdog) = watchdog else {
+            token.cancelled().await;
+            daemon::notify(false, &[NotifyState::Stopping]).context("Failed to notify systemd")?;
+            return Ok(());
+        };
+        info!(timeout = ?watchdog, "Pinging the systemd watchdog");
+        // Ping twice per timeout, as recommended by `sd_watchdog_enabled(3)`.
+        let mut ping = tokio::time::interval(watchdog / 2);
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => {
+                    daemon::notify(false, &[NotifyState::Stopping]).context("Failed to notify systemd")?;
+                    return Ok(());
+                }
+                _ = ping.tick() => match self.check_health().await {
+                    Ok(()) => {
+                        daemon::notify(false, &[NotifyState::Watchdog])
+                            .context("Failed to notify systemd")?;
+                    }
+                    // Let the watchdog expire so systemd restarts the service.
+                    Err(e) => warn!(error = ?e, "Health check failed; skipping watchdog ping"),
+                },
+            }
+        }
+    }
+
+    /// Check that the server accepts connections and that the auth manager
+    /// processes commands.
+    #[cfg(target_os = "linux")]
+    async fn check_health(&self) -> Result<()> {
+        let mut addr = self.listen_addr;
+        if addr.ip().is_unspecified() {
+            addr.set_ip(match addr {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        tokio::time::timeout(HEALTH_TIMEOUT, tokio::net::TcpStream::connect(addr))
+            .await
+            .context("Timed out connecting to server")?
+            .context("Failed to connect to server")?;
+        tokio::time::timeout(HEALTH_TIMEOUT, self.auth_data.ping())
+            .await
+            .context("Timed out pinging auth manager")?
+            .context("Failed to ping auth manager")
+    }
+}
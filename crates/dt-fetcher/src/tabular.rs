@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use dt_api::models::{CharacterId, CurrencyType, Offer, Store, Summary};
+use serde::{Deserialize, Serialize};
+
+use crate::account::AccountBundle;
+
+/// Delimited text formats for spreadsheet users.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Delimited {
+    Csv,
+    Tsv,
+}
+
+impl Delimited {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Delimited::Csv => "text/csv; charset=utf-8",
+            Delimited::Tsv => "text/tab-separated-values; charset=utf-8",
+        }
+    }
+
+    /// Write `rows` with a header row taken from their field names.
+    pub fn write<R: Serialize>(
+        self,
+        writer: impl std::io::Write,
+        rows: impl IntoIterator<Item = R>,
+    ) -> Result<()> {
+        let delimiter = match self {
+            Delimited::Csv => b',',
+            Delimited::Tsv => b'\t',
+        };
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(delimiter)
+            .from_writer(writer);
+        for row in rows {
+            writer.serialize(row).context("Failed to write row")?;
+        }
+        writer.flush().context("Failed to flush rows")
+    }
+
+    pub fn to_vec<R: Serialize>(self, rows: impl IntoIterator<Item = R>) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.write(&mut buf, rows)?;
+        Ok(buf)
+    }
+}
+
+/// An offer flattened into a single row.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OfferRow<'a> {
+    character_id: CharacterId,
+    name: &'a str,
+    category: &'a str,
+    rarity: Option<i32>,
+    item_level: Option<i32>,
+    price: i32,
+    currency_type: CurrencyType,
+    /// `id:rarity` pairs separated by `;`.
+    traits: String,
+    /// `id:rarity` pairs separated by `;`.
+    perks: String,
+    personal: bool,
+    expires: DateTime<Utc>,
+}
+
+impl<'a> OfferRow<'a> {
+    fn new(character_id: CharacterId, store: &Store, offer: &'a Offer, personal: bool) -> Self {
+        let item = offer.description.overrides.item();
+        let join = |pairs: Vec<String>| pairs.join(";");
+        Self {
+            character_id,
+            name: &offer.sku.name,
+            category: &offer.sku.category,
+            rarity: item.map(|item| item.rarity),
+            item_level: item.map(|item| item.item_level),
+            price: offer.price.amount.amount,
+            currency_type: offer.price.amount.amount_type,
+            traits: join(item.map_or_else(Vec::new, |item| {
+                item.traits
+                    .iter()
+                    .map(|t| format!("{}:{}", t.id, t.rarity))
+                    .collect()
+            })),
+            perks: join(item.map_or_else(Vec::new, |item| {
+                item.perks
+                    .iter()
+                    .map(|p| format!("{}:{}", p.id, p.rarity))
+                    .collect()
+            })),
+            personal,
+            expires: store.current_rotation_end,
+        }
+    }
+}
+
+/// Flatten the personal and public offers of a store.
+pub(crate) fn store_rows(
+    character_id: CharacterId,
+    store: &Store,
+) -> impl Iterator<Item = OfferRow<'_>> {
+    let personal = store
+        .personal
+        .iter()
+        .map(move |offer| OfferRow::new(character_id, store, offer, true));
+    let public = store
+        .public
+        .iter()
+        .map(move |offer| OfferRow::new(character_id, store, offer, false));
+    personal.chain(public)
+}
+
+/// Flatten every cached store in a bundle.
+pub(crate) fn bundle_rows(bundle: &AccountBundle) -> impl Iterator<Item = OfferRow<'_>> {
+    sorted(&bundle.marks_store)
+        .into_iter()
+        .chain(sorted(&bundle.credits_store))
+        .flat_map(|(character_id, store)| store_rows(*character_id, store))
+}
+
+fn sorted(stores: &HashMap<CharacterId, Store>) -> Vec<(&CharacterId, &Store)> {
+    let mut stores: Vec<_> = stores.iter().collect();
+    stores.sort_by_key(|(character_id, _)| character_id.0);
+    stores
+}
+
+/// A character flattened into a single row.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CharacterRow<'a> {
+    id: CharacterId,
+    name: &'a str,
+    archetype: &'a str,
+    specialization: &'a str,
+    level: u32,
+}
+
+/// Flatten the characters of a summary.
+pub(crate) fn summary_rows(summary: &Summary) -> impl Iterator<Item = CharacterRow<'_>> {
+    summary.characters.iter().map(|c| CharacterRow {
+        id: c.id,
+        name: &c.name,
+        archetype: &c.archetype,
+        specialization: &c.specialization,
+        level: c.level,
+    })
+}
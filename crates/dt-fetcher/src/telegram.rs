@@ -0,0 +1,357 @@
+//! Sends events as messages from a Telegram bot.
+
+use std::{fmt::Write, sync::Arc, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use futures::future::BoxFuture;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Mutex, time::Instant};
+
+use crate::{
+    config::Secret,
+    notify::{Event, Notifier, RotatedStore},
+    present,
+    slo::Source,
+    watchlist::WatchMatch,
+};
+
+const API_URL: &str = "https://api.telegram.org";
+/// Shortest time between messages. Telegram allows about one message per
+/// second in a chat, and 20 per minute in a group.
+const MIN_INTERVAL: Duration = Duration::from_secs(3);
+/// Longest `retry_after` of a rate-limited message that is waited for before
+/// sending it again.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+/// Longest sending a message may take.
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest message Telegram accepts, in characters.
+const MAX_MESSAGE_CHARS: usize = 4096;
+
+/// The bot and chat events are sent with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TelegramConfig {
+    /// Token of the bot, from `@BotFather`.
+    pub bot_token: Secret,
+    pub chat_id: ChatId,
+}
+
+/// A chat by its numeric id, or a channel by its `@username`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum ChatId {
+    Id(i64),
+    Username(String),
+}
+
+/// When the last message was sent, shared by the Telegram notifiers so that
+/// together they stay within the rate limits of the bot API.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TelegramLimiter(Arc<Mutex<Option<Instant>>>);
+
+/// Sends events as HTML messages to a Telegram chat.
+#[derive(Debug)]
+pub(crate) struct TelegramNotifier {
+    client: reqwest::Client,
+    config: TelegramConfig,
+    limiter: TelegramLimiter,
+}
+
+#[derive(Debug, Serialize)]
+struct SendMessage<'a> {
+    chat_id: &'a ChatId,
+    text: &'a str,
+    parse_mode: &'static str,
+    disable_web_page_preview: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ApiError {
+    description: Option<String>,
+    #[serde(default)]
+    parameters: ResponseParameters,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ResponseParameters {
+    retry_after: Option<u64>,
+}
+
+impl TelegramNotifier {
+    pub fn new(client: reqwest::Client, config: TelegramConfig, limiter: TelegramLimiter) -> Self {
+        Self {
+            client,
+            config,
+            limiter,
+        }
+    }
+
+    /// Send a message once the rate limit allows, retrying once if Telegram
+    /// still asks to wait.
+    async fn send(&self, text: &str) -> Result<()> {
+        let url = format!(
+            "{API_URL}/bot{}/sendMessage",
+            self.config.bot_token.expose()
+        );
+        let body = serde_json::to_vec(&SendMessage {
+            chat_id: &self.config.chat_id,
+            text,
+            parse_mode: "HTML",
+            disable_web_page_preview: true,
+        })
+        .context("Failed to serialize message")?;
+        let mut last_sent = self.limiter.0.lock().await;
+        let mut retried = false;
+        loop {
+            if let Some(last_sent) = *last_sent {
+                tokio::time::sleep_until(last_sent + MIN_INTERVAL).await;
+            }
+            let response = self
+                .client
+                .post(&url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone())
+                .timeout(SEND_TIMEOUT)
+                .send()
+                .await
+                // The URL holds the bot token.
+                .map_err(|e| e.without_url())
+                .context("Failed to send Telegram message")?;
+            *last_sent = Some(Instant::now());
+            let status = response.status();
+            if status.is_success() {
+                return Ok(());
+            }
+            let error: ApiError =
+                serde_json::from_slice(&response.bytes().await.unwrap_or_default())
+                    .unwrap_or_default();
+            let retry_after = error.parameters.retry_after.map(Duration::from_secs);
+            match retry_after {
+                Some(wait) if status == StatusCode::TOO_MANY_REQUESTS && !retried => {
+                    if wait > MAX_RETRY_AFTER {
+                        bail!("Telegram rate limited messages for {wait:?}");
+                    }
+                    tokio::time::sleep(wait).await;
+                    retried = true;
+                }
+                _ => bail!(
+                    "Telegram rejected message with {status}: {}",
+                    error.description.unwrap_or_default()
+                ),
+            }
+        }
+    }
+}
+
+impl Notifier for TelegramNotifier {
+    fn notify<'a>(&'a self, event: &'a Event) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move { self.send(&message(event)).await })
+    }
+}
+
+/// The HTML message of an event.
+fn message(event: &Event) -> String {
+    let lines = match event {
+        Event::StoresRotated { rotations, .. } => rotations_message(rotations),
+        Event::WatchMatched(watch_match) => watch_match_message(watch_match),
+        Event::NeedsReauth { account_id } => vec![
+            "<b>Account needs a new auth</b>".to_string(),
+            format!("Provide one with <code>PUT /auth/{}</code>.", account_id.0),
+        ],
+        Event::Digest(digest) => std::iter::once("<b>Digest</b>".to_string())
+            .chain(digest.body.lines().map(escape))
+            .collect(),
+        Event::CharactersChanged(changes) => {
+            let names = |characters: &[dt_api::models::Character]| {
+                characters
+                    .iter()
+                    .map(|c| escape(&c.name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            let mut lines = vec!["<b>Characters changed</b>".to_string()];
+            if !changes.added.is_empty() {
+                lines.push(format!("Created: {}", names(&changes.added)));
+            }
+            if !changes.removed.is_empty() {
+                lines.push(format!("Deleted: {}", names(&changes.removed)));
+            }
+            lines
+        }
+        Event::ErrorBudgetExceeded {
+            source,
+            error_rate,
+            threshold,
+        } => vec![
+            "<b>Error budget exceeded</b>".to_string(),
+            format!(
+                "{}: {:.1}% errors, threshold {:.1}%",
+                source_name(*source),
+                error_rate * 100.0,
+                threshold * 100.0
+            ),
+        ],
+        Event::ErrorBudgetRecovered { source, error_rate } => vec![
+            "<b>Error budget recovered</b>".to_string(),
+            format!(
+                "{}: {:.1}% errors",
+                source_name(*source),
+                error_rate * 100.0
+            ),
+        ],
+    };
+    join_within_limit(lines)
+}
+
+fn rotations_message(rotations: &[RotatedStore]) -> Vec<String> {
+    let mut lines = vec!["<b>New store rotation</b>".to_string()];
+    for rotation in rotations {
+        lines.push(String::new());
+        lines.push(format!(
+            "<b>{}</b>, {} until {} UTC",
+            escape(&rotation.character_name),
+            rotation.currency_type,
+            rotation.rotation_end.format("%Y-%m-%d %H:%M")
+        ));
+        lines.extend(
+            rotation
+                .new_offers
+                .iter()
+                .map(|name| format!("• {}", escape(name))),
+        );
+    }
+    lines
+}
+
+fn watch_match_message(watch_match: &WatchMatch) -> Vec<String> {
+    let offer = &watch_match.offer.offer;
+    let mut title = "<b>Watch matched</b>".to_string();
+    if let Some(name) = &watch_match.watch_name {
+        let _ = write!(title, ": {}", escape(name));
+    }
+    let mut lines = vec![title, format!("<b>{}</b>", escape(&offer.sku.name))];
+    if let Some(item) = offer.description.overrides.item() {
+        lines.push(format!(
+            "{} {}, item level {}",
+            present::rarity_name(item.rarity).unwrap_or("Unknown"),
+            escape(&offer.sku.category),
+            item.item_level
+        ));
+        if !item.traits.is_empty() {
+            let traits = item.traits.iter().map(|t| (t.id.as_str(), t.rarity));
+            lines.push(format!(
+                "Blessings: {}",
+                escape(&present::summarize(traits))
+            ));
+        }
+        if !item.perks.is_empty() {
+            let perks = item.perks.iter().map(|p| (p.id.as_str(), p.rarity));
+            lines.push(format!("Perks: {}", escape(&present::summarize(perks))));
+        }
+    }
+    let character = watch_match
+        .offer
+        .character_name
+        .as_deref()
+        .map(escape)
+        .unwrap_or_else(|| watch_match.offer.character_id.0.to_string());
+    lines.push(format!(
+        "{} {} in the store of {character}",
+        offer.price.amount.formatted(),
+        watch_match.offer.currency_type
+    ));
+    lines
+}
+
+fn source_name(source: Source) -> &'static str {
+    match source {
+        Source::Upstream => "Upstream calls",
+        Source::Handler => "Server responses",
+    }
+}
+
+/// Escape text for HTML messages.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Join lines into a message, leaving out whole lines beyond the length
+/// Telegram accepts so that no tag is cut off.
+fn join_within_limit(lines: Vec<String>) -> String {
+    const ELLIPSIS: &str = "\n…";
+    let mut message = String::new();
+    let mut chars = 0;
+    for line in lines {
+        let line_chars = line.chars().count() + 1;
+        if chars + line_chars + ELLIPSIS.chars().count() > MAX_MESSAGE_CHARS {
+            message.push_str(ELLIPSIS);
+            break;
+        }
+        if !message.is_empty() {
+            message.push('\n');
+        }
+        message.push_str(&line);
+        chars += line_chars;
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use dt_api::models::{AccountId, CharacterId, CurrencyType, Store};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{account::OfferMatch, watchlist::WatchId};
+
+    #[test]
+    fn formats_rotations_and_matches() {
+        let rotated = Event::StoresRotated {
+            account_id: AccountId(Uuid::nil()),
+            rotations: vec![RotatedStore {
+                character_id: CharacterId(Uuid::nil()),
+                character_name: "Ash <3".to_string(),
+                currency_type: CurrencyType::Marks,
+                rotation_end: Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap(),
+                new_offers: vec!["Lasgun".to_string(), "Chainsword".to_string()],
+            }],
+        };
+        assert_eq!(
+            message(&rotated),
+            "<b>New store rotation</b>\n\n\
+             <b>Ash &lt;3</b>, marks until 2024-01-01 12:00 UTC\n\
+             • Lasgun\n\
+             • Chainsword"
+        );
+
+        let store: Store =
+            serde_json::from_str(include_str!("../tests/fixtures/store.json")).unwrap();
+        let offer = store.offers().next().unwrap().clone();
+        let matched = Event::WatchMatched(Box::new(WatchMatch {
+            watch_id: WatchId(Uuid::nil()),
+            watch_name: Some("Good rolls".to_string()),
+            push_priority: None,
+            offer: OfferMatch {
+                account_id: AccountId(Uuid::nil()),
+                character_id: CharacterId(Uuid::nil()),
+                character_name: Some("Ash".to_string()),
+                currency_type: CurrencyType::Marks,
+                offer: offer.clone(),
+            },
+        }));
+        let text = message(&matched);
+        assert!(text.starts_with("<b>Watch matched</b>: Good rolls\n"));
+        assert!(text.contains(&format!("<b>{}</b>", escape(&offer.sku.name))));
+        assert!(text.ends_with(&format!(
+            "{} marks in the store of Ash",
+            offer.price.amount.formatted()
+        )));
+
+        let long = join_within_limit(vec!["x".repeat(3000), "y".repeat(3000)]);
+        assert_eq!(long, format!("{}\n…", "x".repeat(3000)));
+    }
+}
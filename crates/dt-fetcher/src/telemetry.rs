@@ -0,0 +1,28 @@
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Context, Result};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use tracing::instrument;
+
+static PROMETHEUS_HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Install the global Prometheus metrics recorder.
+pub(crate) fn install() -> Result<()> {
+    let handle = PrometheusBuilder::new()
+        .install_recorder()
+        .context("Failed to install metrics recorder")?;
+    PROMETHEUS_HANDLE
+        .set(handle)
+        .map_err(|_| anyhow!("Metrics recorder already installed"))
+}
+
+/// The metrics in the text exposition format, or `None` if the recorder isn't
+/// installed.
+pub(crate) fn render() -> Option<String> {
+    PROMETHEUS_HANDLE.get().map(PrometheusHandle::render)
+}
+
+#[instrument]
+pub(crate) async fn metrics() -> String {
+    render().unwrap_or_default()
+}
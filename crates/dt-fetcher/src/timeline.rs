@@ -0,0 +1,140 @@
+//! Recent events of each account, for finding out why its cached data is
+//! stale without reading the server logs.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use dt_api::models::{AccountId, CharacterId, CurrencyType};
+use serde::Serialize;
+
+use crate::watchlist::WatchId;
+
+/// Events kept per account; the oldest are dropped beyond this.
+const CAPACITY: usize = 100;
+
+/// What failed to refresh.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum Resource {
+    Auth,
+    Summary,
+    Store,
+}
+
+/// Something that happened to the cached data of an account.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub(crate) enum TimelineEvent {
+    AuthRefreshed,
+    SummaryRefreshed,
+    /// A store of a new rotation was cached.
+    #[serde(rename_all = "camelCase")]
+    StoreRotated {
+        character_id: CharacterId,
+        currency_type: CurrencyType,
+        rotation_end: DateTime<Utc>,
+    },
+    RefreshFailed {
+        resource: Resource,
+        error: String,
+    },
+    /// An offer of a new rotation matched a watch.
+    #[serde(rename_all = "camelCase")]
+    WatchMatched {
+        watch_id: WatchId,
+        character_id: CharacterId,
+        offer: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct TimelineEntry {
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: TimelineEvent,
+}
+
+/// The last [`CAPACITY`] events of every account, kept in memory.
+///
+/// Clones share the events.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Timeline {
+    accounts: Arc<Mutex<HashMap<AccountId, VecDeque<TimelineEntry>>>>,
+}
+
+impl Timeline {
+    pub fn record(&self, account_id: AccountId, event: TimelineEvent) {
+        let mut accounts = self.lock();
+        let entries = accounts.entry(account_id).or_default();
+        if entries.len() == CAPACITY {
+            entries.pop_front();
+        }
+        entries.push_back(TimelineEntry {
+            at: Utc::now(),
+            event,
+        });
+    }
+
+    /// Record that refreshing `resource` failed with `error`.
+    pub fn record_failure(
+        &self,
+        account_id: AccountId,
+        resource: Resource,
+        error: &impl std::fmt::Display,
+    ) {
+        self.record(
+            account_id,
+            TimelineEvent::RefreshFailed {
+                resource,
+                error: error.to_string(),
+            },
+        );
+    }
+
+    /// The recent events of an account, newest first.
+    pub fn recent(&self, account_id: AccountId) -> Vec<TimelineEntry> {
+        self.lock()
+            .get(&account_id)
+            .map(|entries| entries.iter().rev().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<AccountId, VecDeque<TimelineEntry>>> {
+        self.accounts.lock().expect("timeline lock is not poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn keeps_the_newest_events_first() {
+        let timeline = Timeline::default();
+        let account = AccountId(Uuid::from_u128(1));
+        timeline.record(account, TimelineEvent::AuthRefreshed);
+        for _ in 0..CAPACITY {
+            timeline.record(account, TimelineEvent::SummaryRefreshed);
+        }
+        timeline.record_failure(account, Resource::Store, &"upstream down");
+
+        let recent = timeline.recent(account);
+        assert_eq!(recent.len(), CAPACITY);
+        assert_eq!(
+            recent[0].event,
+            TimelineEvent::RefreshFailed {
+                resource: Resource::Store,
+                error: "upstream down".to_string(),
+            }
+        );
+        assert!(recent
+            .iter()
+            .all(|entry| entry.event != TimelineEvent::AuthRefreshed));
+        assert!(timeline.recent(AccountId(Uuid::new_v4())).is_empty());
+    }
+}
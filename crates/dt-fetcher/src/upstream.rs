@@ -0,0 +1,623 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use dt_api::{
+    models::{
+        Character, Characters, CurrencyType, Inventory, ItemCatalog, LeaderboardEntry, MasterData,
+        Store, Summary, Wallets,
+    },
+    Auth, Endpoint, RawResponse,
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, instrument, warn};
+
+#[cfg(feature = "chaos")]
+use crate::chaos::Chaos;
+use crate::{
+    coordination::Coordinator,
+    history::History,
+    request_queue::{Priority, RequestQueue},
+    slo::{Slo, Source},
+    stores::StoreRequest,
+    timeline::Timeline,
+};
+
+/// Backoff after a `429 Too Many Requests` without a `Retry-After` header.
+const DEFAULT_RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(60);
+/// Longest backoff honoured from a `Retry-After` header, so that a bogus one
+/// can't stop fetching for long.
+const MAX_RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(900);
+/// Calls made while rate limited for at most this long wait the rate limit out
+/// instead of failing.
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(5);
+
+/// Settings of the upstream client, applied at startup.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct UpstreamConfig {
+    /// `User-Agent` of upstream requests; none is sent if `None`.
+    pub user_agent: Option<String>,
+    /// Headers sent with every upstream request, such as platform headers.
+    pub headers: BTreeMap<String, String>,
+    /// Seconds idle connections to the upstream are kept open.
+    pub pool_idle_timeout_secs: Option<u64>,
+    /// Maximum number of idle connections kept to the upstream.
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Seconds between HTTP/2 keepalive pings to the upstream.
+    pub http2_keep_alive_interval_secs: Option<u64>,
+    /// Consecutive connect errors after which the client is rebuilt with new
+    /// connections; never rebuilt for errors if `None`.
+    pub recycle_after_connect_errors: Option<u32>,
+    /// Seconds between rebuilds of the client; never rebuilt periodically if
+    /// `None`.
+    pub rebuild_interval_secs: Option<u64>,
+    /// Most pages fetched of a paginated resource such as a leaderboard;
+    /// [`dt_api::DEFAULT_MAX_PAGES`] if `None`.
+    pub max_pages: Option<usize>,
+}
+
+impl UpstreamConfig {
+    /// Build the upstream API client, logging how it identifies itself.
+    pub fn api(&self) -> Result<dt_api::Api> {
+        info!(
+            user_agent = self.user_agent.as_deref().unwrap_or("none"),
+            headers = ?self.headers,
+            "Identifying to upstream"
+        );
+        info!(
+            pool_idle_timeout_secs = ?self.pool_idle_timeout_secs,
+            pool_max_idle_per_host = ?self.pool_max_idle_per_host,
+            http2_keep_alive_interval_secs = ?self.http2_keep_alive_interval_secs,
+            "Tuning upstream connections"
+        );
+        let builder = self
+            .headers
+            .iter()
+            .fold(dt_api::Api::builder(), |builder, (name, value)| {
+                builder.header(name, value)
+            });
+        let builder = match &self.user_agent {
+            Some(user_agent) => builder.user_agent(user_agent),
+            None => builder,
+        };
+        let builder = match self.pool_idle_timeout_secs {
+            Some(secs) => builder.pool_idle_timeout(Duration::from_secs(secs)),
+            None => builder,
+        };
+        let builder = match self.pool_max_idle_per_host {
+            Some(max) => builder.pool_max_idle_per_host(max),
+            None => builder,
+        };
+        let builder = match self.http2_keep_alive_interval_secs {
+            Some(secs) => builder.http2_keep_alive_interval(Duration::from_secs(secs)),
+            None => builder,
+        };
+        let api = builder.build().context("Failed to build upstream client")?;
+        Ok(match self.max_pages {
+            Some(max) => api.with_max_pages(max),
+            None => api,
+        })
+    }
+}
+
+/// Health of the connections to the upstream.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ConnectionState {
+    /// Connect errors since the last successful call or rebuild.
+    pub consecutive_connect_errors: u32,
+    /// Times the client was rebuilt.
+    pub rebuilds: u64,
+    /// When the client was last rebuilt.
+    pub last_rebuilt: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+struct Connection {
+    api: dt_api::Api,
+    state: ConnectionState,
+    /// When the game backend stops rate limiting requests.
+    rate_limited_until: Option<Instant>,
+}
+
+impl Connection {
+    /// Replace the client with one with new connections, so the upstream host
+    /// is resolved again. The current client is kept if that fails.
+    fn rebuild(&mut self, reason: &str) {
+        match self.api.rebuild() {
+            Ok(api) => {
+                info!(reason, "Rebuilt upstream client");
+                self.api = api;
+                self.state.consecutive_connect_errors = 0;
+                self.state.rebuilds += 1;
+                self.state.last_rebuilt = Some(Utc::now());
+                metrics::counter!("dt_fetcher_upstream_rebuilds_total", "reason" => reason.to_string())
+                    .increment(1);
+            }
+            Err(e) => warn!(error = ?e, reason, "Failed to rebuild upstream client"),
+        }
+    }
+}
+
+/// Upstream calls, for injecting faults into them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum UpstreamCall {
+    Summary,
+    Characters,
+    Store,
+    Inventory,
+    Wallets,
+    Leaderboard,
+    MasterData,
+    ItemCatalog,
+    /// Raw responses, for schema drift checks and admins bypassing the cache.
+    Raw,
+    RefreshAuth,
+}
+
+/// Client for the upstream API, applying the shared rate limit to every request
+/// and archiving every fetched store.
+///
+/// Calls wait for the rate limit in the order of the [`Priority`] of the client
+/// making them, which is `Interactive` unless set with
+/// [`Upstream::with_priority`].
+///
+/// Once the game backend responds with `429 Too Many Requests`, calls to it
+/// fail without being made until its `Retry-After` has passed, or wait if it
+/// passes within [`MAX_RATE_LIMIT_WAIT`].
+///
+/// The client is rebuilt after `recycle_after_connect_errors` consecutive
+/// connect errors, as pooled connections keep failing when the upstream moves
+/// to new addresses.
+#[derive(Debug, Clone)]
+pub(crate) struct Upstream {
+    connection: Arc<Mutex<Connection>>,
+    recycle_after_connect_errors: Option<u32>,
+    coordinator: Coordinator,
+    queue: RequestQueue,
+    priority: Priority,
+    history: History,
+    slo: Slo,
+    timeline: Timeline,
+    #[cfg(feature = "chaos")]
+    chaos: Chaos,
+}
+
+impl Upstream {
+    pub fn new(api: dt_api::Api, coordinator: Coordinator, history: History) -> Self {
+        Self {
+            connection: Arc::new(Mutex::new(Connection {
+                api,
+                state: ConnectionState::default(),
+                rate_limited_until: None,
+            })),
+            recycle_after_connect_errors: None,
+            coordinator,
+            queue: RequestQueue::default(),
+            priority: Priority::default(),
+            history,
+            slo: Slo::default(),
+            timeline: Timeline::default(),
+            #[cfg(feature = "chaos")]
+            chaos: Chaos::default(),
+        }
+    }
+
+    /// Rebuild the client after `errors` consecutive connect errors.
+    pub fn with_recycling(self, errors: Option<u32>) -> Self {
+        Self {
+            recycle_after_connect_errors: errors,
+            ..self
+        }
+    }
+
+    /// A client sharing this one, whose calls wait for the rate limit with
+    /// `priority`.
+    pub fn with_priority(&self, priority: Priority) -> Self {
+        Self {
+            priority,
+            ..self.clone()
+        }
+    }
+
+    /// Health of the connections to the upstream.
+    pub fn connection_state(&self) -> ConnectionState {
+        self.lock().state.clone()
+    }
+
+    /// Rebuild the client every `interval`, if set, until `token` is
+    /// cancelled.
+    #[instrument(skip_all)]
+    pub async fn rebuild_periodically(
+        self,
+        interval: Option<Duration>,
+        token: CancellationToken,
+    ) -> Result<()> {
+        let Some(interval) = interval else {
+            token.cancelled().await;
+            return Ok(());
+        };
+        let mut interval = tokio::time::interval_at(Instant::now() + interval, interval);
+        loop {
+            tokio::select! {
+                biased;
+                _ = token.cancelled() => return Ok(()),
+                _ = interval.tick() => self.lock().rebuild("interval"),
+            }
+        }
+    }
+
+    fn api(&self) -> dt_api::Api {
+        self.lock().api.clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.connection
+            .lock()
+            .expect("upstream connection lock is not poisoned")
+    }
+
+    /// Faults injected into the upstream calls.
+    #[cfg(feature = "chaos")]
+    pub fn chaos(&self) -> &Chaos {
+        &self.chaos
+    }
+
+    /// Recent events of each account.
+    pub fn timeline(&self) -> &Timeline {
+        &self.timeline
+    }
+
+    /// Success rates of the upstream calls.
+    pub fn slo(&self) -> &Slo {
+        &self.slo
+    }
+
+    /// Time until the game backend stops rate limiting requests, if it is.
+    pub fn rate_limited_for(&self) -> Option<Duration> {
+        let until = self.lock().rate_limited_until?;
+        let remaining = until.saturating_duration_since(Instant::now());
+        (!remaining.is_zero()).then_some(remaining)
+    }
+
+    /// Make an upstream call once the rate limits allow, recording its
+    /// outcome.
+    async fn call<T>(
+        &self,
+        call: UpstreamCall,
+        request: impl Future<Output = dt_api::Result<T>>,
+    ) -> dt_api::Result<T> {
+        // Auths are refreshed by another host, with its own rate limit.
+        let backend = call != UpstreamCall::RefreshAuth;
+        if backend {
+            self.wait_for_rate_limit(call).await?;
+        }
+        self.permit().await;
+        #[cfg(feature = "chaos")]
+        if let Some(e) = self.chaos.inject(call).await {
+            return self.record(Err(e));
+        }
+        let result = request.await;
+        if let (true, Err(e @ dt_api::Error::RateLimited { .. })) = (backend, &result) {
+            self.back_off(e.retry_after());
+        }
+        self.record(result)
+    }
+
+    /// Wait out a rate limit of the game backend that ends soon, or fail
+    /// without making the call if it doesn't.
+    async fn wait_for_rate_limit(&self, call: UpstreamCall) -> dt_api::Result<()> {
+        let Some(remaining) = self.rate_limited_for() else {
+            return Ok(());
+        };
+        if remaining > MAX_RATE_LIMIT_WAIT {
+            return Err(dt_api::Error::RateLimited {
+                endpoint: format!("{call:?}"),
+                retry_after: Some(remaining),
+            });
+        }
+        tokio::time::sleep(remaining).await;
+        Ok(())
+    }
+
+    /// Stop calling the game backend for `retry_after`, or a default backoff
+    /// if it didn't say.
+    fn back_off(&self, retry_after: Option<Duration>) {
+        let backoff = retry_after
+            .unwrap_or(DEFAULT_RATE_LIMIT_BACKOFF)
+            .min(MAX_RATE_LIMIT_BACKOFF);
+        warn!(backoff = ?backoff, "Upstream rate limited requests; backing off");
+        metrics::counter!("dt_fetcher_upstream_rate_limited_total").increment(1);
+        let until = Instant::now() + backoff;
+        let mut connection = self.lock();
+        connection.rate_limited_until = Some(
+            connection
+                .rate_limited_until
+                .map_or(until, |current| current.max(until)),
+        );
+    }
+
+    /// Record the outcome of an upstream call, rebuilding the client once
+    /// connecting failed too many times in a row.
+    fn record<T>(&self, result: dt_api::Result<T>) -> dt_api::Result<T> {
+        self.slo.record(Source::Upstream, result.is_ok());
+        let mut connection = self.lock();
+        match &result {
+            Err(e) if e.is_connect() => {
+                connection.state.consecutive_connect_errors += 1;
+                if self
+                    .recycle_after_connect_errors
+                    .is_some_and(|max| connection.state.consecutive_connect_errors >= max)
+                {
+                    connection.rebuild("connectErrors");
+                }
+            }
+            Ok(_) => connection.state.consecutive_connect_errors = 0,
+            Err(_) => {}
+        }
+        result
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    pub fn coordinator(&self) -> &Coordinator {
+        &self.coordinator
+    }
+
+    async fn permit(&self) {
+        let _turn = self.queue.turn(self.priority).await;
+        if let Err(e) = self.coordinator.permit().await {
+            warn!(error = %e, "Failed to apply upstream rate limit");
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_summary(&self, auth: &Auth) -> dt_api::Result<Summary> {
+        self.call(UpstreamCall::Summary, self.api().get_summary(auth))
+            .await
+    }
+
+    /// Get the characters of an account, following the `characters` link of
+    /// `summary` if it has one.
+    #[instrument(skip(self, summary))]
+    pub async fn get_characters(
+        &self,
+        auth: &Auth,
+        summary: Option<&Summary>,
+    ) -> dt_api::Result<Characters> {
+        self.call(
+            UpstreamCall::Characters,
+            self.api().get_characters(auth, summary),
+        )
+        .await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_store(
+        &self,
+        auth: &Auth,
+        currency_type: CurrencyType,
+        character: &Character,
+    ) -> dt_api::Result<Store> {
+        let store = self
+            .call(
+                UpstreamCall::Store,
+                self.api().get_store(auth, currency_type, character),
+            )
+            .await?;
+        self.check_store(auth, currency_type, &[character], &store);
+        Ok(store)
+    }
+
+    /// Get the store of the characters of `request`, without personal offers
+    /// unless it asks for them.
+    #[instrument(skip_all, fields(archetype = request.archetype(), currency_type = %currency_type))]
+    pub async fn get_requested_store(
+        &self,
+        auth: &Auth,
+        currency_type: CurrencyType,
+        request: &StoreRequest<'_>,
+    ) -> dt_api::Result<Store> {
+        if request.is_personal() {
+            return self
+                .get_store(auth, currency_type, request.characters()[0])
+                .await;
+        }
+        let store = self
+            .call(
+                UpstreamCall::Store,
+                self.api()
+                    .get_public_store(auth, currency_type, request.archetype()),
+            )
+            .await?;
+        self.check_store(auth, currency_type, request.characters(), &store);
+        Ok(store)
+    }
+
+    /// Report an inconsistent rotation and record the store in the history
+    /// of each character.
+    fn check_store(
+        &self,
+        auth: &Auth,
+        currency_type: CurrencyType,
+        characters: &[&Character],
+        store: &Store,
+    ) {
+        if let Some(issue) = store.rotation_issue() {
+            warn!(
+                archetype = %characters[0].archetype,
+                currency_type = %currency_type,
+                valid_from = %store.catalog.valid_from,
+                valid_to = %store.catalog.valid_to,
+                current_rotation_end = %store.current_rotation_end,
+                "Inconsistent store rotation: {}", issue
+            );
+            metrics::counter!("dt_fetcher_store_rotation_issues_total", "issue" => issue.as_str())
+                .increment(1);
+        }
+        for character in characters {
+            self.history
+                .record(auth.sub, character.id, currency_type, store);
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_inventory(
+        &self,
+        auth: &Auth,
+        character: &Character,
+    ) -> dt_api::Result<Inventory> {
+        self.call(
+            UpstreamCall::Inventory,
+            self.api().get_inventory(auth, character),
+        )
+        .await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_wallets(&self, auth: &Auth) -> dt_api::Result<Wallets> {
+        self.call(UpstreamCall::Wallets, self.api().get_wallets(auth))
+            .await
+    }
+
+    /// Get every entry of a leaderboard, rate limiting each page.
+    #[instrument(skip(self))]
+    pub async fn get_leaderboard(
+        &self,
+        auth: &Auth,
+        board: &str,
+    ) -> dt_api::Result<Vec<LeaderboardEntry>> {
+        let mut entries = Vec::new();
+        let mut continuation_token = None;
+        loop {
+            let page = self
+                .call(
+                    UpstreamCall::Leaderboard,
+                    self.api()
+                        .get_leaderboard_page(auth, board, continuation_token.as_deref()),
+                )
+                .await?;
+            entries.extend(page.items);
+            continuation_token = page.continuation_token;
+            if continuation_token.is_none() {
+                return Ok(entries);
+            }
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_master_data(&self, auth: &Auth) -> dt_api::Result<MasterData> {
+        self.call(UpstreamCall::MasterData, self.api().get_master_data(auth))
+            .await
+    }
+
+    #[instrument(skip(self, master_data))]
+    pub async fn get_item_catalog(
+        &self,
+        auth: &Auth,
+        master_data: &MasterData,
+    ) -> dt_api::Result<ItemCatalog> {
+        self.call(
+            UpstreamCall::ItemCatalog,
+            self.api().get_item_catalog(auth, master_data),
+        )
+        .await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_raw(
+        &self,
+        auth: &Auth,
+        endpoint: Endpoint<'_>,
+    ) -> dt_api::Result<serde_json::Value> {
+        self.call(UpstreamCall::Raw, self.api().get_raw(auth, endpoint))
+            .await
+    }
+
+    /// Get the response of an endpoint as received, for debugging.
+    #[instrument(skip(self))]
+    pub async fn get_response(
+        &self,
+        auth: &Auth,
+        endpoint: Endpoint<'_>,
+    ) -> dt_api::Result<RawResponse> {
+        self.call(UpstreamCall::Raw, self.api().get_response(auth, endpoint))
+            .await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn refresh_auth(&self, auth: &Auth) -> dt_api::Result<Auth> {
+        self.call(UpstreamCall::RefreshAuth, self.api().refresh_auth(auth))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::InMemoryHistoryStorage;
+
+    fn unreachable_auth() -> Auth {
+        serde_json::from_value(serde_json::json!({
+            "AccessToken": "access",
+            "AccountName": "account",
+            "ExpiresIn": 3600,
+            "RefreshToken": "refresh",
+            "Sub": "00000000-0000-0000-0000-000000000001",
+            // Nothing listens on port 1, so connecting fails.
+            "BaseUrl": "http://127.0.0.1:1",
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn rebuilds_after_consecutive_connect_errors() {
+        let upstream = Upstream::new(
+            dt_api::Api::new(),
+            Coordinator::local(None),
+            History::new(InMemoryHistoryStorage::default().into()),
+        )
+        .with_recycling(Some(2));
+        let auth = unreachable_auth();
+
+        assert!(upstream.get_summary(&auth).await.unwrap_err().is_connect());
+        assert_eq!(upstream.connection_state().consecutive_connect_errors, 1);
+        assert_eq!(upstream.connection_state().rebuilds, 0);
+
+        upstream.get_summary(&auth).await.unwrap_err();
+        let state = upstream.connection_state();
+        assert_eq!(state.consecutive_connect_errors, 0);
+        assert_eq!(state.rebuilds, 1);
+        assert!(state.last_rebuilt.is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backs_off_while_rate_limited() {
+        let upstream = Upstream::new(
+            dt_api::Api::new(),
+            Coordinator::local(None),
+            History::new(InMemoryHistoryStorage::default().into()),
+        );
+        let auth = unreachable_auth();
+        upstream.back_off(Some(Duration::from_secs(60)));
+
+        let error = upstream.get_summary(&auth).await.unwrap_err();
+        assert_eq!(error.retry_after(), Some(Duration::from_secs(60)));
+
+        // The rate limit ends soon enough to wait for it.
+        tokio::time::advance(Duration::from_secs(56)).await;
+        assert!(upstream.get_summary(&auth).await.unwrap_err().is_connect());
+        assert_eq!(upstream.rate_limited_for(), None);
+    }
+}
@@ -0,0 +1,136 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use dt_api::models::AccountId;
+use tracing::{error, instrument};
+
+use super::{Watch, WatchEntry, WatchId, WatchMatch, Watchlists};
+use crate::account::Accounts;
+
+#[instrument(skip(state))]
+pub(crate) async fn list_watches(
+    Path(id): Path<AccountId>,
+    State(state): State<Watchlists>,
+) -> Result<Json<Vec<WatchEntry>>, StatusCode> {
+    state.list(id).map(Json).map_err(|e| {
+        error!(sid = ?id, error = %e, "Failed to list watches");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+#[instrument(skip(state))]
+pub(crate) async fn create_watch(
+    Path(id): Path<AccountId>,
+    State(state): State<Watchlists>,
+    Json(watch): Json<Watch>,
+) -> Result<(StatusCode, Json<WatchEntry>), StatusCode> {
+    let watch_id = WatchId(uuid::Uuid::new_v4());
+    if let Err(e) = state.insert(id, watch_id, watch.clone()) {
+        error!(sid = ?id, error = %e, "Failed to create watch");
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    Ok((
+        StatusCode::CREATED,
+        Json(WatchEntry {
+            id: watch_id,
+            watch,
+        }),
+    ))
+}
+
+#[instrument(skip(state))]
+pub(crate) async fn get_watch(
+    Path((id, watch_id)): Path<(AccountId, WatchId)>,
+    State(state): State<Watchlists>,
+) -> Result<Json<WatchEntry>, StatusCode> {
+    match state.get(id, watch_id) {
+        Ok(Some(watch)) => Ok(Json(WatchEntry {
+            id: watch_id,
+            watch,
+        })),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!(sid = ?id, error = %e, "Failed to get watch");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[instrument(skip(state))]
+pub(crate) async fn put_watch(
+    Path((id, watch_id)): Path<(AccountId, WatchId)>,
+    State(state): State<Watchlists>,
+    Json(watch): Json<Watch>,
+) -> StatusCode {
+    let existed = match state.get(id, watch_id) {
+        Ok(watch) => watch.is_some(),
+        Err(e) => {
+            error!(sid = ?id, error = %e, "Failed to get watch");
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+    if let Err(e) = state.insert(id, watch_id, watch) {
+        error!(sid = ?id, error = %e, "Failed to update watch");
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+    if existed {
+        StatusCode::OK
+    } else {
+        StatusCode::CREATED
+    }
+}
+
+#[instrument(skip(state))]
+pub(crate) async fn delete_watch(
+    Path((id, watch_id)): Path<(AccountId, WatchId)>,
+    State(state): State<Watchlists>,
+) -> StatusCode {
+    match state.remove(id, watch_id) {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            error!(sid = ?id, error = %e, "Failed to delete watch");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+#[instrument(skip(watchlists, accounts))]
+pub(crate) async fn matches(
+    Path(id): Path<AccountId>,
+    State(watchlists): State<Watchlists>,
+    State(accounts): State<Accounts>,
+) -> Result<Json<Vec<WatchMatch>>, StatusCode> {
+    let Some(account_data) = accounts.get(&id).await else {
+        error!(sid = ?id, "Failed to find account data");
+        return Err(StatusCode::NOT_FOUND);
+    };
+    watchlists
+        .matches(id, &account_data)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            error!(sid = ?id, error = %e, "Failed to match watches");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+#[instrument(skip(watchlists, accounts))]
+pub(crate) async fn matches_all(
+    State(watchlists): State<Watchlists>,
+    State(accounts): State<Accounts>,
+) -> Result<Json<Vec<WatchMatch>>, StatusCode> {
+    let mut matches = Vec::new();
+    for (id, account_data) in accounts.list().await {
+        match watchlists.matches(id, &account_data).await {
+            Ok(account_matches) => matches.extend(account_matches),
+            Err(e) => {
+                error!(sid = ?id, error = %e, "Failed to match watches");
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+    }
+    Ok(Json(matches))
+}
@@ -0,0 +1,170 @@
+use anyhow::Result;
+use dt_api::models::{AccountId, Offer};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    account::{AccountData, OfferMatch},
+    push::PushPriority,
+};
+
+mod endpoints;
+pub(crate) use endpoints::{
+    create_watch, delete_watch, get_watch, list_watches, matches, matches_all, put_watch,
+};
+
+mod storage;
+pub(crate) use storage::{
+    ErasedWatchlistStorage, InMemoryWatchlistStorage, SledDbWatchlistStorage, WatchlistStorage,
+};
+
+/// Watch id wrapper type
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+pub(crate) struct WatchId(pub Uuid);
+
+/// Filter for the offers an account wants to be notified about.
+///
+/// Every set criterion has to match.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct Watch {
+    /// Label to identify the watch in notifications.
+    pub name: Option<String>,
+    /// Case-insensitive item name pattern; `*` matches any text.
+    pub item_pattern: Option<String>,
+    /// Trait (blessing) ids that must all be present.
+    pub traits: Vec<String>,
+    /// Perk ids that must all be present.
+    pub perks: Vec<String>,
+    /// Minimum item rarity.
+    pub min_rarity: Option<i32>,
+    /// Minimum rarity of the listed traits.
+    pub min_trait_rarity: Option<i32>,
+    /// Priority of the push notifications of matches; not pushed if `None`.
+    pub push_priority: Option<PushPriority>,
+}
+
+impl Watch {
+    pub fn matches(&self, offer: &Offer) -> bool {
+        if let Some(pattern) = &self.item_pattern {
+            if !wildcard_match(pattern, &offer.sku.name) {
+                return false;
+            }
+        }
+        let needs_item =
+            self.min_rarity.is_some() || !self.traits.is_empty() || !self.perks.is_empty();
+        if !needs_item {
+            return true;
+        }
+        let Some(item) = offer.description.overrides.item() else {
+            return false;
+        };
+        self.min_rarity.map_or(true, |min| item.rarity >= min)
+            && self.traits.iter().all(|id| {
+                item.traits.iter().any(|t| {
+                    t.id == *id && self.min_trait_rarity.map_or(true, |min| t.rarity >= min)
+                })
+            })
+            && self
+                .perks
+                .iter()
+                .all(|id| item.perks.iter().any(|p| p.id == *id))
+    }
+}
+
+/// Case-insensitive match of `text` against `pattern`, where `*` matches any
+/// text. Patterns without `*` match anywhere in `text`.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let text = text.to_lowercase();
+    if !pattern.contains('*') {
+        return text.contains(&pattern);
+    }
+    let parts = pattern.split('*').collect::<Vec<_>>();
+    let (first, rest) = parts.split_first().expect("split yields at least one part");
+    let (last, middle) = rest.split_last().expect("pattern contains '*'");
+    let Some(mut remaining) = text.strip_prefix(first) else {
+        return false;
+    };
+    for part in middle {
+        match remaining.find(part) {
+            Some(index) => remaining = &remaining[index + part.len()..],
+            None => return false,
+        }
+    }
+    remaining.ends_with(last)
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct WatchEntry {
+    pub id: WatchId,
+    #[serde(flatten)]
+    pub watch: Watch,
+}
+
+/// Cached offer matching a watch.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct WatchMatch {
+    pub watch_id: WatchId,
+    pub watch_name: Option<String>,
+    pub push_priority: Option<PushPriority>,
+    #[serde(flatten)]
+    pub offer: OfferMatch,
+}
+
+/// Watchlists of all accounts.
+#[derive(Debug, Clone)]
+pub(crate) struct Watchlists {
+    storage: ErasedWatchlistStorage,
+}
+
+impl Watchlists {
+    pub fn new(storage: ErasedWatchlistStorage) -> Self {
+        Self { storage }
+    }
+
+    pub fn list(&self, account: AccountId) -> Result<Vec<WatchEntry>> {
+        self.storage.list(account)
+    }
+
+    pub fn get(&self, account: AccountId, id: WatchId) -> Result<Option<Watch>> {
+        self.storage.get(account, id)
+    }
+
+    pub fn insert(&self, account: AccountId, id: WatchId, watch: Watch) -> Result<()> {
+        self.storage.insert(account, id, watch)
+    }
+
+    pub fn remove(&self, account: AccountId, id: WatchId) -> Result<bool> {
+        self.storage.remove(account, id)
+    }
+
+    /// Find the offers in the cached stores of an account that match its watches.
+    #[instrument(skip(self, account_data))]
+    pub async fn matches(
+        &self,
+        account: AccountId,
+        account_data: &AccountData,
+    ) -> Result<Vec<WatchMatch>> {
+        let mut matches = Vec::new();
+        for entry in self.list(account)? {
+            matches.extend(
+                account_data
+                    .find_offers(account, |offer| entry.watch.matches(offer))
+                    .await
+                    .into_iter()
+                    .map(|offer| WatchMatch {
+                        watch_id: entry.id,
+                        watch_name: entry.watch.name.clone(),
+                        push_priority: entry.watch.push_priority,
+                        offer,
+                    }),
+            );
+        }
+        Ok(matches)
+    }
+}
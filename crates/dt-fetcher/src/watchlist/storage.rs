@@ -0,0 +1,185 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{Context, Result};
+use dt_api::models::AccountId;
+use dyn_clone::DynClone;
+use tracing::{instrument, warn};
+
+use super::{Watch, WatchEntry, WatchId};
+
+pub(crate) trait WatchlistStorage:
+    Send + Sync + DynClone + std::fmt::Debug + 'static
+{
+    fn list(&self, account: AccountId) -> Result<Vec<WatchEntry>>;
+
+    fn get(&self, account: AccountId, id: WatchId) -> Result<Option<Watch>>;
+
+    fn insert(&self, account: AccountId, id: WatchId, watch: Watch) -> Result<()>;
+
+    /// Remove a watch, returning whether it existed.
+    fn remove(&self, account: AccountId, id: WatchId) -> Result<bool>;
+}
+
+dyn_clone::clone_trait_object!(WatchlistStorage);
+
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryWatchlistStorage {
+    watches: Arc<RwLock<HashMap<AccountId, HashMap<WatchId, Watch>>>>,
+}
+
+impl WatchlistStorage for InMemoryWatchlistStorage {
+    #[instrument(skip(self))]
+    fn list(&self, account: AccountId) -> Result<Vec<WatchEntry>> {
+        let watches = self.watches.read().expect("Watchlists poisoned");
+        Ok(watches
+            .get(&account)
+            .into_iter()
+            .flatten()
+            .map(|(id, watch)| WatchEntry {
+                id: *id,
+                watch: watch.clone(),
+            })
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    fn get(&self, account: AccountId, id: WatchId) -> Result<Option<Watch>> {
+        let watches = self.watches.read().expect("Watchlists poisoned");
+        Ok(watches
+            .get(&account)
+            .and_then(|watches| watches.get(&id))
+            .cloned())
+    }
+
+    #[instrument(skip(self))]
+    fn insert(&self, account: AccountId, id: WatchId, watch: Watch) -> Result<()> {
+        let mut watches = self.watches.write().expect("Watchlists poisoned");
+        watches.entry(account).or_default().insert(id, watch);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    fn remove(&self, account: AccountId, id: WatchId) -> Result<bool> {
+        let mut watches = self.watches.write().expect("Watchlists poisoned");
+        Ok(watches
+            .get_mut(&account)
+            .and_then(|watches| watches.remove(&id))
+            .is_some())
+    }
+}
+
+const WATCHLIST_TREE: &str = "watchlists";
+
+/// Watchlists stored in a tree of the auth database, keyed by account id then
+/// watch id.
+#[derive(Debug, Clone)]
+pub struct SledDbWatchlistStorage {
+    tree: sled::Tree,
+}
+
+impl SledDbWatchlistStorage {
+    pub fn new(db: &sled::Db) -> Result<Self> {
+        Ok(Self {
+            tree: db
+                .open_tree(WATCHLIST_TREE)
+                .context("Failed to open watchlists")?,
+        })
+    }
+
+    fn key(account: AccountId, id: WatchId) -> Vec<u8> {
+        [account.0.as_bytes().as_slice(), id.0.as_bytes().as_slice()].concat()
+    }
+}
+
+impl WatchlistStorage for SledDbWatchlistStorage {
+    #[instrument(skip(self))]
+    fn list(&self, account: AccountId) -> Result<Vec<WatchEntry>> {
+        let mut entries = Vec::new();
+        for result in self.tree.scan_prefix(account.0.as_bytes()) {
+            let (key, value) = result.context("Failed to read watchlist")?;
+            let entry = uuid::Uuid::from_slice(&key[16..])
+                .context("Failed to deserialize watch id")
+                .and_then(|id| {
+                    Ok(WatchEntry {
+                        id: WatchId(id),
+                        watch: serde_json::from_slice(&value)
+                            .context("Failed to deserialize watch")?,
+                    })
+                });
+            match entry {
+                Ok(entry) => entries.push(entry),
+                Err(e) => warn!(key = ?key, error = %e, "Skipping invalid watch"),
+            }
+        }
+        Ok(entries)
+    }
+
+    #[instrument(skip(self))]
+    fn get(&self, account: AccountId, id: WatchId) -> Result<Option<Watch>> {
+        self.tree
+            .get(Self::key(account, id))
+            .context("Failed to get watch")?
+            .map(|value| serde_json::from_slice(&value).context("Failed to deserialize watch"))
+            .transpose()
+    }
+
+    #[instrument(skip(self))]
+    fn insert(&self, account: AccountId, id: WatchId, watch: Watch) -> Result<()> {
+        let value = serde_json::to_vec(&watch).context("Failed to serialize watch")?;
+        self.tree
+            .insert(Self::key(account, id), value)
+            .context("Failed to insert watch")?;
+        self.tree.flush().context("Failed to flush")?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    fn remove(&self, account: AccountId, id: WatchId) -> Result<bool> {
+        let removed = self
+            .tree
+            .remove(Self::key(account, id))
+            .context("Failed to remove watch")?;
+        self.tree.flush().context("Failed to flush")?;
+        Ok(removed.is_some())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ErasedWatchlistStorage(Box<dyn WatchlistStorage>);
+
+impl WatchlistStorage for ErasedWatchlistStorage {
+    #[instrument(skip(self))]
+    fn list(&self, account: AccountId) -> Result<Vec<WatchEntry>> {
+        self.0.list(account)
+    }
+
+    #[instrument(skip(self))]
+    fn get(&self, account: AccountId, id: WatchId) -> Result<Option<Watch>> {
+        self.0.get(account, id)
+    }
+
+    #[instrument(skip(self))]
+    fn insert(&self, account: AccountId, id: WatchId, watch: Watch) -> Result<()> {
+        self.0.insert(account, id, watch)
+    }
+
+    #[instrument(skip(self))]
+    fn remove(&self, account: AccountId, id: WatchId) -> Result<bool> {
+        self.0.remove(account, id)
+    }
+}
+
+impl From<InMemoryWatchlistStorage> for ErasedWatchlistStorage {
+    fn from(value: InMemoryWatchlistStorage) -> Self {
+        Self(Box::new(value))
+    }
+}
+
+impl From<SledDbWatchlistStorage> for ErasedWatchlistStorage {
+    fn from(value: SledDbWatchlistStorage) -> Self {
+        Self(Box::new(value))
+    }
+}
@@ -0,0 +1,255 @@
+//! Running as a Windows service, and logging to the Windows event log.
+
+use std::{
+    ffi::{OsStr, OsString},
+    fmt::Write as _,
+    os::windows::ffi::OsStrExt,
+    sync::Mutex,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use tokio_util::sync::CancellationToken;
+use tracing::{
+    field::{Field, Visit},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context as LayerContext, Layer};
+use windows_service::{
+    define_windows_service,
+    service::{
+        ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+        ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+    },
+    service_control_handler::{self, ServiceControlHandlerResult},
+    service_dispatcher,
+    service_manager::{ServiceManager, ServiceManagerAccess},
+};
+use windows_sys::Win32::{
+    Foundation::HANDLE,
+    System::EventLog::{
+        DeregisterEventSource, RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE,
+        EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE,
+    },
+};
+
+use crate::cli::Args;
+
+/// Name of the service, and the source of its event log entries.
+const SERVICE_NAME: &str = "dt-fetcher";
+const SERVICE_DISPLAY_NAME: &str = "DT Fetcher";
+/// How long to wait for the service to stop when uninstalling.
+const STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Arguments for `service_main`, which the service dispatcher calls without
+/// them.
+static SERVICE_ARGS: Mutex<Option<Args>> = Mutex::new(None);
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Register the service to run this executable with the arguments of the
+/// current invocation, minus `install-service`.
+pub(crate) fn install_service() -> Result<()> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )
+    .context("Failed to connect to the service manager")?;
+    let mut launch_arguments: Vec<OsString> = std::env::args_os()
+        .skip(1)
+        .filter(|arg| arg != "install-service")
+        .collect();
+    launch_arguments.push("run-service".into());
+    let info = ServiceInfo {
+        name: SERVICE_NAME.into(),
+        display_name: SERVICE_DISPLAY_NAME.into(),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe().context("Failed to get executable path")?,
+        launch_arguments,
+        dependencies: Vec::new(),
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager
+        .create_service(&info, ServiceAccess::CHANGE_CONFIG)
+        .context("Failed to create service")?;
+    service
+        .set_description("Caches Darktide store and account data")
+        .context("Failed to set service description")?;
+    println!("Installed the {SERVICE_NAME} service");
+    Ok(())
+}
+
+/// Stop the service if it is running, then remove it.
+pub(crate) fn uninstall_service() -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .context("Failed to connect to the service manager")?;
+    let service = manager
+        .open_service(
+            SERVICE_NAME,
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+        )
+        .context("Failed to open service")?;
+    // Deletion only completes once the service has stopped.
+    service.delete().context("Failed to delete service")?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop().context("Failed to stop service")?;
+        let deadline = std::time::Instant::now() + STOP_TIMEOUT;
+        while service.query_status()?.current_state != ServiceState::Stopped {
+            if std::time::Instant::now() > deadline {
+                return Err(anyhow!("Timed out waiting for the service to stop"));
+            }
+            std::thread::sleep(Duration::from_millis(500));
+        }
+    }
+    println!("Uninstalled the {SERVICE_NAME} service");
+    Ok(())
+}
+
+/// Hand the process over to the service dispatcher, which runs `service_main`
+/// until the service is stopped.
+pub(crate) fn run_service(args: Args) -> Result<()> {
+    *SERVICE_ARGS.lock().expect("service args lock poisoned") = Some(args);
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+        .context("Failed to start service dispatcher")
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    let Some(args) = SERVICE_ARGS
+        .lock()
+        .expect("service args lock poisoned")
+        .take()
+    else {
+        return;
+    };
+    if let Err(e) = run_as_service(args) {
+        // Logging may not have been set up, so report to the event log directly.
+        if let Ok(layer) = EventLogLayer::new() {
+            layer.report(Level::ERROR, &format!("Service failed: {e:?}"));
+        }
+    }
+}
+
+fn run_as_service(args: Args) -> Result<()> {
+    let token = CancellationToken::new();
+    let stop_token = token.clone();
+    let status_handle =
+        service_control_handler::register(SERVICE_NAME, move |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                stop_token.cancel();
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        })
+        .context("Failed to register service control handler")?;
+    let status = |current_state, controls_accepted, exit_code| ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state,
+        controls_accepted,
+        exit_code: ServiceExitCode::Win32(exit_code),
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    };
+    status_handle
+        .set_service_status(status(
+            ServiceState::Running,
+            ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+            0,
+        ))
+        .context("Failed to report service running")?;
+    let result =
+        crate::cli::runtime().and_then(|runtime| runtime.block_on(crate::cli::run(args, token)));
+    status_handle
+        .set_service_status(status(
+            ServiceState::Stopped,
+            ServiceControlAccept::empty(),
+            u32::from(result.is_err()),
+        ))
+        .context("Failed to report service stopped")?;
+    result
+}
+
+/// Tracing layer writing events to the Application event log.
+pub(crate) struct EventLogLayer {
+    source: HANDLE,
+}
+
+// The handle is only passed to `ReportEventW`, which is thread-safe.
+unsafe impl Send for EventLogLayer {}
+unsafe impl Sync for EventLogLayer {}
+
+impl EventLogLayer {
+    pub fn new() -> Result<Self> {
+        let name = wide(OsStr::new(SERVICE_NAME));
+        // SAFETY: `name` is a nul-terminated wide string that outlives the call.
+        let source = unsafe { RegisterEventSourceW(std::ptr::null(), name.as_ptr()) };
+        if source == 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to register event source");
+        }
+        Ok(Self { source })
+    }
+
+    fn report(&self, level: Level, message: &str) {
+        let event_type = match level {
+            Level::ERROR => EVENTLOG_ERROR_TYPE,
+            Level::WARN => EVENTLOG_WARNING_TYPE,
+            _ => EVENTLOG_INFORMATION_TYPE,
+        };
+        let message = wide(OsStr::new(message));
+        let strings = [message.as_ptr()];
+        // SAFETY: `source` is a registered event source, and `strings` holds
+        // one nul-terminated wide string that outlives the call.
+        unsafe {
+            ReportEventW(
+                self.source,
+                event_type,
+                0,
+                0,
+                std::ptr::null_mut(),
+                1,
+                0,
+                strings.as_ptr(),
+                std::ptr::null(),
+            );
+        }
+    }
+}
+
+impl Drop for EventLogLayer {
+    fn drop(&mut self) {
+        // SAFETY: `source` was registered in `new` and is not used after this.
+        unsafe {
+            DeregisterEventSource(self.source);
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for EventLogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: LayerContext<'_, S>) {
+        let metadata = event.metadata();
+        let mut visitor = MessageVisitor(format!("{}: ", metadata.target()));
+        event.record(&mut visitor);
+        self.report(*metadata.level(), &visitor.0);
+    }
+}
+
+/// Formats the message of an event followed by its other fields.
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.0, "{value:?}");
+        } else {
+            let _ = write!(self.0, ", {}: {value:?}", field.name());
+        }
+    }
+}
+
+fn wide(s: &OsStr) -> Vec<u16> {
+    s.encode_wide().chain(std::iter::once(0)).collect()
+}
@@ -52,6 +52,7 @@
             version = "0.1.0";
             src = craneLib.cleanCargoSource (craneLib.path ./.);
             strictDeps = true;
+            DT_FETCHER_GIT_COMMIT = self.rev or self.dirtyRev or "unknown";
             nativeBuildInputs = [
               pkgs.pkg-config
             ];
@@ -190,7 +191,9 @@
                         cfg.listenAddr
                       ]));
               in {
-                Type = "exec";
+                Type = "notify";
+                WatchdogSec = 60;
+                Restart = "on-failure";
                 DynamicUser = true;
                 ExecStart = "${pkg}/bin/dt-fetcher ${args}";
                 EnvironmentFile = mkIf (cfg.environmentFile != null) cfg.environmentFile;